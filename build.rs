@@ -1,4 +1,6 @@
 fn main() -> std::io::Result<()> {
+    // the library on its own has no executable to embed a manifest in
+    #[cfg(feature = "cli")]
     if cfg!(target_os = "windows") {
         // We need to set the 'longPathAware' manifest key, so that file paths with length >260 chars will work.
         // This happens sometimes since we encode IDs for duplicate files.
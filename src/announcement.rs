@@ -0,0 +1,279 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use futures_util::future;
+use scraper::{Html, Selector};
+use tokio::io::AsyncWrite;
+
+use crate::file::File;
+use crate::module::{Announcement, AnnouncementAttachment};
+use crate::resource;
+use crate::resource::{OverwriteMode, OverwriteResult, Resource, RetryableError};
+use crate::util::{format_date, html_to_text, parse_time, sanitise_filename};
+use crate::{Api, Result};
+
+pub struct AnnouncementHandle {
+    id: String,
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct AnnouncementFile {
+    id: String,
+    path: PathBuf,
+    title: String,
+    description: String,
+    display_from: SystemTime,
+    last_updated: SystemTime,
+    attachments: Vec<AnnouncementAttachment>,
+}
+
+pub(crate) async fn fetch_announcements(
+    api: &Api,
+    module_id: &str,
+    archived: bool,
+) -> Result<Vec<Announcement>> {
+    let path = format!(
+        "announcement/{}/{}?populate=Attachments&sortby=displayFrom%20ASC",
+        if archived { "Archived" } else { "NonArchived" },
+        module_id
+    );
+    let api_data = api.api_as_json_paged::<Announcement>(&path).await?;
+    if let Some(announcements) = api_data.data {
+        Ok(announcements)
+    } else {
+        Err("Invalid API response from server: type mismatch")
+    }
+}
+
+impl AnnouncementHandle {
+    pub fn new(id: String, path: PathBuf) -> AnnouncementHandle {
+        AnnouncementHandle { id, path }
+    }
+
+    // loads both the current and the archived announcements
+    pub async fn load(self, api: &Api) -> Result<Vec<AnnouncementFile>> {
+        let (non_archived, archived) = future::join(
+            fetch_announcements(api, &self.id, false),
+            fetch_announcements(api, &self.id, true),
+        )
+        .await;
+        let mut announcements = non_archived?;
+        announcements.append(&mut archived?);
+
+        announcements
+            .into_iter()
+            .map(|a| {
+                let display_from = a
+                    .display_from
+                    .as_deref()
+                    .map(parse_time)
+                    .transpose()?
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                let last_updated = a
+                    .last_updated_date
+                    .as_deref()
+                    .map(parse_time)
+                    .transpose()?
+                    .unwrap_or(display_from);
+                Ok(AnnouncementFile {
+                    path: self.path.join(sanitise_filename(&format!(
+                        "{} - {}.md",
+                        format_date(display_from),
+                        a.title
+                    ))),
+                    id: a.id,
+                    title: a.title,
+                    description: a.description,
+                    display_from,
+                    last_updated,
+                    attachments: a.attachments.unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+}
+
+// Escapes what would otherwise end or break the path of a relative link
+fn encode_link(path: &str) -> String {
+    path.replace('%', "%25")
+        .replace(' ', "%20")
+        .replace('#', "%23")
+        .replace('?', "%3F")
+}
+
+impl AnnouncementFile {
+    /// The files attached to the announcement, which go in a folder named after it, next to it
+    pub fn attachments(&self) -> Result<Vec<File>> {
+        let folder = self.path.with_extension("");
+        self.attachments
+            .iter()
+            .map(|attachment| {
+                let last_updated = match &attachment.last_updated_date {
+                    Some(date) => parse_time(date)?,
+                    None => self.last_updated,
+                };
+                let name = attachment
+                    .file_name
+                    .clone()
+                    .unwrap_or_else(|| attachment.name.clone());
+                Ok(File::attachment(
+                    attachment.id.clone(),
+                    folder.join(sanitise_filename(&name)),
+                    name,
+                    last_updated,
+                    attachment.file_size,
+                ))
+            })
+            .collect()
+    }
+
+    fn image_sources(&self) -> Vec<String> {
+        let fragment = Html::parse_fragment(&self.description);
+        let selector = Selector::parse("img[src]").unwrap();
+        let mut seen = HashSet::new();
+        fragment
+            .select(&selector)
+            .filter_map(|img| img.value().attr("src"))
+            .filter(|src| seen.insert(*src))
+            .map(|src| src.to_owned())
+            .collect()
+    }
+
+    // Writes the announcement as sanitised HTML next to `destination`, with its images in a
+    // folder named after it, e.g. `<name>.html` and `<name>_files/1.png`. Images that cannot be
+    // downloaded keep linking to the server.
+    async fn save_html(&self, api: &Api, destination: &Path) -> Result<()> {
+        let stem = destination
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let images_dir_name = format!("{}_files", stem);
+        let images_dir = destination.with_file_name(&images_dir_name);
+
+        let mut local_sources = HashMap::new();
+        for (i, src) in self.image_sources().into_iter().enumerate() {
+            let url = match api.api_base_url.join(&src) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url,
+                _ => continue,
+            };
+            let name = match Path::new(url.path()).extension() {
+                Some(extension) => format!(
+                    "{}.{}",
+                    i + 1,
+                    sanitise_filename(&extension.to_string_lossy())
+                ),
+                None => (i + 1).to_string(),
+            };
+            let data = match api.get_linked(url).await {
+                Ok(data) => data,
+                Err(_) => continue,
+            };
+            tokio::fs::create_dir_all(&images_dir)
+                .await
+                .map_err(|_| "Unable to create directory")?;
+            tokio::fs::write(images_dir.join(&name), data)
+                .await
+                .map_err(|_| "Failed writing to disk")?;
+            local_sources.insert(src, encode_link(&format!("{}/{}", images_dir_name, name)));
+        }
+
+        let body = ammonia::Builder::default()
+            .attribute_filter(move |element, attribute, value| {
+                match (element, attribute, local_sources.get(value)) {
+                    ("img", "src", Some(local)) => Some(local.clone().into()),
+                    _ => Some(value.into()),
+                }
+            })
+            .clean(&self.description)
+            .to_string();
+        let title = htmlescape::encode_minimal(&self.title);
+        let html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n<h1>{}</h1>\n<p><em>Posted on {}</em></p>\n{}\n</body>\n</html>\n",
+            title,
+            title,
+            format_date(self.display_from),
+            body
+        );
+        tokio::fs::write(destination.with_extension("html"), html)
+            .await
+            .map_err(|_| "Failed writing to disk")
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut markdown = format!(
+            "# {}\n\n_Posted on {}_\n\n{}\n",
+            self.title,
+            format_date(self.display_from),
+            html_to_text(&self.description)
+        );
+        if !self.attachments.is_empty() {
+            markdown.push_str("\nAttachments:\n");
+            for attachment in &self.attachments {
+                markdown.push_str(&format!("- {}\n", attachment.name));
+            }
+        }
+        markdown
+    }
+}
+
+#[async_trait]
+impl Resource for AnnouncementFile {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+    fn path_mut(&mut self) -> &mut PathBuf {
+        &mut self.path
+    }
+
+    fn last_updated(&self) -> SystemTime {
+        self.last_updated
+    }
+
+    async fn download(
+        &self,
+        api: &Api,
+        destination: &Path,
+        temp_destination: &Path,
+        overwrite: OverwriteMode,
+    ) -> Result<OverwriteResult> {
+        let result = resource::do_retryable_download(
+            api,
+            self,
+            destination,
+            temp_destination,
+            overwrite,
+            move |_| future::ready(Ok(self.to_markdown())),
+            move |_, content, temp_destination| async move {
+                tokio::fs::write(temp_destination, content)
+                    .await
+                    .map_err(|_| RetryableError::Fail("Failed writing to disk"))
+            },
+        )
+        .await?;
+        // the copy is also made for announcements that were downloaded before it was asked for
+        let wrote_markdown = !matches!(
+            result,
+            OverwriteResult::AlreadyHave | OverwriteResult::Skipped
+        );
+        if api.announcement_html && (wrote_markdown || !destination.with_extension("html").exists())
+        {
+            self.save_html(api, destination).await?;
+        }
+        Ok(result)
+    }
+
+    async fn download_to_writer(
+        &self,
+        _api: &Api,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        resource::write_text(&self.to_markdown(), writer).await
+    }
+}
@@ -0,0 +1,152 @@
+// Packaging downloaded resources into a single zip file, with a manifest of what is inside
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use fluminurs::module::Module;
+use fluminurs::resource::Resource;
+use fluminurs::Result;
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+const VIDEO_EXTENSIONS: [&str; 2] = ["mp4", "mkv"];
+
+pub fn is_video(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| {
+            VIDEO_EXTENSIONS
+                .iter()
+                .any(|video| extension.eq_ignore_ascii_case(video))
+        })
+        .unwrap_or(false)
+}
+
+// Zip files always use forward slashes
+fn zip_name(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Writes the files below `root` into a zip file, along with `extra_files` given by name and contents
+pub fn write_zip(
+    root: &Path,
+    archive: &Path,
+    files: &[PathBuf],
+    extra_files: &[(String, Vec<u8>)],
+) -> Result<()> {
+    let file = fs::File::create(archive).map_err(|_| "Unable to create archive")?;
+    let mut zip = ZipWriter::new(file);
+    for path in files {
+        let name = zip_name(path.strip_prefix(root).map_err(|_| "Invalid file path")?);
+        // videos hardly compress any further, so they are only stored
+        let options = FileOptions::default()
+            .compression_method(if is_video(path) {
+                zip::CompressionMethod::Stored
+            } else {
+                zip::CompressionMethod::Deflated
+            })
+            .large_file(true);
+        zip.start_file(name, options)
+            .map_err(|_| "Unable to write archive")?;
+        let mut source = fs::File::open(path).map_err(|_| "Unable to read file to archive")?;
+        io::copy(&mut source, &mut zip).map_err(|_| "Unable to write archive")?;
+    }
+    for (name, contents) in extra_files {
+        zip.start_file(name, FileOptions::default())
+            .map_err(|_| "Unable to write archive")?;
+        io::Write::write_all(&mut zip, contents).map_err(|_| "Unable to write archive")?;
+    }
+    zip.finish().map_err(|_| "Unable to write archive")?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestEntry {
+    kind: &'static str,
+    id: String,
+    path: String,
+    last_updated: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest<'a> {
+    module: &'a str,
+    name: &'a str,
+    term: &'a str,
+    created: String,
+    resources: &'a [ManifestEntry],
+}
+
+fn format_time(time: std::time::SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// The resources of a module that were downloaded below `root`, to be packaged together
+pub struct ModuleArchive {
+    root: PathBuf,
+    entries: Vec<ManifestEntry>,
+}
+
+impl ModuleArchive {
+    pub fn new(root: &Path) -> ModuleArchive {
+        ModuleArchive {
+            root: root.to_owned(),
+            entries: vec![],
+        }
+    }
+
+    /// Adds the resources that are present in the root, leaving out those that failed to download
+    pub fn add<T: Resource>(&mut self, kind: &'static str, resources: &[T]) {
+        self.entries.extend(
+            resources
+                .iter()
+                .filter(|resource| self.root.join(resource.path()).is_file())
+                .map(|resource| ManifestEntry {
+                    kind,
+                    id: resource.id().to_owned(),
+                    path: zip_name(resource.path()),
+                    last_updated: format_time(resource.last_updated()),
+                }),
+        );
+    }
+
+    /// Writes the resources and the manifest into a zip file, returning the number of resources
+    pub async fn write(self, module: &Module, archive: &Path) -> Result<usize> {
+        let manifest = serde_json::to_vec_pretty(&Manifest {
+            module: &module.code,
+            name: &module.name,
+            term: &module.term,
+            created: format_time(std::time::SystemTime::now()),
+            resources: &self.entries,
+        })
+        .map_err(|_| "Unable to serialise manifest")?;
+        let files = self
+            .entries
+            .iter()
+            .map(|entry| self.root.join(&entry.path))
+            .collect::<Vec<_>>();
+        let count = files.len();
+        let (root, archive) = (self.root, archive.to_owned());
+        // writing the archive is blocking, and may take a while
+        tokio::task::spawn_blocking(move || {
+            write_zip(
+                &root,
+                &archive,
+                &files,
+                &[(MANIFEST_FILE_NAME.to_owned(), manifest)],
+            )
+        })
+        .await
+        .map_err(|_| "Unable to write archive")??;
+        Ok(count)
+    }
+}
@@ -0,0 +1,137 @@
+// Problems that need the user to do something, as opposed to transient failures that the next
+// run is likely to get past. They are collected during a run and summarised at the end, with
+// what to do about each of them.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use fluminurs::conferencing::RECORDING_PASSWORD_REJECTED;
+use fluminurs::resource::DOWNLOAD_REFUSED;
+use fluminurs::streamer::FFMPEG_REQUIRED;
+use fluminurs::{Error, ACCESS_DENIED, INVALID_CREDENTIALS, SESSION_EXPIRED};
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Problem {
+    SessionExpired,
+    InvalidCredentials,
+    AccessDenied,
+    RecordingPassword,
+    NeedsFfmpeg,
+}
+
+impl Problem {
+    fn from_error(error: Error) -> Option<Problem> {
+        match error {
+            SESSION_EXPIRED => Some(Problem::SessionExpired),
+            INVALID_CREDENTIALS => Some(Problem::InvalidCredentials),
+            ACCESS_DENIED | DOWNLOAD_REFUSED => Some(Problem::AccessDenied),
+            RECORDING_PASSWORD_REJECTED => Some(Problem::RecordingPassword),
+            FFMPEG_REQUIRED => Some(Problem::NeedsFfmpeg),
+            _ => None,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Problem::SessionExpired => "The login session expired",
+            Problem::InvalidCredentials => "The stored credentials were rejected",
+            Problem::AccessDenied => "The server denied access to these",
+            Problem::RecordingPassword => "These Zoom recordings need a password",
+            Problem::NeedsFfmpeg => "These videos need ffmpeg for muxing or remuxing",
+        }
+    }
+
+    fn suggestion(self) -> &'static str {
+        match self {
+            Problem::SessionExpired => {
+                "Log in again by deleting the cookie jar (see --cookie-jar), or with --import-cookies-from a browser that is logged in"
+            }
+            Problem::InvalidCredentials => {
+                "Delete the credential file (see --credential-file) and run again to enter them anew"
+            }
+            Problem::AccessDenied => {
+                "Check that they open on the website; if they do not, ask the teaching team for access"
+            }
+            Problem::RecordingPassword => {
+                "Get the share link and password from the teaching team, then run: fluminurs-cli grab <link> --password <password>"
+            }
+            Problem::NeedsFfmpeg => {
+                "Install ffmpeg (or pass its location with --ffmpeg) and run again to download them"
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Attention {
+    // the paths that each problem affected
+    problems: Mutex<BTreeMap<Problem, Vec<String>>>,
+    transient_failures: AtomicUsize,
+}
+
+/// Whether the failure is likely to pass by itself, as opposed to needing the user to act
+pub fn is_transient(error: Error) -> bool {
+    Problem::from_error(error).is_none()
+}
+
+impl Attention {
+    /// Records a failure to process the resource at `path`
+    pub fn record(&self, error: Error, path: &Path) {
+        match Problem::from_error(error) {
+            Some(problem) => self
+                .problems
+                .lock()
+                .unwrap()
+                .entry(problem)
+                .or_default()
+                .push(path.to_string_lossy().into_owned()),
+            None => {
+                self.transient_failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Records a failure that ended the run, which is not about any resource in particular
+    pub fn record_run_error(&self, error: Error) {
+        if let Some(problem) = Problem::from_error(error) {
+            self.problems.lock().unwrap().entry(problem).or_default();
+        }
+    }
+
+    /// Prints what needs attention, if anything, and starts over for the next run
+    pub fn report(&self) {
+        let problems = std::mem::take(&mut *self.problems.lock().unwrap());
+        let transient_failures = self.transient_failures.swap(0, Ordering::Relaxed);
+        if problems.is_empty() {
+            if transient_failures > 0 {
+                errln!(
+                    "{} downloads failed, most likely temporarily; they are tried again on the next run",
+                    transient_failures
+                );
+            }
+            return;
+        }
+
+        errln!("");
+        errln!("Needs attention:");
+        for (problem, paths) in &problems {
+            if paths.is_empty() {
+                errln!("* {}", problem.description());
+            } else {
+                errln!("* {} ({}):", problem.description(), paths.len());
+                for path in paths {
+                    errln!("  - {}", path);
+                }
+            }
+            errln!("  {}", problem.suggestion());
+        }
+        if transient_failures > 0 {
+            errln!(
+                "{} other downloads failed, most likely temporarily; they are tried again on the next run",
+                transient_failures
+            );
+        }
+    }
+}
@@ -0,0 +1,268 @@
+// A read-through cache of the direct downloads that fluminurs resolves, so that several devices
+// on a LAN (pointed at it with `--download-proxy`), or repeated verify runs, fetch each file from
+// the origin only once. Files are cached by a hash of the URL without its query, which expires,
+// and of the key the client gives, which names a version of the resource. A client can thus only
+// ever get a file cached under the URL it was fetched from.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Url};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use fluminurs::{Api, Result, DOWNLOAD_PROXY_PATH};
+
+use crate::clean::TEMP_FILE_PREFIX;
+
+const MAX_REQUEST_SIZE: usize = 8192;
+// the hosts that LumiNUS, Panopto and Zoom serve downloads from, along with their subdomains
+const ALLOWED_HOSTS: &[&str] = &["luminus.nus.edu.sg", "panopto.com", "zoom.us"];
+
+struct CacheProxy {
+    client: Client,
+    cache_dir: PathBuf,
+    // so that concurrent requests for a file that is not cached yet fetch it only once
+    fetching: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+enum Response {
+    File(PathBuf),
+    Status(String),
+}
+
+// So that the proxy cannot be used to fetch anything else
+fn is_allowed_url(url: &Url) -> bool {
+    let host = match url.host_str() {
+        Some(host) => host,
+        None => return false,
+    };
+    url.scheme() == "https"
+        && ALLOWED_HOSTS.iter().any(|allowed| {
+            host == *allowed
+                || host
+                    .strip_suffix(allowed)
+                    .is_some_and(|subdomain| subdomain.ends_with('.'))
+        })
+}
+
+// The name of the file in the cache, as hex so that it is safe as a file name
+fn cache_key(url: &Url, version: &str) -> String {
+    let mut resource = url.clone();
+    resource.set_query(None);
+    resource.set_fragment(None);
+    let mut hasher = Sha256::new();
+    hasher.update(resource.as_str().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(version.as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn bad_request() -> Response {
+    Response::Status("400 Bad Request".to_owned())
+}
+
+/// Serves the cache on `address` until the process is stopped.
+/// Anyone who can reach the address can make it fetch files from LumiNUS, Panopto and Zoom,
+/// so it is meant for trusted networks.
+pub async fn serve(address: SocketAddr, cache_dir: PathBuf) -> Result<()> {
+    tokio::fs::create_dir_all(&cache_dir)
+        .await
+        .map_err(|_| "Unable to create cache directory")?;
+    let listener = TcpListener::bind(address)
+        .await
+        .map_err(|_| "Unable to listen on the given address")?;
+    outln!(
+        "Serving the download cache in {} on http://{}",
+        cache_dir.display(),
+        address
+    );
+    let proxy = Arc::new(CacheProxy {
+        // the certificates of the client are the ones that fluminurs trusts
+        client: Api::anonymous()?.get_client().clone(),
+        cache_dir,
+        fetching: Mutex::new(HashMap::new()),
+    });
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(_) => continue,
+        };
+        let proxy = proxy.clone();
+        tokio::spawn(async move {
+            // a broken connection only affects its own request
+            proxy.handle_connection(stream).await.ok();
+        });
+    }
+}
+
+impl CacheProxy {
+    async fn handle_connection(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        // requests to us have no body, so we only need to read up to the end of the headers
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..read]);
+        }
+        let request = String::from_utf8_lossy(&request);
+        let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+        let method = request_line.next().unwrap_or("");
+        let target = request_line.next().unwrap_or("");
+
+        let response = match method {
+            "GET" | "HEAD" => self.respond(target).await,
+            _ => Response::Status("405 Method Not Allowed".to_owned()),
+        };
+        match response {
+            Response::File(path) => {
+                let mut file = tokio::fs::File::open(&path).await?;
+                let metadata = file.metadata().await?;
+                let last_modified = metadata
+                    .modified()
+                    .map(|modified| DateTime::<Utc>::from(modified).to_rfc2822())
+                    .unwrap_or_default();
+                let headers = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nLast-Modified: {}\r\nConnection: close\r\n\r\n",
+                    metadata.len(),
+                    last_modified
+                );
+                stream.write_all(headers.as_bytes()).await?;
+                if method == "GET" {
+                    tokio::io::copy(&mut file, &mut stream).await?;
+                }
+            }
+            Response::Status(status_line) => {
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status_line
+                );
+                stream.write_all(response.as_bytes()).await?;
+            }
+        }
+        stream.shutdown().await
+    }
+
+    async fn respond(&self, target: &str) -> Response {
+        // the target is only a path and query, which needs a base to be parsed
+        let target = match Url::parse(&format!("http://localhost{}", target)) {
+            Ok(target) => target,
+            Err(_) => return bad_request(),
+        };
+        if target.path() != DOWNLOAD_PROXY_PATH {
+            return Response::Status("404 Not Found".to_owned());
+        }
+        let query = target.query_pairs().collect::<HashMap<_, _>>();
+        let (key, url) = match (query.get("key"), query.get("url")) {
+            (Some(version), Some(url)) if !version.is_empty() => match Url::parse(url) {
+                Ok(url) if is_allowed_url(&url) => (cache_key(&url, version), url),
+                _ => return Response::Status("403 Forbidden".to_owned()),
+            },
+            _ => return bad_request(),
+        };
+
+        let path = self.cache_dir.join(&key);
+        let lock = self
+            .fetching
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let response = {
+            let _guard = lock.lock().await;
+            if path.is_file() {
+                Response::File(path)
+            } else {
+                match self.fetch(&key, url, &path).await {
+                    Ok(()) => {
+                        outln!("Cached {}", key);
+                        Response::File(path)
+                    }
+                    Err(status_line) => Response::Status(status_line),
+                }
+            }
+        };
+        // the entry is only needed while the file is fetched, by those waiting for it
+        let mut fetching = self.fetching.lock().unwrap();
+        if Arc::strong_count(&lock) == 2 {
+            fetching.remove(&key);
+        }
+        response
+    }
+
+    // Fetches the file from the origin into the cache, or returns the status line to respond with
+    async fn fetch(&self, key: &str, url: Url, path: &Path) -> std::result::Result<(), String> {
+        let bad_gateway = || "502 Bad Gateway".to_owned();
+        let mut res = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|_| bad_gateway())?;
+        let status = res.status();
+        // refusals are passed on, so that the client can resolve the URL again if it expired
+        if !status.is_success() {
+            return Err(format!(
+                "{} {}",
+                status.as_u16(),
+                status.canonical_reason().unwrap_or("")
+            ));
+        }
+        let content_length = res.content_length();
+        let last_modified = res
+            .headers()
+            .get(reqwest::header::LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok());
+
+        let temp_path = self.cache_dir.join(format!("{}{}", TEMP_FILE_PREFIX, key));
+        let mut file = tokio::fs::File::create(&temp_path)
+            .await
+            .map_err(|_| "500 Internal Server Error".to_owned())?;
+        let mut written = 0;
+        let result = async {
+            while let Some(chunk) = res.chunk().await.map_err(|_| bad_gateway())? {
+                file.write_all(&chunk)
+                    .await
+                    .map_err(|_| "500 Internal Server Error".to_owned())?;
+                written += chunk.len() as u64;
+            }
+            file.flush()
+                .await
+                .map_err(|_| "500 Internal Server Error".to_owned())?;
+            // a truncated file must not be served from the cache afterwards
+            match content_length {
+                Some(length) if length != written => Err(bad_gateway()),
+                _ => Ok(()),
+            }
+        }
+        .await;
+        drop(file);
+        if let Err(status_line) = result {
+            tokio::fs::remove_file(&temp_path).await.ok();
+            return Err(status_line);
+        }
+        if let Some(last_modified) = last_modified {
+            filetime::set_file_mtime(
+                &temp_path,
+                filetime::FileTime::from_system_time(last_modified.into()),
+            )
+            .ok();
+        }
+        tokio::fs::rename(&temp_path, path)
+            .await
+            .map_err(|_| "500 Internal Server Error".to_owned())
+    }
+}
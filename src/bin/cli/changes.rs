@@ -0,0 +1,253 @@
+// A cumulative feed of the changes made by each sync, as a `changes.jsonl` in the destination,
+// so that other tools can follow along without having to diff the downloaded files
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use fluminurs::resource::{OverwriteResult, Resource};
+use fluminurs::Result;
+
+const CHANGES_FILE_NAME: &str = "changes.jsonl";
+// the kind of the records of the folders that were renamed, which files are in
+const FOLDER_KIND: &str = "Folder";
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Change {
+    Added,
+    Updated,
+    Removed,
+    Renamed,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangeRecord {
+    time: String,
+    change: Change,
+    kind: String,
+    id: String,
+    path: String,
+    last_updated: Option<String>,
+    // where a renamed resource was before
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous_path: Option<String>,
+}
+
+pub struct ChangeFeed {
+    // only a complete listing tells us that something is gone
+    detect_removals: bool,
+}
+
+fn format_time(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// E.g. `AnnouncementFile` for `fluminurs::announcement::AnnouncementFile`
+pub fn resource_kind<T: Resource>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+pub fn module_code(path: &Path) -> Option<String> {
+    match path.components().next() {
+        Some(Component::Normal(code)) => Some(code.to_string_lossy().into_owned()),
+        _ => None,
+    }
+}
+
+impl ChangeFeed {
+    pub fn new(detect_removals: bool) -> ChangeFeed {
+        ChangeFeed { detect_removals }
+    }
+
+    /// Whether resources that are left out of the listings would be recorded as removed
+    pub fn detects_removals(&self) -> bool {
+        self.detect_removals
+    }
+
+    /// Appends a record for every resource that was added or updated by this sync,
+    /// and for every resource recorded earlier that is no longer listed.
+    pub fn record<T: Resource>(
+        &self,
+        destination: &Path,
+        files: &[T],
+        results: &[(&T, Result<OverwriteResult>)],
+    ) -> Result<()> {
+        let feed_path = destination.join(CHANGES_FILE_NAME);
+        let kind = resource_kind::<T>();
+        let now = format_time(SystemTime::now());
+
+        let mut records = results
+            .iter()
+            .filter_map(|(file, result)| change_record(&now, kind, *file, result))
+            .collect::<Vec<_>>();
+
+        if self.detect_removals {
+            let listed = files.iter().map(|file| file.id()).collect::<HashSet<_>>();
+            // modules that failed to load list nothing at all, which is not the same as everything being removed
+            let listed_modules = files
+                .iter()
+                .filter_map(|file| module_code(file.path()))
+                .collect::<HashSet<_>>();
+            let mut known = read_known_resources(&feed_path, kind)?
+                .into_iter()
+                .collect::<Vec<_>>();
+            known.sort();
+            records.extend(
+                known
+                    .into_iter()
+                    .filter(|(id, path)| {
+                        !listed.contains(id.as_str())
+                            && module_code(Path::new(path))
+                                .map(|code| listed_modules.contains(&code))
+                                .unwrap_or(false)
+                    })
+                    .map(|(id, path)| ChangeRecord {
+                        time: now.clone(),
+                        change: Change::Removed,
+                        kind: kind.to_owned(),
+                        id,
+                        path,
+                        last_updated: None,
+                        previous_path: None,
+                    }),
+            );
+        }
+
+        append_records(&feed_path, &records)
+    }
+
+    /// Records the resources that were renamed on the server, and moved along with them
+    pub fn record_renames<T: Resource>(
+        &self,
+        destination: &Path,
+        renamed: &[(&T, PathBuf)],
+    ) -> Result<()> {
+        let now = format_time(SystemTime::now());
+        let records = renamed
+            .iter()
+            .map(|(file, previous_path)| ChangeRecord {
+                time: now.clone(),
+                change: Change::Renamed,
+                kind: resource_kind::<T>().to_owned(),
+                id: file.id().to_owned(),
+                path: file.path().to_string_lossy().into_owned(),
+                last_updated: Some(format_time(file.last_updated())),
+                previous_path: Some(previous_path.to_string_lossy().into_owned()),
+            })
+            .collect::<Vec<_>>();
+        append_records(&destination.join(CHANGES_FILE_NAME), &records)
+    }
+
+    /// Records the folders that were renamed or moved on the server, and moved along with them,
+    /// by their id, where they were and where they are now
+    pub fn record_folder_renames(
+        &self,
+        destination: &Path,
+        moved: &[(String, PathBuf, PathBuf)],
+    ) -> Result<()> {
+        let now = format_time(SystemTime::now());
+        let records = moved
+            .iter()
+            .map(|(id, previous_path, path)| ChangeRecord {
+                time: now.clone(),
+                change: Change::Renamed,
+                kind: FOLDER_KIND.to_owned(),
+                id: id.clone(),
+                path: path.to_string_lossy().into_owned(),
+                last_updated: None,
+                previous_path: Some(previous_path.to_string_lossy().into_owned()),
+            })
+            .collect::<Vec<_>>();
+        append_records(&destination.join(CHANGES_FILE_NAME), &records)
+    }
+
+    /// Appends a record for a download that succeeded when it was tried again.
+    /// Removals were already looked for when the whole listing was recorded.
+    pub fn record_retried<T: Resource>(
+        &self,
+        destination: &Path,
+        file: &T,
+        result: &Result<OverwriteResult>,
+    ) -> Result<()> {
+        let now = format_time(SystemTime::now());
+        let records = change_record(&now, resource_kind::<T>(), file, result)
+            .into_iter()
+            .collect::<Vec<_>>();
+        append_records(&destination.join(CHANGES_FILE_NAME), &records)
+    }
+}
+
+fn change_record<T: Resource>(
+    now: &str,
+    kind: &str,
+    file: &T,
+    result: &Result<OverwriteResult>,
+) -> Option<ChangeRecord> {
+    let change = match result {
+        Ok(OverwriteResult::NewFile) => Change::Added,
+        Ok(OverwriteResult::Overwritten) | Ok(OverwriteResult::Renamed { .. }) => Change::Updated,
+        _ => return None,
+    };
+    Some(ChangeRecord {
+        time: now.to_owned(),
+        change,
+        kind: kind.to_owned(),
+        id: file.id().to_owned(),
+        path: file.path().to_string_lossy().into_owned(),
+        last_updated: Some(format_time(file.last_updated())),
+        previous_path: None,
+    })
+}
+
+fn append_records(feed_path: &Path, records: &[ChangeRecord]) -> Result<()> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    let mut lines = String::new();
+    for record in records {
+        lines.push_str(
+            &serde_json::to_string(record).map_err(|_| "Unable to serialise change record")?,
+        );
+        lines.push('\n');
+    }
+    // a single write, since other kinds of resources may be recorded into the same file concurrently
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(feed_path)
+        .and_then(|mut file| file.write_all(lines.as_bytes()))
+        .map_err(|_| "Unable to write to the change feed")
+}
+
+// Replays the feed to find the resources of this kind that are currently present, by id
+fn read_known_resources(feed_path: &Path, kind: &str) -> Result<HashMap<String, String>> {
+    let contents = match fs::read_to_string(feed_path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(_) => return Err("Unable to read the change feed"),
+    };
+    let mut known = HashMap::new();
+    for record in contents
+        .lines()
+        .filter_map(|line| serde_json::from_str::<ChangeRecord>(line).ok())
+        .filter(|record| record.kind == kind)
+    {
+        match record.change {
+            Change::Added | Change::Updated | Change::Renamed => {
+                known.insert(record.id, record.path);
+            }
+            Change::Removed => {
+                known.remove(&record.id);
+            }
+        }
+    }
+    Ok(known)
+}
@@ -0,0 +1,79 @@
+// Temporary files are left behind when a run crashes or is killed in the middle of a download,
+// both the partial downloads and the streams that ffmpeg would have muxed. They all start with the
+// same prefix, so a sweep of the destinations finds them. Only those that have not been written to
+// for a while are removed, as another run (e.g. a daemon) may be downloading into the same place.
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::format_size;
+
+/// The prefix of the temporary files of downloads, as well as of the streams of videos
+pub const TEMP_FILE_PREFIX: &str = "~!";
+/// How long a temporary file has to be left alone before it is taken to be orphaned
+pub const STALE_AGE: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Default)]
+pub struct Sweep {
+    pub removed: usize,
+    pub reclaimed: u64,
+}
+
+fn is_stale(modified: SystemTime, now: SystemTime) -> bool {
+    now.duration_since(modified)
+        .map(|age| age >= STALE_AGE)
+        .unwrap_or(false)
+}
+
+fn sweep_dir(dir: &Path, now: SystemTime, sweep: &mut Sweep) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        // symlinks are not followed, as they may lead outside the destination
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        let path = entry.path();
+        if metadata.is_dir() {
+            sweep_dir(&path, now, sweep);
+        } else if metadata.is_file()
+            && entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(TEMP_FILE_PREFIX)
+            && metadata
+                .modified()
+                .map(|modified| is_stale(modified, now))
+                .unwrap_or(false)
+        {
+            match fs::remove_file(&path) {
+                Ok(_) => {
+                    sweep.removed += 1;
+                    sweep.reclaimed += metadata.len();
+                }
+                Err(_) => errln!("Unable to remove {}", path.to_string_lossy()),
+            }
+        }
+    }
+}
+
+/// Removes the orphaned temporary files within `dirs`, and says how much space that freed up
+pub fn sweep<'a>(dirs: impl IntoIterator<Item = &'a Path>) -> Sweep {
+    let now = SystemTime::now();
+    let mut sweep = Sweep::default();
+    for dir in dirs {
+        sweep_dir(dir, now, &mut sweep);
+    }
+    if sweep.removed > 0 {
+        outln!(
+            "Removed {} temporary files left over from earlier runs, reclaiming {}",
+            sweep.removed,
+            format_size(sweep.reclaimed)
+        );
+    }
+    sweep
+}
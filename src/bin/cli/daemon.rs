@@ -0,0 +1,509 @@
+// Watch mode: syncing periodically, with a small HTTP server for triggering syncs,
+// showing a status dashboard and serving metrics
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Component, Path};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+
+use fluminurs::module::Module;
+use fluminurs::Result;
+
+use crate::metrics::Metrics;
+use crate::schedule::Schedule;
+
+const MAX_RECENT_ERRORS: usize = 10;
+const MAX_NOTICES: usize = 10;
+const MAX_REQUEST_SIZE: usize = 8192;
+// a client that does not finish sending its request in time is cut off, rather than waited for
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+struct ModuleStatus {
+    pending: usize,
+    failed: usize,
+    last_success: Option<SystemTime>,
+    recent_errors: VecDeque<(SystemTime, String)>,
+}
+
+#[derive(Default)]
+struct State {
+    syncing: bool,
+    last_started: Option<SystemTime>,
+    last_finished: Option<SystemTime>,
+    modules: BTreeMap<String, ModuleStatus>,
+    recent_errors: VecDeque<(SystemTime, String)>,
+    notices: VecDeque<(SystemTime, String)>,
+}
+
+/// Sync progress of each module, keyed by module code
+pub struct SyncStatus {
+    state: Mutex<State>,
+    metrics: Arc<Metrics>,
+}
+
+fn push_limited(entries: &mut VecDeque<(SystemTime, String)>, limit: usize, entry: String) {
+    if entries.len() == limit {
+        entries.pop_front();
+    }
+    entries.push_back((SystemTime::now(), entry));
+}
+
+fn push_error(errors: &mut VecDeque<(SystemTime, String)>, error: String) {
+    push_limited(errors, MAX_RECENT_ERRORS, error)
+}
+
+// Resources are placed in a folder named after their module
+fn module_code(path: &Path) -> String {
+    match path.components().next() {
+        Some(Component::Normal(code)) => code.to_string_lossy().into_owned(),
+        _ => String::new(),
+    }
+}
+
+impl SyncStatus {
+    pub fn new<I: IntoIterator<Item = String>>(module_codes: I) -> SyncStatus {
+        SyncStatus {
+            state: Mutex::new(State {
+                modules: module_codes
+                    .into_iter()
+                    .map(|code| (code, ModuleStatus::default()))
+                    .collect(),
+                ..State::default()
+            }),
+            metrics: Arc::default(),
+        }
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    fn begin_sync(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.syncing = true;
+        state.last_started = Some(SystemTime::now());
+        for module in state.modules.values_mut() {
+            module.pending = 0;
+            module.failed = 0;
+        }
+    }
+
+    fn end_sync(&self, result: &Result<()>) {
+        self.metrics.record_sync(result);
+        let mut state = self.state.lock().unwrap();
+        let now = SystemTime::now();
+        state.syncing = false;
+        state.last_finished = Some(now);
+        match result {
+            Ok(()) => {
+                for module in state.modules.values_mut() {
+                    if module.failed == 0 {
+                        module.last_success = Some(now);
+                    }
+                }
+            }
+            Err(e) => push_error(&mut state.recent_errors, e.to_string()),
+        }
+    }
+
+    pub fn add_pending(&self, path: &Path) {
+        let mut state = self.state.lock().unwrap();
+        state.modules.entry(module_code(path)).or_default().pending += 1;
+    }
+
+    pub fn finish_item(&self, path: &Path, result: Result<()>) {
+        let mut state = self.state.lock().unwrap();
+        let module = state.modules.entry(module_code(path)).or_default();
+        module.pending = module.pending.saturating_sub(1);
+        if let Err(e) = result {
+            module.failed += 1;
+            push_error(
+                &mut module.recent_errors,
+                format!("{}: {}", path.to_string_lossy(), e),
+            );
+        }
+    }
+
+    pub fn add_module(&self, code: String) {
+        let mut state = self.state.lock().unwrap();
+        state.modules.entry(code).or_default();
+    }
+
+    /// Shows a message on the dashboard, for things the user should act on
+    pub fn notify(&self, notice: String) {
+        let mut state = self.state.lock().unwrap();
+        push_limited(&mut state.notices, MAX_NOTICES, notice);
+    }
+
+    fn render_metrics(&self) -> String {
+        let syncing = self.state.lock().unwrap().syncing;
+        self.metrics.render(syncing)
+    }
+
+    // The button to sync only works when the dashboard was opened with the token
+    fn render_html(&self, token: Option<&str>) -> String {
+        let state = self.state.lock().unwrap();
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <meta http-equiv=\"refresh\" content=\"30\">\n<title>fluminurs</title>\n\
+             <style>body{font-family:sans-serif}td,th{padding:4px 12px;text-align:left;vertical-align:top}</style>\n\
+             </head>\n<body>\n<h1>fluminurs</h1>\n",
+        );
+        html.push_str(&format!(
+            "<p>{}. Last sync started {}, finished {}.</p>\n",
+            if state.syncing { "Syncing" } else { "Idle" },
+            format_time(state.last_started),
+            format_time(state.last_finished)
+        ));
+        if !state.notices.is_empty() {
+            html.push_str("<h2>Notices</h2>\n");
+            html.push_str(&render_errors(&state.notices));
+        }
+        if let Some(token) = token {
+            html.push_str(&format!(
+                "<form method=\"post\" action=\"/sync?token={}\"><button type=\"submit\">Sync now</button></form>\n",
+                escape_html(token)
+            ));
+        }
+        html.push_str(
+            "<table>\n<tr><th>Module</th><th>Status</th><th>Last success</th>\
+             <th>Pending</th><th>Recent errors</th></tr>\n",
+        );
+        for (code, module) in &state.modules {
+            let status = if state.syncing && module.pending > 0 {
+                "Syncing"
+            } else if module.failed > 0 {
+                "Failed"
+            } else if module.last_success.is_some() {
+                "Up to date"
+            } else {
+                "Not synced"
+            };
+            html.push_str(&format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                escape_html(code),
+                status,
+                format_time(module.last_success),
+                module.pending,
+                render_errors(&module.recent_errors)
+            ));
+        }
+        html.push_str("</table>\n");
+        if !state.recent_errors.is_empty() {
+            html.push_str("<h2>Sync errors</h2>\n");
+            html.push_str(&render_errors(&state.recent_errors));
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+fn format_time(time: Option<SystemTime>) -> String {
+    match time {
+        Some(time) => DateTime::<Local>::from(time)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string(),
+        None => "never".to_owned(),
+    }
+}
+
+fn render_errors(errors: &VecDeque<(SystemTime, String)>) -> String {
+    if errors.is_empty() {
+        return String::new();
+    }
+    let mut html = String::from("<ul>");
+    for (time, error) in errors.iter().rev() {
+        html.push_str(&format!(
+            "<li>{} {}</li>",
+            format_time(Some(*time)),
+            escape_html(error)
+        ));
+    }
+    html.push_str("</ul>");
+    html
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Keeps track of the modules on the account between syncs, to tell when new ones appear
+pub struct ModuleWatcher {
+    known_modules: Mutex<HashSet<String>>,
+    known_terms: Mutex<HashSet<String>>,
+    synced_modules: Mutex<HashSet<String>>,
+    // whether new modules are synced too, or only reported
+    add_new_modules: bool,
+}
+
+impl ModuleWatcher {
+    /// `all_modules` are all the modules on the account, of which `synced_modules` are synced
+    pub fn new(
+        all_modules: &[Module],
+        synced_modules: &[Module],
+        add_new_modules: bool,
+    ) -> ModuleWatcher {
+        ModuleWatcher {
+            known_modules: Mutex::new(all_modules.iter().map(|m| m.id.clone()).collect()),
+            known_terms: Mutex::new(all_modules.iter().map(|m| m.term.clone()).collect()),
+            synced_modules: Mutex::new(synced_modules.iter().map(|m| m.id.clone()).collect()),
+            add_new_modules,
+        }
+    }
+
+    /// Reports the modules and terms that were not on the account before,
+    /// and returns the modules that should be synced
+    pub fn update(&self, all_modules: Vec<Module>, status: &SyncStatus) -> Vec<Module> {
+        let mut known_modules = self.known_modules.lock().unwrap();
+        let mut known_terms = self.known_terms.lock().unwrap();
+        let mut synced_modules = self.synced_modules.lock().unwrap();
+        for module in &all_modules {
+            if known_terms.insert(module.term.clone()) {
+                let notice = format!("New term {} is available", module.term);
+                outln!("{}", notice);
+                status.notify(notice);
+            }
+            if !known_modules.insert(module.id.clone()) {
+                continue;
+            }
+            let notice = if self.add_new_modules {
+                synced_modules.insert(module.id.clone());
+                status.add_module(module.code.clone());
+                format!("New module {} {} will be synced", module.code, module.name)
+            } else {
+                format!(
+                    "New module {} {} is available (sync it with --modules, or use --add-new-modules)",
+                    module.code, module.name
+                )
+            };
+            outln!("{}", notice);
+            status.notify(notice);
+        }
+        all_modules
+            .into_iter()
+            .filter(|module| synced_modules.contains(&module.id))
+            .collect()
+    }
+}
+
+/// Runs `sync` every `interval`, and whenever a sync is triggered through the HTTP server, which
+/// is served on the address if given. Triggering a sync takes the token, as `?token=` or as
+/// `Authorization: Bearer`. Never returns, unless the HTTP server cannot be started.
+pub async fn watch<F, Fut>(
+    schedule: Schedule,
+    serve: Option<(SocketAddr, String)>,
+    status: Arc<SyncStatus>,
+    mut sync: F,
+) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let trigger = Arc::new(Notify::new());
+    if let Some((address, token)) = serve {
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|_| "Unable to listen on the given address")?;
+        outln!("Serving dashboard on http://{}/?token={}", address, token);
+        tokio::spawn(serve_dashboard(
+            listener,
+            status.clone(),
+            trigger.clone(),
+            Arc::from(token),
+        ));
+    }
+
+    if !schedule.syncs_at_start() {
+        wait_for_sync(&schedule, &trigger).await;
+    }
+    loop {
+        status.begin_sync();
+        let result = sync().await;
+        if let Err(e) = &result {
+            errln!("Sync failed: {}", e);
+        }
+        status.end_sync(&result);
+        wait_for_sync(&schedule, &trigger).await;
+    }
+}
+
+async fn wait_for_sync(schedule: &Schedule, trigger: &Notify) {
+    let (wait, message) = schedule.next();
+    outln!("{}", message);
+    tokio::select! {
+        _ = tokio::time::sleep(wait) => {}
+        _ = trigger.notified() => outln!("Sync triggered"),
+    }
+}
+
+async fn serve_dashboard(
+    listener: TcpListener,
+    status: Arc<SyncStatus>,
+    trigger: Arc<Notify>,
+    token: Arc<str>,
+) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(_) => continue,
+        };
+        let status = status.clone();
+        let trigger = trigger.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            // a broken connection only affects its own request
+            handle_connection(stream, &status, &trigger, &token)
+                .await
+                .ok();
+        });
+    }
+}
+
+// Requests to us have no body, so we only need to read up to the end of the headers
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    Ok(request)
+}
+
+// The token of a request, from the query or from an `Authorization: Bearer` header
+fn request_token<'a>(query: Option<&'a str>, headers: &'a str) -> Option<&'a str> {
+    let from_query = query.and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+    });
+    from_query.or_else(|| {
+        headers.lines().find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            if !name.trim().eq_ignore_ascii_case("authorization") {
+                return None;
+            }
+            value.trim().strip_prefix("Bearer ").map(str::trim)
+        })
+    })
+}
+
+// Compares every byte, so that how long it takes does not tell how much of the token was right
+fn tokens_match(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    status: &SyncStatus,
+    trigger: &Notify,
+    token: &str,
+) -> std::io::Result<()> {
+    let request = tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream))
+        .await
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or("").split_whitespace();
+    let method = request_line.next().unwrap_or("");
+    let target = request_line.next().unwrap_or("");
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    };
+    let authorised = request_token(query, &request).is_some_and(|given| tokens_match(given, token));
+
+    let (status_line, content_type, body) = match (method, path) {
+        ("GET", "/") => (
+            "200 OK",
+            "text/html; charset=utf-8",
+            status.render_html(Some(token).filter(|_| authorised)),
+        ),
+        ("GET", "/metrics") => (
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            status.render_metrics(),
+        ),
+        ("POST", "/sync") if !authorised => (
+            "401 Unauthorized",
+            "text/plain",
+            "Triggering a sync needs the token that was shown when the daemon started\n".to_owned(),
+        ),
+        ("POST", "/sync") => {
+            trigger.notify_one();
+            // sends browsers that used the button back to the dashboard
+            ("303 See Other", "text/plain", "Sync triggered\n".to_owned())
+        }
+        (_, "/") | (_, "/sync") | (_, "/metrics") => (
+            "405 Method Not Allowed",
+            "text/plain",
+            "Method not allowed\n".to_owned(),
+        ),
+        _ => ("404 Not Found", "text/plain", "Not found\n".to_owned()),
+    };
+    let location = match query {
+        Some(query) => format!("Location: /?{}\r\n", query),
+        None => "Location: /\r\n".to_owned(),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\n{}Content-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status_line,
+        if status_line.starts_with("303") {
+            location.as_str()
+        } else {
+            ""
+        },
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_token_of_a_request() {
+        assert_eq!(request_token(Some("a=1&token=abc"), ""), Some("abc"));
+        assert_eq!(
+            request_token(
+                None,
+                "POST /sync HTTP/1.1\r\nauthorization: Bearer abc\r\n\r\n"
+            ),
+            Some("abc")
+        );
+        assert_eq!(
+            request_token(Some("a=1"), "POST /sync HTTP/1.1\r\n\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn only_matches_the_whole_token() {
+        assert!(tokens_match("abc", "abc"));
+        assert!(!tokens_match("abd", "abc"));
+        assert!(!tokens_match("ab", "abc"));
+        assert!(!tokens_match("", "abc"));
+    }
+}
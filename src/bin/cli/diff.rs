@@ -0,0 +1,215 @@
+// Compares a local directory with what is on LumiNUS, without downloading anything. Downloads get
+// the modification time of their resource on the server, so a copy that is older than the server's
+// was updated there since, and one that is newer (or differs in size) was changed here.
+//
+// Of the files that nothing on the server has the path of, those in the manifest were downloaded
+// once and have since been removed from LumiNUS, which are the only ones that pruning removes, and
+// only if they were not changed here since. The rest are the user's own, like notes, and are left
+// alone.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use fluminurs::module::Module;
+use fluminurs::resource::Resource;
+use fluminurs::util::sanitise_filename;
+use fluminurs::Result;
+
+use crate::clean::TEMP_FILE_PREFIX;
+use crate::format_size;
+use crate::manifest::{self, Manifest};
+
+// filesystems like FAT only keep modification times to 2 seconds
+const TIME_TOLERANCE: Duration = Duration::from_secs(2);
+
+struct Remote {
+    last_updated: SystemTime,
+    size: Option<u64>,
+}
+
+pub struct Diff {
+    root: PathBuf,
+    remote: BTreeMap<PathBuf, Remote>,
+    // the folders of the modules, which are the only ones looked through for local-only files
+    module_dirs: BTreeSet<PathBuf>,
+    // what fluminurs downloaded there, according to the manifest
+    manifest: Manifest,
+}
+
+#[derive(Default)]
+struct Differences {
+    new_remote: Vec<PathBuf>,
+    updated_remote: Vec<PathBuf>,
+    // downloaded by fluminurs, but no longer on the server
+    removed_remote: Vec<PathBuf>,
+    local_only: Vec<PathBuf>,
+    conflicting: Vec<PathBuf>,
+}
+
+fn is_bookkeeping(name: &str) -> bool {
+    name.starts_with(TEMP_FILE_PREFIX) || name.starts_with('.')
+}
+
+fn find_local_only(
+    root: &Path,
+    dir: &Path,
+    remote: &BTreeMap<PathBuf, Remote>,
+    local_only: &mut Vec<PathBuf>,
+) {
+    let entries = match fs::read_dir(root.join(dir)) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        if is_bookkeeping(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        let path = dir.join(entry.file_name());
+        match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => find_local_only(root, &path, remote, local_only),
+            Ok(_) if !remote.contains_key(&path) => local_only.push(path),
+            _ => {}
+        }
+    }
+}
+
+fn print_section(title: &str, paths: &[PathBuf]) {
+    if paths.is_empty() {
+        return;
+    }
+    outln!("{} ({}):", title, paths.len());
+    for path in paths {
+        outln!("  {}", path.display());
+    }
+}
+
+impl Diff {
+    pub fn new<P: AsRef<Path>>(root: P, modules: &[Module]) -> Diff {
+        Diff {
+            root: root.as_ref().to_owned(),
+            remote: BTreeMap::new(),
+            module_dirs: modules
+                .iter()
+                .filter(|module| module.has_access())
+                .map(|module| PathBuf::from(sanitise_filename(&module.code)))
+                .collect(),
+            manifest: Manifest::load(root.as_ref()),
+        }
+    }
+
+    pub fn add<T: Resource>(&mut self, resources: &[T]) {
+        for resource in resources {
+            if let Some(first) = resource.path().components().next() {
+                self.module_dirs.insert(PathBuf::from(first.as_os_str()));
+            }
+            self.remote.insert(
+                resource.path().to_owned(),
+                Remote {
+                    last_updated: resource.last_updated(),
+                    size: resource.size(),
+                },
+            );
+        }
+    }
+
+    fn differences(&self) -> Differences {
+        let mut differences = Differences::default();
+        for (path, remote) in &self.remote {
+            let metadata = match fs::metadata(self.root.join(path)) {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => {
+                    differences.new_remote.push(path.clone());
+                    continue;
+                }
+            };
+            let modified = match metadata.modified() {
+                Ok(modified) => modified,
+                Err(_) => continue,
+            };
+            let same_size = remote.size.is_none_or(|size| size == metadata.len());
+            if modified + TIME_TOLERANCE < remote.last_updated {
+                differences.updated_remote.push(path.clone());
+            } else if !same_size
+                // a newer copy that still has the size of the server's was only touched
+                || (modified > remote.last_updated + TIME_TOLERANCE && remote.size.is_none())
+            {
+                differences.conflicting.push(path.clone());
+            }
+        }
+        let mut not_remote = vec![];
+        for dir in &self.module_dirs {
+            find_local_only(&self.root, dir, &self.remote, &mut not_remote);
+        }
+        not_remote.sort();
+        (differences.removed_remote, differences.local_only) = not_remote
+            .into_iter()
+            .partition(|path| self.manifest.contains(path));
+        differences
+    }
+
+    /// Prints what differs between the directory and LumiNUS, by how it differs
+    pub fn report(&self) {
+        let differences = self.differences();
+        print_section("New on LumiNUS", &differences.new_remote);
+        print_section("Updated on LumiNUS", &differences.updated_remote);
+        print_section("No longer on LumiNUS", &differences.removed_remote);
+        print_section(
+            "Only here, not downloaded by fluminurs",
+            &differences.local_only,
+        );
+        print_section(
+            "Conflicting, as they were changed here",
+            &differences.conflicting,
+        );
+        if differences.new_remote.is_empty()
+            && differences.updated_remote.is_empty()
+            && differences.removed_remote.is_empty()
+            && differences.local_only.is_empty()
+            && differences.conflicting.is_empty()
+        {
+            outln!("{} is in sync with LumiNUS", self.root.display());
+        } else {
+            outln!(
+                "{} new, {} updated, {} removed, {} only here, {} conflicting",
+                differences.new_remote.len(),
+                differences.updated_remote.len(),
+                differences.removed_remote.len(),
+                differences.local_only.len(),
+                differences.conflicting.len()
+            );
+        }
+    }
+
+    /// Removes the files that fluminurs downloaded and are no longer on LumiNUS, and only those
+    pub fn prune(&self) -> Result<()> {
+        let mut removed = vec![];
+        let mut reclaimed = 0;
+        for path in self.differences().removed_remote {
+            let full_path = self.root.join(&path);
+            if !self.manifest.is_unchanged(&path) {
+                errln!(
+                    "Not pruning {}, as it was changed since it was downloaded",
+                    full_path.display()
+                );
+                continue;
+            }
+            let size = fs::metadata(&full_path).map_or(0, |metadata| metadata.len());
+            match fs::remove_file(&full_path) {
+                Ok(_) => {
+                    reclaimed += size;
+                    removed.push(path);
+                }
+                Err(_) => errln!("Unable to remove {}", full_path.display()),
+            }
+        }
+        manifest::forget(&self.root, &removed)?;
+        outln!(
+            "Pruned {} files, reclaiming {}",
+            removed.len(),
+            format_size(reclaimed)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+// Hashing of local files, for finding duplicates among downloads.
+// Hashing is bound by the disk and CPU, so it runs on rayon's thread pool instead of the async runtime.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+
+// Files are read in chunks, so that large videos are never held in memory as a whole
+const CHUNK_SIZE: usize = 1 << 20;
+
+type Hash = [u8; 32];
+
+pub fn hash_file(path: &Path) -> io::Result<Hash> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize().into())
+}
+
+/// Hashes the files in parallel and groups those with identical contents.
+/// Files that cannot be read are left out.
+pub async fn find_duplicates(paths: Vec<PathBuf>) -> Vec<Vec<PathBuf>> {
+    tokio::task::spawn_blocking(move || {
+        // only files of the same size can have the same contents, so the rest need not be hashed
+        let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+        for path in paths {
+            if let Ok(metadata) = std::fs::metadata(&path) {
+                by_size.entry(metadata.len()).or_default().push(path);
+            }
+        }
+        let hashes = by_size
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .flatten()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .filter_map(|path| Some((hash_file(&path).ok()?, path)))
+            .collect::<Vec<_>>();
+        let mut groups: HashMap<Hash, Vec<PathBuf>> = HashMap::new();
+        for (hash, path) in hashes {
+            groups.entry(hash).or_default().push(path);
+        }
+        let mut duplicates = groups
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .map(|mut group| {
+                group.sort();
+                group
+            })
+            .collect::<Vec<_>>();
+        duplicates.sort();
+        duplicates
+    })
+    .await
+    .unwrap_or_default()
+}
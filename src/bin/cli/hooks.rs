@@ -0,0 +1,186 @@
+// Commands run after a sync that downloaded or updated anything, e.g. to upload the new files
+// with rclone or to reindex them for search. `--post-sync-hook` is told about everything, and
+// each section of the config file may have a `post-sync-hook` of its own for that type alone.
+//
+// The commands are run by the shell, with these environment variables:
+//
+//     FLUMINURS_RESOURCE_TYPES  the types that changed, e.g. `files,multimedia`
+//     FLUMINURS_NEW_COUNT       the number of resources downloaded for the first time
+//     FLUMINURS_UPDATED_COUNT   the number of resources that were updated
+//     FLUMINURS_FAILED_COUNT    the number of downloads that failed
+//     FLUMINURS_CHANGED_FILES   a file listing the paths that were downloaded or updated, one per
+//                               line, as there may be too many for a variable
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+
+use tokio::process::Command;
+
+use fluminurs::resource::{OverwriteResult, Resource};
+use fluminurs::Result;
+
+use crate::changes::resource_kind;
+use crate::clean::TEMP_FILE_PREFIX;
+
+#[derive(Default)]
+struct Changes {
+    new: Vec<PathBuf>,
+    updated: Vec<PathBuf>,
+    failed: usize,
+}
+
+/// The hooks to run, and what changed since they were last run
+#[derive(Default)]
+pub struct Hooks {
+    post_sync: Option<String>,
+    // by the section of the config file, e.g. `files`
+    by_type: BTreeMap<&'static str, String>,
+    changes: Mutex<BTreeMap<&'static str, Changes>>,
+}
+
+// The section of the config file that each kind of resource is set up in
+fn resource_type<T: Resource>() -> &'static str {
+    match resource_kind::<T>() {
+        "File" => "files",
+        "InternalVideo" | "ExternalVideo" => "multimedia",
+        "WebLectureVideo" => "weblectures",
+        "ZoomRecording" => "conferences",
+        "AnnouncementFile" => "announcements",
+        "ForumThread" => "forums",
+        "Quiz" => "quizzes",
+        "Gradebook" => "gradebooks",
+        "Roster" => "rosters",
+        "Weblink" => "weblinks",
+        "LessonPlan" => "lesson-plans",
+        kind => kind,
+    }
+}
+
+async fn run_hook(command: &str, changes: &[(&str, &Changes)]) -> Result<()> {
+    let changed = changes
+        .iter()
+        .flat_map(|(_, changes)| changes.new.iter().chain(&changes.updated))
+        .map(|path| path.to_string_lossy())
+        .collect::<Vec<_>>();
+    let list = std::env::temp_dir().join(format!(
+        "{}hook-{}-{}.txt",
+        TEMP_FILE_PREFIX,
+        std::process::id(),
+        changes
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join("-")
+    ));
+    fs::write(&list, changed.join("\n") + "\n")
+        .map_err(|_| "Unable to write the list of changed files")?;
+
+    let count = |f: fn(&Changes) -> usize| {
+        changes
+            .iter()
+            .map(|(_, changes)| f(changes))
+            .sum::<usize>()
+            .to_string()
+    };
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let output = shell
+        .arg(command)
+        .env(
+            "FLUMINURS_RESOURCE_TYPES",
+            changes
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(","),
+        )
+        .env("FLUMINURS_NEW_COUNT", count(|changes| changes.new.len()))
+        .env(
+            "FLUMINURS_UPDATED_COUNT",
+            count(|changes| changes.updated.len()),
+        )
+        .env("FLUMINURS_FAILED_COUNT", count(|changes| changes.failed))
+        .env("FLUMINURS_CHANGED_FILES", &list)
+        .stdin(Stdio::null())
+        .output()
+        .await;
+    fs::remove_file(&list).ok();
+
+    // the output goes through the usual writer, so that it is not mixed up with the rest
+    let output = output.map_err(|_| "Unable to start the hook")?;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        outln!("{}", line);
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        errln!("{}", line);
+    }
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err("Hook exited with an error")
+    }
+}
+
+impl Hooks {
+    pub fn new(post_sync: Option<String>, by_type: BTreeMap<&'static str, String>) -> Hooks {
+        Hooks {
+            post_sync,
+            by_type,
+            changes: Mutex::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.post_sync.is_none() && self.by_type.is_empty()
+    }
+
+    /// Records the final result of a download, after any retries
+    pub fn record<T: Resource>(&self, path: &Path, result: &Result<OverwriteResult>) {
+        let mut changes = self.changes.lock().unwrap();
+        let changes = changes.entry(resource_type::<T>()).or_default();
+        match result {
+            Ok(OverwriteResult::NewFile) => changes.new.push(path.to_owned()),
+            Ok(OverwriteResult::Overwritten) | Ok(OverwriteResult::Renamed { .. }) => {
+                changes.updated.push(path.to_owned())
+            }
+            Ok(OverwriteResult::Skipped) | Ok(OverwriteResult::AlreadyHave) => {}
+            Err(_) => changes.failed += 1,
+        }
+    }
+
+    /// Runs the hooks of the types that had anything downloaded or updated, then the hook of the
+    /// whole sync if anything was, and starts over for the next sync
+    pub async fn run(&self) {
+        let changes = std::mem::take(&mut *self.changes.lock().unwrap());
+        let changed = changes
+            .iter()
+            .filter(|(_, changes)| !changes.new.is_empty() || !changes.updated.is_empty())
+            .map(|(name, changes)| (*name, changes))
+            .collect::<Vec<_>>();
+        if changed.is_empty() {
+            return;
+        }
+        for (name, changes) in &changed {
+            if let Some(command) = self.by_type.get(name) {
+                if let Err(e) = run_hook(command, &[(name, changes)]).await {
+                    errln!("Failed to run the post-sync hook of {}: {}", name, e);
+                }
+            }
+        }
+        if let Some(command) = &self.post_sync {
+            if let Err(e) = run_hook(command, &changed).await {
+                errln!("Failed to run the post-sync hook: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,468 @@
+// A full-screen picker for the modules, folders and files to download, for those who would rather
+// not learn the flags. Files are only listed once their module is opened, since listing every
+// module up front takes a while. What is picked is then downloaded like any other sync.
+
+use std::collections::{BTreeSet, HashSet};
+use std::io::{self, Stdout};
+use std::path::{Path, PathBuf};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::Terminal;
+
+use fluminurs::file::File;
+use fluminurs::module::Module;
+use fluminurs::resource::Resource;
+use fluminurs::{Api, Result};
+
+use crate::{load_modules_files, ModuleTypeFlags};
+
+const HELP: &str =
+    "↑/↓ move  →/enter open  ← close  space select  a select all  d download  q quit";
+
+struct ModuleEntry {
+    module: Module,
+    // `None` until the module is first opened
+    files: Option<Vec<File>>,
+    expanded: bool,
+    // the folders that are open, relative to the module
+    expanded_folders: HashSet<PathBuf>,
+    // indices into `files`
+    selected: BTreeSet<usize>,
+}
+
+// A line of the tree as it is currently shown
+enum Row {
+    Module(usize),
+    Folder {
+        module: usize,
+        path: PathBuf,
+        depth: usize,
+    },
+    File {
+        module: usize,
+        file: usize,
+        depth: usize,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Checked {
+    None,
+    Some,
+    All,
+}
+
+impl Checked {
+    fn of(selected: usize, total: usize) -> Checked {
+        if selected == 0 {
+            Checked::None
+        } else if selected == total {
+            Checked::All
+        } else {
+            Checked::Some
+        }
+    }
+
+    fn mark(self) -> &'static str {
+        match self {
+            Checked::None => "[ ]",
+            Checked::Some => "[-]",
+            Checked::All => "[x]",
+        }
+    }
+}
+
+// The path of the file within its module, as the first component is the module code
+fn relative_path(file: &File) -> &Path {
+    let mut components = file.path().components();
+    components.next();
+    components.as_path()
+}
+
+impl ModuleEntry {
+    fn new(module: Module) -> ModuleEntry {
+        ModuleEntry {
+            module,
+            files: None,
+            expanded: false,
+            expanded_folders: HashSet::new(),
+            selected: BTreeSet::new(),
+        }
+    }
+
+    // The files within `folder` (relative to the module), or all of them for the empty path
+    fn files_under<'a>(&'a self, folder: &'a Path) -> impl Iterator<Item = usize> + 'a {
+        self.files
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter(move |(_, file)| relative_path(file).starts_with(folder))
+            .map(|(index, _)| index)
+    }
+
+    fn checked(&self, folder: &Path) -> Checked {
+        let (selected, total) =
+            self.files_under(folder)
+                .fold((0, 0), |(selected, total), index| {
+                    (
+                        selected + self.selected.contains(&index) as usize,
+                        total + 1,
+                    )
+                });
+        Checked::of(selected, total)
+    }
+
+    // Selects everything within `folder`, unless it is all selected already
+    fn toggle(&mut self, folder: &Path) {
+        let indices = self.files_under(folder).collect::<Vec<_>>();
+        if self.checked(folder) == Checked::All {
+            for index in indices {
+                self.selected.remove(&index);
+            }
+        } else {
+            self.selected.extend(indices);
+        }
+    }
+
+    fn is_visible(&self, path: &Path) -> bool {
+        path.ancestors()
+            .skip(1)
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .all(|ancestor| self.expanded_folders.contains(ancestor))
+    }
+
+    fn push_rows(&self, module: usize, rows: &mut Vec<Row>) {
+        let files = match (&self.files, self.expanded) {
+            (Some(files), true) => files,
+            _ => return,
+        };
+        let mut seen_folders = HashSet::new();
+        for (index, file) in files.iter().enumerate() {
+            let path = relative_path(file);
+            // the folders of a file are shown when the first file in them comes up
+            let mut folders = path
+                .ancestors()
+                .skip(1)
+                .filter(|ancestor| !ancestor.as_os_str().is_empty())
+                .collect::<Vec<_>>();
+            folders.reverse();
+            for folder in folders {
+                if seen_folders.insert(folder.to_owned()) && self.is_visible(folder) {
+                    rows.push(Row::Folder {
+                        module,
+                        path: folder.to_owned(),
+                        depth: folder.components().count(),
+                    });
+                }
+            }
+            if self.is_visible(path) {
+                rows.push(Row::File {
+                    module,
+                    file: index,
+                    depth: path.components().count(),
+                });
+            }
+        }
+    }
+}
+
+struct Picker<'a> {
+    api: &'a Api,
+    modules: Vec<ModuleEntry>,
+    state: ListState,
+    message: String,
+}
+
+impl<'a> Picker<'a> {
+    fn rows(&self) -> Vec<Row> {
+        let mut rows = vec![];
+        for (index, entry) in self.modules.iter().enumerate() {
+            rows.push(Row::Module(index));
+            entry.push_rows(index, &mut rows);
+        }
+        rows
+    }
+
+    fn selected_count(&self) -> usize {
+        self.modules.iter().map(|entry| entry.selected.len()).sum()
+    }
+
+    fn render(&mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<()> {
+        let items = self
+            .rows()
+            .iter()
+            .map(|row| {
+                let line = match row {
+                    Row::Module(index) => {
+                        let entry = &self.modules[*index];
+                        let mark = match &entry.files {
+                            Some(_) => entry.checked(Path::new("")).mark(),
+                            None => Checked::None.mark(),
+                        };
+                        format!(
+                            "{} {} {} {}",
+                            mark,
+                            if entry.expanded { "▾" } else { "▸" },
+                            entry.module.code,
+                            entry.module.name
+                        )
+                    }
+                    Row::Folder {
+                        module,
+                        path,
+                        depth,
+                    } => {
+                        let entry = &self.modules[*module];
+                        format!(
+                            "{}{} {} {}",
+                            "  ".repeat(*depth),
+                            entry.checked(path).mark(),
+                            if entry.expanded_folders.contains(path) {
+                                "▾"
+                            } else {
+                                "▸"
+                            },
+                            path.file_name().unwrap_or_default().to_string_lossy()
+                        )
+                    }
+                    Row::File {
+                        module,
+                        file,
+                        depth,
+                    } => {
+                        let entry = &self.modules[*module];
+                        let checked = if entry.selected.contains(file) {
+                            Checked::All
+                        } else {
+                            Checked::None
+                        };
+                        format!(
+                            "{}{}   {}",
+                            "  ".repeat(*depth),
+                            checked.mark(),
+                            entry.files.as_ref().unwrap()[*file]
+                                .path()
+                                .file_name()
+                                .unwrap_or_default()
+                                .to_string_lossy()
+                        )
+                    }
+                };
+                ListItem::new(line)
+            })
+            .collect::<Vec<_>>();
+        let status = format!("{} files selected. {}", self.selected_count(), self.message);
+        terminal
+            .draw(|frame| {
+                let [tree, footer] = Layout::vertical([Constraint::Min(1), Constraint::Length(2)])
+                    .areas(frame.size());
+                let list = List::new(items)
+                    .block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title("Pick what to download"),
+                    )
+                    .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+                frame.render_stateful_widget(list, tree, &mut self.state);
+                frame.render_widget(
+                    Paragraph::new(vec![Line::from(status), Line::from(HELP)]),
+                    footer,
+                );
+            })
+            .map_err(|_| "Unable to draw to the terminal")?;
+        Ok(())
+    }
+
+    // Lists the files of the module the first time it is needed
+    async fn load(
+        &mut self,
+        index: usize,
+        terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    ) -> Result<()> {
+        if self.modules[index].files.is_some() {
+            return Ok(());
+        }
+        self.message = format!("Loading {}...", self.modules[index].module.code);
+        self.render(terminal)?;
+        let module = self.modules[index].module.clone();
+        let files = load_modules_files(
+            self.api,
+            &[module],
+            ModuleTypeFlags::empty(),
+            false,
+            None,
+            None,
+            None,
+        )
+        .await;
+        self.message = match &files {
+            Ok(_) => String::new(),
+            Err(e) => format!("Unable to load {}: {}", self.modules[index].module.code, e),
+        };
+        self.modules[index].files = Some(files.unwrap_or_default());
+        Ok(())
+    }
+
+    async fn open(
+        &mut self,
+        row: &Row,
+        terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    ) -> Result<()> {
+        match row {
+            Row::Module(index) => {
+                self.load(*index, terminal).await?;
+                self.modules[*index].expanded = true;
+            }
+            Row::Folder { module, path, .. } => {
+                self.modules[*module].expanded_folders.insert(path.clone());
+            }
+            Row::File { .. } => {}
+        }
+        Ok(())
+    }
+
+    // Closes the row, or the folder or module that it is in
+    fn close(&mut self, row: &Row, rows: &[Row]) {
+        let (module, path) = match row {
+            Row::Module(index) => {
+                self.modules[*index].expanded = false;
+                return;
+            }
+            Row::Folder { module, path, .. } => (*module, path.clone()),
+            Row::File { module, file, .. } => (
+                *module,
+                relative_path(&self.modules[*module].files.as_ref().unwrap()[*file]).to_owned(),
+            ),
+        };
+        let entry = &mut self.modules[module];
+        if entry.expanded_folders.remove(&path) {
+            return;
+        }
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        let target = if parent.as_os_str().is_empty() {
+            entry.expanded = false;
+            rows.iter()
+                .position(|row| matches!(row, Row::Module(index) if *index == module))
+        } else {
+            entry.expanded_folders.remove(parent);
+            rows.iter().position(
+                |row| matches!(row, Row::Folder { module: m, path, .. } if *m == module && path == parent),
+            )
+        };
+        self.state.select(target);
+    }
+
+    async fn toggle(
+        &mut self,
+        row: &Row,
+        terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    ) -> Result<()> {
+        match row {
+            Row::Module(index) => {
+                self.load(*index, terminal).await?;
+                self.modules[*index].toggle(Path::new(""));
+            }
+            Row::Folder { module, path, .. } => self.modules[*module].toggle(path),
+            Row::File { module, file, .. } => {
+                let selected = &mut self.modules[*module].selected;
+                if !selected.remove(file) {
+                    selected.insert(*file);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the files picked, or `None` if the user quit
+    async fn run(
+        &mut self,
+        terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    ) -> Result<Option<Vec<File>>> {
+        self.state.select(Some(0));
+        loop {
+            self.render(terminal)?;
+            let key = match event::read().map_err(|_| "Unable to read from the terminal")? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            let rows = self.rows();
+            let current = self.state.selected().unwrap_or(0).min(rows.len() - 1);
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                KeyCode::Up | KeyCode::Char('k') => {
+                    self.state.select(Some(current.saturating_sub(1)));
+                }
+                KeyCode::Down | KeyCode::Char('j') => {
+                    self.state.select(Some((current + 1).min(rows.len() - 1)));
+                }
+                KeyCode::Right | KeyCode::Enter | KeyCode::Char('l') => {
+                    self.open(&rows[current], terminal).await?;
+                }
+                KeyCode::Left | KeyCode::Char('h') => self.close(&rows[current], &rows),
+                KeyCode::Char(' ') => self.toggle(&rows[current], terminal).await?,
+                KeyCode::Char('a') => {
+                    for index in 0..self.modules.len() {
+                        self.load(index, terminal).await?;
+                        self.modules[index].toggle(Path::new(""));
+                    }
+                }
+                KeyCode::Char('d') if self.selected_count() > 0 => {
+                    let files = self
+                        .modules
+                        .iter_mut()
+                        .flat_map(|entry| {
+                            let files = entry.files.take().unwrap_or_default();
+                            let selected = std::mem::take(&mut entry.selected);
+                            files
+                                .into_iter()
+                                .enumerate()
+                                .filter(move |(index, _)| selected.contains(index))
+                                .map(|(_, file)| file)
+                        })
+                        .collect();
+                    return Ok(Some(files));
+                }
+                KeyCode::Char('d') => self.message = "Nothing is selected yet.".to_owned(),
+                _ => {}
+            }
+        }
+    }
+}
+
+// Puts the terminal back the way it was, however the picker ends
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        disable_raw_mode().ok();
+        execute!(io::stdout(), LeaveAlternateScreen).ok();
+    }
+}
+
+/// Lets the user pick files from `modules`, returning `None` if they quit without picking any
+pub async fn pick_files(api: &Api, modules: Vec<Module>) -> Result<Option<Vec<File>>> {
+    if modules.is_empty() {
+        return Err("No modules to pick from");
+    }
+    enable_raw_mode().map_err(|_| "Unable to set up the terminal")?;
+    let _guard = TerminalGuard;
+    execute!(io::stdout(), EnterAlternateScreen).map_err(|_| "Unable to set up the terminal")?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout()))
+        .map_err(|_| "Unable to set up the terminal")?;
+    let mut picker = Picker {
+        api,
+        modules: modules.into_iter().map(ModuleEntry::new).collect(),
+        state: ListState::default(),
+        message: String::new(),
+    };
+    picker.run(&mut terminal).await
+}
@@ -0,0 +1,173 @@
+// Replaces the copies of identical files that a sync downloaded (e.g. the same slides in
+// cross-listed modules) with links to one of them, to save disk space. Only the files written in
+// the run are linked, never the user's own, as editing one of a set of hard links edits them all.
+// The links are recorded in the manifests of the destinations, so that a symbolic link whose file
+// was since removed or changed can be taken away again before the next sync, which then downloads
+// a copy of its own. Hard links need no such care, as a download replaces the file.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::clean::TEMP_FILE_PREFIX;
+use crate::format_size;
+use crate::hashing;
+use crate::manifest::Manifest;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    Hard,
+    Symbolic,
+}
+
+pub struct Links {
+    kind: LinkKind,
+}
+
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(_: &Path, _: &Path) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+impl Links {
+    pub fn new(kind: LinkKind) -> Links {
+        Links { kind }
+    }
+
+    /// Takes away the symbolic links in the destinations whose files were removed or changed
+    /// since they were linked, so that the sync downloads their own copies again
+    pub fn release_stale(&self, destinations: &[&Path]) {
+        let mut released = 0;
+        for destination in destinations {
+            let mut manifest = Manifest::load(destination);
+            let stale = manifest.stale_links();
+            if stale.is_empty() {
+                continue;
+            }
+            for path in &stale {
+                let link = destination.join(path);
+                let is_symlink = fs::symlink_metadata(&link)
+                    .map(|metadata| metadata.file_type().is_symlink())
+                    .unwrap_or(false);
+                if is_symlink {
+                    match fs::remove_file(&link) {
+                        Ok(_) => released += 1,
+                        Err(_) => errln!("Unable to remove the stale link {}", link.display()),
+                    }
+                }
+            }
+            manifest.forget(&stale);
+            if let Err(e) = manifest.save() {
+                errln!("Failed to update the manifest: {}", e);
+            }
+        }
+        if released > 0 {
+            outln!(
+                "Removed {} links to files that were removed or changed since",
+                released
+            );
+        }
+    }
+
+    /// Replaces the copies of identical files among those `downloaded` (as the destination and
+    /// the path within it) with links to one copy each
+    pub async fn link_duplicates(&self, downloaded: &[(PathBuf, PathBuf)]) {
+        let by_path = downloaded
+            .iter()
+            .map(|(destination, path)| (destination.join(path), (destination, path)))
+            .collect::<HashMap<_, _>>();
+        let mut manifests = BTreeMap::new();
+        let mut linked = 0;
+        let mut saved = 0;
+        for group in hashing::find_duplicates(by_path.keys().cloned().collect()).await {
+            // the copies get the modification time of the one they link to, so the latest one is
+            // linked to, as an older one would make the others look outdated
+            let original = match group
+                .iter()
+                .max_by_key(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            {
+                Some(original) => original,
+                None => continue,
+            };
+            let copies = group
+                .iter()
+                .filter(|copy| *copy != original && !same_file(original, copy))
+                .collect::<Vec<_>>();
+            if copies.is_empty() {
+                continue;
+            }
+            let target = match self.kind {
+                LinkKind::Hard => original.clone(),
+                LinkKind::Symbolic => match fs::canonicalize(original) {
+                    Ok(target) => target,
+                    Err(_) => continue,
+                },
+            };
+            for copy in copies {
+                let size = fs::metadata(copy).map(|m| m.len()).unwrap_or(0);
+                match self.replace_with_link(&target, copy) {
+                    Ok(()) => {
+                        linked += 1;
+                        saved += size;
+                        let (destination, path) = by_path[copy];
+                        manifests
+                            .entry(destination)
+                            .or_insert_with(|| Manifest::load(destination))
+                            .add_link(path, target.clone(), self.kind);
+                    }
+                    Err(e) => errln!("Unable to link {}: {}", copy.display(), e),
+                }
+            }
+        }
+        for manifest in manifests.values() {
+            if let Err(e) = manifest.save() {
+                errln!("Failed to update the manifest: {}", e);
+            }
+        }
+        if linked > 0 {
+            outln!(
+                "Linked {} duplicate files, saving {}",
+                linked,
+                format_size(saved)
+            );
+        }
+    }
+
+    // The link is made next to the copy first, so that the copy is only replaced once it exists
+    fn replace_with_link(&self, target: &Path, copy: &Path) -> std::io::Result<()> {
+        let mut temp_name = OsString::from(TEMP_FILE_PREFIX);
+        temp_name.push(copy.file_name().unwrap_or_default());
+        let temp_path = copy.with_file_name(temp_name);
+        fs::remove_file(&temp_path).ok();
+        match self.kind {
+            LinkKind::Hard => fs::hard_link(target, &temp_path)?,
+            LinkKind::Symbolic => symlink(target, &temp_path)?,
+        }
+        fs::rename(&temp_path, copy).inspect_err(|_| {
+            fs::remove_file(&temp_path).ok();
+        })
+    }
+}
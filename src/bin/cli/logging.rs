@@ -0,0 +1,95 @@
+// Diagnostics, as opposed to the output meant for the user: what the library and the CLI log goes
+// to stderr at the verbosity asked for, and in full detail to a log file if there is one, so that
+// failures like a rejected Zoom validation can be traced back to the requests that led to them.
+
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::sync::Mutex;
+
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+use fluminurs::Result;
+
+use crate::{emit, OutputStream};
+
+// Hands each event to the output thread in one piece, so that it does not interleave with other output
+struct StderrWriter {
+    buffer: Vec<u8>,
+}
+
+impl io::Write for StderrWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for StderrWriter {
+    fn drop(&mut self) {
+        let event = String::from_utf8_lossy(&self.buffer);
+        let event = event.trim_end_matches('\n');
+        if !event.is_empty() {
+            emit(OutputStream::Stderr, event.to_owned());
+        }
+    }
+}
+
+struct Stderr;
+
+impl<'a> MakeWriter<'a> for Stderr {
+    type Writer = StderrWriter;
+
+    fn make_writer(&'a self) -> StderrWriter {
+        StderrWriter { buffer: vec![] }
+    }
+}
+
+/// `verbosity` is the number of `-v`s given, or -1 for `--quiet`
+pub fn init(verbosity: i64, log_file: Option<&str>) -> Result<()> {
+    let level = match verbosity {
+        i64::MIN..=-1 => LevelFilter::ERROR,
+        0 => LevelFilter::WARN,
+        1 => LevelFilter::INFO,
+        2 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    // RUST_LOG can still pick out what to show, e.g. `RUST_LOG=fluminurs::conferencing=debug`
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    let stderr_layer = tracing_subscriber::fmt::layer()
+        .with_writer(Stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .without_time()
+        .with_target(false)
+        .with_filter(filter);
+    let file_layer = match log_file {
+        Some(path) => {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|_| "Unable to open the log file")?;
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(Mutex::new(file))
+                    .with_ansi(false)
+                    .with_filter(LevelFilter::DEBUG),
+            )
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(stderr_layer)
+        .with(file_layer)
+        .try_init()
+        .map_err(|_| "Unable to set up logging")
+}
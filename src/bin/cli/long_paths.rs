@@ -0,0 +1,133 @@
+// Keeps the paths of downloads within a length budget, for Windows setups that ignore the long
+// path support of the manifest (network drives, older shells). The folders of paths that would be
+// too long are shortened to their first few characters and a hash of their name, the same way on
+// every run, and the original names are kept in a file in the destination to look them up by.
+
+use std::borrow::Cow;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use fluminurs::resource::Resource;
+use fluminurs::Result;
+
+use crate::clean::TEMP_FILE_PREFIX;
+
+const MAPPING_FILE_NAME: &str = "shortened-paths.json";
+// the characters of a folder name that are kept, before the hash
+const SHORTENED_PREFIX_LENGTH: usize = 16;
+// a tilde and 6 hex digits
+const HASH_SUFFIX_LENGTH: usize = 7;
+
+fn shorten_name(name: &str) -> String {
+    let hash = Sha256::digest(name.as_bytes());
+    let prefix = name
+        .chars()
+        .take(SHORTENED_PREFIX_LENGTH)
+        .collect::<String>();
+    format!(
+        "{}~{:02x}{:02x}{:02x}",
+        prefix.trim_end(),
+        hash[0],
+        hash[1],
+        hash[2]
+    )
+}
+
+fn path_key(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn char_count(path: &Path) -> usize {
+    path.to_string_lossy().chars().count()
+}
+
+// The folders of `path` (relative to the destination), from the outermost one
+fn folders(path: &Path) -> impl Iterator<Item = &Path> {
+    let mut folders = path.ancestors().skip(1).collect::<Vec<_>>();
+    folders.pop(); // the empty path
+    folders.into_iter().rev()
+}
+
+/// Returns the resources with the folders of paths longer than `max_length` shortened, including
+/// the folders that were shortened in earlier runs.
+pub fn fit_paths<'a, T: Resource + Clone>(
+    destination: &Path,
+    resources: &'a [T],
+    max_length: usize,
+) -> Result<Cow<'a, [T]>> {
+    let mapping_file = destination.join(MAPPING_FILE_NAME);
+    // original folder -> shortened folder, relative to the destination
+    let mut mapping = fs::read(&mapping_file)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<BTreeMap<String, String>>(&data).ok())
+        .unwrap_or_default();
+    // the length that the destination adds, as an absolute path with a separator after it
+    let base_length = std::env::current_dir()
+        .map(|cwd| char_count(&cwd.join(destination)))
+        .unwrap_or_else(|_| char_count(destination))
+        + 1;
+
+    let mut shortened = mapping.keys().cloned().collect::<BTreeSet<_>>();
+    for resource in resources {
+        let length = base_length + char_count(resource.path()) + TEMP_FILE_PREFIX.len();
+        if length > max_length {
+            // every file in a folder has to agree on its name, so a folder is shortened for all
+            shortened.extend(
+                folders(resource.path())
+                    .filter(|folder| {
+                        folder
+                            .file_name()
+                            .map_or(0, |name| name.to_string_lossy().chars().count())
+                            > SHORTENED_PREFIX_LENGTH + HASH_SUFFIX_LENGTH
+                    })
+                    .map(path_key),
+            );
+        }
+    }
+    if shortened.is_empty() {
+        return Ok(Cow::Borrowed(resources));
+    }
+
+    let mut changed = false;
+    let resources = resources
+        .iter()
+        .map(|resource| {
+            let mut resource = resource.clone();
+            let mut path = PathBuf::new();
+            for folder in folders(resource.path()) {
+                let name = folder.file_name().unwrap_or_default().to_string_lossy();
+                let key = path_key(folder);
+                if shortened.contains(&key) {
+                    path.push(shorten_name(&name));
+                    if let Entry::Vacant(entry) = mapping.entry(key) {
+                        entry.insert(path_key(&path));
+                        changed = true;
+                    }
+                } else {
+                    path.push(name.as_ref());
+                }
+            }
+            if let Some(name) = resource.path().file_name() {
+                path.push(name);
+            }
+            if base_length + char_count(&path) + TEMP_FILE_PREFIX.len() > max_length {
+                tracing::warn!(path = %path.display(), "Path is too long even with shortened folders");
+            }
+            *resource.path_mut() = path;
+            resource
+        })
+        .collect::<Vec<_>>();
+
+    if changed {
+        let data = serde_json::to_vec_pretty(&mapping).map_err(|_| "Unable to serialise paths")?;
+        fs::write(&mapping_file, data).map_err(|_| "Unable to write the shortened paths")?;
+    }
+    Ok(Cow::Owned(resources))
+}
@@ -0,0 +1,4211 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::TimeZone;
+use clap::{App, Arg, ArgGroup, SubCommand};
+use futures_util::{future, stream, FutureExt, StreamExt};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+
+use fluminurs::announcement::AnnouncementFile;
+use fluminurs::browser_cookies::{import_into, read_browser_cookies, read_cookie_file, Browser};
+use fluminurs::calendar::{to_ics, CalendarEvent};
+use fluminurs::conferencing::ZoomRecording;
+use fluminurs::cookie_jar::PersistentCookieJar;
+use fluminurs::crypto;
+use fluminurs::file::{File, Flatten, IndexPrefix};
+use fluminurs::forum::ForumThread;
+use fluminurs::grab::{resolve_link, LinkResource};
+use fluminurs::gradebook::Gradebook;
+use fluminurs::layout::PathTemplate;
+use fluminurs::lesson_plan::LessonPlan;
+use fluminurs::listing_cache::ListingCache;
+use fluminurs::module::Module;
+use fluminurs::multimedia::ExternalVideo;
+use fluminurs::multimedia::InternalVideo;
+use fluminurs::quiz::Quiz;
+use fluminurs::rate_limit::DEFAULT_REQUESTS_PER_SECOND;
+use fluminurs::resource::{
+    sort_and_make_all_paths_unique, OverwriteMode, OverwriteResult, Resource,
+};
+use fluminurs::roster::Roster;
+use fluminurs::scheduler::{DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_CONNECTIONS_PER_HOST};
+use fluminurs::streamer::{VideoContainer, VideoQuality, FFMPEG_REQUIRED};
+use fluminurs::util::{html_to_text, set_filename_normalisation, FilenameNormalisation};
+use fluminurs::weblecture::WebLectureVideo;
+use fluminurs::weblink::Weblink;
+use fluminurs::{
+    add_root_certificates, Api, Result, DEFAULT_MAX_RESPONSE_SIZE, REQUIRES_TEACHING_ACCESS,
+};
+
+#[macro_use]
+extern crate bitflags;
+
+const PKG_NAME: &str = env!("CARGO_PKG_NAME");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
+
+const LOW_MEMORY_PARALLELISM: usize = 2;
+const LOW_MEMORY_MAX_RESPONSE_SIZE: usize = 16 << 20;
+// Long enough for a run right after another, short enough to pick up new files within a lecture
+const LISTING_CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+// Settings from the config file, with a section for each type of resource
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    updated: Option<OverwriteMode>,
+    // a PEM file of extra certificates to trust, like `--ca-cert`
+    ca_cert: Option<String>,
+    // where resources are saved, like `--layout`
+    layout: Option<String>,
+    files: SourceConfig,
+    multimedia: SourceConfig,
+    weblectures: SourceConfig,
+    conferences: SourceConfig,
+    announcements: SourceConfig,
+    forums: SourceConfig,
+    quizzes: SourceConfig,
+    gradebooks: SourceConfig,
+    rosters: SourceConfig,
+    weblinks: SourceConfig,
+    lesson_plans: SourceConfig,
+    notifications: notifications::NotificationsConfig,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct SourceConfig {
+    updated: Option<OverwriteMode>,
+    // run after a sync that downloaded or updated any resource of this type
+    post_sync_hook: Option<String>,
+}
+
+impl Config {
+    // The hook of each section that has one, by the name of the section
+    fn post_sync_hooks(&self) -> BTreeMap<&'static str, String> {
+        [
+            ("files", &self.files),
+            ("multimedia", &self.multimedia),
+            ("weblectures", &self.weblectures),
+            ("conferences", &self.conferences),
+            ("announcements", &self.announcements),
+            ("forums", &self.forums),
+            ("quizzes", &self.quizzes),
+            ("gradebooks", &self.gradebooks),
+            ("rosters", &self.rosters),
+            ("weblinks", &self.weblinks),
+            ("lesson-plans", &self.lesson_plans),
+        ]
+        .into_iter()
+        .filter_map(|(name, section)| Some((name, section.post_sync_hook.clone()?)))
+        .collect()
+    }
+}
+
+impl SourceConfig {
+    // the setting of the section takes precedence over the global one
+    fn overwrite_mode(&self, global: OverwriteMode) -> OverwriteMode {
+        self.updated.unwrap_or(global)
+    }
+}
+
+fn load_config(config_file: &str, required: bool) -> Result<Config> {
+    match fs::read_to_string(config_file) {
+        Ok(content) => serde_json::from_str(&content).map_err(|_| "Invalid config file"),
+        Err(_) if !required => Ok(Config::default()),
+        Err(_) => Err("Unable to read config file"),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Login {
+    username: String,
+    password: String,
+}
+
+bitflags! {
+    struct ModuleTypeFlags: u8 {
+        const TAKING = 0x01;
+        const TEACHING = 0x02;
+    }
+}
+
+bitflags! {
+    struct ResourceTypeFlags: u16 {
+        const FILES = 0x001;
+        const MULTIMEDIA = 0x002;
+        const WEBLECTURES = 0x004;
+        const CONFERENCES = 0x008;
+        const ANNOUNCEMENTS = 0x010;
+        const FORUMS = 0x020;
+        const QUIZZES = 0x040;
+        const WEBLINKS = 0x080;
+        const GRADEBOOKS = 0x100;
+        const ROSTERS = 0x200;
+        const LESSON_PLANS = 0x400;
+    }
+}
+
+// All user-facing output goes through a single writer thread, so that the lines printed by
+// concurrent downloads never interleave, and so that how they are shown can be swapped out
+#[derive(Clone, Copy)]
+enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+trait Renderer: Send {
+    fn line(&mut self, stream: OutputStream, line: &str);
+    fn flush(&mut self);
+}
+
+struct PlainRenderer {
+    stdout: io::BufWriter<io::Stdout>,
+    stderr: io::Stderr,
+}
+
+impl PlainRenderer {
+    fn new() -> PlainRenderer {
+        PlainRenderer {
+            stdout: io::BufWriter::new(io::stdout()),
+            stderr: io::stderr(),
+        }
+    }
+}
+
+impl Renderer for PlainRenderer {
+    fn line(&mut self, stream: OutputStream, line: &str) {
+        // each line is written in one go, together with its newline
+        let line = format!("{}\n", line);
+        match stream {
+            OutputStream::Stdout => self.stdout.write_all(line.as_bytes()),
+            OutputStream::Stderr => {
+                // keep stdout and stderr in order when both go to the terminal
+                self.stdout.flush().ok();
+                self.stderr.write_all(line.as_bytes())
+            }
+        }
+        .ok();
+    }
+
+    fn flush(&mut self) {
+        self.stdout.flush().ok();
+        self.stderr.flush().ok();
+    }
+}
+
+enum OutputMessage {
+    Line(OutputStream, String),
+    Flush(mpsc::Sender<()>),
+}
+
+static OUTPUT: Mutex<Option<mpsc::Sender<OutputMessage>>> = Mutex::new(None);
+
+fn start_output(mut renderer: Box<dyn Renderer>) -> thread::JoinHandle<()> {
+    let (sender, receiver) = mpsc::channel();
+    *OUTPUT.lock().expect("Output lock is poisoned") = Some(sender);
+    thread::spawn(move || {
+        while let Ok(message) = receiver.recv() {
+            let mut message = Some(message);
+            // render everything that is pending before flushing
+            while let Some(m) = message.take() {
+                match m {
+                    OutputMessage::Line(stream, line) => renderer.line(stream, &line),
+                    OutputMessage::Flush(done) => {
+                        renderer.flush();
+                        done.send(()).ok();
+                    }
+                }
+                message = receiver.try_recv().ok();
+            }
+            renderer.flush();
+        }
+    })
+}
+
+fn finish_output(writer: thread::JoinHandle<()>) {
+    OUTPUT.lock().expect("Output lock is poisoned").take();
+    writer.join().ok();
+}
+
+// Set by --quiet, which leaves only warnings and errors
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+fn emit(stream: OutputStream, line: String) {
+    if let OutputStream::Stdout = stream {
+        if QUIET.load(Ordering::Relaxed) {
+            return;
+        }
+    }
+    let output = OUTPUT.lock().expect("Output lock is poisoned");
+    match output.as_ref() {
+        Some(sender) => {
+            sender.send(OutputMessage::Line(stream, line)).ok();
+        }
+        // before the writer is started or after it is finished
+        None => match stream {
+            OutputStream::Stdout => println!("{}", line),
+            OutputStream::Stderr => eprintln!("{}", line),
+        },
+    }
+}
+
+// Waits until everything sent so far has been written, e.g. before prompting the user
+fn flush_output() {
+    let (done, wait) = mpsc::channel();
+    let sent = OUTPUT
+        .lock()
+        .expect("Output lock is poisoned")
+        .as_ref()
+        .map(|sender| sender.send(OutputMessage::Flush(done)).is_ok())
+        .unwrap_or(false);
+    if sent {
+        wait.recv().ok();
+    }
+}
+
+macro_rules! outln {
+    () => {
+        $crate::emit($crate::OutputStream::Stdout, String::new())
+    };
+    ($($arg:tt)*) => {
+        $crate::emit($crate::OutputStream::Stdout, format!($($arg)*))
+    };
+}
+
+macro_rules! errln {
+    ($($arg:tt)*) => {
+        $crate::emit($crate::OutputStream::Stderr, format!($($arg)*))
+    };
+}
+
+mod archive;
+mod attention;
+mod cache_proxy;
+mod changes;
+mod clean;
+mod daemon;
+mod diff;
+mod hashing;
+mod hooks;
+mod interactive;
+mod links;
+mod logging;
+mod long_paths;
+mod manifest;
+mod metrics;
+#[cfg(all(feature = "fuse", unix))]
+mod mount;
+mod notifications;
+mod publish;
+mod report;
+mod retry;
+mod s3;
+mod schedule;
+mod season_end;
+mod sessions;
+mod stats;
+mod subscriptions;
+mod summary;
+mod tree;
+mod vault;
+mod webdav;
+
+fn write_prompt(prompt: &str) {
+    flush_output();
+    eprint!("{}", prompt);
+    io::stderr().flush().expect("Unable to flush stderr");
+}
+
+fn get_input(prompt: &str) -> String {
+    let mut input = String::new();
+    write_prompt(prompt);
+    io::stdin()
+        .read_line(&mut input)
+        .expect("Unable to get input");
+    input.trim().to_string()
+}
+
+fn get_password(prompt: &str) -> String {
+    write_prompt(prompt);
+    rpassword::read_password().expect("Unable to get non-echo input mode for password")
+}
+
+async fn print_announcements(api: &Api, modules: &[Module]) -> Result<()> {
+    let module_announcements = future::join_all(
+        modules
+            .iter()
+            .map(|module| module.get_announcements(api, false)),
+    )
+    .await;
+    for (module, announcements) in modules.iter().zip(module_announcements) {
+        let announcements = announcements?;
+        outln!("# {} {}", module.code, module.name);
+        outln!();
+        for ann in announcements {
+            outln!("=== {} ===", ann.title);
+            outln!("{}", html_to_text(&ann.description));
+        }
+        outln!();
+        outln!();
+    }
+    Ok(())
+}
+
+// The folder that the resources of a module are listed in, which is `<code>` or `<code>/<kind>`
+// unless there is a layout, which places each resource itself
+fn module_root(layout: Option<&PathTemplate>, code: &str, kind: Option<&str>) -> PathBuf {
+    match (layout, kind) {
+        (Some(_), _) => PathBuf::new(),
+        (None, Some(kind)) => Path::new(code).join(kind),
+        (None, None) => Path::new(code).to_owned(),
+    }
+}
+
+// The resources of a module, which can be laid out and collected with those of other modules
+trait ModuleResources {
+    fn lay_out(&mut self, layout: Option<&PathTemplate>, module: &Module, kind: &str);
+    fn append(&mut self, other: Self);
+}
+
+impl<T: Resource> ModuleResources for Vec<T> {
+    fn lay_out(&mut self, layout: Option<&PathTemplate>, module: &Module, kind: &str) {
+        if let Some(layout) = layout {
+            layout.lay_out(module, kind, self);
+        }
+        // to avoid duplicate files from being corrupted,
+        // we append the id to duplicate resources
+        sort_and_make_all_paths_unique(self);
+    }
+
+    fn append(&mut self, mut other: Self) {
+        Vec::append(self, &mut other);
+    }
+}
+
+impl<A: ModuleResources, B: ModuleResources> ModuleResources for (A, B) {
+    fn lay_out(&mut self, layout: Option<&PathTemplate>, module: &Module, kind: &str) {
+        self.0.lay_out(layout, module, kind);
+        self.1.lay_out(layout, module, kind);
+    }
+
+    fn append(&mut self, other: Self) {
+        self.0.append(other.0);
+        self.1.append(other.1);
+    }
+}
+
+// Loads the resources of all modules that can be accessed at the same time, and lays them out
+// under `kind`. The modules that fail to load are reported and left out.
+async fn load_modules_with<'a, R, F, Fut>(
+    api: &'a Api,
+    modules: &'a [Module],
+    layout: Option<&PathTemplate>,
+    kind: &str,
+    load: F,
+) -> Result<R>
+where
+    R: ModuleResources + Default,
+    F: Fn(&'a Api, &'a Module) -> Fut,
+    Fut: Future<Output = Result<R>>,
+{
+    let results = future::join_all(
+        modules
+            .iter()
+            .filter(|module| module.has_access())
+            .map(|module| load(api, module).map(move |result| (module, result))),
+    )
+    .await;
+
+    let mut resources = R::default();
+    for (module, result) in results {
+        match result {
+            Ok(mut loaded) => {
+                loaded.lay_out(layout, module, kind);
+                resources.append(loaded);
+            }
+            Err(e) => errln!("Failed loading module {}: {}", kind.to_lowercase(), e),
+        }
+    }
+    Ok(resources)
+}
+
+async fn load_modules_announcements(
+    api: &Api,
+    modules: &[Module],
+    layout: Option<&PathTemplate>,
+) -> Result<Vec<AnnouncementFile>> {
+    load_modules_with(api, modules, layout, "Announcements", |api, module| {
+        module
+            .announcements_root(|code| module_root(layout, code, Some("Announcements")))
+            .load(api)
+    })
+    .await
+}
+
+// The files attached to the announcements, which are downloaded next to them
+fn announcement_attachments(announcements: &[AnnouncementFile]) -> Vec<File> {
+    let mut attachments = vec![];
+    for announcement in announcements {
+        match announcement.attachments() {
+            Ok(mut files) => attachments.append(&mut files),
+            Err(e) => errln!("Failed loading announcement attachments: {}", e),
+        }
+    }
+    sort_and_make_all_paths_unique(&mut attachments);
+    attachments
+}
+
+async fn load_modules_files(
+    api: &Api,
+    modules: &[Module],
+    include_uploadable_folders: ModuleTypeFlags,
+    regularize_uploadable: bool,
+    index_prefix: Option<IndexPrefix>,
+    flatten: Option<Flatten>,
+    layout: Option<&PathTemplate>,
+) -> Result<Vec<File>> {
+    load_modules_with(api, modules, layout, "Files", |api, module| {
+        module
+            .workbin_root(|code| module_root(layout, code, None))
+            .load(
+                api,
+                include_uploadable_folders.contains(if module.is_teaching() {
+                    ModuleTypeFlags::TEACHING
+                } else {
+                    ModuleTypeFlags::TAKING
+                }),
+                regularize_uploadable,
+                index_prefix,
+                flatten,
+            )
+    })
+    .await
+}
+
+async fn load_modules_multimedia(
+    api: &Api,
+    modules: &[Module],
+    layout: Option<&PathTemplate>,
+) -> Result<(Vec<InternalVideo>, Vec<ExternalVideo>)> {
+    load_modules_with(api, modules, layout, "Multimedia", |api, module| {
+        module
+            .multimedia_root(|code| module_root(layout, code, Some("Multimedia")))
+            .load(api)
+    })
+    .await
+}
+
+async fn load_modules_weblectures(
+    api: &Api,
+    modules: &[Module],
+    date_prefix: bool,
+    layout: Option<&PathTemplate>,
+) -> Result<Vec<WebLectureVideo>> {
+    load_modules_with(api, modules, layout, "Web Lectures", |api, module| {
+        module
+            .weblecture_root(|code| module_root(layout, code, Some("Web Lectures")))
+            .load(api, date_prefix)
+    })
+    .await
+}
+
+// Leaves out the recordings that are already in `downloaded_to`, if given. Those can only be told
+// apart without a layout, which may put the recordings anywhere.
+async fn load_modules_conferences(
+    api: &Api,
+    modules: &[Module],
+    downloaded_to: Option<&Path>,
+    layout: Option<&PathTemplate>,
+) -> Result<Vec<ZoomRecording>> {
+    load_modules_with(
+        api,
+        modules,
+        layout,
+        "Conferences",
+        |api, module| async move {
+            let conference =
+                module.conferencing_root(|code| module_root(layout, code, Some("Conferences")));
+            match downloaded_to.filter(|_| layout.is_none()) {
+                Some(destination) => conference.load_missing(api, destination).await,
+                None => conference.load(api).await,
+            }
+        },
+    )
+    .await
+}
+
+async fn load_modules_forums(
+    api: &Api,
+    modules: &[Module],
+    layout: Option<&PathTemplate>,
+) -> Result<Vec<ForumThread>> {
+    load_modules_with(api, modules, layout, "Forum", |api, module| {
+        module
+            .forum_root(|code| module_root(layout, code, Some("Forum")))
+            .load(api)
+    })
+    .await
+}
+
+async fn load_modules_quizzes(
+    api: &Api,
+    modules: &[Module],
+    layout: Option<&PathTemplate>,
+) -> Result<Vec<Quiz>> {
+    load_modules_with(api, modules, layout, "Quizzes", |api, module| {
+        module
+            .quiz_root(|code| module_root(layout, code, Some("Quizzes")))
+            .load(api)
+    })
+    .await
+}
+
+async fn load_modules_gradebooks(
+    api: &Api,
+    modules: &[Module],
+    full_class: bool,
+    layout: Option<&PathTemplate>,
+) -> Result<Vec<Gradebook>> {
+    load_modules_with(api, modules, layout, "Gradebook", |api, module| {
+        module
+            .gradebook_root(|code| module_root(layout, code, None))
+            .load(api, full_class)
+    })
+    .await
+}
+
+async fn load_modules_weblinks(
+    api: &Api,
+    modules: &[Module],
+    layout: Option<&PathTemplate>,
+) -> Result<Vec<Weblink>> {
+    load_modules_with(api, modules, layout, "Weblinks", |api, module| {
+        module
+            .weblink_root(|code| module_root(layout, code, Some("Weblinks")))
+            .load(api)
+    })
+    .await
+}
+
+async fn load_modules_lesson_plans(
+    api: &Api,
+    modules: &[Module],
+    layout: Option<&PathTemplate>,
+) -> Result<Vec<LessonPlan>> {
+    load_modules_with(api, modules, layout, "Lesson Plans", |api, module| {
+        module
+            .lesson_plan_root(|code| module_root(layout, code, None))
+            .load(api)
+    })
+    .await
+}
+
+async fn load_modules_rosters(
+    api: &Api,
+    modules: &[Module],
+    layout: Option<&PathTemplate>,
+) -> Result<Vec<Roster>> {
+    load_modules_with(api, modules, layout, "Roster", |api, module| async move {
+        match module.roster_root(|code| module_root(layout, code, None)) {
+            Some(roster) => roster.load(api).await,
+            None => Ok(vec![]),
+        }
+    })
+    .await
+}
+
+// Only events that have not ended yet are exported, as the calendar is meant for what's upcoming
+async fn export_calendar(api: &Api, modules: &[Module], destination: &str) -> Result<()> {
+    let now = SystemTime::now();
+    let module_events = future::join_all(modules.iter().filter(|module| module.has_access()).map(
+        |module| async move {
+            let conferencing = module.conferencing_root(|code| Path::new(code).to_owned());
+            let (meetings, weblectures) = future::join(
+                conferencing.load_meetings(api),
+                module
+                    .weblecture_root(|code| Path::new(code).to_owned())
+                    // calendar events carry their own date, so the names are not prefixed
+                    .load(api, false),
+            )
+            .await;
+
+            let mut events = meetings?
+                .into_iter()
+                .map(|meeting| CalendarEvent {
+                    uid: format!("{}@fluminurs", meeting.id),
+                    summary: format!("{}: {}", module.code, meeting.name),
+                    start: meeting.start_date,
+                    end: meeting.end_date,
+                    description: meeting
+                        .join_url
+                        .as_ref()
+                        .map(|url| format!("Join Zoom meeting: {}", url)),
+                    url: meeting.join_url,
+                })
+                .collect::<Vec<_>>();
+            events.extend(weblectures?.into_iter().filter_map(|weblecture| {
+                Some(CalendarEvent {
+                    uid: format!("{}@fluminurs", weblecture.id()),
+                    summary: format!(
+                        "{}: {}",
+                        module.code,
+                        weblecture.path().file_stem()?.to_string_lossy()
+                    ),
+                    start: weblecture.start_date()?,
+                    end: weblecture.end_date(),
+                    description: None,
+                    url: None,
+                })
+            }));
+            Ok::<_, &'static str>(events)
+        },
+    ))
+    .await;
+
+    let mut events = vec![];
+    for module_event in module_events {
+        match module_event {
+            Ok(mut module_events) => events.append(&mut module_events),
+            Err(e) => errln!("Failed loading module calendar: {}", e),
+        }
+    }
+    events.retain(|event| event.end.unwrap_or(event.start) >= now);
+    events.sort_by_key(|event| event.start);
+
+    fs::write(destination, to_ics(&events)).map_err(|_| "Unable to write calendar file")?;
+    outln!("Exported {} events to {}", events.len(), destination);
+    Ok(())
+}
+
+struct ResourceFilter {
+    include_globset: Option<GlobSet>,
+    exclude_globset: Option<GlobSet>,
+    // lowercase and without the dot
+    only_extensions: Option<Vec<String>>,
+    skip_extensions: Vec<String>,
+    since: Option<SystemTime>,
+    until: Option<SystemTime>,
+    subscriptions: Option<subscriptions::Subscriptions>,
+}
+
+impl ResourceFilter {
+    fn is_empty(&self) -> bool {
+        self.include_globset.is_none()
+            && self.exclude_globset.is_none()
+            && self.only_extensions.is_none()
+            && self.skip_extensions.is_empty()
+            && self.since.is_none()
+            && self.until.is_none()
+            && self.subscriptions.is_none()
+    }
+
+    fn matches_extension(&self, path: &Path) -> bool {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let listed = |extensions: &[String]| {
+            extension
+                .as_ref()
+                .is_some_and(|extension| extensions.contains(extension))
+        };
+        self.only_extensions
+            .as_ref()
+            .map(|extensions| listed(extensions))
+            .unwrap_or(true)
+            && !listed(&self.skip_extensions)
+    }
+
+    fn matches<T: Resource>(&self, resource: &T) -> bool {
+        let excluded = self
+            .exclude_globset
+            .as_ref()
+            .map(|glob_set| glob_set.is_match(resource.path()))
+            .unwrap_or(false)
+            || !self.matches_extension(resource.path());
+        let included = self
+            .include_globset
+            .as_ref()
+            .map(|glob_set| glob_set.is_match(resource.path()))
+            .unwrap_or(false);
+        let last_updated = resource.last_updated();
+        let in_date_range = self
+            .since
+            .map(|since| last_updated >= since)
+            .unwrap_or(true)
+            && self.until.map(|until| last_updated < until).unwrap_or(true);
+
+        // `include` takes precedence over `exclude` and the extensions, but neither overrides the date range.
+        // Subscriptions are checked last, so that only resources which are synced get flagged as new.
+        (!excluded || included)
+            && in_date_range
+            && self
+                .subscriptions
+                .as_ref()
+                .map(|subscriptions| subscriptions.matches(resource.path()))
+                .unwrap_or(true)
+    }
+}
+
+fn filter_resources<T: Resource>(resources: Vec<T>, filter: &ResourceFilter) -> Vec<T> {
+    if filter.is_empty() {
+        resources
+    } else {
+        resources
+            .into_iter()
+            .filter(|resource| filter.matches(resource))
+            .collect::<Vec<_>>()
+    }
+}
+
+// For the generated resources (gradebooks, rosters and lesson plans), which are not filtered
+// otherwise, but are still documents that `--only-ext` and `--skip-ext` may leave out
+fn filter_extensions<T: Resource>(resources: Vec<T>, filter: &ResourceFilter) -> Vec<T> {
+    resources
+        .into_iter()
+        .filter(|resource| filter.matches_extension(resource.path()))
+        .collect::<Vec<_>>()
+}
+
+// Parses best, worst, audio, or a maximum height like 720p
+fn parse_video_quality(quality: &str) -> Option<VideoQuality> {
+    match quality.to_lowercase().as_str() {
+        "best" => Some(VideoQuality::Best),
+        "worst" => Some(VideoQuality::Worst),
+        "audio" => Some(VideoQuality::Audio),
+        height => height
+            .strip_suffix('p')
+            .and_then(|height| height.parse::<u32>().ok())
+            .map(VideoQuality::Height),
+    }
+}
+
+// Parses a YYYY-MM-DD date as the start of that day in local time
+fn parse_date(date: &str) -> Option<SystemTime> {
+    let date = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    chrono::Local
+        .from_local_datetime(&date.and_hms(0, 0, 0))
+        .earliest()
+        .map(SystemTime::from)
+}
+
+fn list_resources<T: Resource>(resources: &[T], tree: bool) {
+    let sizes = resources
+        .iter()
+        .map(|resource| resource.size())
+        .collect::<Vec<_>>();
+    list_resources_with_sizes(resources, &sizes, tree);
+}
+
+// Lists the resources with their sizes where they are known, and the total of those
+fn list_resources_with_sizes<T: Resource>(resources: &[T], sizes: &[Option<u64>], tree: bool) {
+    if tree {
+        tree::print_tree(
+            resources
+                .iter()
+                .map(|resource| resource.path())
+                .zip(sizes.iter().copied()),
+        );
+        return;
+    }
+    for (resource, size) in resources.iter().zip(sizes) {
+        match size {
+            Some(size) => outln!("{} ({})", resource.path().display(), format_size(*size)),
+            None => outln!("{}", resource.path().display()),
+        }
+    }
+    let known = sizes.iter().flatten().collect::<Vec<_>>();
+    if !known.is_empty() {
+        outln!(
+            "Total: {} in {} files{}",
+            format_size(known.iter().copied().sum()),
+            known.len(),
+            if known.len() < resources.len() {
+                format!(", and {} of unknown size", resources.len() - known.len())
+            } else {
+                String::new()
+            }
+        );
+    }
+}
+
+// Lists the resources with their sizes, asking the server for those that the listing left out
+async fn list_resources_long<T: Resource>(api: &Api, resources: &[T], tree: bool) {
+    let sizes = stream::iter(resources)
+        .map(|resource| async move {
+            match resource.size() {
+                Some(size) => Some(size),
+                None => resource
+                    .remote_info(api)
+                    .await
+                    .ok()
+                    .flatten()
+                    .and_then(|info| info.size),
+            }
+        })
+        .buffered(16)
+        .collect::<Vec<_>>()
+        .await;
+    list_resources_with_sizes(resources, &sizes, tree);
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+// The size of a download and how fast it came down, e.g. `1.2 MB in 3.4s, 361.4 KB/s`
+fn format_transfer(bytes: u64, elapsed: Duration) -> String {
+    let speed = bytes as f64 / elapsed.as_secs_f64().max(0.001);
+    format!(
+        "{} in {:.1}s, {}/s",
+        format_size(bytes),
+        elapsed.as_secs_f64(),
+        format_size(speed as u64)
+    )
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+// Lists web lectures together with their duration and estimated size,
+// which requires launching Panopto for each of them
+async fn list_weblectures_long(api: &Api, weblectures: &[WebLectureVideo]) {
+    let mut total_duration = Duration::default();
+    let mut total_size = 0;
+    let mut details_stream = stream::iter(weblectures.iter())
+        .map(|weblecture| async move { (weblecture, weblecture.get_details(api).await) })
+        .buffered(4);
+    while let Some((weblecture, details)) = details_stream.next().await {
+        match details {
+            Ok(details) => {
+                total_duration += details.duration;
+                total_size += details.estimated_size.unwrap_or(0);
+                outln!(
+                    "{}\t{}\t{}",
+                    weblecture.path().display(),
+                    format_duration(details.duration),
+                    details
+                        .estimated_size
+                        .map(|size| format!("~{}", format_size(size)))
+                        .unwrap_or_else(|| "unknown size".to_owned())
+                );
+            }
+            Err(e) => outln!(
+                "{}\t(unable to get details: {})",
+                weblecture.path().display(),
+                e
+            ),
+        }
+    }
+    outln!(
+        "Total: {} web lectures, {}, ~{}",
+        weblectures.len(),
+        format_duration(total_duration),
+        format_size(total_size)
+    );
+}
+
+async fn download_resource<T: Resource>(
+    api: &Api,
+    file: &T,
+    path: PathBuf,
+    temp_path: PathBuf,
+    overwrite_mode: OverwriteMode,
+) -> Result<OverwriteResult> {
+    let started = Instant::now();
+    let result = match file.download(api, &path, &temp_path, overwrite_mode).await {
+        // teaching-only resources are expected to be unavailable in modules that are only taken
+        Err(REQUIRES_TEACHING_ACCESS) => return Ok(OverwriteResult::Skipped),
+        result => result,
+    };
+    let transfer = || {
+        let size = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+        format_transfer(size, started.elapsed())
+    };
+    match &result {
+        Ok(OverwriteResult::NewFile) => {
+            outln!("Downloaded to {} ({})", path.to_string_lossy(), transfer())
+        }
+        Ok(OverwriteResult::AlreadyHave) => {}
+        Ok(OverwriteResult::Skipped) => outln!("Skipped {}", path.to_string_lossy()),
+        Ok(OverwriteResult::Overwritten) => {
+            outln!("Updated {} ({})", path.to_string_lossy(), transfer())
+        }
+        Ok(OverwriteResult::Renamed { renamed_path }) => outln!(
+            "Renamed {} to {} ({})",
+            path.to_string_lossy(),
+            renamed_path.to_string_lossy(),
+            transfer()
+        ),
+        Err(FFMPEG_REQUIRED) => outln!("Skipped {} (needs ffmpeg)", path.to_string_lossy()),
+        Err(e) => errln!("Failed to download {}: {}", path.to_string_lossy(), e),
+    }
+    result
+}
+
+async fn verify_resource<T: Resource>(api: &Api, file: &T, path: PathBuf) -> VerifyResult {
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(_) => {
+            outln!("Missing {}", path.to_string_lossy());
+            return VerifyResult::Missing;
+        }
+    };
+    let remote = match file.remote_info(api).await {
+        Ok(Some(remote)) => remote,
+        Ok(None) => return VerifyResult::Unverifiable,
+        Err(e) => {
+            errln!("Failed to resolve {}: {}", path.to_string_lossy(), e);
+            return VerifyResult::Failed;
+        }
+    };
+    match remote.size {
+        Some(size) if size != metadata.len() => {
+            outln!(
+                "Size mismatch for {}: {} bytes locally, {} bytes on the server",
+                path.to_string_lossy(),
+                metadata.len(),
+                size
+            );
+            VerifyResult::Mismatch
+        }
+        // downloads get the last updated time of the resource as their modified time
+        _ if metadata
+            .modified()
+            .map(|modified| modified < file.last_updated())
+            .unwrap_or(false) =>
+        {
+            outln!("Outdated {}", path.to_string_lossy());
+            VerifyResult::Mismatch
+        }
+        _ => VerifyResult::Ok,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum VerifyResult {
+    Ok,
+    Missing,
+    Mismatch,
+    Unverifiable,
+    Failed,
+}
+
+// Checks the local copies against the server instead of downloading anything
+async fn verify_resources<T: Resource>(
+    api: &Api,
+    files: &[T],
+    dest_path: &Path,
+    parallelism: usize,
+    find_duplicates: bool,
+) {
+    let results = stream::iter(files.iter())
+        .map(|file| verify_resource(api, file, dest_path.join(file.path())))
+        .buffer_unordered(parallelism)
+        .collect::<Vec<_>>()
+        .await;
+    let count = |result| results.iter().filter(|r| **r == result).count();
+    outln!(
+        "Verified {} files: {} ok, {} missing, {} mismatched, {} unverifiable, {} failed",
+        results.len(),
+        count(VerifyResult::Ok),
+        count(VerifyResult::Missing),
+        count(VerifyResult::Mismatch),
+        count(VerifyResult::Unverifiable),
+        count(VerifyResult::Failed)
+    );
+
+    if find_duplicates {
+        let paths = files
+            .iter()
+            .map(|file| dest_path.join(file.path()))
+            .collect::<Vec<_>>();
+        let duplicates = hashing::find_duplicates(paths).await;
+        for group in &duplicates {
+            outln!(
+                "Duplicates: {}",
+                group
+                    .iter()
+                    .map(|path| path.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        outln!("Found {} sets of duplicate files", duplicates.len());
+    }
+}
+
+// Reports the final result of a download to wherever the context keeps track of them
+fn finish_download<T: Resource>(
+    context: &DownloadContext,
+    file: &T,
+    dest_path: &Path,
+    real_path: &Path,
+    result: &Result<OverwriteResult>,
+    duration: Duration,
+) {
+    match (result, &context.session) {
+        (Err(e), Some(session)) => {
+            context.attention.record(e, real_path);
+            if let Err(e) = session.fail(dest_path, file, e) {
+                errln!("Failed to update the session: {}", e);
+            }
+        }
+        (Err(e), None) => context.attention.record(e, real_path),
+        (Ok(_), Some(session)) => {
+            if let Err(e) = session.finish(dest_path, file) {
+                errln!("Failed to update the session: {}", e);
+            }
+        }
+        (Ok(_), None) => {}
+    }
+    if let Some(status) = &context.status {
+        status.finish_item(file.path(), result.as_ref().map(|_| ()).map_err(|e| *e));
+        status.metrics().record_download::<T>(result, real_path);
+    }
+    context.summary.record::<T>(result, real_path, duration);
+    if let Some(report) = &context.report {
+        report.record(file, real_path, result, duration);
+    }
+    if let Some(digest) = &context.digest {
+        digest.record(file, result);
+    }
+    if let Some(hooks) = &context.hooks {
+        hooks.record::<T>(real_path, result);
+    }
+}
+
+async fn download_resources<T: Resource + Clone + Send + 'static>(
+    api: &Api,
+    files: &[T],
+    destination: &str,
+    overwrite_mode: OverwriteMode,
+    parallelism: usize,
+    context: &DownloadContext,
+) -> Result<()> {
+    if schedule::is_video::<T>()
+        && !context.verify_only
+        && context.quiet_hours.is_some_and(|quiet| quiet.is_quiet())
+    {
+        outln!(
+            "Leaving the videos for {} for a sync outside the quiet hours",
+            destination
+        );
+        return Ok(());
+    }
+    if s3::is_s3_url(destination) {
+        if context.verify_only {
+            return Err("Downloads to S3 cannot be verified");
+        }
+        outln!("Download to {}", destination);
+        let bucket = s3::Bucket::from_url(destination)?;
+        return upload_resources(
+            api,
+            files,
+            destination,
+            &bucket,
+            overwrite_mode,
+            parallelism,
+            context,
+        )
+        .await;
+    }
+    let dest_path = Path::new(destination);
+    let files = match context.max_path_length {
+        Some(max_length) if dest_path.is_dir() => {
+            long_paths::fit_paths(dest_path, files, max_length)?
+        }
+        _ => Cow::Borrowed(files),
+    };
+    let files = &files[..];
+    if context.verify_only {
+        outln!("Verify {}", destination);
+        if !dest_path.is_dir() {
+            return Err("Download destination does not exist or is not a directory");
+        }
+        verify_resources(api, files, dest_path, parallelism, context.find_duplicates).await;
+        return Ok(());
+    }
+    outln!("Download to {}", destination);
+    if !dest_path.is_dir() {
+        return Err("Download destination does not exist or is not a directory");
+    }
+
+    // files that only changed their names are moved rather than downloaded again
+    match manifest::follow_renames(dest_path, files) {
+        Ok(renamed) => {
+            if let Some(change_feed) = &context.change_feed {
+                if let Err(e) = change_feed.record_renames(dest_path, &renamed) {
+                    errln!("Failed to update the change feed: {}", e);
+                }
+            }
+        }
+        Err(e) => errln!("Failed to follow renamed files: {}", e),
+    }
+
+    // leftovers of an interrupted session are downloaded whatever the overwrite mode says
+    let (queued, overwrite_mode) = match &context.session {
+        Some(session) => match session.queue(dest_path, files)? {
+            (leftovers, true) => (leftovers, OverwriteMode::Overwrite),
+            (queued, false) => (queued, overwrite_mode),
+        },
+        None => (files.iter().collect(), overwrite_mode),
+    };
+    if let Some(status) = &context.status {
+        for file in &queued {
+            status.add_pending(file.path());
+        }
+    }
+    let results = stream::iter(queued)
+        .map(|file| {
+            let temp_path = dest_path
+                .join(file.path().parent().unwrap())
+                .join(make_temp_file_name(file.path().file_name().unwrap()));
+            let real_path = dest_path.join(file.path());
+            async move {
+                let started = Instant::now();
+                let result = download_resource(
+                    api,
+                    file,
+                    real_path.clone(),
+                    temp_path.clone(),
+                    overwrite_mode,
+                )
+                .await;
+                let duration = started.elapsed();
+                match (&result, &context.retries) {
+                    // the final result is only known once it has been tried again
+                    (Err(e), Some(retries)) if attention::is_transient(e) => {
+                        let (api, file, dest_path) =
+                            (api.clone(), file.clone(), dest_path.to_owned());
+                        let path = real_path.clone();
+                        retries.push(
+                            path,
+                            Box::new(move |context| {
+                                async move {
+                                    let started = Instant::now();
+                                    let result = download_resource(
+                                        &api,
+                                        &file,
+                                        real_path.clone(),
+                                        temp_path,
+                                        overwrite_mode,
+                                    )
+                                    .await;
+                                    finish_download(
+                                        context,
+                                        &file,
+                                        &dest_path,
+                                        &real_path,
+                                        &result,
+                                        duration + started.elapsed(),
+                                    );
+                                    if let Some(change_feed) = &context.change_feed {
+                                        if let Err(e) =
+                                            change_feed.record_retried(&dest_path, &file, &result)
+                                        {
+                                            errln!("Failed to update the change feed: {}", e);
+                                        }
+                                    }
+                                    let succeeded = result.is_ok();
+                                    context.record_written(&dest_path, &[(&file, result)]);
+                                    succeeded
+                                }
+                                .boxed()
+                            }),
+                        );
+                    }
+                    _ => finish_download(context, file, dest_path, &real_path, &result, duration),
+                }
+                (file, result)
+            }
+        })
+        .buffer_unordered(parallelism)
+        .collect::<Vec<_>>()
+        .await;
+
+    if let Some(change_feed) = &context.change_feed {
+        if let Err(e) = change_feed.record(dest_path, files, &results) {
+            errln!("Failed to update the change feed: {}", e);
+        }
+    }
+    context.record_written(dest_path, &results);
+
+    Ok(())
+}
+
+// Downloads the resources into an S3 bucket instead, which has no sessions or change feed to keep
+async fn upload_resources<T: Resource>(
+    api: &Api,
+    files: &[T],
+    destination: &str,
+    bucket: &s3::Bucket,
+    overwrite_mode: OverwriteMode,
+    parallelism: usize,
+    context: &DownloadContext,
+) -> Result<()> {
+    let dest_path = Path::new(destination);
+    if let Some(status) = &context.status {
+        for file in files {
+            status.add_pending(file.path());
+        }
+    }
+    stream::iter(files)
+        .map(|file| async move {
+            let real_path = dest_path.join(file.path());
+            let started = Instant::now();
+            let result = match bucket.download(api, file, overwrite_mode).await {
+                Err(REQUIRES_TEACHING_ACCESS) => Ok(OverwriteResult::Skipped),
+                result => result,
+            };
+            match &result {
+                Ok(OverwriteResult::NewFile) => {
+                    outln!("Downloaded to {}", real_path.to_string_lossy())
+                }
+                Ok(OverwriteResult::AlreadyHave) => {}
+                Ok(OverwriteResult::Skipped) => outln!("Skipped {}", real_path.to_string_lossy()),
+                Ok(OverwriteResult::Overwritten) => {
+                    outln!("Updated {}", real_path.to_string_lossy())
+                }
+                Ok(OverwriteResult::Renamed { renamed_path }) => outln!(
+                    "Renamed {} to {}",
+                    real_path.to_string_lossy(),
+                    renamed_path.to_string_lossy()
+                ),
+                Err(FFMPEG_REQUIRED) => {
+                    outln!("Skipped {} (needs ffmpeg)", real_path.to_string_lossy())
+                }
+                Err(e) => errln!("Failed to download {}: {}", real_path.to_string_lossy(), e),
+            }
+            finish_download(
+                context,
+                file,
+                dest_path,
+                &real_path,
+                &result,
+                started.elapsed(),
+            );
+        })
+        .buffer_unordered(parallelism)
+        .collect::<Vec<_>>()
+        .await;
+    Ok(())
+}
+
+fn find_resource<'a, T: Resource>(resources: &'a [T], target: &str) -> Option<&'a T> {
+    resources
+        .iter()
+        .find(|resource| resource.path() == Path::new(target) || resource.id() == target)
+}
+
+// Looks for the resource with the given path (as listed) or id, loading each kind of resource
+// in turn, and writes the first match to stdout
+async fn write_to_stdout(
+    api: &mut Api,
+    modules: &[Module],
+    target: &str,
+    options: &SyncOptions,
+) -> Result<()> {
+    let mut stdout = tokio::io::stdout();
+    let layout = options.layout.as_ref();
+
+    let files = load_modules_files(
+        api,
+        modules,
+        options.include_uploadable_folders,
+        options.regularize_uploadable,
+        options.index_prefix,
+        options.flatten,
+        layout,
+    )
+    .await?;
+    if let Some(file) = find_resource(&files, target) {
+        return file.download_to_writer(api, &mut stdout).await;
+    }
+
+    let (internal_videos, external_videos) = load_modules_multimedia(api, modules, layout).await?;
+    if let Some(video) = find_resource(&internal_videos, target) {
+        return video.download_to_writer(api, &mut stdout).await;
+    }
+    if let Some(video) = find_resource(&external_videos, target) {
+        return video.download_to_writer(api, &mut stdout).await;
+    }
+
+    let weblectures =
+        load_modules_weblectures(api, modules, options.weblecture_date_prefix, layout).await?;
+    if let Some(weblecture) = find_resource(&weblectures, target) {
+        return weblecture.download_to_writer(api, &mut stdout).await;
+    }
+
+    let announcements = load_modules_announcements(api, modules, layout).await?;
+    if let Some(announcement) = find_resource(&announcements, target) {
+        return announcement.download_to_writer(api, &mut stdout).await;
+    }
+
+    let threads = load_modules_forums(api, modules, layout).await?;
+    if let Some(thread) = find_resource(&threads, target) {
+        return thread.download_to_writer(api, &mut stdout).await;
+    }
+
+    let quizzes = load_modules_quizzes(api, modules, layout).await?;
+    if let Some(quiz) = find_resource(&quizzes, target) {
+        return quiz.download_to_writer(api, &mut stdout).await;
+    }
+
+    let weblinks = load_modules_weblinks(api, modules, layout).await?;
+    if let Some(weblink) = find_resource(&weblinks, target) {
+        return weblink.download_to_writer(api, &mut stdout).await;
+    }
+
+    let lesson_plans = load_modules_lesson_plans(api, modules, layout).await?;
+    if let Some(lesson_plan) = find_resource(&lesson_plans, target) {
+        return lesson_plan.download_to_writer(api, &mut stdout).await;
+    }
+
+    let conferences = load_modules_conferences(api, modules, None, layout).await?;
+    if let Some(conference) = find_resource(&conferences, target) {
+        api.login_zoom().await?;
+        return conference.download_to_writer(api, &mut stdout).await;
+    }
+
+    Err("No resource matches the path or id given to --stdout")
+}
+
+async fn grab(
+    api: &Api,
+    link: &str,
+    password: Option<&str>,
+    destination: &str,
+    overwrite_mode: OverwriteMode,
+) -> Result<()> {
+    match resolve_link(api, link, password).await? {
+        LinkResource::File(file) => {
+            download_resources(
+                api,
+                &[file],
+                destination,
+                overwrite_mode,
+                1,
+                &DownloadContext::default(),
+            )
+            .await
+        }
+        LinkResource::Panopto(video) => {
+            download_resources(
+                api,
+                &[video],
+                destination,
+                overwrite_mode,
+                1,
+                &DownloadContext::default(),
+            )
+            .await
+        }
+        LinkResource::Zoom(recording) => {
+            download_resources(
+                api,
+                &[recording],
+                destination,
+                overwrite_mode,
+                1,
+                &DownloadContext::default(),
+            )
+            .await
+        }
+    }
+}
+
+fn make_temp_file_name(name: &OsStr) -> OsString {
+    let prepend = OsStr::new(clean::TEMP_FILE_PREFIX);
+    let mut res = OsString::with_capacity(prepend.len() + name.len());
+    res.push(prepend);
+    res.push(name);
+    res
+}
+
+// For scheduled runs (e.g. in CI or a container), which cannot be prompted and should not keep a
+// credential file around
+const USERNAME_VAR: &str = "FLUMINURS_USERNAME";
+const PASSWORD_VAR: &str = "FLUMINURS_PASSWORD";
+const TOKEN_VAR: &str = "FLUMINURS_TOKEN";
+// for triggering syncs through the dashboard of watch mode, instead of a new one on every start
+const SERVE_TOKEN_VAR: &str = "FLUMINURS_SERVE_TOKEN";
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn env_credentials() -> Option<(String, String)> {
+    Some((env_var(USERNAME_VAR)?, env_var(PASSWORD_VAR)?))
+}
+
+fn read_stored_credentials(credential_file: &str) -> Option<(String, String)> {
+    if let Some(credentials) = env_credentials() {
+        return Some(credentials);
+    }
+    let content = read_credential_file(credential_file).ok()??;
+    let login = serde_json::from_slice::<Login>(&content).ok()?;
+    Some((login.username, login.password))
+}
+
+// The passphrase of an encrypted credential file, which is only asked for once per run,
+// even when watching logs in again for every sync
+static CREDENTIAL_PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+fn credential_passphrase() -> &'static str {
+    CREDENTIAL_PASSPHRASE.get_or_init(|| get_password("Credential file passphrase: "))
+}
+
+// Reads the credential file, which is encrypted unless it was stored by an older version
+// (or on purpose) in plain text, or returns `None` if there is none
+fn read_credential_file(credential_file: &str) -> Result<Option<Vec<u8>>> {
+    let data = match fs::read(credential_file) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(_) => return Err("Unable to read credentials"),
+    };
+    if crypto::is_encrypted(&data) {
+        crypto::decrypt(credential_passphrase(), &data).map(Some)
+    } else {
+        Ok(Some(data))
+    }
+}
+
+fn get_credentials(credential_file: &str) -> Result<(String, String)> {
+    if let Some(content) = read_credential_file(credential_file)? {
+        if let Ok(login) = serde_json::from_slice::<Login>(&content) {
+            Ok((login.username, login.password))
+        } else {
+            outln!("Corrupt credentials.json, deleting file...");
+            fs::remove_file(Path::new(credential_file))
+                .map_err(|_| "Unable to delete credential file")?;
+            get_credentials(credential_file)
+        }
+    } else {
+        let username = get_input("Username (include the nusstu\\ prefix): ");
+        let password = get_password("Password: ");
+        Ok((username, password))
+    }
+}
+
+fn import_browser_cookies(browser: &str, jar: &PersistentCookieJar) -> Result<()> {
+    let browser = Browser::from_name(browser).expect("Invalid browser for --import-cookies-from");
+    if !confirm(
+        "Import your NUS, Zoom and Panopto cookies from the browser into the cookie jar? [y/n]",
+    ) {
+        return Ok(());
+    }
+    let cookies = read_browser_cookies(browser)?;
+    import_into(&cookies, jar);
+    errln!("Imported {} cookies", cookies.len());
+    Ok(())
+}
+
+fn import_cookie_file(file: &str, jar: &PersistentCookieJar) -> Result<()> {
+    let cookies = read_cookie_file(file)?;
+    import_into(&cookies, jar);
+    errln!("Imported {} cookies from {}", cookies.len(), file);
+    Ok(())
+}
+
+// Sessions are only kept between runs in a jar that the user asked for
+fn load_cookie_jar(cookie_jar_file: Option<&str>, encrypt: bool) -> Result<PersistentCookieJar> {
+    let cookie_jar_file = match cookie_jar_file {
+        Some(cookie_jar_file) => cookie_jar_file,
+        None => return Ok(PersistentCookieJar::in_memory()),
+    };
+    if encrypt || PersistentCookieJar::is_encrypted(cookie_jar_file) {
+        let passphrase = get_password("Cookie jar passphrase: ");
+        PersistentCookieJar::load(cookie_jar_file, Some(&passphrase))
+    } else {
+        PersistentCookieJar::load(cookie_jar_file, None)
+    }
+}
+
+fn save_listing_cache(listing_cache: &Option<Arc<ListingCache>>) {
+    let listing_cache = match listing_cache {
+        Some(listing_cache) => listing_cache,
+        None => return,
+    };
+    let hits = listing_cache.take_hits();
+    if hits > 0 {
+        outln!(
+            "{} folder listings were taken from the listing cache, and may be up to {} minutes old (--refresh fetches them again)",
+            hits,
+            LISTING_CACHE_TTL.as_secs() / 60
+        );
+    }
+    if let Err(e) = listing_cache.save() {
+        errln!("Failed to save listing cache: {}", e);
+    }
+}
+
+// With an existing session from an earlier run or a browser, we might not need the credentials at all
+async fn login(credential_file: &str, cookie_jar: &Arc<PersistentCookieJar>) -> Result<Api> {
+    if let Some(token) = env_var(TOKEN_VAR) {
+        return Api::with_token_and_cookies(&token, cookie_jar.clone());
+    }
+    let api = if cookie_jar.domains().is_empty() {
+        None
+    } else {
+        match Api::with_cookies(cookie_jar.clone()).await {
+            Ok(api) => Some(api),
+            Err(e) => {
+                errln!("Unable to reuse the session in the cookie jar: {}", e);
+                None
+            }
+        }
+    };
+    let api = match (api, env_credentials()) {
+        (Some(api), _) => api,
+        // credentials from the environment are neither prompted for nor stored
+        (None, Some((username, password))) => {
+            Api::with_login_and_cookies(&username, &password, cookie_jar.clone()).await?
+        }
+        (None, None) => {
+            let (username, password) = get_credentials(credential_file)?;
+            let api = Api::with_login_and_cookies(&username, &password, cookie_jar.clone()).await?;
+            if !Path::new(credential_file).exists() {
+                match store_credentials(credential_file, &username, &password) {
+                    Ok(_) => (),
+                    Err(e) => errln!("Failed to store credentials: {}", e),
+                }
+            }
+            api
+        }
+    };
+    if let Err(e) = cookie_jar.save() {
+        errln!("Failed to save cookie jar: {}", e);
+    }
+    Ok(api)
+}
+
+fn store_credentials(credential_file: &str, username: &str, password: &str) -> Result<()> {
+    if confirm("Store credentials? [y/n]") {
+        let login = Login {
+            username: username.to_owned(),
+            password: password.to_owned(),
+        };
+        let serialised =
+            serde_json::to_string(&login).map_err(|_| "Unable to serialise credentials")?;
+        let passphrase = get_password(
+            "Passphrase to encrypt them with (leave empty to store them in plain text): ",
+        );
+        let data = if passphrase.is_empty() {
+            errln!("WARNING: the credentials are stored in plain text");
+            serialised.into_bytes()
+        } else {
+            if get_password("Repeat the passphrase: ") != passphrase {
+                return Err("Passphrases do not match");
+            }
+            let data = crypto::encrypt(&passphrase, serialised.as_bytes())?;
+            CREDENTIAL_PASSPHRASE.set(passphrase).ok();
+            data
+        };
+        fs::write(credential_file, data).map_err(|_| "Unable to write to credentials file")?;
+    }
+    Ok(())
+}
+
+// Encrypts a credential file that was stored in plain text
+fn encrypt_credential_file(credential_file: &str) -> Result<()> {
+    let data = match fs::read(credential_file) {
+        Ok(data) if !crypto::is_encrypted(&data) => data,
+        // nothing stored yet, or already encrypted
+        _ => return Ok(()),
+    };
+    serde_json::from_slice::<Login>(&data).map_err(|_| "Unable to parse credential file")?;
+    let passphrase = get_password("Passphrase to encrypt the credential file with: ");
+    if get_password("Repeat the passphrase: ") != passphrase {
+        return Err("Passphrases do not match");
+    }
+    let data = crypto::encrypt(&passphrase, &data)?;
+    fs::write(credential_file, data).map_err(|_| "Unable to write to credentials file")?;
+    CREDENTIAL_PASSPHRASE.set(passphrase).ok();
+    errln!("Encrypted {}", credential_file);
+    Ok(())
+}
+
+fn confirm(prompt: &str) -> bool {
+    write_prompt(&format!("{} ", prompt));
+    let mut answer = String::new();
+    while answer != "y" && answer != "n" {
+        answer = get_input("");
+        answer.make_ascii_lowercase();
+    }
+    answer == "y"
+}
+
+// What to list and download, as given on the command line
+struct SyncOptions {
+    do_announcements: bool,
+    announcements_download_destination: Option<String>,
+    do_files: bool,
+    download_destination: Option<String>,
+    do_multimedia: bool,
+    multimedia_download_destination: Option<String>,
+    do_weblectures: bool,
+    long_listing: bool,
+    tree_listing: bool,
+    weblectures_download_destination: Option<String>,
+    do_conferences: bool,
+    conference_assets: bool,
+    conference_views: bool,
+    conferences_download_destination: Option<String>,
+    do_forums: bool,
+    forums_download_destination: Option<String>,
+    do_quizzes: bool,
+    quizzes_download_destination: Option<String>,
+    gradebooks_download_destination: Option<String>,
+    full_class_gradebook: bool,
+    calendar_destination: Option<String>,
+    rosters_download_destination: Option<String>,
+    do_weblinks: bool,
+    weblinks_download_destination: Option<String>,
+    lesson_plans_download_destination: Option<String>,
+    include_uploadable_folders: ModuleTypeFlags,
+    regularize_uploadable: bool,
+    index_prefix: Option<IndexPrefix>,
+    flatten: Option<Flatten>,
+    weblecture_date_prefix: bool,
+    layout: Option<PathTemplate>,
+    publish_folders: Vec<String>,
+    resource_filter: ResourceFilter,
+    config: Config,
+    overwrite_mode: OverwriteMode,
+}
+
+// Turns a pass on or off depending on whether its type is selected. A selected type is listed
+// with `--list`, and downloaded to the common destination unless it has a destination of its own.
+fn select_type(
+    selected: bool,
+    list: bool,
+    common_destination: &Option<String>,
+    do_list: &mut bool,
+    download_destination: &mut Option<String>,
+) {
+    if selected {
+        *do_list |= list;
+        if download_destination.is_none() {
+            *download_destination = common_destination.clone();
+        }
+    } else {
+        *do_list = false;
+        *download_destination = None;
+    }
+}
+
+impl SyncOptions {
+    // Where anything may be downloaded to, each only once
+    fn destinations(&self) -> Vec<&Path> {
+        let mut destinations = [
+            &self.announcements_download_destination,
+            &self.download_destination,
+            &self.multimedia_download_destination,
+            &self.weblectures_download_destination,
+            &self.conferences_download_destination,
+            &self.forums_download_destination,
+            &self.quizzes_download_destination,
+            &self.gradebooks_download_destination,
+            &self.rosters_download_destination,
+            &self.weblinks_download_destination,
+            &self.lesson_plans_download_destination,
+        ]
+        .into_iter()
+        .flatten()
+        // buckets are not local folders
+        .filter(|destination| !s3::is_s3_url(destination))
+        .map(Path::new)
+        .collect::<Vec<_>>();
+        destinations.sort();
+        destinations.dedup();
+        destinations
+    }
+
+    // `--download-to` is the destination of files, which doubles as the common destination
+    fn select_types(&mut self, types: ResourceTypeFlags, list: bool) {
+        let common_destination = self.download_destination.clone();
+        let mut no_listing = false;
+        select_type(
+            types.contains(ResourceTypeFlags::FILES),
+            list,
+            &common_destination,
+            &mut self.do_files,
+            &mut self.download_destination,
+        );
+        select_type(
+            types.contains(ResourceTypeFlags::MULTIMEDIA),
+            list,
+            &common_destination,
+            &mut self.do_multimedia,
+            &mut self.multimedia_download_destination,
+        );
+        select_type(
+            types.contains(ResourceTypeFlags::WEBLECTURES),
+            list,
+            &common_destination,
+            &mut self.do_weblectures,
+            &mut self.weblectures_download_destination,
+        );
+        select_type(
+            types.contains(ResourceTypeFlags::CONFERENCES),
+            list,
+            &common_destination,
+            &mut self.do_conferences,
+            &mut self.conferences_download_destination,
+        );
+        select_type(
+            types.contains(ResourceTypeFlags::ANNOUNCEMENTS),
+            list,
+            &common_destination,
+            &mut self.do_announcements,
+            &mut self.announcements_download_destination,
+        );
+        select_type(
+            types.contains(ResourceTypeFlags::FORUMS),
+            list,
+            &common_destination,
+            &mut self.do_forums,
+            &mut self.forums_download_destination,
+        );
+        select_type(
+            types.contains(ResourceTypeFlags::QUIZZES),
+            list,
+            &common_destination,
+            &mut self.do_quizzes,
+            &mut self.quizzes_download_destination,
+        );
+        select_type(
+            types.contains(ResourceTypeFlags::WEBLINKS),
+            list,
+            &common_destination,
+            &mut self.do_weblinks,
+            &mut self.weblinks_download_destination,
+        );
+        // these can only be downloaded
+        select_type(
+            types.contains(ResourceTypeFlags::GRADEBOOKS),
+            false,
+            &common_destination,
+            &mut no_listing,
+            &mut self.gradebooks_download_destination,
+        );
+        select_type(
+            types.contains(ResourceTypeFlags::ROSTERS),
+            false,
+            &common_destination,
+            &mut no_listing,
+            &mut self.rosters_download_destination,
+        );
+        select_type(
+            types.contains(ResourceTypeFlags::LESSON_PLANS),
+            false,
+            &common_destination,
+            &mut no_listing,
+            &mut self.lesson_plans_download_destination,
+        );
+    }
+}
+
+// How downloads are carried out, and where their progress is reported to
+#[derive(Default)]
+struct DownloadContext {
+    verify_only: bool,
+    find_duplicates: bool,
+    status: Option<Arc<daemon::SyncStatus>>,
+    change_feed: Option<changes::ChangeFeed>,
+    // failures that the user has to act on, to be listed after the sync
+    attention: attention::Attention,
+    // the number of concurrent downloads, when not left to the defaults
+    jobs: Option<usize>,
+    video_jobs: Option<usize>,
+    // the named session that keeps track of the downloads left to do
+    session: Option<sessions::Session>,
+    // failed downloads to try again at the end of the run, if they are tried again at all
+    retries: Option<retry::RetryQueue>,
+    // how the downloads of each kind of resource went
+    summary: summary::RunSummary,
+    // a record of every download, to be written out at the end of the run
+    report: Option<report::RunReport>,
+    // the longest a path may be, with the folders of longer ones shortened
+    max_path_length: Option<usize>,
+    // the new content of the sync, for the webhooks to be told about
+    digest: Option<notifications::Digest>,
+    // the commands to run once the sync is done, with what it changed
+    hooks: Option<hooks::Hooks>,
+    // the hours during which videos are left for a later sync
+    quiet_hours: Option<schedule::QuietHours>,
+    // the files written in the run, as their destination and path within it, when the duplicates
+    // among them are to be linked
+    written: Option<Mutex<Vec<(PathBuf, PathBuf)>>>,
+}
+
+impl DownloadContext {
+    // Keeps track of the files that the downloads wrote, in the manifest of the destination
+    fn record_written<T: Resource>(
+        &self,
+        dest_path: &Path,
+        results: &[(&T, Result<OverwriteResult>)],
+    ) {
+        if let Err(e) = manifest::record(dest_path, results) {
+            errln!("Failed to update the manifest: {}", e);
+        }
+        if let Some(written) = &self.written {
+            written.lock().unwrap().extend(
+                results
+                    .iter()
+                    .filter(|(_, result)| manifest::was_written(result))
+                    .map(|(resource, _)| (dest_path.to_owned(), resource.path().to_owned())),
+            );
+        }
+    }
+
+    // `default` suits the kind of resource, e.g. fewer for generated ones that load the server
+    fn jobs(&self, default: usize) -> usize {
+        self.jobs.unwrap_or(default)
+    }
+
+    fn video_jobs(&self, default: usize) -> usize {
+        self.video_jobs.unwrap_or(default)
+    }
+
+    async fn notify(&self, config: &notifications::NotificationsConfig) {
+        if let Some(digest) = &self.digest {
+            digest.send(config).await;
+        }
+    }
+
+    async fn run_hooks(&self) {
+        if let Some(hooks) = &self.hooks {
+            hooks.run().await;
+        }
+    }
+
+    fn write_report(&self) {
+        if let Some(report) = &self.report {
+            if let Err(e) = report.write() {
+                errln!("Failed to write the run report: {}", e);
+            }
+        }
+    }
+
+    // Tries the downloads that failed again, now that whatever got in the way may have passed
+    async fn retry_failed(&self) {
+        if let Some(retries) = &self.retries {
+            retries.run(self, self.jobs(4)).await;
+        }
+    }
+
+    // Rounds off a sync, also when it stopped halfway, so that what did download is reported
+    async fn finish_sync(&self, result: Result<()>) -> Result<()> {
+        self.retry_failed().await;
+        if let Some(session) = &self.session {
+            if let Err(e) = session.flush() {
+                errln!("Failed to update the session: {}", e);
+            }
+        }
+        self.summary.report();
+        if let Err(e) = result {
+            self.attention.record_run_error(e);
+        }
+        self.attention.report();
+        result
+    }
+}
+
+// Downloads the resources of the module that are not in the staging folder yet,
+// and packages everything into one zip file
+async fn archive_module(
+    api: &mut Api,
+    module: Module,
+    staging: &str,
+    destination: &Path,
+    context: &DownloadContext,
+) -> Result<()> {
+    let modules = [module];
+    let mut module_archive = archive::ModuleArchive::new(Path::new(staging));
+
+    let files = load_modules_files(
+        api,
+        &modules,
+        ModuleTypeFlags::empty(),
+        false,
+        None,
+        None,
+        None,
+    )
+    .await?;
+    download_resources(
+        api,
+        &files,
+        staging,
+        OverwriteMode::Skip,
+        context.jobs(64),
+        context,
+    )
+    .await?;
+    module_archive.add("file", &files);
+
+    let (internal_videos, external_videos) = load_modules_multimedia(api, &modules, None).await?;
+    download_resources(
+        api,
+        &internal_videos,
+        staging,
+        OverwriteMode::Skip,
+        context.video_jobs(4),
+        context,
+    )
+    .await?;
+    download_resources(
+        api,
+        &external_videos,
+        staging,
+        OverwriteMode::Skip,
+        context.video_jobs(4),
+        context,
+    )
+    .await?;
+    module_archive.add("multimedia", &internal_videos);
+    module_archive.add("multimedia", &external_videos);
+
+    let weblectures = load_modules_weblectures(api, &modules, false, None).await?;
+    download_resources(
+        api,
+        &weblectures,
+        staging,
+        OverwriteMode::Skip,
+        context.video_jobs(4),
+        context,
+    )
+    .await?;
+    module_archive.add("weblecture", &weblectures);
+
+    let conferences = load_modules_conferences(api, &modules, None, None).await?;
+    if !conferences.is_empty() {
+        match api.login_zoom().await {
+            Ok(_) => {
+                download_resources(
+                    api,
+                    &conferences,
+                    staging,
+                    OverwriteMode::Skip,
+                    context.video_jobs(4),
+                    context,
+                )
+                .await?
+            }
+            Err(e) => errln!("Failed to log in to Zoom, leaving out conferences: {}", e),
+        }
+    }
+    module_archive.add("conference", &conferences);
+
+    let announcements = load_modules_announcements(api, &modules, None).await?;
+    download_resources(
+        api,
+        &announcements,
+        staging,
+        OverwriteMode::Skip,
+        context.jobs(64),
+        context,
+    )
+    .await?;
+    module_archive.add("announcement", &announcements);
+    let attachments = announcement_attachments(&announcements);
+    download_resources(
+        api,
+        &attachments,
+        staging,
+        OverwriteMode::Skip,
+        context.jobs(64),
+        context,
+    )
+    .await?;
+    module_archive.add("announcement attachment", &attachments);
+
+    context.retry_failed().await;
+    let count = module_archive.write(&modules[0], destination).await?;
+    context.summary.report();
+    outln!("Archived {} resources to {}", count, destination.display());
+    if context.summary.any_failed() {
+        return Err(summary::SOME_DOWNLOADS_FAILED);
+    }
+    Ok(())
+}
+
+// Writes the announcements, forum threads and lesson plans of the modules as notes under `root`
+async fn export_vault(api: &Api, modules: &[Module], root: &str) -> Result<()> {
+    let mut vault = vault::Vault::new(root);
+    let announcements = load_modules_announcements(api, modules, None).await?;
+    vault.export(api, &announcements, "Announcements").await;
+    let threads = load_modules_forums(api, modules, None).await?;
+    vault.export(api, &threads, "Forum").await;
+    let lesson_plans = load_modules_lesson_plans(api, modules, None).await?;
+    vault.export(api, &lesson_plans, "Lesson plan").await;
+    vault.write_module_notes(modules)
+}
+
+// Summarises what the module has, from its listings and whatever was downloaded to `root`
+async fn print_module_stats(api: &Api, module: Module, root: &Path) -> Result<()> {
+    let modules = [module];
+    let mut stats = stats::ModuleStats::new(&modules[0], root);
+
+    let files = load_modules_files(
+        api,
+        &modules,
+        ModuleTypeFlags::empty(),
+        false,
+        None,
+        None,
+        None,
+    )
+    .await?;
+    stats.add_files(api, &files).await;
+
+    let (internal_videos, external_videos) = load_modules_multimedia(api, &modules, None).await?;
+    stats.add_videos(api, "multimedia", &internal_videos).await;
+    stats
+        .add_videos(api, "external multimedia", &external_videos)
+        .await;
+
+    let weblectures = load_modules_weblectures(api, &modules, false, None).await?;
+    stats.add_weblectures(api, &weblectures).await;
+
+    let conferences = load_modules_conferences(api, &modules, None, None).await?;
+    stats.add_videos(api, "conferences", &conferences).await;
+
+    let announcements = load_modules_announcements(api, &modules, None).await?;
+    stats.add_others("announcements", &announcements);
+
+    stats.print();
+    Ok(())
+}
+
+// Compares the directory with the resources that a sync with the default options would download
+async fn print_diff(api: &Api, modules: &[Module], root: &Path, prune: bool) -> Result<()> {
+    let mut diff = diff::Diff::new(root, modules);
+
+    let files = load_modules_files(
+        api,
+        modules,
+        ModuleTypeFlags::empty(),
+        false,
+        None,
+        None,
+        None,
+    )
+    .await?;
+    diff.add(&files);
+
+    let (internal_videos, external_videos) = load_modules_multimedia(api, modules, None).await?;
+    diff.add(&internal_videos);
+    diff.add(&external_videos);
+
+    let weblectures = load_modules_weblectures(api, modules, false, None).await?;
+    diff.add(&weblectures);
+
+    let conferences = load_modules_conferences(api, modules, None, None).await?;
+    diff.add(&conferences);
+
+    let announcements = load_modules_announcements(api, modules, None).await?;
+    diff.add(&announcements);
+    diff.add(&announcement_attachments(&announcements));
+
+    let forums = load_modules_forums(api, modules, None).await?;
+    diff.add(&forums);
+
+    diff.report();
+    if prune {
+        diff.prune()?;
+    }
+    Ok(())
+}
+
+// The listing and downloading that is done on every sync, which is once per run
+// outside of watch mode
+async fn sync(
+    api: &mut Api,
+    modules: &[Module],
+    options: &SyncOptions,
+    context: &DownloadContext,
+) -> Result<()> {
+    if options.do_announcements {
+        print_announcements(api, modules).await?;
+    }
+
+    if let Some(destination) = &options.announcements_download_destination {
+        let module_announcements =
+            load_modules_announcements(api, modules, options.layout.as_ref()).await?;
+        let module_announcements = filter_resources(module_announcements, &options.resource_filter);
+        let overwrite_mode = options
+            .config
+            .announcements
+            .overwrite_mode(options.overwrite_mode);
+        download_resources(
+            api,
+            &module_announcements,
+            destination,
+            overwrite_mode,
+            context.jobs(64),
+            context,
+        )
+        .await?;
+        download_resources(
+            api,
+            &announcement_attachments(&module_announcements),
+            destination,
+            overwrite_mode,
+            context.jobs(64),
+            context,
+        )
+        .await?;
+    }
+
+    if options.do_files || options.download_destination.is_some() {
+        let module_file = load_modules_files(
+            api,
+            modules,
+            options.include_uploadable_folders,
+            options.regularize_uploadable,
+            options.index_prefix,
+            options.flatten,
+            options.layout.as_ref(),
+        )
+        .await?;
+        // publishing compares against everything on the server, not just what is synced
+        let all_files = if options.publish_folders.is_empty() {
+            vec![]
+        } else {
+            module_file.clone()
+        };
+        let module_file = filter_resources(module_file, &options.resource_filter);
+
+        if options.do_files {
+            if options.long_listing {
+                list_resources_long(api, &module_file, options.tree_listing).await;
+            } else {
+                list_resources(&module_file, options.tree_listing);
+            }
+        }
+
+        if let Some(destination) = &options.download_destination {
+            let dest_path = Path::new(destination);
+            if !context.verify_only && !s3::is_s3_url(destination) && dest_path.is_dir() {
+                match manifest::follow_folder_renames(dest_path, &module_file) {
+                    Ok(moved) => {
+                        if let Some(change_feed) = &context.change_feed {
+                            if let Err(e) = change_feed.record_folder_renames(dest_path, &moved) {
+                                errln!("Failed to update the change feed: {}", e);
+                            }
+                        }
+                    }
+                    Err(e) => errln!("Failed to follow renamed folders: {}", e),
+                }
+            }
+            download_resources(
+                api,
+                &module_file,
+                destination,
+                options.config.files.overwrite_mode(options.overwrite_mode),
+                context.jobs(64),
+                context,
+            )
+            .await?;
+            // after downloading, so that files which were just updated from the server are not uploaded again
+            if !options.publish_folders.is_empty()
+                && !context.verify_only
+                && !s3::is_s3_url(destination)
+            {
+                publish::publish(
+                    api,
+                    modules,
+                    &all_files,
+                    Path::new(destination),
+                    &options.publish_folders,
+                )
+                .await?;
+            }
+        }
+    }
+
+    if options.do_multimedia || options.multimedia_download_destination.is_some() {
+        let (module_internal_multimedia, module_external_multimedia) =
+            load_modules_multimedia(api, modules, options.layout.as_ref()).await?;
+        let module_internal_multimedia =
+            filter_resources(module_internal_multimedia, &options.resource_filter);
+        let module_external_multimedia =
+            filter_resources(module_external_multimedia, &options.resource_filter);
+
+        if options.do_multimedia {
+            list_resources(&module_internal_multimedia, options.tree_listing);
+            list_resources(&module_external_multimedia, options.tree_listing);
+        }
+
+        if let Some(destination) = &options.multimedia_download_destination {
+            // We download internal and external multimedia separately
+            // because we don't want the download slots to be shared between them
+            // (since internal multimedia is from LumiNUS but external multimedia is from Panopto)
+            let (internal_result, external_result) = future::join(
+                download_resources(
+                    api,
+                    &module_internal_multimedia,
+                    destination,
+                    options
+                        .config
+                        .multimedia
+                        .overwrite_mode(options.overwrite_mode),
+                    context.video_jobs(4),
+                    context,
+                ),
+                download_resources(
+                    api,
+                    &module_external_multimedia,
+                    destination,
+                    options
+                        .config
+                        .multimedia
+                        .overwrite_mode(options.overwrite_mode),
+                    context.video_jobs(4),
+                    context,
+                ),
+            )
+            .await;
+            internal_result?;
+            external_result?;
+        }
+    }
+
+    if options.do_weblectures || options.weblectures_download_destination.is_some() {
+        let module_weblectures = load_modules_weblectures(
+            api,
+            modules,
+            options.weblecture_date_prefix,
+            options.layout.as_ref(),
+        )
+        .await?;
+        let module_weblectures = filter_resources(module_weblectures, &options.resource_filter);
+
+        if options.do_weblectures {
+            if options.long_listing {
+                list_weblectures_long(api, &module_weblectures).await;
+            } else {
+                list_resources(&module_weblectures, options.tree_listing);
+            }
+        }
+
+        if let Some(destination) = &options.weblectures_download_destination {
+            download_resources(
+                api,
+                &module_weblectures,
+                destination,
+                options
+                    .config
+                    .weblectures
+                    .overwrite_mode(options.overwrite_mode),
+                context.video_jobs(4),
+                context,
+            )
+            .await?;
+        }
+    }
+
+    if options.do_conferences || options.conferences_download_destination.is_some() {
+        // everything has to be listed to tell what is gone, or to check what is there
+        let needs_all = options.do_conferences
+            || context.verify_only
+            || context.find_duplicates
+            || context
+                .change_feed
+                .as_ref()
+                .is_some_and(|feed| feed.detects_removals());
+        let downloaded_to = match &options.conferences_download_destination {
+            Some(destination) if !needs_all => Some(Path::new(destination)),
+            _ => None,
+        };
+        let module_conferences =
+            load_modules_conferences(api, modules, downloaded_to, options.layout.as_ref()).await?;
+        let module_conferences = filter_resources(module_conferences, &options.resource_filter)
+            .into_iter()
+            .map(|conference| {
+                conference
+                    .with_assets(options.conference_assets)
+                    .with_views(options.conference_views)
+            })
+            .collect::<Vec<_>>();
+
+        if options.do_conferences {
+            list_resources(&module_conferences, options.tree_listing);
+        }
+
+        if let Some(destination) = &options.conferences_download_destination {
+            if !module_conferences.is_empty() {
+                match api.login_zoom().await {
+                    Err(e) => {
+                        errln!("Failed to log in to Zoom: {}", e);
+                    }
+                    Ok(_) => {
+                        outln!("Logged in to Zoom");
+                        download_resources(
+                            api,
+                            &module_conferences,
+                            destination,
+                            options
+                                .config
+                                .conferences
+                                .overwrite_mode(options.overwrite_mode),
+                            context.video_jobs(4),
+                            context,
+                        )
+                        .await?;
+                    }
+                }
+            }
+        }
+    }
+
+    if options.do_forums || options.forums_download_destination.is_some() {
+        let module_forums = load_modules_forums(api, modules, options.layout.as_ref()).await?;
+        let module_forums = filter_resources(module_forums, &options.resource_filter);
+
+        if options.do_forums {
+            list_resources(&module_forums, options.tree_listing);
+        }
+
+        if let Some(destination) = &options.forums_download_destination {
+            download_resources(
+                api,
+                &module_forums,
+                destination,
+                options.config.forums.overwrite_mode(options.overwrite_mode),
+                context.jobs(16),
+                context,
+            )
+            .await?;
+        }
+    }
+
+    if options.do_quizzes || options.quizzes_download_destination.is_some() {
+        let module_quizzes = load_modules_quizzes(api, modules, options.layout.as_ref()).await?;
+        let module_quizzes = filter_resources(module_quizzes, &options.resource_filter);
+
+        if options.do_quizzes {
+            list_resources(&module_quizzes, options.tree_listing);
+        }
+
+        if let Some(destination) = &options.quizzes_download_destination {
+            download_resources(
+                api,
+                &module_quizzes,
+                destination,
+                options
+                    .config
+                    .quizzes
+                    .overwrite_mode(options.overwrite_mode),
+                context.jobs(16),
+                context,
+            )
+            .await?;
+        }
+    }
+
+    if let Some(destination) = &options.gradebooks_download_destination {
+        let module_gradebooks = load_modules_gradebooks(
+            api,
+            modules,
+            options.full_class_gradebook,
+            options.layout.as_ref(),
+        )
+        .await?;
+        let module_gradebooks = filter_extensions(module_gradebooks, &options.resource_filter);
+        download_resources(
+            api,
+            &module_gradebooks,
+            destination,
+            options
+                .config
+                .gradebooks
+                .overwrite_mode(options.overwrite_mode),
+            context.jobs(16),
+            context,
+        )
+        .await?;
+    }
+
+    if let Some(destination) = &options.calendar_destination {
+        export_calendar(api, modules, destination).await?;
+    }
+
+    if let Some(destination) = &options.rosters_download_destination {
+        let module_rosters = load_modules_rosters(api, modules, options.layout.as_ref()).await?;
+        let module_rosters = filter_extensions(module_rosters, &options.resource_filter);
+        download_resources(
+            api,
+            &module_rosters,
+            destination,
+            options
+                .config
+                .rosters
+                .overwrite_mode(options.overwrite_mode),
+            context.jobs(16),
+            context,
+        )
+        .await?;
+    }
+
+    if options.do_weblinks || options.weblinks_download_destination.is_some() {
+        let module_weblinks = load_modules_weblinks(api, modules, options.layout.as_ref()).await?;
+        let module_weblinks = filter_resources(module_weblinks, &options.resource_filter);
+
+        if options.do_weblinks {
+            for weblink in &module_weblinks {
+                outln!("{}\t{}", weblink.path().display(), weblink.url());
+            }
+        }
+
+        if let Some(destination) = &options.weblinks_download_destination {
+            download_resources(
+                api,
+                &module_weblinks,
+                destination,
+                options
+                    .config
+                    .weblinks
+                    .overwrite_mode(options.overwrite_mode),
+                context.jobs(64),
+                context,
+            )
+            .await?;
+        }
+    }
+
+    if let Some(destination) = &options.lesson_plans_download_destination {
+        let module_lesson_plans =
+            load_modules_lesson_plans(api, modules, options.layout.as_ref()).await?;
+        let module_lesson_plans = filter_extensions(module_lesson_plans, &options.resource_filter);
+        download_resources(
+            api,
+            &module_lesson_plans,
+            destination,
+            options
+                .config
+                .lesson_plans
+                .overwrite_mode(options.overwrite_mode),
+            context.jobs(16),
+            context,
+        )
+        .await?;
+    }
+
+    if let Some(subscriptions) = &options.resource_filter.subscriptions {
+        let flagged = subscriptions.finish_sync()?;
+        if !flagged.is_empty() {
+            outln!("New folders outside your subscriptions were synced, and will be until you subscribe to or unsubscribe from them:");
+            for entry in &flagged {
+                outln!("- {}", entry);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let writer = start_output(Box::new(PlainRenderer::new()));
+    let result = run().await;
+    finish_output(writer);
+    result
+}
+
+async fn run() -> Result<()> {
+    let authors = format!("{} and contributors", env!("CARGO_PKG_AUTHORS"));
+    let app = App::new(PKG_NAME)
+        .version(VERSION)
+        .author(&*authors)
+        .about(DESCRIPTION)
+        .arg(Arg::with_name("announcements").long("announcements"))
+        .arg(
+            Arg::with_name("download-announcements")
+                .long("download-announcements-to")
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("files").long("files"))
+        .arg(
+            Arg::with_name("download")
+                .long("download-to")
+                .takes_value(true)
+                .help("Download to this folder, or to an S3 bucket given as s3://bucket/prefix"),
+        )
+        .arg(Arg::with_name("list-multimedia").long("list-multimedia"))
+        .arg(
+            Arg::with_name("download-multimedia")
+                .long("download-multimedia-to")
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("list-weblectures").long("list-weblectures"))
+        .arg(
+            Arg::with_name("long")
+                .long("long")
+                .help("Show duration and estimated size when listing web lectures, and ask the server for the sizes of files that the listing leaves out (slower)"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .takes_value(true)
+                .value_name("format")
+                .possible_values(&["flat", "tree"])
+                .default_value("flat")
+                .help("List paths one per line (flat), or as a tree of folders with the number of items and total size under each (tree)"),
+        )
+        .arg(
+            Arg::with_name("download-weblectures")
+                .long("download-weblectures-to")
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("list-conferences").long("list-conferences"))
+        .arg(
+            Arg::with_name("download-conferences")
+                .long("download-conferences-to")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("conference-assets")
+                .long("conference-assets")
+                .help("Also download the chat logs and transcripts of Zoom recordings"),
+        )
+        .arg(
+            Arg::with_name("conference-views")
+                .long("conference-views")
+                .help("Also download the other views of Zoom recordings that have them, like the gallery or the shared screen"),
+        )
+        .arg(Arg::with_name("list-forums").long("list-forums"))
+        .arg(
+            Arg::with_name("download-forums")
+                .long("download-forums-to")
+                .takes_value(true),
+        )
+        .arg(Arg::with_name("list-quizzes").long("list-quizzes"))
+        .arg(
+            Arg::with_name("download-quizzes")
+                .long("download-quizzes-to")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("download-gradebooks")
+                .long("download-gradebooks-to")
+                .takes_value(true)
+                .help("Export the gradebook of each module to CSV"),
+        )
+        .arg(
+            Arg::with_name("full-class-gradebook")
+                .long("full-class-gradebook")
+                .help("Export the marks of the whole class for modules you are teaching"),
+        )
+        .arg(
+            Arg::with_name("download-rosters")
+                .long("download-rosters-to")
+                .takes_value(true)
+                .help("Export the class roster and groups of each module you teach to CSV"),
+        )
+        .arg(Arg::with_name("list-weblinks").long("list-weblinks"))
+        .arg(
+            Arg::with_name("download-weblinks")
+                .long("download-weblinks-to")
+                .takes_value(true)
+                .help("Save weblinks as .url shortcut files"),
+        )
+        .arg(
+            Arg::with_name("download-lesson-plans")
+                .long("download-lesson-plans-to")
+                .takes_value(true)
+                .help("Export the lesson plan of each module to Markdown"),
+        )
+        .arg(
+            Arg::with_name("stdout")
+                .long("stdout")
+                .takes_value(true)
+                .value_name("path-or-id")
+                .number_of_values(1)
+                .help("Write a single resource (by its listed path or id) to stdout, e.g. to pipe into a player"),
+        )
+        .arg(
+            Arg::with_name("credential-file")
+                .long("credential-file")
+                .takes_value(true)
+                .help("File to store the credentials in (default: login.json). FLUMINURS_USERNAME and FLUMINURS_PASSWORD, or a FLUMINURS_TOKEN, in the environment are used instead if set"),
+        )
+        .arg(
+            Arg::with_name("include-uploadable")
+                .long("include-uploadable-folders")
+                .takes_value(true)
+                .min_values(0)
+                .max_values(u64::MAX)
+                .possible_values(&["taking", "teaching", "all"]),
+        )
+        .arg(Arg::with_name("regularize-uploadable").long("regularize-uploadable-files"))
+        .arg(
+            Arg::with_name("publish")
+                .long("publish")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("MODULE/FOLDER")
+                .requires("download")
+                .help("For modules you teach, upload files that are new or changed in this folder of the download destination to the workbin"),
+        )
+        .arg(
+            Arg::with_name("types")
+                .long("types")
+                .takes_value(true)
+                .use_delimiter(true)
+                .min_values(1)
+                .possible_values(&[
+                    "files",
+                    "multimedia",
+                    "weblectures",
+                    "conferences",
+                    "announcements",
+                    "forums",
+                    "quizzes",
+                    "weblinks",
+                    "gradebooks",
+                    "rosters",
+                    "lesson-plans",
+                ])
+                .help("Only process these types of resources, downloading them to the folder given by --download-to unless they have a folder of their own"),
+        )
+        .arg(
+            Arg::with_name("list")
+                .long("list")
+                .requires("types")
+                .help("List the resources of the types given by --types"),
+        )
+        .arg(
+            Arg::with_name("flatten")
+                .long("flatten")
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1)
+                .value_name("naming")
+                .possible_values(&["prefix", "names"])
+                .help("Put all files of a module in one folder, named e.g. \"Tutorials - Week 1 - Sheet.pdf\" (prefix, the default) or \"Sheet.pdf\" (names)"),
+        )
+        .arg(
+            Arg::with_name("layout")
+                .long("layout")
+                .takes_value(true)
+                .value_name("template")
+                .help("Where to save resources, e.g. \"{term}/{module}/{type}/{path}\" or \"{module} - {name}\", with {term}, {module}, {course}, {type}, {dir}, {name} and {path}"),
+        )
+        .arg(
+            Arg::with_name("index-prefix")
+                .long("index-prefix")
+                .takes_value(true)
+                .value_name("order")
+                .possible_values(&["server", "upload-date"])
+                .help("Prefix files with their position in the folder, e.g. \"03 - Recurrences.pdf\""),
+        )
+        .arg(
+            Arg::with_name("updated")
+                .long("updated")
+                .takes_value(true)
+                .value_name("action-on-updated-files")
+                .possible_values(&["skip", "overwrite", "rename", "versioned"])
+                .number_of_values(1)
+                .default_value("skip")
+                .help("What to do with files that were updated on the server, unless the config file says otherwise for that type of resource"),
+        )
+        .arg(
+            Arg::with_name("keep-versions")
+                .long("keep-versions")
+                .takes_value(true)
+                .value_name("N")
+                .help("With --updated versioned, how many old versions of each file to keep in .versions (default: 3)"),
+        )
+        .arg(
+            Arg::with_name("trash-dir")
+                .long("trash-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("Move files that are overwritten, or old versions that are pruned, into a dated folder in DIR instead of deleting them"),
+        )
+        .arg(
+            Arg::with_name("verify-only-remote")
+                .long("verify-only-remote")
+                .help("Check the local copies against the server instead of downloading"),
+        )
+        .arg(
+            Arg::with_name("find-duplicates")
+                .long("find-duplicates")
+                .requires("verify-only-remote")
+                .help("When verifying, also report local files with identical contents"),
+        )
+        .arg(
+            Arg::with_name("link-duplicates")
+                .long("link-duplicates")
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1)
+                .value_name("kind")
+                .possible_values(&["hard", "symbolic"])
+                .conflicts_with("verify-only-remote")
+                .help("After downloading, replace the downloads with identical contents by links to one of them (hard links unless symbolic is given), recorded in the manifest"),
+        )
+        .arg(
+            Arg::with_name("weblecture-date-prefix")
+                .long("weblecture-date-prefix")
+                .help("Prefix web lecture names with the date they were last updated, e.g. 2023-02-14 - Lecture.mp4"),
+        )
+        .arg(
+            Arg::with_name("normalise-filenames")
+                .long("normalise-filenames")
+                .takes_value(true)
+                .value_name("form")
+                .possible_values(&["none", "nfc", "ascii"])
+                .default_value("none")
+                .help("Normalise accented and other non-ASCII names to NFC, or to plain ASCII, so that they match across platforms"),
+        )
+        .arg(
+            Arg::with_name("video-container")
+                .long("video-container")
+                .takes_value(true)
+                .value_name("container")
+                .possible_values(&["mp4", "mkv"])
+                .default_value("mp4")
+                .help("Container to save videos in, for streams that do not remux into mp4 cleanly"),
+        )
+        .arg(
+            Arg::with_name("video-quality")
+                .long("video-quality")
+                .takes_value(true)
+                .value_name("quality")
+                .default_value("best")
+                .help("Variant of streamed videos to download: best, worst, audio, or the best up to a height such as 720p"),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .long("jobs")
+                .takes_value(true)
+                .value_name("N")
+                .help("Number of files and other resources to download at once (default: 64 for files, 16 for generated resources)"),
+        )
+        .arg(
+            Arg::with_name("video-jobs")
+                .long("video-jobs")
+                .takes_value(true)
+                .value_name("N")
+                .help("Number of videos to download at once (default: 4)"),
+        )
+        .arg(
+            Arg::with_name("max-path-length")
+                .long("max-path-length")
+                .takes_value(true)
+                .value_name("N")
+                .help("Shorten the folders of paths longer than N characters, listing the original names in shortened-paths.json, e.g. 259 for Windows programs that are not aware of long paths (default: no limit)"),
+        )
+        .arg(
+            Arg::with_name("max-connections")
+                .long("max-connections")
+                .takes_value(true)
+                .value_name("N")
+                .help("Number of downloads of any kind that may run at once (default: 32)"),
+        )
+        .arg(
+            Arg::with_name("max-requests-per-second")
+                .long("max-requests-per-second")
+                .takes_value(true)
+                .value_name("N")
+                .help("Number of calls to the LumiNUS API that may be made per second, or 0 for no limit (default: 20)"),
+        )
+        .arg(
+            Arg::with_name("max-connections-per-host")
+                .long("max-connections-per-host")
+                .takes_value(true)
+                .value_name("N")
+                .help("Number of downloads from each of LumiNUS, Panopto and Zoom that may run at once (default: 16)"),
+        )
+        .arg(
+            Arg::with_name("download-proxy")
+                .long("download-proxy")
+                .takes_value(true)
+                .value_name("URL")
+                .help("Fetch files through the cache-proxy subcommand running at this URL, e.g. on another device on the LAN"),
+        )
+        .arg(
+            Arg::with_name("low-memory")
+                .long("low-memory")
+                .help("Use less memory at the cost of speed, e.g. on a Raspberry Pi: fewer concurrent downloads, smaller buffers, a lower --max-response-size, and downloading streams without ffmpeg where possible"),
+        )
+        .arg(
+            Arg::with_name("max-response-size")
+                .long("max-response-size")
+                .takes_value(true)
+                .value_name("MB")
+                .help("Largest API response to accept, to keep memory bounded on small devices (default: 64, or 16 with --low-memory, 0 for no limit)"),
+        )
+        .arg(
+            Arg::with_name("panopto-podcast")
+                .long("panopto-podcast")
+                .help("Download the pre-rendered podcast of Panopto sessions where available, instead of muxing all streams"),
+        )
+        .arg(
+            Arg::with_name("announcement-html")
+                .long("announcement-html")
+                .help("Also save announcements as sanitised HTML, with the images they embed downloaded next to them"),
+        )
+        .arg(
+            Arg::with_name("change-feed")
+                .long("change-feed")
+                .help("Append a record of each added, updated, renamed or removed resource to changes.jsonl in the download destination, and move renamed files instead of downloading them again"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .help("Log more of what is going on to stderr; give it twice to include requests and retries"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .conflicts_with("verbose")
+                .help("Print only warnings and errors"),
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .long("log-file")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Append a detailed debug log, with the URLs requested, their statuses and any retries, to this file"),
+        )
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write a JSON report of every resource processed, with what was done, its size, how long it took and any error"),
+        )
+        .arg(
+            Arg::with_name("post-sync-hook")
+                .long("post-sync-hook")
+                .takes_value(true)
+                .value_name("CMD")
+                .help("Run this shell command after a sync that downloaded or updated anything, with what changed in FLUMINURS_* environment variables"),
+        )
+        .arg(
+            Arg::with_name("session")
+                .long("session")
+                .takes_value(true)
+                .value_name("name")
+                .help("Name this run, so that if it is interrupted, `resume <name>` continues where it stopped. In watch mode, the queue is kept across restarts of the daemon instead."),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .takes_value(true)
+                .value_name("MINUTES")
+                .conflicts_with("stdout")
+                .help("Keep running, and sync again after this many minutes"),
+        )
+        .arg(
+            Arg::with_name("schedule")
+                .long("schedule")
+                .takes_value(true)
+                .min_values(0)
+                .value_name("CRON")
+                .conflicts_with_all(&["watch", "stdout"])
+                .help("Keep running, and sync at the times of this cron expression (minute hour day month weekday), or every night at 3am without one"),
+        )
+        .group(ArgGroup::with_name("daemon").args(&["watch", "schedule"]))
+        .arg(
+            Arg::with_name("quiet-hours")
+                .long("quiet-hours")
+                .takes_value(true)
+                .value_name("HH:MM-HH:MM")
+                .help("Leave videos, the heaviest downloads, for a sync outside these hours, e.g. 09:00-18:00"),
+        )
+        .arg(
+            Arg::with_name("add-new-modules")
+                .long("add-new-modules")
+                .requires("daemon")
+                .help("In watch mode, also sync modules that appear on the account later, such as those of a new term"),
+        )
+        .arg(
+            Arg::with_name("serve")
+                .long("serve")
+                .takes_value(true)
+                .value_name("ADDRESS:PORT")
+                .requires("daemon")
+                .help("In watch mode, serve a status dashboard on this address, or on :PORT for this machine only, where POST /sync triggers a sync and /metrics has metrics for Prometheus. Triggering a sync takes the token that is shown at the start, or FLUMINURS_SERVE_TOKEN in the environment if set"),
+        )
+        .arg(
+            Arg::with_name("ca-cert")
+                .long("ca-cert")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("PEM file of extra root or intermediate certificates to trust, for when NUS changes its certificate chain"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Config file with per-resource-type settings (default: config.json, if it exists)"),
+        )
+        .arg(
+            Arg::with_name("term")
+                .long("term")
+                .takes_value(true)
+                .value_name("term")
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("modules")
+                .long("modules")
+                .takes_value(true)
+                .value_name("modules")
+                .min_values(1)
+                .max_values(u64::MAX),
+        )
+        .arg(
+            Arg::with_name("ffmpeg")
+                .long("ffmpeg")
+                .takes_value(true)
+                .value_name("ffmpeg-path")
+                .number_of_values(1)
+                .default_value("ffmpeg")
+                .help("Path to ffmpeg executable for downloading multimedia"),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Glob of file paths to exclude"),
+        )
+        .arg(
+            Arg::with_name("include")
+                .long("include")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Glob of file paths to include. Takes precedence over exclude"),
+        )
+        .arg(
+            Arg::with_name("only-ext")
+                .long("only-ext")
+                .takes_value(true)
+                .use_delimiter(true)
+                .min_values(1)
+                .value_name("extensions")
+                .help("Only download resources with these extensions, e.g. pdf,pptx,docx"),
+        )
+        .arg(
+            Arg::with_name("skip-ext")
+                .long("skip-ext")
+                .takes_value(true)
+                .use_delimiter(true)
+                .min_values(1)
+                .value_name("extensions")
+                .help("Skip resources with these extensions, e.g. mp4"),
+        )
+        .arg(
+            Arg::with_name("since")
+                .long("since")
+                .takes_value(true)
+                .value_name("YYYY-MM-DD")
+                .number_of_values(1)
+                .help("Only include resources last updated on or after this date"),
+        )
+        .arg(
+            Arg::with_name("until")
+                .long("until")
+                .takes_value(true)
+                .value_name("YYYY-MM-DD")
+                .number_of_values(1)
+                .help("Only include resources last updated on or before this date"),
+        )
+        .arg(
+            Arg::with_name("export-calendar")
+                .long("export-calendar")
+                .takes_value(true)
+                .value_name("FILE.ics")
+                .help("Export upcoming Zoom meetings and web lectures to an iCalendar file"),
+        )
+        .arg(
+            Arg::with_name("subscriptions")
+                .long("subscriptions")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("File to keep the subscribed folders in (default: subscriptions.json)"),
+        )
+        .arg(
+            Arg::with_name("ignore-subscriptions")
+                .long("ignore-subscriptions")
+                .help("Sync everything, instead of only the subscribed folders"),
+        )
+        .arg(
+            Arg::with_name("cookie-jar")
+                .long("cookie-jar")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("File to keep the login sessions in between runs, readable only by you (default: sessions last for the run only)"),
+        )
+        .arg(
+            Arg::with_name("encrypt-cookie-jar")
+                .long("encrypt-cookie-jar")
+                .requires("cookie-jar")
+                .help("Encrypt the cookie jar with a passphrase"),
+        )
+        .arg(
+            Arg::with_name("encrypt-credentials")
+                .long("encrypt-credentials")
+                .help("Encrypt a credential file that was stored in plain text with a passphrase"),
+        )
+        .arg(
+            Arg::with_name("listing-cache")
+                .long("listing-cache")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("File to keep folder listings in for a few minutes between runs, so that a run soon after another one reuses them (default: listings are always fetched)"),
+        )
+        .arg(
+            Arg::with_name("refresh")
+                .long("refresh")
+                .help("Fetch all folder listings again, instead of reusing the ones in the --listing-cache"),
+        )
+        .arg(
+            Arg::with_name("import-cookies-from")
+                .long("import-cookies-from")
+                .takes_value(true)
+                .alias("cookies-from-browser")
+                .possible_values(&["firefox", "chrome"])
+                .help("Reuse the NUS, Zoom and Panopto sessions of a browser when logging in"),
+        )
+        .arg(
+            Arg::with_name("import-cookies-file")
+                .long("import-cookies-file")
+                .alias("cookies")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Reuse the NUS, Zoom and Panopto sessions in a cookie file exported from a browser (Netscape format, as written by curl and yt-dlp) when logging in"),
+        )
+        .subcommand(
+            SubCommand::with_name("grab")
+                .about("Download a resource from a LumiNUS file, Panopto or Zoom link")
+                .arg(Arg::with_name("link").required(true).value_name("link"))
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .value_name("dir")
+                        .default_value(".")
+                        .help("Directory to download to"),
+                )
+                .arg(
+                    Arg::with_name("password")
+                        .long("password")
+                        .takes_value(true)
+                        .help("Password of the Zoom recording, if any"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("subscribe")
+                .about("Only sync these folders or channels from now on, or list the subscriptions if none are given")
+                .arg(
+                    Arg::with_name("paths")
+                        .multiple(true)
+                        .value_name("MODULE/FOLDER"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("unsubscribe")
+                .about("Stop syncing these folders or channels")
+                .arg(
+                    Arg::with_name("paths")
+                        .multiple(true)
+                        .required(true)
+                        .value_name("MODULE/FOLDER"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("archive")
+                .about("Package the files, multimedia, web lectures, conferences and announcements of a module into one zip file")
+                .arg(
+                    Arg::with_name("module")
+                        .long("module")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("code"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("FILE.zip"),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .value_name("dir")
+                        .default_value(".")
+                        .help("Directory to download to first, where already downloaded resources are reused"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Summarise what a module has: files by type, total size, web lecture hours, announcements and the last activity")
+                .arg(
+                    Arg::with_name("module")
+                        .long("module")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("code"),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .value_name("dir")
+                        .default_value(".")
+                        .help("Directory the module was downloaded to, whose copies give the sizes without asking the server"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compare a directory with LumiNUS without downloading anything: what is new or updated on LumiNUS, only in the directory, or changed in both")
+                .arg(
+                    Arg::with_name("dir")
+                        .long("dir")
+                        .takes_value(true)
+                        .value_name("dir")
+                        .default_value(".")
+                        .help("Directory that the modules were downloaded to"),
+                )
+                .arg(
+                    Arg::with_name("prune")
+                        .long("prune")
+                        .help("Remove the files that fluminurs downloaded but are no longer on LumiNUS. Files that it did not download are never removed."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("interactive")
+                .about("Pick the modules, folders and files to download from a list, instead of with flags")
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .takes_value(true)
+                        .value_name("dir")
+                        .default_value(".")
+                        .help("Directory to download to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("season-end")
+                .about("Archive the modules of a finished term that are no longer accessible, with prompts before each step")
+                .arg(
+                    Arg::with_name("dir")
+                        .long("dir")
+                        .takes_value(true)
+                        .value_name("dir")
+                        .default_value(".")
+                        .help("Directory that the modules were downloaded to"),
+                )
+                .arg(
+                    Arg::with_name("archive")
+                        .long("archive")
+                        .takes_value(true)
+                        .possible_values(&["zip", "snapshot"])
+                        .default_value("zip")
+                        .help("Whether to put the modules into a zip file, or move them into a folder for the term"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cache-proxy")
+                .about("Serve a cache of LumiNUS downloads to fluminurs on other devices (see --download-proxy), fetching each file only once. Only use it on trusted networks.")
+                .arg(
+                    Arg::with_name("listen")
+                        .long("listen")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("ADDRESS:PORT"),
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .long("dir")
+                        .takes_value(true)
+                        .value_name("dir")
+                        .default_value("cache")
+                        .help("Directory to keep the cached files in"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serve the files of the modules over WebDAV, to browse them as a network drive without syncing. Files are fetched from LumiNUS as they are read.")
+                .arg(
+                    Arg::with_name("webdav")
+                        .long("webdav")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("ADDRESS:PORT")
+                        .help("Address to serve on, or :PORT for this machine only. Anyone who can reach it can read the files of the account."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("vault")
+                .about("Export the announcements, forum threads and lesson plans of the modules as Markdown notes with YAML front matter, for Obsidian or Logseq")
+                .arg(
+                    Arg::with_name("dir")
+                        .required(true)
+                        .value_name("dir")
+                        .help("Vault to export to, where notes are only written again once they are updated on LumiNUS"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("clean")
+                .about("Remove the temporary files that crashed or interrupted runs left behind, once they are an hour old")
+                .arg(
+                    Arg::with_name("dirs")
+                        .multiple(true)
+                        .value_name("dir")
+                        .default_value(".")
+                        .help("Directories to look through, including their subdirectories"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("resume")
+                .about("Continue an interrupted --session where it stopped")
+                .arg(Arg::with_name("name").required(true).value_name("session")),
+        );
+    #[cfg(all(feature = "fuse", unix))]
+    let app = app.subcommand(
+        SubCommand::with_name("mount")
+            .about("Mount the files of the modules as a read-only filesystem, fetching files from LumiNUS as they are read")
+            .arg(
+                Arg::with_name("mountpoint")
+                    .required(true)
+                    .value_name("dir"),
+            )
+            .arg(
+                Arg::with_name("cache-dir")
+                    .long("cache-dir")
+                    .takes_value(true)
+                    .value_name("dir")
+                    .default_value("mount-cache")
+                    .help("Directory to keep the parts of files that were read in"),
+            ),
+    );
+    let matches = app.clone().get_matches();
+    // resuming runs the command line of the session again
+    let (matches, resumed_session) = match matches.subcommand_matches("resume") {
+        Some(resume_matches) => {
+            let name = resume_matches
+                .value_of("name")
+                .expect("Session name is required")
+                .to_owned();
+            let args = sessions::Session::recorded_args(&name)?;
+            let matches = app.get_matches_from(std::iter::once(PKG_NAME.to_owned()).chain(args));
+            (matches, Some(name))
+        }
+        None => (matches, None),
+    };
+    let verbosity = if matches.is_present("quiet") {
+        QUIET.store(true, Ordering::Relaxed);
+        -1
+    } else {
+        matches.occurrences_of("verbose") as i64
+    };
+    logging::init(verbosity, matches.value_of("log-file"))?;
+    let credential_file = matches
+        .value_of("credential-file")
+        .unwrap_or("login.json")
+        .to_owned();
+    if matches.is_present("encrypt-credentials") {
+        encrypt_credential_file(&credential_file)?;
+    }
+    let subscriptions_file = matches
+        .value_of("subscriptions")
+        .unwrap_or("subscriptions.json")
+        .to_owned();
+    let listing_cache = matches
+        .value_of("listing-cache")
+        .map(|path| Arc::new(ListingCache::load(path, LISTING_CACHE_TTL)));
+    if let (Some(listing_cache), true) = (&listing_cache, matches.is_present("refresh")) {
+        listing_cache.clear();
+    }
+    let do_announcements = matches.is_present("announcements");
+    let announcements_download_destination = matches
+        .value_of("download-announcements")
+        .map(|s| s.to_owned());
+    let do_files = matches.is_present("files");
+    let download_destination = matches.value_of("download").map(|s| s.to_owned());
+    let do_multimedia = matches.is_present("list-multimedia");
+    let multimedia_download_destination = matches
+        .value_of("download-multimedia")
+        .map(|s| s.to_owned());
+    let do_weblectures = matches.is_present("list-weblectures");
+    let long_listing = matches.is_present("long");
+    let tree_listing = matches.value_of("output") == Some("tree");
+    let weblectures_download_destination = matches
+        .value_of("download-weblectures")
+        .map(|s| s.to_owned());
+    let do_conferences = matches.is_present("list-conferences");
+    let conference_assets = matches.is_present("conference-assets");
+    let conference_views = matches.is_present("conference-views");
+    let conferences_download_destination = matches
+        .value_of("download-conferences")
+        .map(|s| s.to_owned());
+    let stdout_target = matches.value_of("stdout").map(|s| s.to_owned());
+    let do_forums = matches.is_present("list-forums");
+    let forums_download_destination = matches.value_of("download-forums").map(|s| s.to_owned());
+    let do_quizzes = matches.is_present("list-quizzes");
+    let quizzes_download_destination = matches.value_of("download-quizzes").map(|s| s.to_owned());
+    let gradebooks_download_destination = matches
+        .value_of("download-gradebooks")
+        .map(|s| s.to_owned());
+    let full_class_gradebook = matches.is_present("full-class-gradebook");
+    let calendar_destination = matches.value_of("export-calendar").map(|s| s.to_owned());
+    let rosters_download_destination = matches.value_of("download-rosters").map(|s| s.to_owned());
+    let do_weblinks = matches.is_present("list-weblinks");
+    let weblinks_download_destination = matches.value_of("download-weblinks").map(|s| s.to_owned());
+    let lesson_plans_download_destination = matches
+        .value_of("download-lesson-plans")
+        .map(|s| s.to_owned());
+    let include_uploadable_folders = matches
+        .values_of("include-uploadable")
+        .map(|mut values| {
+            let include_flags = values
+                .try_fold(ModuleTypeFlags::empty(), |flag, s| {
+                    match s.to_lowercase().as_str() {
+                        "taking" => Ok(flag | ModuleTypeFlags::TAKING),
+                        "teaching" => Ok(flag | ModuleTypeFlags::TEACHING),
+                        "all" => Ok(flag | ModuleTypeFlags::all()),
+                        _ => Err("Invalid module type"),
+                    }
+                })
+                .expect("Unable to parse parameters of include-uploadable");
+            if include_flags.is_empty() {
+                ModuleTypeFlags::all()
+            } else {
+                include_flags
+            }
+        })
+        .unwrap_or_else(ModuleTypeFlags::empty);
+    let regularize_uploadable = matches.is_present("regularize-uploadable");
+    if regularize_uploadable && include_uploadable_folders == ModuleTypeFlags::empty() {
+        panic!("Cannot use --regularize-uploadable when --include-uploadable is not specified, since no uploadable folders are downloaded by default");
+    }
+    let index_prefix = matches
+        .value_of("index-prefix")
+        .map(|s| match s.to_lowercase().as_str() {
+            "server" => IndexPrefix::ServerOrder,
+            "upload-date" => IndexPrefix::UploadDate,
+            _ => panic!("Unable to parse parameter of index-prefix"),
+        });
+    let flatten = if matches.is_present("flatten") {
+        match matches.value_of("flatten").unwrap_or("prefix") {
+            "prefix" => Some(Flatten::PrefixFolders),
+            "names" => Some(Flatten::NamesOnly),
+            _ => panic!("Unable to parse parameter of flatten"),
+        }
+    } else {
+        None
+    };
+    let weblecture_date_prefix = matches.is_present("weblecture-date-prefix");
+    let publish_folders = matches
+        .values_of("publish")
+        .map(|values| values.map(|s| s.to_owned()).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let resource_types = matches.values_of("types").map(|mut values| {
+        values
+            .try_fold(ResourceTypeFlags::empty(), |flag, s| {
+                match s.to_lowercase().as_str() {
+                    "files" => Ok(flag | ResourceTypeFlags::FILES),
+                    "multimedia" => Ok(flag | ResourceTypeFlags::MULTIMEDIA),
+                    "weblectures" => Ok(flag | ResourceTypeFlags::WEBLECTURES),
+                    "conferences" => Ok(flag | ResourceTypeFlags::CONFERENCES),
+                    "announcements" => Ok(flag | ResourceTypeFlags::ANNOUNCEMENTS),
+                    "forums" => Ok(flag | ResourceTypeFlags::FORUMS),
+                    "quizzes" => Ok(flag | ResourceTypeFlags::QUIZZES),
+                    "weblinks" => Ok(flag | ResourceTypeFlags::WEBLINKS),
+                    "gradebooks" => Ok(flag | ResourceTypeFlags::GRADEBOOKS),
+                    "rosters" => Ok(flag | ResourceTypeFlags::ROSTERS),
+                    "lesson-plans" => Ok(flag | ResourceTypeFlags::LESSON_PLANS),
+                    _ => Err("Invalid resource type"),
+                }
+            })
+            .expect("Unable to parse parameters of types")
+    });
+    let panopto_podcast = matches.is_present("panopto-podcast");
+    let announcement_html = matches.is_present("announcement-html");
+    let video_container = match matches.value_of("video-container").unwrap_or("mp4") {
+        "mp4" => VideoContainer::Mp4,
+        "mkv" => VideoContainer::Mkv,
+        _ => panic!("Unable to parse parameter of video-container"),
+    };
+    set_filename_normalisation(
+        match matches.value_of("normalise-filenames").unwrap_or("none") {
+            "nfc" => FilenameNormalisation::Nfc,
+            "ascii" => FilenameNormalisation::Ascii,
+            _ => FilenameNormalisation::None,
+        },
+    );
+    let video_quality = parse_video_quality(matches.value_of("video-quality").unwrap_or("best"))
+        .expect("Unable to parse parameter of video-quality");
+    // a profile for small devices like a Raspberry Pi, which trades speed for memory
+    let low_memory = matches.is_present("low-memory");
+    let parse_jobs = |name: &str| {
+        matches.value_of(name).map(|s| {
+            s.parse::<usize>()
+                .ok()
+                .filter(|jobs| *jobs > 0)
+                .expect("Invalid number of concurrent downloads")
+        })
+    };
+    let download_proxy = matches.value_of("download-proxy").map(|s| {
+        reqwest::Url::parse(s)
+            .expect("Invalid URL for --download-proxy, expected e.g. http://HOST:PORT")
+    });
+    let trash_dir = matches.value_of("trash-dir").map(PathBuf::from);
+    let max_connections = parse_jobs("max-connections").unwrap_or(DEFAULT_MAX_CONNECTIONS);
+    let max_connections_per_host =
+        parse_jobs("max-connections-per-host").unwrap_or(DEFAULT_MAX_CONNECTIONS_PER_HOST);
+    let jobs = parse_jobs("jobs").or_else(|| low_memory.then_some(LOW_MEMORY_PARALLELISM));
+    let video_jobs =
+        parse_jobs("video-jobs").or_else(|| low_memory.then_some(LOW_MEMORY_PARALLELISM));
+    // fluminurs itself is aware of long paths on Windows, so paths are only shortened when asked
+    let max_path_length = matches
+        .value_of("max-path-length")
+        .map(|s| {
+            s.parse::<usize>()
+                .expect("Invalid number of characters for --max-path-length")
+        })
+        .filter(|length| *length > 0);
+    // given in megabytes, where 0 lifts the limit
+    let max_response_size = match matches.value_of("max-response-size") {
+        Some(s) => match s
+            .parse::<usize>()
+            .expect("Invalid number of megabytes for --max-response-size")
+        {
+            0 => None,
+            megabytes => Some(megabytes << 20),
+        },
+        None if low_memory => Some(LOW_MEMORY_MAX_RESPONSE_SIZE),
+        None => Some(DEFAULT_MAX_RESPONSE_SIZE),
+    };
+    // where 0 lifts the limit
+    let max_requests_per_second = match matches.value_of("max-requests-per-second") {
+        Some(s) => match s
+            .parse::<u32>()
+            .expect("Invalid number of requests for --max-requests-per-second")
+        {
+            0 => None,
+            rate => Some(rate),
+        },
+        None => Some(DEFAULT_REQUESTS_PER_SECOND),
+    };
+    let verify_only = matches.is_present("verify-only-remote");
+    let find_duplicates = matches.is_present("find-duplicates");
+    let links = matches.is_present("link-duplicates").then(|| {
+        let kind = match matches.value_of("link-duplicates").unwrap_or("hard") {
+            "hard" => links::LinkKind::Hard,
+            "symbolic" => links::LinkKind::Symbolic,
+            _ => panic!("Unable to parse parameter of link-duplicates"),
+        };
+        links::Links::new(kind)
+    });
+    let record_changes = matches.is_present("change-feed");
+    let watch_interval = matches.value_of("watch").map(|s| {
+        let minutes = s
+            .parse::<u64>()
+            .ok()
+            .filter(|minutes| *minutes > 0)
+            .expect("Invalid number of minutes for --watch");
+        schedule::Schedule::Every(Duration::from_secs(minutes * 60))
+    });
+    let watch_schedule = if matches.is_present("schedule") {
+        let cron = matches
+            .value_of("schedule")
+            .unwrap_or(schedule::DEFAULT_CRON);
+        Some(schedule::Schedule::Cron(schedule::Cron::parse(cron)?))
+    } else {
+        watch_interval
+    };
+    let quiet_hours = matches
+        .value_of("quiet-hours")
+        .map(schedule::QuietHours::parse)
+        .transpose()?;
+    let serve = matches
+        .value_of("serve")
+        .map(|address| {
+            match address.strip_prefix(':') {
+                Some(port) => format!("127.0.0.1:{}", port),
+                None => address.to_owned(),
+            }
+            .parse::<SocketAddr>()
+            .map_err(|_| "Invalid address for --serve, expected ADDRESS:PORT or :PORT")
+        })
+        .transpose()?
+        .map(|address| {
+            let token = env_var(SERVE_TOKEN_VAR)
+                .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()));
+            (address, token)
+        });
+    let config = load_config(
+        matches.value_of("config").unwrap_or("config.json"),
+        matches.is_present("config"),
+    )?;
+    // `--updated` has a default value, so it only overrides the config file when given explicitly
+    let layout = matches
+        .value_of("layout")
+        .or(config.layout.as_deref())
+        .map(|template| PathTemplate::parse(template).expect("Unable to parse the layout"));
+    let overwrite_mode = if matches.occurrences_of("updated") > 0 {
+        matches
+            .value_of("updated")
+            .map(|s| match s.to_lowercase().as_str() {
+                "skip" => OverwriteMode::Skip,
+                "overwrite" => OverwriteMode::Overwrite,
+                "rename" => OverwriteMode::Rename,
+                "versioned" => OverwriteMode::Versioned(
+                    matches
+                        .value_of("keep-versions")
+                        .unwrap_or("3")
+                        .parse()
+                        .expect("Invalid number for --keep-versions"),
+                ),
+                _ => panic!("Unable to parse parameter of overwrite_mode"),
+            })
+    } else {
+        None
+    }
+    .or(config.updated)
+    .unwrap_or(OverwriteMode::Skip);
+    // loaded before anything connects, as every client is built with them
+    if let Some(ca_cert) = matches.value_of("ca-cert").or(config.ca_cert.as_deref()) {
+        let pem = fs::read(ca_cert).expect("Unable to read the --ca-cert file");
+        let count = add_root_certificates(&pem).expect("Unable to load the --ca-cert file");
+        tracing::info!("Trusting {} extra certificates from {}", count, ca_cert);
+    }
+    let specified_term = matches.value_of("term").map(|s| {
+        if s.len() == 4 && s.chars().all(char::is_numeric) {
+            s.to_owned()
+        } else {
+            panic!("Invalid input term")
+        }
+    });
+    let specified_modules = matches
+        .values_of("modules")
+        .map(|it| it.collect::<Vec<&str>>());
+    let exclude_globset = matches.values_of("exclude").and_then(|values| {
+        let mut builder = GlobSetBuilder::new();
+        for glob in values {
+            builder.add(Glob::new(glob).ok()?);
+        }
+        builder.build().ok()
+    });
+    let include_globset = matches.values_of("include").and_then(|values| {
+        let mut builder = GlobSetBuilder::new();
+        for glob in values {
+            builder.add(Glob::new(glob).ok()?);
+        }
+        builder.build().ok()
+    });
+    let since = matches
+        .value_of("since")
+        .map(|s| parse_date(s).expect("Invalid date for --since, expected YYYY-MM-DD"));
+    // `--until` is inclusive, so we filter up to the start of the following day
+    let until = matches.value_of("until").map(|s| {
+        parse_date(s).expect("Invalid date for --until, expected YYYY-MM-DD")
+            + Duration::from_secs(24 * 60 * 60)
+    });
+    let subscriptions = subscriptions::Subscriptions::load(&subscriptions_file)?;
+    if let Some(subscribe_matches) = matches.subcommand_matches("subscribe") {
+        return match subscribe_matches.values_of("paths") {
+            Some(paths) => {
+                for path in paths {
+                    subscriptions.subscribe(path)?;
+                }
+                subscriptions.save()
+            }
+            None => {
+                for entry in subscriptions.subscribed() {
+                    outln!("{}", entry);
+                }
+                Ok(())
+            }
+        };
+    }
+    if let Some(unsubscribe_matches) = matches.subcommand_matches("unsubscribe") {
+        for path in unsubscribe_matches
+            .values_of("paths")
+            .expect("Paths are required")
+        {
+            subscriptions.unsubscribe(path)?;
+        }
+        return subscriptions.save();
+    }
+    let extensions = |name| {
+        matches.values_of(name).map(|values| {
+            values
+                .map(|extension| extension.trim_start_matches('.').to_lowercase())
+                .collect::<Vec<_>>()
+        })
+    };
+    let resource_filter = ResourceFilter {
+        include_globset,
+        exclude_globset,
+        only_extensions: extensions("only-ext"),
+        skip_extensions: extensions("skip-ext").unwrap_or_default(),
+        since,
+        until,
+        // without subscriptions, everything is synced
+        subscriptions: Some(subscriptions).filter(|subscriptions| {
+            !subscriptions.is_empty() && !matches.is_present("ignore-subscriptions")
+        }),
+    };
+
+    if let Some(clean_matches) = matches.subcommand_matches("clean") {
+        let dirs = clean_matches
+            .values_of("dirs")
+            .map(|dirs| dirs.map(Path::new).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if clean::sweep(dirs).removed == 0 {
+            outln!("No temporary files to remove");
+        }
+        return Ok(());
+    }
+
+    if let Some(cache_proxy_matches) = matches.subcommand_matches("cache-proxy") {
+        let address = cache_proxy_matches
+            .value_of("listen")
+            .expect("Address is required")
+            .parse::<SocketAddr>()
+            .map_err(|_| "Invalid address for --listen, expected ADDRESS:PORT")?;
+        let cache_dir = cache_proxy_matches.value_of("dir").unwrap_or("cache");
+        return cache_proxy::serve(address, PathBuf::from(cache_dir)).await;
+    }
+
+    if let Some(grab_matches) = matches.subcommand_matches("grab") {
+        // log in only with stored credentials, as public links don't need a LumiNUS login at all
+        let api = match read_stored_credentials(&credential_file) {
+            Some((username, password)) => Api::with_login(&username, &password).await?,
+            None => Api::anonymous()?,
+        }
+        .with_ffmpeg(matches.value_of("ffmpeg").unwrap_or("ffmpeg").to_owned())
+        .with_panopto_podcast(panopto_podcast)
+        .with_video_container(video_container)
+        .with_video_quality(video_quality)
+        .with_max_response_size(max_response_size)
+        .with_low_memory(low_memory)
+        .with_connection_limits(max_connections, max_connections_per_host)
+        .with_rate_limit(max_requests_per_second)
+        .with_download_proxy(download_proxy.clone())
+        .with_trash_dir(trash_dir.clone());
+        return grab(
+            &api,
+            grab_matches.value_of("link").expect("Link is required"),
+            grab_matches.value_of("password"),
+            grab_matches.value_of("to").unwrap_or("."),
+            overwrite_mode,
+        )
+        .await;
+    }
+
+    let cookie_jar = Arc::new(load_cookie_jar(
+        matches.value_of("cookie-jar"),
+        matches.is_present("encrypt-cookie-jar"),
+    )?);
+    if let Some(browser) = matches.value_of("import-cookies-from") {
+        import_browser_cookies(browser, &cookie_jar)?;
+    }
+    if let Some(file) = matches.value_of("import-cookies-file") {
+        import_cookie_file(file, &cookie_jar)?;
+    }
+    let api = login(&credential_file, &cookie_jar).await?;
+    let ffmpeg = matches.value_of("ffmpeg").unwrap_or("ffmpeg").to_owned();
+
+    if let Some(archive_matches) = matches.subcommand_matches("archive") {
+        let code = archive_matches
+            .value_of("module")
+            .expect("Module is required");
+        let module = api
+            .modules(specified_term)
+            .await?
+            .into_iter()
+            .find(|module| module.code == code)
+            .ok_or("Module is not available")?;
+        let mut api = api
+            .with_ffmpeg(ffmpeg)
+            .with_panopto_podcast(panopto_podcast)
+            .with_announcement_html(announcement_html)
+            .with_video_container(video_container)
+            .with_video_quality(video_quality)
+            .with_max_response_size(max_response_size)
+            .with_low_memory(low_memory)
+            .with_connection_limits(max_connections, max_connections_per_host)
+            .with_rate_limit(max_requests_per_second)
+            .with_download_proxy(download_proxy.clone())
+            .with_listing_cache(listing_cache.clone());
+        let result = archive_module(
+            &mut api,
+            module,
+            archive_matches.value_of("from").unwrap_or("."),
+            Path::new(
+                archive_matches
+                    .value_of("to")
+                    .expect("Destination is required"),
+            ),
+            &DownloadContext {
+                jobs,
+                video_jobs,
+                retries: Some(retry::RetryQueue::default()),
+                ..DownloadContext::default()
+            },
+        )
+        .await;
+        save_listing_cache(&listing_cache);
+        return result;
+    }
+
+    if let Some(stats_matches) = matches.subcommand_matches("stats") {
+        let code = stats_matches
+            .value_of("module")
+            .expect("Module is required");
+        let module = api
+            .modules(specified_term)
+            .await?
+            .into_iter()
+            .find(|module| module.code == code)
+            .ok_or("Module is not available")?;
+        return print_module_stats(
+            &api,
+            module,
+            Path::new(stats_matches.value_of("from").unwrap_or(".")),
+        )
+        .await;
+    }
+
+    if let Some(diff_matches) = matches.subcommand_matches("diff") {
+        let modules = api.modules(specified_term).await?;
+        return print_diff(
+            &api,
+            &modules,
+            Path::new(diff_matches.value_of("dir").unwrap_or(".")),
+            diff_matches.is_present("prune"),
+        )
+        .await;
+    }
+
+    if let Some(serve_matches) = matches.subcommand_matches("serve") {
+        let address = serve_matches
+            .value_of("webdav")
+            .expect("Address is required");
+        let address = match address.strip_prefix(':') {
+            Some(port) => format!("127.0.0.1:{}", port),
+            None => address.to_owned(),
+        }
+        .parse::<SocketAddr>()
+        .expect("Invalid address for --webdav, expected ADDRESS:PORT or :PORT");
+        let api = api
+            .with_max_response_size(max_response_size)
+            .with_connection_limits(max_connections, max_connections_per_host)
+            .with_rate_limit(max_requests_per_second);
+        let modules = api.modules(specified_term).await?;
+        return webdav::serve(api, modules, address).await;
+    }
+
+    if let Some(vault_matches) = matches.subcommand_matches("vault") {
+        let api = api
+            .with_max_response_size(max_response_size)
+            .with_connection_limits(max_connections, max_connections_per_host)
+            .with_rate_limit(max_requests_per_second);
+        let modules = api.modules(specified_term).await?;
+        return export_vault(
+            &api,
+            &modules,
+            vault_matches.value_of("dir").expect("Vault is required"),
+        )
+        .await;
+    }
+
+    #[cfg(all(feature = "fuse", unix))]
+    if let Some(mount_matches) = matches.subcommand_matches("mount") {
+        let api = api
+            .with_max_response_size(max_response_size)
+            .with_connection_limits(max_connections, max_connections_per_host)
+            .with_rate_limit(max_requests_per_second);
+        let modules = api.modules(specified_term).await?;
+        return mount::mount(
+            api,
+            modules,
+            PathBuf::from(
+                mount_matches
+                    .value_of("mountpoint")
+                    .expect("Mount point is required"),
+            ),
+            PathBuf::from(mount_matches.value_of("cache-dir").unwrap_or("mount-cache")),
+        )
+        .await;
+    }
+
+    if let Some(interactive_matches) = matches.subcommand_matches("interactive") {
+        let api = api
+            .with_max_response_size(max_response_size)
+            .with_low_memory(low_memory)
+            .with_connection_limits(max_connections, max_connections_per_host)
+            .with_rate_limit(max_requests_per_second)
+            .with_download_proxy(download_proxy.clone())
+            .with_trash_dir(trash_dir.clone())
+            .with_listing_cache(listing_cache.clone());
+        let modules = api
+            .modules(specified_term)
+            .await?
+            .into_iter()
+            .filter(|module| module.has_access())
+            .collect();
+        let files = interactive::pick_files(&api, modules).await;
+        save_listing_cache(&listing_cache);
+        let files = match files? {
+            Some(files) => files,
+            None => return Ok(()),
+        };
+        let context = DownloadContext {
+            jobs,
+            retries: Some(retry::RetryQueue::default()),
+            max_path_length,
+            ..DownloadContext::default()
+        };
+        download_resources(
+            &api,
+            &files,
+            interactive_matches.value_of("to").unwrap_or("."),
+            overwrite_mode,
+            context.jobs(64),
+            &context,
+        )
+        .await?;
+        context.retry_failed().await;
+        context.summary.report();
+        context.attention.report();
+        cookie_jar.save()?;
+        if context.summary.any_failed() {
+            return Err(summary::SOME_DOWNLOADS_FAILED);
+        }
+        return Ok(());
+    }
+
+    if let Some(season_end_matches) = matches.subcommand_matches("season-end") {
+        let term = match specified_term {
+            Some(term) => term,
+            None => get_input("Term that has finished (e.g. 2210): "),
+        };
+        // modules that are retaken in a later term are still accessible
+        let current_codes = api
+            .modules(None)
+            .await?
+            .into_iter()
+            .filter(|module| module.has_access())
+            .map(|module| module.code)
+            .collect::<Vec<_>>();
+        let finished_modules = api
+            .modules(Some(term.clone()))
+            .await?
+            .into_iter()
+            .map(|module| module.code)
+            .filter(|code| !current_codes.contains(code))
+            .collect::<Vec<_>>();
+        let options = season_end::SeasonEndOptions {
+            root: PathBuf::from(season_end_matches.value_of("dir").unwrap_or(".")),
+            term,
+            archive_format: match season_end_matches.value_of("archive").unwrap_or("zip") {
+                "zip" => season_end::ArchiveFormat::Zip,
+                "snapshot" => season_end::ArchiveFormat::Snapshot,
+                _ => panic!("Unable to parse parameter of archive"),
+            },
+            ffmpeg_path: ffmpeg,
+        };
+        return season_end::season_end(
+            &options,
+            &finished_modules,
+            &subscriptions::Subscriptions::load(&subscriptions_file)?,
+            |question| get_input(&format!("{} [y/N] ", question)).eq_ignore_ascii_case("y"),
+        )
+        .await;
+    }
+    let mut api = api
+        .with_ffmpeg(ffmpeg.clone())
+        .with_panopto_podcast(panopto_podcast)
+        .with_announcement_html(announcement_html)
+        .with_video_container(video_container)
+        .with_video_quality(video_quality)
+        .with_max_response_size(max_response_size)
+        .with_low_memory(low_memory)
+        .with_connection_limits(max_connections, max_connections_per_host)
+        .with_rate_limit(max_requests_per_second)
+        .with_download_proxy(download_proxy.clone())
+        .with_trash_dir(trash_dir.clone())
+        .with_listing_cache(listing_cache.clone());
+
+    // when writing a resource to stdout, all the chatter has to be kept out of the way
+    let chatty = stdout_target.is_none();
+    let name = api.name().await?;
+    if chatty {
+        outln!("Hi {}!", name);
+    }
+    let all_modules = api.modules(specified_term.clone()).await?;
+    // kept for telling new modules apart in watch mode
+    let account_modules = all_modules.clone();
+    let modules = if let Some(module_codes) = specified_modules {
+        for module_code in &module_codes {
+            if !all_modules.iter().any(|m| m.code == *module_code) {
+                panic!("Module {} is not available", module_code);
+            }
+        }
+        let filtered_modules = all_modules
+            .into_iter()
+            .filter(|m| module_codes.contains(&m.code.as_str()))
+            .collect::<Vec<Module>>();
+        if chatty {
+            outln!("Selected modules:");
+            for module in &filtered_modules {
+                outln!("- {} {}", module.code, module.name);
+            }
+        }
+        filtered_modules
+    } else {
+        if chatty {
+            outln!("You are taking:");
+            for module in all_modules.iter().filter(|m| m.is_taking()) {
+                outln!("- {} {}", module.code, module.name);
+            }
+            outln!("You are teaching:");
+            for module in all_modules.iter().filter(|m| m.is_teaching()) {
+                outln!("- {} {}", module.code, module.name);
+            }
+        }
+        all_modules
+    };
+
+    let mut options = SyncOptions {
+        do_announcements,
+        announcements_download_destination,
+        do_files,
+        download_destination,
+        do_multimedia,
+        multimedia_download_destination,
+        do_weblectures,
+        long_listing,
+        tree_listing,
+        weblectures_download_destination,
+        do_conferences,
+        conference_assets,
+        conference_views,
+        conferences_download_destination,
+        do_forums,
+        forums_download_destination,
+        do_quizzes,
+        quizzes_download_destination,
+        gradebooks_download_destination,
+        full_class_gradebook,
+        calendar_destination,
+        rosters_download_destination,
+        do_weblinks,
+        weblinks_download_destination,
+        lesson_plans_download_destination,
+        include_uploadable_folders,
+        regularize_uploadable,
+        index_prefix,
+        flatten,
+        weblecture_date_prefix,
+        layout,
+        publish_folders,
+        resource_filter,
+        config,
+        overwrite_mode,
+    };
+    if let Some(target) = stdout_target {
+        return write_to_stdout(&mut api, &modules, &target, &options).await;
+    }
+    if let Some(types) = resource_types {
+        options.select_types(types, matches.is_present("list"));
+    }
+    // whatever an earlier run left half done would otherwise sit there forever
+    clean::sweep(options.destinations());
+    // removals can only be told apart from filtered out resources when nothing is filtered out
+    let detect_removals = options.resource_filter.is_empty();
+    let change_feed = || record_changes.then(|| changes::ChangeFeed::new(detect_removals));
+    let report = || {
+        matches
+            .value_of("report")
+            .map(|path| report::RunReport::new(PathBuf::from(path)))
+    };
+    let hooks = || {
+        let hooks = hooks::Hooks::new(
+            matches.value_of("post-sync-hook").map(|s| s.to_owned()),
+            options.config.post_sync_hooks(),
+        );
+        (!hooks.is_empty()).then_some(hooks)
+    };
+    // the email is daily in watch mode, rather than after every sync
+    let digest = |daily: bool| {
+        (!options.config.notifications.is_empty()).then(|| {
+            if daily {
+                notifications::Digest::daily()
+            } else {
+                notifications::Digest::default()
+            }
+        })
+    };
+
+    if let Some(watch_schedule) = watch_schedule {
+        let status = Arc::new(daemon::SyncStatus::new(
+            modules.iter().map(|module| module.code.clone()),
+        ));
+        let context = DownloadContext {
+            verify_only,
+            find_duplicates,
+            status: Some(status.clone()),
+            change_feed: change_feed(),
+            attention: attention::Attention::default(),
+            jobs,
+            video_jobs,
+            // `resume` runs the recorded command line, which is kept as it is
+            session: match (&resumed_session, matches.value_of("session")) {
+                (Some(name), _) => Some(sessions::Session::watch(name, None)?),
+                (None, Some(name)) => Some(sessions::Session::watch(
+                    name,
+                    Some(std::env::args().skip(1).collect()),
+                )?),
+                (None, None) => None,
+            },
+            retries: Some(retry::RetryQueue::default()),
+            summary: summary::RunSummary::default(),
+            report: report(),
+            max_path_length,
+            digest: digest(true),
+            hooks: hooks(),
+            quiet_hours,
+            written: None,
+        };
+        let module_watcher = daemon::ModuleWatcher::new(
+            &account_modules,
+            &modules,
+            matches.is_present("add-new-modules"),
+        );
+        let (credential_file, cookie_jar, ffmpeg) = (&credential_file, &cookie_jar, &ffmpeg);
+        let (options, context, module_watcher) = (&options, &context, &module_watcher);
+        let (status, specified_term, download_proxy, trash_dir) =
+            (&status, &specified_term, &download_proxy, &trash_dir);
+        // log in again on every sync, since the session does not last forever
+        return daemon::watch(watch_schedule, serve, status.clone(), move || async move {
+            let api = login(credential_file, cookie_jar).await?;
+            let transport = status.metrics().count_api_errors(api.get_transport());
+            let mut api = api
+                .with_transport(transport)
+                .with_ffmpeg(ffmpeg.clone())
+                .with_panopto_podcast(panopto_podcast)
+                .with_announcement_html(announcement_html)
+                .with_video_container(video_container)
+                .with_video_quality(video_quality)
+                .with_max_response_size(max_response_size)
+                .with_low_memory(low_memory)
+                .with_connection_limits(max_connections, max_connections_per_host)
+                .with_rate_limit(max_requests_per_second)
+                .with_download_proxy(download_proxy.clone())
+                .with_trash_dir(trash_dir.clone());
+            // modules are listed again, as a new term may have started since the last sync
+            let modules = module_watcher.update(api.modules(specified_term.clone()).await?, status);
+            let result = sync(&mut api, &modules, options, context).await;
+            let result = context.finish_sync(result).await;
+            context.notify(&options.config.notifications).await;
+            context.run_hooks().await;
+            context.write_report();
+            result?;
+            cookie_jar.save()
+        })
+        .await;
+    }
+
+    let session = match resumed_session {
+        Some(name) => Some(sessions::Session::resume(&name)?),
+        None => matches
+            .value_of("session")
+            .map(|name| sessions::Session::start(name, std::env::args().skip(1).collect()))
+            .transpose()?,
+    };
+    let context = DownloadContext {
+        verify_only,
+        find_duplicates,
+        status: None,
+        change_feed: change_feed(),
+        attention: attention::Attention::default(),
+        jobs,
+        video_jobs,
+        session,
+        retries: Some(retry::RetryQueue::default()),
+        summary: summary::RunSummary::default(),
+        report: report(),
+        max_path_length,
+        digest: digest(false),
+        hooks: hooks(),
+        quiet_hours,
+        written: links.is_some().then(Mutex::default),
+    };
+    if let Some(links) = &links {
+        links.release_stale(&options.destinations());
+    }
+    let result = sync(&mut api, &modules, &options, &context).await;
+    let result = context.finish_sync(result).await;
+    if let (Some(links), Some(written)) = (&links, &context.written) {
+        let written = written.lock().unwrap().clone();
+        links.link_duplicates(&written).await;
+    }
+    context.notify(&options.config.notifications).await;
+    context.run_hooks().await;
+    context.write_report();
+    save_listing_cache(&listing_cache);
+    if let Some(session) = &context.session {
+        session.end()?;
+    }
+    result?;
+
+    // the Zoom and Panopto sessions picked up along the way are worth keeping too
+    cookie_jar.save()?;
+
+    if context.summary.any_failed() {
+        return Err(summary::SOME_DOWNLOADS_FAILED);
+    }
+    Ok(())
+}
@@ -0,0 +1,413 @@
+// The files that fluminurs has downloaded into a destination, as a `manifest.json` in it, with
+// their size and modification time as they were written. Files that are not in the manifest were
+// put there by someone else (e.g. notes next to the lecture slides), and pruning leaves them alone
+// even once nothing on the server has their path, as it does files changed since they were written.
+// Copies that were replaced with links to an identical file keep what they link to. Each file
+// also keeps which resource it is a copy of, so that it can be moved along when that resource is
+// renamed on the server, rather than downloaded again. The folders that files are in are kept by
+// their ids too, so that folders renamed or moved on the server are moved locally as a whole.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use fluminurs::file::File;
+use fluminurs::resource::{OverwriteResult, Resource};
+use fluminurs::Result;
+
+use crate::changes::resource_kind;
+use crate::clean::TEMP_FILE_PREFIX;
+use crate::links::LinkKind;
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+// How a file was when fluminurs wrote it
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    // in seconds since the epoch
+    modified: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link: Option<Link>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resource: Option<ResourceId>,
+}
+
+// The kind of resource is part of it, as e.g. the gradebook and roster of a module share its id
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct ResourceId {
+    kind: String,
+    id: String,
+}
+
+impl ResourceId {
+    fn of<T: Resource>(resource: &T) -> ResourceId {
+        ResourceId {
+            kind: resource_kind::<T>().to_owned(),
+            id: resource.id().to_owned(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Link {
+    target: PathBuf,
+    kind: LinkKind,
+}
+
+impl Entry {
+    fn of(path: &Path) -> Option<Entry> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Entry {
+            size: metadata.len(),
+            modified: metadata
+                .modified()
+                .ok()?
+                .duration_since(UNIX_EPOCH)
+                .ok()?
+                .as_secs(),
+            link: None,
+            resource: None,
+        })
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        Entry::of(path)
+            .is_some_and(|entry| entry.size == self.size && entry.modified == self.modified)
+    }
+}
+
+/// Whether the download wrote the file, rather than leaving what was there
+pub fn was_written(result: &Result<OverwriteResult>) -> bool {
+    matches!(
+        result,
+        Ok(OverwriteResult::NewFile)
+            | Ok(OverwriteResult::Overwritten)
+            | Ok(OverwriteResult::Renamed { .. })
+    )
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    files: BTreeMap<String, Entry>,
+    // the path of each folder, by its id
+    #[serde(default)]
+    folders: BTreeMap<String, String>,
+}
+
+pub struct Manifest {
+    destination: PathBuf,
+    files: BTreeMap<PathBuf, Entry>,
+    folders: BTreeMap<String, PathBuf>,
+}
+
+fn path_key(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl Manifest {
+    /// The manifest of the destination, with paths relative to it
+    pub fn load(destination: &Path) -> Manifest {
+        let data = fs::read(destination.join(MANIFEST_FILE_NAME)).unwrap_or_default();
+        // manifests used to have only the files
+        let manifest_file = serde_json::from_slice::<ManifestFile>(&data)
+            .ok()
+            .or_else(|| {
+                serde_json::from_slice(&data)
+                    .ok()
+                    .map(|files| ManifestFile {
+                        files,
+                        folders: BTreeMap::new(),
+                    })
+            });
+        let (files, folders) = manifest_file
+            .map(|manifest_file| (manifest_file.files, manifest_file.folders))
+            .unwrap_or_default();
+        Manifest {
+            destination: destination.to_owned(),
+            files: files
+                .into_iter()
+                .map(|(path, entry)| (PathBuf::from(path), entry))
+                .collect(),
+            folders: folders
+                .into_iter()
+                .map(|(id, path)| (id, PathBuf::from(path)))
+                .collect(),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let manifest_file = ManifestFile {
+            files: self
+                .files
+                .iter()
+                .map(|(path, entry)| (path_key(path), entry.clone()))
+                .collect(),
+            folders: self
+                .folders
+                .iter()
+                .map(|(id, path)| (id.clone(), path_key(path)))
+                .collect(),
+        };
+        let data = serde_json::to_vec_pretty(&manifest_file)
+            .map_err(|_| "Unable to serialise manifest")?;
+        let temp_path = self
+            .destination
+            .join(format!("{}{}", TEMP_FILE_PREFIX, MANIFEST_FILE_NAME));
+        fs::write(&temp_path, data).map_err(|_| "Unable to write manifest")?;
+        fs::rename(&temp_path, self.destination.join(MANIFEST_FILE_NAME))
+            .map_err(|_| "Unable to write manifest")
+    }
+
+    /// Whether fluminurs downloaded the file
+    pub fn contains(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+
+    /// Whether fluminurs downloaded the file, and it is still as it was written
+    pub fn is_unchanged(&self, path: &Path) -> bool {
+        self.files
+            .get(path)
+            .is_some_and(|entry| entry.matches(&self.destination.join(path)))
+    }
+
+    /// The symbolic links whose files were removed or changed since they were linked to
+    pub fn stale_links(&self) -> Vec<PathBuf> {
+        self.files
+            .iter()
+            .filter(|(path, entry)| {
+                entry
+                    .link
+                    .as_ref()
+                    .is_some_and(|link| link.kind == LinkKind::Symbolic)
+                    && !entry.matches(&self.destination.join(path))
+            })
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Takes the file as it is now to be as fluminurs wrote it
+    pub fn add(&mut self, path: &Path) {
+        if let Some(entry) = Entry::of(&self.destination.join(path)) {
+            let resource = self.resource_of(path);
+            self.files
+                .insert(path.to_owned(), Entry { resource, ..entry });
+        }
+    }
+
+    /// Records that the file was replaced with a link to `target`
+    pub fn add_link(&mut self, path: &Path, target: PathBuf, kind: LinkKind) {
+        if let Some(entry) = Entry::of(&self.destination.join(path)) {
+            let link = Some(Link { target, kind });
+            let resource = self.resource_of(path);
+            self.files.insert(
+                path.to_owned(),
+                Entry {
+                    link,
+                    resource,
+                    ..entry
+                },
+            );
+        }
+    }
+
+    fn resource_of(&self, path: &Path) -> Option<ResourceId> {
+        self.files
+            .get(path)
+            .and_then(|entry| entry.resource.clone())
+    }
+
+    // Keeps the files of a folder that was moved from `from` to `to` with the folder
+    fn move_files(&mut self, from: &Path, to: &Path) {
+        let moved = self
+            .files
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in moved {
+            if let (Some(entry), Ok(rest)) = (self.files.remove(&path), path.strip_prefix(from)) {
+                self.files.insert(to.join(rest), entry);
+            }
+        }
+    }
+
+    pub fn forget<'a>(&mut self, paths: impl IntoIterator<Item = &'a PathBuf>) {
+        for path in paths {
+            self.files.remove(path);
+        }
+    }
+}
+
+/// Adds the resources that were just written. Files that were already there are left out, as
+/// they may be the user's own, and a file that was moved aside stays in only if it was in before.
+pub fn record<T: Resource>(
+    destination: &Path,
+    results: &[(&T, Result<OverwriteResult>)],
+) -> Result<()> {
+    let mut manifest = Manifest::load(destination);
+    let mut changed = false;
+    for (resource, result) in results {
+        if !was_written(result) {
+            // files written before the manifest kept their resources are still ours
+            if let (Ok(_), Some(entry)) = (result, manifest.files.get_mut(resource.path())) {
+                if entry.resource.is_none() {
+                    entry.resource = Some(ResourceId::of(*resource));
+                    changed = true;
+                }
+            }
+            continue;
+        }
+        if let Ok(OverwriteResult::Renamed { renamed_path }) = result {
+            if let (Some(entry), Ok(renamed_path)) = (
+                manifest.files.remove(resource.path()),
+                renamed_path.strip_prefix(destination),
+            ) {
+                // the copy moved aside is an old version, which is not followed to new names
+                let entry = Entry {
+                    resource: None,
+                    ..entry
+                };
+                manifest.files.insert(renamed_path.to_owned(), entry);
+            }
+        }
+        manifest.add(resource.path());
+        if let Some(entry) = manifest.files.get_mut(resource.path()) {
+            entry.resource = Some(ResourceId::of(*resource));
+        }
+        changed = true;
+    }
+    if changed {
+        manifest.save()
+    } else {
+        Ok(())
+    }
+}
+
+/// Moves the local copies of resources that were renamed on the server since they were
+/// downloaded, instead of downloading them again under their new names, and returns the moved
+/// resources with where they were. Whether the moved copies are up to date is then up to the
+/// download as usual.
+pub fn follow_renames<'a, T: Resource>(
+    destination: &Path,
+    resources: &'a [T],
+) -> Result<Vec<(&'a T, PathBuf)>> {
+    let mut manifest = Manifest::load(destination);
+    let known = manifest
+        .files
+        .iter()
+        .filter_map(|(path, entry)| Some((entry.resource.clone()?, path.clone())))
+        .collect::<HashMap<_, _>>();
+    let mut renamed = vec![];
+    for resource in resources {
+        let previous_path = match known.get(&ResourceId::of(resource)) {
+            Some(previous_path) if previous_path != resource.path() => previous_path,
+            _ => continue,
+        };
+        let from = destination.join(previous_path);
+        let to = destination.join(resource.path());
+        if !from.is_file() || to.exists() {
+            continue;
+        }
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent).map_err(|_| "Unable to create directory")?;
+        }
+        match fs::rename(&from, &to) {
+            Ok(_) => {
+                outln!(
+                    "Renamed {} to {}",
+                    previous_path.display(),
+                    resource.path().display()
+                );
+                if let Some(entry) = manifest.files.remove(previous_path) {
+                    manifest.files.insert(resource.path().to_owned(), entry);
+                }
+                renamed.push((resource, previous_path.clone()));
+            }
+            Err(_) => errln!(
+                "Unable to rename {} to {}",
+                previous_path.display(),
+                resource.path().display()
+            ),
+        }
+    }
+    if !renamed.is_empty() {
+        manifest.save()?;
+    }
+    Ok(renamed)
+}
+
+/// Moves the local folders that were renamed or moved on the server since their files were
+/// synced, with everything in them, and returns where each moved folder was and is now.
+/// This is done before their files are synced, so that those are found where they now belong.
+pub fn follow_folder_renames(
+    destination: &Path,
+    files: &[File],
+) -> Result<Vec<(String, PathBuf, PathBuf)>> {
+    let mut manifest = Manifest::load(destination);
+    let folders = files
+        .iter()
+        .filter_map(|file| Some((file.folder_id()?, file.path().parent()?)))
+        .collect::<HashMap<_, _>>();
+    // outer folders first, since moving them moves the folders inside along with them
+    let mut folders = folders.into_iter().collect::<Vec<_>>();
+    folders.sort_by_key(|(_, path)| path.components().count());
+
+    let mut changed = false;
+    let mut moved = vec![];
+    for (id, path) in folders {
+        let previous_path = match manifest.folders.get(id) {
+            Some(previous_path) if previous_path == path => continue,
+            Some(previous_path) => previous_path.clone(),
+            None => {
+                manifest.folders.insert(id.to_owned(), path.to_owned());
+                changed = true;
+                continue;
+            }
+        };
+        // where the folder is now, if a folder around it was moved already
+        let mut current = previous_path.clone();
+        for (_, from, to) in &moved {
+            if let Ok(rest) = current.strip_prefix(from) {
+                current = Path::new(to).join(rest);
+            }
+        }
+        let (from, to) = (destination.join(&current), destination.join(path));
+        if current != path && from.is_dir() && !to.exists() {
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent).map_err(|_| "Unable to create directory")?;
+            }
+            match fs::rename(&from, &to) {
+                Ok(_) => {
+                    outln!("Moved {} to {}", current.display(), path.display());
+                    manifest.move_files(&current, path);
+                    moved.push((id.to_owned(), current, path.to_owned()));
+                }
+                Err(_) => errln!("Unable to move {} to {}", current.display(), path.display()),
+            }
+        }
+        manifest.folders.insert(id.to_owned(), path.to_owned());
+        changed = true;
+    }
+    if changed {
+        manifest.save()?;
+    }
+    Ok(moved)
+}
+
+/// Removes paths that are no longer on disk, e.g. after they were pruned
+pub fn forget<'a>(
+    destination: &Path,
+    removed: impl IntoIterator<Item = &'a PathBuf>,
+) -> Result<()> {
+    let mut manifest = Manifest::load(destination);
+    manifest.forget(removed);
+    manifest.save()
+}
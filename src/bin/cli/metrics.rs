@@ -0,0 +1,208 @@
+// Counters of watch mode for Prometheus (e.g. to chart with Grafana), served by the dashboard in
+// the text format at /metrics. They start over whenever fluminurs does, which Prometheus expects.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use reqwest::{Request, Response};
+
+use fluminurs::resource::{OverwriteResult, Resource};
+use fluminurs::transport::HttpTransport;
+use fluminurs::Result;
+
+use crate::changes::resource_kind;
+
+#[derive(Debug, Default)]
+struct Counters {
+    // by kind of resource
+    downloads: BTreeMap<&'static str, u64>,
+    failures: BTreeMap<&'static str, u64>,
+    downloaded_bytes: u64,
+    // by status code, or `none` for requests that got no response
+    api_errors: BTreeMap<String, u64>,
+    syncs: u64,
+    failed_syncs: u64,
+    last_sync: Option<SystemTime>,
+    last_successful_sync: Option<SystemTime>,
+}
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+fn timestamp(time: Option<SystemTime>) -> u64 {
+    time.and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn push_metric(text: &mut String, name: &str, kind: &str, help: &str) {
+    text.push_str(&format!(
+        "# HELP {} {}\n# TYPE {} {}\n",
+        name, help, name, kind
+    ));
+}
+
+impl Metrics {
+    /// Counts a download by its result, with the size of the file if it was downloaded
+    pub fn record_download<T: Resource>(&self, result: &Result<OverwriteResult>, path: &Path) {
+        let mut counters = self.counters.lock().unwrap();
+        match result {
+            Ok(OverwriteResult::NewFile | OverwriteResult::Overwritten)
+            | Ok(OverwriteResult::Renamed { .. }) => {
+                *counters.downloads.entry(resource_kind::<T>()).or_default() += 1;
+                counters.downloaded_bytes += fs::metadata(path).map_or(0, |m| m.len());
+            }
+            Ok(OverwriteResult::AlreadyHave | OverwriteResult::Skipped) => {}
+            Err(_) => *counters.failures.entry(resource_kind::<T>()).or_default() += 1,
+        }
+    }
+
+    pub fn record_sync(&self, result: &Result<()>) {
+        let mut counters = self.counters.lock().unwrap();
+        let now = Some(SystemTime::now());
+        counters.syncs += 1;
+        counters.last_sync = now;
+        match result {
+            Ok(()) => counters.last_successful_sync = now,
+            Err(_) => counters.failed_syncs += 1,
+        }
+    }
+
+    fn record_api_error(&self, error: String) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .api_errors
+            .entry(error)
+            .or_default() += 1;
+    }
+
+    /// Wraps the transport of an `Api`, to count the requests that fail
+    pub fn count_api_errors(
+        self: &Arc<Metrics>,
+        transport: Arc<dyn HttpTransport>,
+    ) -> Arc<dyn HttpTransport> {
+        Arc::new(CountingTransport {
+            inner: transport,
+            metrics: self.clone(),
+        })
+    }
+
+    pub fn render(&self, syncing: bool) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut text = String::new();
+        push_metric(
+            &mut text,
+            "fluminurs_downloads_total",
+            "counter",
+            "Files downloaded, by kind of resource",
+        );
+        for (kind, count) in &counters.downloads {
+            text.push_str(&format!(
+                "fluminurs_downloads_total{{kind=\"{}\"}} {}\n",
+                kind, count
+            ));
+        }
+        push_metric(
+            &mut text,
+            "fluminurs_download_failures_total",
+            "counter",
+            "Downloads that failed, by kind of resource",
+        );
+        for (kind, count) in &counters.failures {
+            text.push_str(&format!(
+                "fluminurs_download_failures_total{{kind=\"{}\"}} {}\n",
+                kind, count
+            ));
+        }
+        push_metric(
+            &mut text,
+            "fluminurs_downloaded_bytes_total",
+            "counter",
+            "Size of the files downloaded",
+        );
+        text.push_str(&format!(
+            "fluminurs_downloaded_bytes_total {}\n",
+            counters.downloaded_bytes
+        ));
+        push_metric(
+            &mut text,
+            "fluminurs_api_errors_total",
+            "counter",
+            "Requests that failed, by status code (none when there was no response)",
+        );
+        for (status, count) in &counters.api_errors {
+            text.push_str(&format!(
+                "fluminurs_api_errors_total{{status=\"{}\"}} {}\n",
+                status, count
+            ));
+        }
+        push_metric(&mut text, "fluminurs_syncs_total", "counter", "Syncs run");
+        text.push_str(&format!("fluminurs_syncs_total {}\n", counters.syncs));
+        push_metric(
+            &mut text,
+            "fluminurs_failed_syncs_total",
+            "counter",
+            "Syncs that failed",
+        );
+        text.push_str(&format!(
+            "fluminurs_failed_syncs_total {}\n",
+            counters.failed_syncs
+        ));
+        push_metric(
+            &mut text,
+            "fluminurs_last_sync_timestamp_seconds",
+            "gauge",
+            "When the last sync finished, or 0 for never",
+        );
+        text.push_str(&format!(
+            "fluminurs_last_sync_timestamp_seconds {}\n",
+            timestamp(counters.last_sync)
+        ));
+        push_metric(
+            &mut text,
+            "fluminurs_last_successful_sync_timestamp_seconds",
+            "gauge",
+            "When the last successful sync finished, or 0 for never",
+        );
+        text.push_str(&format!(
+            "fluminurs_last_successful_sync_timestamp_seconds {}\n",
+            timestamp(counters.last_successful_sync)
+        ));
+        push_metric(
+            &mut text,
+            "fluminurs_syncing",
+            "gauge",
+            "Whether a sync is running",
+        );
+        text.push_str(&format!("fluminurs_syncing {}\n", u8::from(syncing)));
+        text
+    }
+}
+
+#[derive(Debug)]
+struct CountingTransport {
+    inner: Arc<dyn HttpTransport>,
+    metrics: Arc<Metrics>,
+}
+
+#[async_trait]
+impl HttpTransport for CountingTransport {
+    async fn execute(&self, request: Request) -> Result<Response> {
+        let result = self.inner.execute(request).await;
+        match &result {
+            Ok(res) if res.status().is_client_error() || res.status().is_server_error() => self
+                .metrics
+                .record_api_error(res.status().as_str().to_owned()),
+            Ok(_) => {}
+            Err(_) => self.metrics.record_api_error("none".to_owned()),
+        }
+        result
+    }
+}
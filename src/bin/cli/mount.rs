@@ -0,0 +1,435 @@
+// A read-only filesystem of the files of each module, mounted with FUSE. The folders of a module
+// are listed when it is first opened, and files are fetched from LumiNUS in blocks as they are
+// read, which are kept in a cache folder so that reading them again needs no download.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use fuser::consts::FOPEN_DIRECT_IO;
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+    ReplyOpen, Request, FUSE_ROOT_ID,
+};
+use libc::{c_int, EIO, EISDIR, ENOENT, ENOTDIR};
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::{StatusCode, Url};
+use tokio::runtime::Handle;
+
+use fluminurs::file::File;
+use fluminurs::module::Module;
+use fluminurs::resource::{sort_and_make_all_paths_unique, SimpleDownloadableResource};
+use fluminurs::{Api, Result};
+
+use crate::clean::TEMP_FILE_PREFIX;
+
+const BLOCK_SIZE: u64 = 1024 * 1024;
+// how long the kernel may keep what it was told, as nothing changes while mounted
+const TTL: Duration = Duration::from_secs(60);
+
+enum Node {
+    // the children of a module are listed when it is first opened
+    Folder {
+        parent: u64,
+        module: Option<Module>,
+        children: Option<BTreeMap<OsString, u64>>,
+    },
+    File {
+        file: File,
+        // known once the file is opened
+        size: Option<u64>,
+        url: Option<Url>,
+    },
+}
+
+struct ModuleFs {
+    api: Api,
+    runtime: Handle,
+    cache_dir: PathBuf,
+    // by inode number, from the root at 1
+    nodes: Vec<Node>,
+}
+
+/// Mounts the files of the modules at `mountpoint` until it is unmounted (e.g. with
+/// `fusermount -u`), keeping the blocks that were read in `cache_dir`
+pub async fn mount(
+    api: Api,
+    modules: Vec<Module>,
+    mountpoint: PathBuf,
+    cache_dir: PathBuf,
+) -> Result<()> {
+    fs::create_dir_all(&cache_dir).map_err(|_| "Unable to create cache directory")?;
+    let mut filesystem = ModuleFs {
+        api,
+        runtime: Handle::current(),
+        cache_dir,
+        nodes: vec![Node::Folder {
+            parent: FUSE_ROOT_ID,
+            module: None,
+            children: None,
+        }],
+    };
+    let mut root = BTreeMap::new();
+    for module in modules.into_iter().filter(|module| module.has_access()) {
+        let name = OsString::from(&module.code);
+        let ino = filesystem.add_node(Node::Folder {
+            parent: FUSE_ROOT_ID,
+            module: Some(module),
+            children: None,
+        });
+        root.insert(name, ino);
+    }
+    if let Some(Node::Folder { children, .. }) = filesystem.node_mut(FUSE_ROOT_ID) {
+        *children = Some(root);
+    }
+
+    outln!(
+        "Mounting the files of the modules on {}",
+        mountpoint.display()
+    );
+    let options = [
+        MountOption::RO,
+        MountOption::FSName("fluminurs".to_owned()),
+        MountOption::DefaultPermissions,
+    ];
+    // the filesystem calls back from a thread of its own, which waits on the runtime
+    tokio::task::spawn_blocking(move || fuser::mount2(filesystem, &mountpoint, &options))
+        .await
+        .map_err(|_| "Filesystem stopped unexpectedly")?
+        .map_err(|_| "Unable to mount the filesystem")
+}
+
+impl ModuleFs {
+    fn add_node(&mut self, node: Node) -> u64 {
+        self.nodes.push(node);
+        self.nodes.len() as u64
+    }
+
+    fn node(&self, ino: u64) -> Option<&Node> {
+        ino.checked_sub(1)
+            .and_then(|index| self.nodes.get(index as usize))
+    }
+
+    fn node_mut(&mut self, ino: u64) -> Option<&mut Node> {
+        ino.checked_sub(1)
+            .and_then(|index| self.nodes.get_mut(index as usize))
+    }
+
+    // The children of a folder, listing the files of a module the first time it is opened
+    fn children(&mut self, ino: u64) -> std::result::Result<BTreeMap<OsString, u64>, c_int> {
+        let module = match self.node(ino).ok_or(ENOENT)? {
+            Node::Folder {
+                children: Some(children),
+                ..
+            } => return Ok(children.clone()),
+            Node::Folder {
+                module: Some(module),
+                ..
+            } => module.clone(),
+            Node::Folder { .. } => return Err(EIO),
+            Node::File { .. } => return Err(ENOTDIR),
+        };
+        let mut files = self
+            .runtime
+            .block_on(module.workbin_root(|_| PathBuf::new()).load(
+                &self.api,
+                module.is_teaching(),
+                false,
+                None,
+                None,
+            ))
+            .map_err(|e| {
+                errln!("Failed to list the files of {}: {}", module.code, e);
+                EIO
+            })?;
+        sort_and_make_all_paths_unique(&mut files);
+
+        // folders are only known by the files in them, so empty ones are left out
+        let mut folder_inos = HashMap::from([(PathBuf::new(), ino)]);
+        let mut folders = BTreeMap::<PathBuf, BTreeMap<OsString, u64>>::new();
+        for file in files {
+            let path = file.path().to_owned();
+            let mut parent = PathBuf::new();
+            for component in path.parent().unwrap_or(Path::new("")).components() {
+                let name = component.as_os_str();
+                let folder_path = parent.join(name);
+                if !folder_inos.contains_key(&folder_path) {
+                    let folder_ino = self.add_node(Node::Folder {
+                        parent: folder_inos[&parent],
+                        module: None,
+                        children: None,
+                    });
+                    folder_inos.insert(folder_path.clone(), folder_ino);
+                    folders
+                        .entry(parent)
+                        .or_default()
+                        .insert(name.to_owned(), folder_ino);
+                }
+                parent = folder_path;
+            }
+            let file_ino = self.add_node(Node::File {
+                file,
+                size: None,
+                url: None,
+            });
+            if let Some(name) = path.file_name() {
+                folders
+                    .entry(parent)
+                    .or_default()
+                    .insert(name.to_owned(), file_ino);
+            }
+        }
+        for (path, folder_ino) in folder_inos {
+            if let Some(Node::Folder { children, .. }) = self.node_mut(folder_ino) {
+                *children = Some(folders.remove(&path).unwrap_or_default());
+            }
+        }
+        self.children(ino)
+    }
+
+    fn attr(&self, ino: u64, req: &Request<'_>) -> Option<FileAttr> {
+        let (kind, size, modified, perm) = match self.node(ino)? {
+            Node::Folder { .. } => (FileType::Directory, 0, UNIX_EPOCH, 0o555),
+            Node::File { file, size, .. } => (
+                FileType::RegularFile,
+                size.unwrap_or(0),
+                file.last_updated(),
+                0o444,
+            ),
+        };
+        Some(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: modified,
+            mtime: modified,
+            ctime: modified,
+            crtime: modified,
+            kind,
+            perm,
+            nlink: 1,
+            uid: req.uid(),
+            gid: req.gid(),
+            rdev: 0,
+            blksize: BLOCK_SIZE as u32,
+            flags: 0,
+        })
+    }
+
+    // Where a part of a version of a file is kept, e.g. a block by its index or the size
+    fn cache_path(&self, file: &File, part: &str) -> PathBuf {
+        let version = file
+            .last_updated()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        self.cache_dir
+            .join(format!("{}-{}-{}", file.id(), version, part))
+    }
+
+    fn write_cache(&self, path: &Path, data: &[u8]) {
+        let mut temp_name = OsString::from(TEMP_FILE_PREFIX);
+        temp_name.push(path.file_name().unwrap_or_default());
+        let temp_path = path.with_file_name(temp_name);
+        if fs::write(&temp_path, data).is_ok() {
+            fs::rename(&temp_path, path).ok();
+        }
+    }
+
+    // The download URL of the file, resolved again if `refresh` as they expire
+    fn url(&mut self, ino: u64, refresh: bool) -> Result<Url> {
+        let file = match self.node(ino).ok_or("File does not exist")? {
+            Node::File { url: Some(url), .. } if !refresh => return Ok(url.clone()),
+            Node::File { file, .. } => file.clone(),
+            Node::Folder { .. } => return Err("Not a file"),
+        };
+        let resolved = self.runtime.block_on(file.get_download_url(&self.api))?;
+        if let Some(Node::File { url, .. }) = self.node_mut(ino) {
+            *url = Some(resolved.clone());
+        }
+        Ok(resolved)
+    }
+
+    // Fetches a block of the file, with the size of the whole file
+    fn fetch_block(&mut self, ino: u64, index: u64) -> Result<(Vec<u8>, Option<u64>)> {
+        let file = match self.node(ino).ok_or("File does not exist")? {
+            Node::File { file, .. } => file.clone(),
+            Node::Folder { .. } => return Err("Not a file"),
+        };
+        let size_path = self.cache_path(&file, "size");
+        if let Ok(data) = fs::read(self.cache_path(&file, &index.to_string())) {
+            let total = fs::read_to_string(&size_path)
+                .ok()
+                .and_then(|total| total.parse().ok());
+            return Ok((data, total));
+        }
+
+        let start = index * BLOCK_SIZE;
+        let range = format!("bytes={}-{}", start, start + BLOCK_SIZE - 1);
+        let mut refresh = false;
+        let res = loop {
+            let url = self.url(ino, refresh)?;
+            let res = self
+                .runtime
+                .block_on(self.api.get_client().get(url).header(RANGE, &range).send())
+                .map_err(|_| "Failed to fetch the file")?;
+            match res.status() {
+                // the download URL expired, which it does after a while
+                StatusCode::FORBIDDEN if !refresh => refresh = true,
+                // a read just past the end of a file whose size is a multiple of the block size
+                StatusCode::RANGE_NOT_SATISFIABLE => return Ok((vec![], None)),
+                status if status.is_success() => break res,
+                _ => return Err("Failed to fetch the file"),
+            }
+        };
+        let is_partial = res.status() == StatusCode::PARTIAL_CONTENT;
+        let total = res
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit('/').next())
+            .and_then(|total| total.parse::<u64>().ok());
+        let body = self
+            .runtime
+            .block_on(res.bytes())
+            .map_err(|_| "Failed to fetch the file")?;
+
+        // servers that ignore the range send the whole file, which is cached as blocks all the same
+        let (blocks, total) = if is_partial {
+            (vec![(index, body.to_vec())], total)
+        } else {
+            let blocks = body
+                .chunks(BLOCK_SIZE as usize)
+                .enumerate()
+                .map(|(i, chunk)| (i as u64, chunk.to_vec()))
+                .collect::<Vec<_>>();
+            (blocks, Some(body.len() as u64))
+        };
+        if let Some(total) = total {
+            self.write_cache(&size_path, total.to_string().as_bytes());
+        }
+        let mut data = vec![];
+        for (i, block) in blocks {
+            self.write_cache(&self.cache_path(&file, &i.to_string()), &block);
+            if i == index {
+                data = block;
+            }
+        }
+        Ok((data, total))
+    }
+}
+
+impl Filesystem for ModuleFs {
+    fn lookup(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let ino = match self.children(parent) {
+            Ok(children) => children.get(name).copied(),
+            Err(e) => return reply.error(e),
+        };
+        match ino.and_then(|ino| self.attr(ino, req)) {
+            Some(attr) => reply.entry(&TTL, &attr, 0),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.attr(ino, req) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let children = match self.children(ino) {
+            Ok(children) => children,
+            Err(e) => return reply.error(e),
+        };
+        let parent = match self.node(ino) {
+            Some(Node::Folder { parent, .. }) => *parent,
+            _ => FUSE_ROOT_ID,
+        };
+        let entries = [
+            (ino, FileType::Directory, OsString::from(".")),
+            (parent, FileType::Directory, OsString::from("..")),
+        ]
+        .into_iter()
+        .chain(children.into_iter().map(|(name, child)| {
+            let kind = match self.node(child) {
+                Some(Node::File { .. }) => FileType::RegularFile,
+                _ => FileType::Directory,
+            };
+            (child, kind, name)
+        }));
+        for (i, (child, kind, name)) in entries.enumerate().skip(offset as usize) {
+            // the offset is that of the next entry, to carry on from
+            if reply.add(child, i as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, _flags: i32, reply: ReplyOpen) {
+        if matches!(self.node(ino), Some(Node::Folder { .. })) {
+            return reply.error(EISDIR);
+        }
+        // the first block tells the size, which reads are limited by
+        match self.fetch_block(ino, 0) {
+            Ok((_, Some(total))) => {
+                if let Some(Node::File { size, .. }) = self.node_mut(ino) {
+                    *size = Some(total);
+                }
+            }
+            Ok((_, None)) => {}
+            Err(e) => {
+                errln!("Failed to open a file: {}", e);
+                return reply.error(EIO);
+            }
+        }
+        // reads are passed on as they are, as the size is not known for cached files
+        reply.opened(0, FOPEN_DIRECT_IO);
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let offset = offset.max(0) as u64;
+        let end = offset + u64::from(size);
+        let mut data = Vec::with_capacity(size as usize);
+        let mut index = offset / BLOCK_SIZE;
+        while index * BLOCK_SIZE < end {
+            let block = match self.fetch_block(ino, index) {
+                Ok((block, _)) => block,
+                Err(e) => {
+                    errln!("Failed to read a file: {}", e);
+                    return reply.error(EIO);
+                }
+            };
+            let block_start = index * BLOCK_SIZE;
+            let from = offset.saturating_sub(block_start).min(block.len() as u64) as usize;
+            let to = (end - block_start).min(block.len() as u64) as usize;
+            data.extend_from_slice(&block[from..to]);
+            // a short block is the last one
+            if (block.len() as u64) < BLOCK_SIZE {
+                break;
+            }
+            index += 1;
+        }
+        reply.data(&data);
+    }
+}
@@ -0,0 +1,315 @@
+// Webhooks and emails that are told what each sync brought in, e.g. to post the new files,
+// announcements and recordings of a module into the group chat of the class. They are set up in
+// the `notifications` section of the config file:
+//
+//     "notifications": {
+//         "webhooks": [
+//             { "url": "https://discord.com/api/webhooks/...", "format": "discord" },
+//             { "url": "https://api.telegram.org/bot<token>/sendMessage", "format": "telegram", "chat-id": "-100123" }
+//         ],
+//         "email": {
+//             "host": "smtp.gmail.com", "username": "me@gmail.com", "password": "app password",
+//             "from": "me@gmail.com", "to": ["me@u.nus.edu"]
+//         }
+//     }
+//
+// The email is a digest of the whole day in watch mode, and of the run otherwise.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_smtp::smtp::authentication::Credentials;
+use async_smtp::{
+    ClientSecurity, ClientTlsParameters, EmailAddress, Envelope, SendableEmail, ServerAddress,
+    SmtpClient,
+};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use fluminurs::resource::{OverwriteResult, Resource};
+use fluminurs::Result;
+
+use crate::changes::{module_code, resource_kind};
+
+// the longest messages that each chat accepts, in characters
+const DISCORD_MAX_LENGTH: usize = 2000;
+const SLACK_MAX_LENGTH: usize = 40000;
+const TELEGRAM_MAX_LENGTH: usize = 4096;
+const EMAIL_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct NotificationsConfig {
+    webhooks: Vec<Webhook>,
+    email: Option<EmailConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Webhook {
+    url: String,
+    #[serde(default)]
+    format: WebhookFormat,
+    // the chat to post into, for Telegram
+    chat_id: Option<String>,
+}
+
+#[derive(Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum WebhookFormat {
+    // the digest itself, as JSON
+    #[default]
+    Generic,
+    Discord,
+    Slack,
+    Telegram,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct EmailConfig {
+    host: String,
+    // defaults to the usual port of the security, e.g. 465 for TLS
+    port: Option<u16>,
+    #[serde(default)]
+    security: SmtpSecurity,
+    username: Option<String>,
+    password: Option<String>,
+    from: String,
+    to: Vec<String>,
+}
+
+#[derive(Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SmtpSecurity {
+    #[default]
+    Tls,
+    StartTls,
+    None,
+}
+
+#[derive(Default, Clone, Serialize)]
+struct ModuleDigest {
+    files: Vec<String>,
+    announcements: Vec<String>,
+    recordings: Vec<String>,
+}
+
+/// The new files, announcements and recordings of each module, gathered over a sync
+#[derive(Default)]
+pub struct Digest {
+    modules: Mutex<BTreeMap<String, ModuleDigest>>,
+    // what came in since the last email
+    unmailed: Mutex<BTreeMap<String, ModuleDigest>>,
+    // when the next email is due, or none to send one after every sync
+    next_email: Mutex<Option<Instant>>,
+}
+
+fn name(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+// The names, shortened to the first few with a count of the rest
+fn list(names: &[String]) -> String {
+    const SHOWN: usize = 5;
+    let mut list = names
+        .iter()
+        .take(SHOWN)
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    if names.len() > SHOWN {
+        list.push_str(&format!(" and {} more", names.len() - SHOWN));
+    }
+    list
+}
+
+fn truncate(text: String, max_length: usize) -> String {
+    if text.chars().count() <= max_length {
+        text
+    } else {
+        let mut truncated = text.chars().take(max_length - 1).collect::<String>();
+        truncated.push('…');
+        truncated
+    }
+}
+
+impl NotificationsConfig {
+    pub fn is_empty(&self) -> bool {
+        self.webhooks.is_empty() && self.email.is_none()
+    }
+}
+
+impl EmailConfig {
+    async fn send(&self, subject: &str, text: &str) -> Result<()> {
+        let from = EmailAddress::new(self.from.clone()).map_err(|_| "Invalid sender address")?;
+        let to = self
+            .to
+            .iter()
+            .map(|to| EmailAddress::new(to.clone()))
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| "Invalid recipient address")?;
+        let envelope = Envelope::new(Some(from), to).map_err(|_| "No recipients for the email")?;
+        // the body is encoded, as file names are not always ASCII, in lines of at most 76 characters
+        let body = base64::encode(text.replace('\n', "\r\n"))
+            .as_bytes()
+            .chunks(76)
+            .map(|line| std::str::from_utf8(line).expect("Base64 is ASCII"))
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        let message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+             Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n{}",
+            self.from,
+            self.to.join(", "),
+            subject,
+            Local::now().to_rfc2822(),
+            body,
+        );
+        let email = SendableEmail::new(envelope, "fluminurs-digest", message);
+
+        let tls =
+            || ClientTlsParameters::new(self.host.clone(), async_native_tls::TlsConnector::new());
+        let (security, default_port) = match self.security {
+            SmtpSecurity::Tls => (ClientSecurity::Wrapper(tls()), 465),
+            SmtpSecurity::StartTls => (ClientSecurity::Required(tls()), 587),
+            SmtpSecurity::None => (ClientSecurity::None, 25),
+        };
+        let mut client = SmtpClient::with_security(
+            ServerAddress::new(self.host.clone(), self.port.unwrap_or(default_port)),
+            security,
+        );
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            client = client.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        client
+            .into_transport()
+            .connect_and_send(email)
+            .await
+            .map(|_| ())
+            .map_err(|_| "Unable to send the email")
+    }
+}
+
+impl Digest {
+    /// A digest that emails what came in once a day, rather than after every sync
+    pub fn daily() -> Digest {
+        Digest {
+            next_email: Mutex::new(Some(Instant::now() + EMAIL_INTERVAL)),
+            ..Digest::default()
+        }
+    }
+
+    /// Notes a resource that was downloaded for the first time
+    pub fn record<T: Resource>(&self, file: &T, result: &Result<OverwriteResult>) {
+        if !matches!(result, Ok(OverwriteResult::NewFile)) {
+            return;
+        }
+        let module = module_code(file.path()).unwrap_or_default();
+        let mut modules = self.modules.lock().unwrap();
+        let digest = modules.entry(module).or_default();
+        let names = match resource_kind::<T>() {
+            "File" => &mut digest.files,
+            "AnnouncementFile" => &mut digest.announcements,
+            "InternalVideo" | "ExternalVideo" | "WebLectureVideo" | "ZoomRecording" => {
+                &mut digest.recordings
+            }
+            _ => return,
+        };
+        names.push(name(file.path()));
+    }
+
+    fn text(modules: &BTreeMap<String, ModuleDigest>) -> String {
+        let mut text = String::from("New on LumiNUS:");
+        for (module, digest) in modules {
+            text.push_str(&format!("\n\n{}", module));
+            for (label, names) in [
+                ("Files", &digest.files),
+                ("Announcements", &digest.announcements),
+                ("Recordings", &digest.recordings),
+            ] {
+                if !names.is_empty() {
+                    text.push_str(&format!("\n{} ({}): {}", label, names.len(), list(names)));
+                }
+            }
+        }
+        text
+    }
+
+    /// Posts what was gathered to every webhook, if anything new came in, and starts over. The
+    /// email is sent along if it is due.
+    pub async fn send(&self, config: &NotificationsConfig) {
+        let modules = std::mem::take(&mut *self.modules.lock().unwrap());
+        if let Some(email) = &config.email {
+            self.send_email(email, &modules).await;
+        }
+        if modules.is_empty() || config.webhooks.is_empty() {
+            return;
+        }
+        let text = Digest::text(&modules);
+        let client = reqwest::Client::new();
+        for webhook in &config.webhooks {
+            let payload = match webhook.format {
+                WebhookFormat::Generic => json!({ "modules": modules }),
+                WebhookFormat::Discord => {
+                    json!({ "content": truncate(text.clone(), DISCORD_MAX_LENGTH) })
+                }
+                WebhookFormat::Slack => json!({ "text": truncate(text.clone(), SLACK_MAX_LENGTH) }),
+                WebhookFormat::Telegram => json!({
+                    "chat_id": webhook.chat_id,
+                    "text": truncate(text.clone(), TELEGRAM_MAX_LENGTH),
+                }),
+            };
+            let sent = client
+                .post(&webhook.url)
+                .json(&payload)
+                .send()
+                .await
+                .and_then(|res| res.error_for_status());
+            // the URL is left out, as it holds the token of the webhook
+            match sent {
+                Ok(_) => {}
+                Err(e) => match e.status() {
+                    Some(status) => errln!("Failed to notify webhook: {}", status),
+                    None => errln!("Failed to notify webhook: unable to reach it"),
+                },
+            }
+        }
+    }
+
+    async fn send_email(&self, email: &EmailConfig, modules: &BTreeMap<String, ModuleDigest>) {
+        let unmailed = {
+            let mut unmailed = self.unmailed.lock().unwrap();
+            for (module, digest) in modules {
+                let merged = unmailed.entry(module.clone()).or_default();
+                merged.files.extend(digest.files.iter().cloned());
+                merged
+                    .announcements
+                    .extend(digest.announcements.iter().cloned());
+                merged.recordings.extend(digest.recordings.iter().cloned());
+            }
+            let mut next_email = self.next_email.lock().unwrap();
+            match *next_email {
+                Some(due) if due > Instant::now() => return,
+                Some(due) => *next_email = Some(due + EMAIL_INTERVAL),
+                None => {}
+            }
+            std::mem::take(&mut *unmailed)
+        };
+        // nothing is sent on days without anything new
+        if unmailed.is_empty() {
+            return;
+        }
+        let subject = format!("New on LumiNUS, {}", Local::now().format("%-d %b %Y"));
+        let text = Digest::text(&unmailed);
+        if let Err(e) = email.send(&subject, &text).await {
+            errln!("Failed to email the digest: {}", e);
+        }
+    }
+}
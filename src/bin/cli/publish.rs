@@ -0,0 +1,148 @@
+// Publishing for teaching staff: files that are added or changed locally in designated folders
+// are uploaded to the corresponding workbin folders. The manifest tells the files that fluminurs
+// wrote apart from the rest, so that only those added or changed by the user are published, and
+// not e.g. the copies that `--updated rename` moves aside.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use fluminurs::file::File;
+use fluminurs::module::Module;
+use fluminurs::resource::Resource;
+use fluminurs::{Api, Result};
+
+use crate::manifest::Manifest;
+
+// Splits `CS2103T/Tutorials` into the module code and the folder within the workbin
+fn split_folder(folder: &str) -> Option<(String, PathBuf)> {
+    let mut components = Path::new(folder)
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)));
+    let code = components
+        .next()?
+        .as_os_str()
+        .to_string_lossy()
+        .into_owned();
+    Some((code, components.collect()))
+}
+
+fn is_publishable(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .file_name()
+            .map(|name| {
+                let name = name.to_string_lossy();
+                // temporary files of downloads, and hidden files
+                !name.starts_with("~!") && !name.starts_with('.')
+            })
+            .unwrap_or(false)
+}
+
+/// Uploads the files directly in each of the `folders` (given as `MODULE/FOLDER`, relative to
+/// `destination`) that fluminurs did not download, or were changed since they were downloaded.
+/// Changed files are uploaded under their name on the server, rather than their local one.
+/// `remote_files` are all the workbin files of the modules.
+pub async fn publish(
+    api: &Api,
+    modules: &[Module],
+    remote_files: &[File],
+    destination: &Path,
+    folders: &[String],
+) -> Result<()> {
+    let remote_files = remote_files
+        .iter()
+        .map(|file| (file.path(), file))
+        .collect::<HashMap<_, _>>();
+    let mut manifest = Manifest::load(destination);
+    let mut published = false;
+    for folder in folders {
+        let (code, relative_path) = split_folder(folder).ok_or("Invalid folder to publish")?;
+        let module = match modules
+            .iter()
+            .find(|module| module.code == code && module.is_teaching())
+        {
+            Some(module) => module,
+            None => {
+                errln!(
+                    "Not publishing {}, as you are not teaching {}",
+                    folder,
+                    code
+                );
+                continue;
+            }
+        };
+        let handle = match module
+            .workbin_root(|code| Path::new(code).to_owned())
+            .subfolder(api, &relative_path)
+            .await
+        {
+            Ok(handle) => handle,
+            Err(e) => {
+                errln!("Not publishing {}: {}", folder, e);
+                continue;
+            }
+        };
+
+        let local_folder = destination.join(handle.path());
+        let entries = match fs::read_dir(&local_folder) {
+            Ok(entries) => entries,
+            Err(_) => {
+                errln!("Not publishing {}, as it does not exist locally", folder);
+                continue;
+            }
+        };
+        let mut local_files = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| is_publishable(path))
+            .collect::<Vec<_>>();
+        local_files.sort();
+
+        for local_file in local_files {
+            let remote_path = match local_file.strip_prefix(destination) {
+                Ok(remote_path) => remote_path,
+                Err(_) => continue,
+            };
+            let (change, name) = if manifest.contains(remote_path) {
+                if manifest.is_unchanged(remote_path) {
+                    continue;
+                }
+                match remote_files.get(remote_path) {
+                    Some(file) => ("changed", file.name().to_owned()),
+                    None => {
+                        errln!(
+                            "Not publishing {}, as it is no longer on LumiNUS",
+                            remote_path.display()
+                        );
+                        continue;
+                    }
+                }
+            } else if remote_files.contains_key(remote_path) {
+                // e.g. downloaded before the manifest was kept
+                continue;
+            } else {
+                let name = local_file
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned();
+                ("new", name)
+            };
+            match handle.upload_as(api, &local_file, &name).await {
+                Ok(()) => {
+                    outln!("Published {} file {}", change, remote_path.display());
+                    // it is now as it is on the server, so it is only published again once changed
+                    if change == "changed" {
+                        manifest.add(remote_path);
+                        published = true;
+                    }
+                }
+                Err(e) => errln!("Failed publishing {}: {}", remote_path.display(), e),
+            }
+        }
+    }
+    if published {
+        manifest.save()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+// A JSON record of everything a run did, for dashboards and other tools to build on.
+// It is written anew at the end of each run, so in watch mode it covers the last sync.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+
+use fluminurs::resource::{OverwriteResult, Resource};
+use fluminurs::Result;
+
+use crate::changes::resource_kind;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportEntry {
+    kind: &'static str,
+    id: String,
+    path: String,
+    // what was done, as in `OverwriteResult`, or `failed`
+    action: &'static str,
+    // where the old copy was moved to, when renamed
+    renamed_path: Option<String>,
+    // the size of the file that was written, if anything was
+    bytes: Option<u64>,
+    duration_ms: u128,
+    error: Option<&'static str>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Report<'a> {
+    started: String,
+    finished: String,
+    resources: &'a [ReportEntry],
+}
+
+pub struct RunReport {
+    path: PathBuf,
+    started: Mutex<SystemTime>,
+    entries: Mutex<Vec<ReportEntry>>,
+}
+
+fn format_time(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+impl RunReport {
+    pub fn new(path: PathBuf) -> RunReport {
+        RunReport {
+            path,
+            started: Mutex::new(SystemTime::now()),
+            entries: Mutex::new(vec![]),
+        }
+    }
+
+    /// Records the final result of downloading `file` to `real_path`
+    pub fn record<T: Resource>(
+        &self,
+        file: &T,
+        real_path: &Path,
+        result: &Result<OverwriteResult>,
+        duration: Duration,
+    ) {
+        let (action, renamed_path) = match result {
+            Ok(OverwriteResult::NewFile) => ("newFile", None),
+            Ok(OverwriteResult::AlreadyHave) => ("alreadyHave", None),
+            Ok(OverwriteResult::Skipped) => ("skipped", None),
+            Ok(OverwriteResult::Overwritten) => ("overwritten", None),
+            Ok(OverwriteResult::Renamed { renamed_path }) => {
+                ("renamed", Some(renamed_path.to_string_lossy().into_owned()))
+            }
+            Err(_) => ("failed", None),
+        };
+        let bytes = match result {
+            Ok(OverwriteResult::NewFile)
+            | Ok(OverwriteResult::Overwritten)
+            | Ok(OverwriteResult::Renamed { .. }) => {
+                fs::metadata(real_path).ok().map(|metadata| metadata.len())
+            }
+            _ => None,
+        };
+        self.entries.lock().unwrap().push(ReportEntry {
+            kind: resource_kind::<T>(),
+            id: file.id().to_owned(),
+            path: file.path().to_string_lossy().into_owned(),
+            action,
+            renamed_path,
+            bytes,
+            duration_ms: duration.as_millis(),
+            error: result.as_ref().err().copied(),
+        });
+    }
+
+    /// Writes out what was recorded since the last time, and starts over for the next run
+    pub fn write(&self) -> Result<()> {
+        let entries = std::mem::take(&mut *self.entries.lock().unwrap());
+        let started = std::mem::replace(&mut *self.started.lock().unwrap(), SystemTime::now());
+        let report = Report {
+            started: format_time(started),
+            finished: format_time(SystemTime::now()),
+            resources: &entries,
+        };
+        let content =
+            serde_json::to_string_pretty(&report).map_err(|_| "Unable to serialise run report")?;
+        fs::write(&self.path, content).map_err(|_| "Unable to write run report")
+    }
+}
@@ -0,0 +1,46 @@
+// Downloads that fail for reasons that are likely to pass, e.g. congestion on the server, are
+// tried once more at the end of the run instead of being left for the next run.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use futures_util::future::{self, BoxFuture};
+use futures_util::{stream, StreamExt};
+
+use crate::DownloadContext;
+
+// Downloads the resource again and reports the result, telling whether it succeeded this time
+pub type Retry = Box<dyn for<'a> FnOnce(&'a DownloadContext) -> BoxFuture<'a, bool> + Send>;
+
+#[derive(Default)]
+pub struct RetryQueue {
+    // the path of each failed download, with how to try it again
+    failed: Mutex<Vec<(PathBuf, Retry)>>,
+}
+
+impl RetryQueue {
+    pub fn push(&self, path: PathBuf, retry: Retry) {
+        self.failed.lock().unwrap().push((path, retry));
+    }
+
+    /// Tries the failed downloads again, and lists the ones that failed once more
+    pub async fn run(&self, context: &DownloadContext, parallelism: usize) {
+        let failed = std::mem::take(&mut *self.failed.lock().unwrap());
+        if failed.is_empty() {
+            return;
+        }
+        outln!("Retrying {} failed downloads", failed.len());
+        let still_failed = stream::iter(failed)
+            .map(|(path, retry)| async move { (path, retry(context).await) })
+            .buffer_unordered(parallelism)
+            .filter_map(|(path, succeeded)| future::ready((!succeeded).then_some(path)))
+            .collect::<Vec<_>>()
+            .await;
+        if !still_failed.is_empty() {
+            errln!("Failed to download, even after retrying:");
+            for path in &still_failed {
+                errln!("  {}", path.to_string_lossy());
+            }
+        }
+    }
+}
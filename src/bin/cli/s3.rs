@@ -0,0 +1,376 @@
+// Downloads into an S3 (or S3-compatible, like MinIO) bucket, given as `s3://bucket/prefix` in
+// place of a download folder. Each resource is downloaded into a temporary file first, as S3 needs
+// the size of an object before it is uploaded. When each resource was last updated is kept in the
+// metadata of its object, to tell whether it changed, like the modification time of a local file.
+//
+// The bucket is reached with the usual environment variables of the AWS tools:
+// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, `AWS_SESSION_TOKEN` (if any), `AWS_REGION` and
+// `AWS_ENDPOINT_URL` (for other services than AWS).
+
+use std::path::{Component, Path};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::header::{CONTENT_LENGTH, LAST_MODIFIED};
+use reqwest::{Client, Method, Response, StatusCode, Url};
+use sha2::{Digest, Sha256};
+use tokio_util::io::ReaderStream;
+
+use fluminurs::resource::{OverwriteMode, OverwriteResult, Resource};
+use fluminurs::Result;
+
+use crate::clean::TEMP_FILE_PREFIX;
+
+pub const S3_SCHEME: &str = "s3://";
+const DEFAULT_REGION: &str = "us-east-1";
+const LAST_UPDATED_HEADER: &str = "x-amz-meta-last-updated";
+const VERSIONS_DIR_NAME: &str = ".versions";
+// the SHA-256 of an empty payload, which most requests have
+const EMPTY_PAYLOAD_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+pub struct Bucket {
+    client: Client,
+    endpoint: Url,
+    region: String,
+    bucket: String,
+    prefix: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+pub fn is_s3_url(destination: &str) -> bool {
+    destination.starts_with(S3_SCHEME)
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Escapes all but the unreserved characters, and slashes if `keep_slashes`, as SigV4 expects
+fn uri_encode(text: &str, keep_slashes: bool) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if keep_slashes => "/".to_owned(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn key_of(path: &Path) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Like the stem and extension of a local file, with everything after the first dot as extension
+fn split_name(key: &str) -> (&str, &str, Option<&str>) {
+    let (dir, name) = match key.rfind('/') {
+        Some(i) => (&key[..=i], &key[i + 1..]),
+        None => ("", key),
+    };
+    match name.find('.') {
+        Some(i) => (dir, &name[..i], Some(&name[i + 1..])),
+        None => (dir, name, None),
+    }
+}
+
+fn with_name(dir: &str, stem: &str, extension: Option<&str>) -> String {
+    match extension {
+        Some(extension) => format!("{}{}.{}", dir, stem, extension),
+        None => format!("{}{}", dir, stem),
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+impl Bucket {
+    /// The bucket and prefix of an `s3://bucket/prefix` URL, with the credentials from the
+    /// environment
+    pub fn from_url(url: &str) -> Result<Bucket> {
+        let rest = url.strip_prefix(S3_SCHEME).ok_or("Not an S3 URL")?;
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            return Err("S3 URL has no bucket");
+        }
+        let region = env_var("AWS_REGION")
+            .or_else(|| env_var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| DEFAULT_REGION.to_owned());
+        let endpoint = env_var("AWS_ENDPOINT_URL")
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+        Ok(Bucket {
+            client: Client::new(),
+            endpoint: Url::parse(&endpoint).map_err(|_| "Invalid S3 endpoint")?,
+            region,
+            bucket: bucket.to_owned(),
+            prefix: prefix.trim_matches('/').to_owned(),
+            access_key: env_var("AWS_ACCESS_KEY_ID").ok_or("AWS_ACCESS_KEY_ID is not set")?,
+            secret_key: env_var("AWS_SECRET_ACCESS_KEY")
+                .ok_or("AWS_SECRET_ACCESS_KEY is not set")?,
+            session_token: env_var("AWS_SESSION_TOKEN"),
+        })
+    }
+
+    fn object_key(&self, path: &Path) -> String {
+        match (self.prefix.as_str(), key_of(path)) {
+            ("", key) => key,
+            (prefix, key) => format!("{}/{}", prefix, key),
+        }
+    }
+
+    // Sends a request for the object, signed with Signature Version 4. Objects are addressed by
+    // path, which every S3-compatible service understands.
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        mut headers: Vec<(String, String)>,
+        body: Option<(reqwest::Body, u64)>,
+    ) -> Result<Response> {
+        let path = format!(
+            "{}/{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            uri_encode(&self.bucket, false),
+            uri_encode(key, true)
+        );
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or(""), port),
+            None => url.host_str().unwrap_or("").to_owned(),
+        };
+        let now = Utc::now();
+        let date_time = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        // uploads are streamed, so their payload is not hashed, which S3 allows over HTTPS
+        let payload_hash = match body {
+            Some(_) => "UNSIGNED-PAYLOAD",
+            None => EMPTY_PAYLOAD_HASH,
+        };
+
+        headers.push(("host".to_owned(), host));
+        headers.push(("x-amz-content-sha256".to_owned(), payload_hash.to_owned()));
+        headers.push(("x-amz-date".to_owned(), date_time.clone()));
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token".to_owned(), token.clone()));
+        }
+        headers.sort();
+        let canonical_headers = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect::<String>();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method, path, canonical_headers, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            date_time,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = hmac(format!("AWS4{}", self.secret_key).as_bytes(), &date);
+        let key = hmac(&key, &self.region);
+        let key = hmac(&key, "s3");
+        let key = hmac(&key, "aws4_request");
+        let signature = hex(&hmac(&key, &string_to_sign));
+
+        let mut request = self.client.request(method, url).header(
+            "authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.access_key, scope, signed_headers, signature
+            ),
+        );
+        for (name, value) in headers.into_iter().filter(|(name, _)| name != "host") {
+            request = request.header(name, value);
+        }
+        if let Some((body, length)) = body {
+            request = request.header(CONTENT_LENGTH, length).body(body);
+        }
+        request.send().await.map_err(|_| "Unable to reach S3")
+    }
+
+    // When the resource in the object was last updated, if the object exists
+    async fn last_updated(&self, key: &str) -> Result<Option<SystemTime>> {
+        let res = self.send(Method::HEAD, key, vec![], None).await?;
+        match res.status() {
+            StatusCode::NOT_FOUND => return Ok(None),
+            StatusCode::FORBIDDEN => return Err("Access to the S3 bucket was denied"),
+            status if !status.is_success() => return Err("Unable to look up S3 object"),
+            _ => {}
+        }
+        let headers = res.headers();
+        let from_metadata = headers
+            .get(LAST_UPDATED_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        // objects that were put there by something else only have the time they were uploaded
+        let uploaded = headers
+            .get(LAST_MODIFIED)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+            .map(SystemTime::from);
+        Ok(from_metadata.or(uploaded).or(Some(UNIX_EPOCH)))
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.last_updated(key).await.map(|time| time.is_some())
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let source = format!(
+            "/{}/{}",
+            uri_encode(&self.bucket, false),
+            uri_encode(from, true)
+        );
+        let res = self
+            .send(
+                Method::PUT,
+                to,
+                vec![("x-amz-copy-source".to_owned(), source)],
+                None,
+            )
+            .await?;
+        if res.status().is_success() {
+            Ok(())
+        } else {
+            Err("Unable to copy S3 object")
+        }
+    }
+
+    // The first key of the form `<dir><stem>_1.<ext>` (or without the number) that is not taken
+    async fn free_key(&self, dir: &str, stem: &str, extension: Option<&str>) -> Result<String> {
+        let mut key = with_name(dir, stem, extension);
+        let mut i = 0;
+        while self.exists(&key).await? {
+            i += 1;
+            key = with_name(dir, &format!("{}_{}", stem, i), extension);
+        }
+        Ok(key)
+    }
+
+    // Decides whether to upload the resource, keeping a copy of the old version if asked to
+    async fn prepare_key(
+        &self,
+        key: &str,
+        overwrite: OverwriteMode,
+        last_updated: SystemTime,
+    ) -> Result<(bool, OverwriteResult)> {
+        let old_time = match self.last_updated(key).await? {
+            Some(old_time) => old_time,
+            None => return Ok((true, OverwriteResult::NewFile)),
+        };
+        if unix_time(last_updated) <= unix_time(old_time) {
+            return Ok((false, OverwriteResult::AlreadyHave));
+        }
+        let (dir, stem, extension) = split_name(key);
+        let renamed_key = match overwrite {
+            OverwriteMode::Skip => return Ok((false, OverwriteResult::Skipped)),
+            OverwriteMode::Overwrite => return Ok((true, OverwriteResult::Overwritten)),
+            OverwriteMode::Rename => {
+                let date = DateTime::<Local>::from(old_time).format("%Y-%m-%d");
+                let stem = format!("{}_autorename_{}", stem, date);
+                self.free_key(dir, &stem, extension).await?
+            }
+            // old versions are kept, but not pruned, as that needs listing the bucket
+            OverwriteMode::Versioned(_) => {
+                let time = DateTime::<Local>::from(old_time).format("%Y-%m-%d_%H%M%S");
+                let dir = format!("{}{}/", dir, VERSIONS_DIR_NAME);
+                let stem = format!("{}_{}", stem, time);
+                self.free_key(&dir, &stem, extension).await?
+            }
+        };
+        self.copy(key, &renamed_key).await?;
+        Ok((
+            true,
+            OverwriteResult::Renamed {
+                renamed_path: format!("{}{}/{}", S3_SCHEME, self.bucket, renamed_key).into(),
+            },
+        ))
+    }
+
+    /// Downloads the resource into its object, like `Resource::download` does into a file
+    pub async fn download<T: Resource>(
+        &self,
+        api: &fluminurs::Api,
+        resource: &T,
+        overwrite: OverwriteMode,
+    ) -> Result<OverwriteResult> {
+        let key = self.object_key(resource.path());
+        let (should_upload, result) = self
+            .prepare_key(&key, overwrite, resource.last_updated())
+            .await?;
+        if !should_upload {
+            return Ok(result);
+        }
+
+        let temp_path = std::env::temp_dir().join(format!(
+            "{}s3-{}",
+            TEMP_FILE_PREFIX,
+            hex(&Sha256::digest(key.as_bytes()))
+        ));
+        let uploaded = async {
+            let mut file = tokio::fs::File::create(&temp_path)
+                .await
+                .map_err(|_| "Unable to create temporary file")?;
+            resource.download_to_writer(api, &mut file).await?;
+            drop(file);
+            let file = tokio::fs::File::open(&temp_path)
+                .await
+                .map_err(|_| "Unable to read temporary file")?;
+            let length = file
+                .metadata()
+                .await
+                .map_err(|_| "Unable to read temporary file")?
+                .len();
+            let res = self
+                .send(
+                    Method::PUT,
+                    &key,
+                    vec![(
+                        LAST_UPDATED_HEADER.to_owned(),
+                        unix_time(resource.last_updated()).to_string(),
+                    )],
+                    Some((reqwest::Body::wrap_stream(ReaderStream::new(file)), length)),
+                )
+                .await?;
+            if res.status().is_success() {
+                Ok(())
+            } else {
+                Err("S3 refused the upload")
+            }
+        }
+        .await;
+        tokio::fs::remove_file(&temp_path).await.ok();
+        uploaded.map(|_| result)
+    }
+}
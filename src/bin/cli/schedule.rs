@@ -0,0 +1,291 @@
+// When the daemon syncs, either every so many minutes or at the times of a cron expression, and
+// the quiet hours during which videos, the heaviest downloads, are left for a later sync.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, NaiveTime, TimeZone, Timelike};
+
+use fluminurs::resource::Resource;
+use fluminurs::Result;
+
+use crate::changes::resource_kind;
+
+// every night at 3am, when nobody minds the bandwidth
+pub const DEFAULT_CRON: &str = "0 3 * * *";
+// how far ahead to look for the next time that a cron expression matches, in minutes
+const MAX_LOOKAHEAD: i64 = 366 * 24 * 60;
+
+pub enum Schedule {
+    Every(Duration),
+    Cron(Cron),
+}
+
+// The values each field of a cron expression matches
+pub struct Cron {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    // days match either field when both are restricted, as in cron
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+#[derive(Clone, Copy)]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+// Parses a field like `*`, `5`, `1-5`, `*/15` or `0,30`, into which of `min..=max` it matches
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>> {
+    let mut matches = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                Some(
+                    step.parse::<u32>()
+                        .ok()
+                        .filter(|step| *step > 0)
+                        .ok_or("Invalid step in cron expression")?,
+                ),
+            ),
+            None => (part, None),
+        };
+        let (from, to) = match range {
+            "*" => (min, max),
+            range => {
+                let parse = |value: &str| {
+                    value
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|value| (min..=max).contains(value))
+                        .ok_or("Invalid value in cron expression")
+                };
+                match range.split_once('-') {
+                    Some((from, to)) => (parse(from)?, parse(to)?),
+                    // a single value with a step, like `5/15`, goes on to the end
+                    None if step.is_some() => (parse(range)?, max),
+                    None => (parse(range)?, parse(range)?),
+                }
+            }
+        };
+        for value in (from..=to).step_by(step.unwrap_or(1) as usize) {
+            matches[value as usize] = true;
+        }
+    }
+    Ok(matches)
+}
+
+impl Cron {
+    /// Parses the five fields of a cron expression: minute, hour, day, month and weekday
+    pub fn parse(expression: &str) -> Result<Cron> {
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err("Cron expression needs 5 fields: minute hour day month weekday");
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // Sunday is both 0 and 7
+        weekdays[0] |= weekdays[7];
+        Ok(Cron {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            days_restricted: fields[2] != "*",
+            weekdays_restricted: fields[4] != "*",
+        })
+    }
+
+    fn matches(&self, time: &DateTime<Local>) -> bool {
+        let day = self.days[time.day() as usize];
+        let weekday = self.weekdays[time.weekday().num_days_from_sunday() as usize];
+        let day_matches = match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        };
+        self.minutes[time.minute() as usize]
+            && self.hours[time.hour() as usize]
+            && self.months[time.month() as usize]
+            && day_matches
+    }
+
+    /// The next time after `now` that the expression matches
+    fn next_after(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = now.naive_local().with_second(0)?.with_nanosecond(0)?;
+        (1..=MAX_LOOKAHEAD)
+            .map(|minutes| start + chrono::Duration::minutes(minutes))
+            // times skipped by daylight saving do not exist, and are left out
+            .filter_map(|time| Local.from_local_datetime(&time).earliest())
+            .find(|time| self.matches(time))
+    }
+}
+
+impl Schedule {
+    /// Whether the daemon syncs as soon as it starts, rather than waiting for the schedule
+    pub fn syncs_at_start(&self) -> bool {
+        matches!(self, Schedule::Every(_))
+    }
+
+    /// How long to wait for the next sync, and a message that says when it is
+    pub fn next(&self) -> (Duration, String) {
+        match self {
+            Schedule::Every(interval) => (
+                *interval,
+                format!("Next sync in {} minutes", interval.as_secs() / 60),
+            ),
+            Schedule::Cron(cron) => {
+                let now = Local::now();
+                match cron.next_after(now) {
+                    Some(next) => (
+                        (next - now).to_std().unwrap_or_default(),
+                        format!("Next sync at {}", next.format("%Y-%m-%d %H:%M")),
+                    ),
+                    // e.g. the 31st of February, which never comes
+                    None => (
+                        Duration::from_secs(MAX_LOOKAHEAD as u64 * 60),
+                        "The schedule never matches, so no sync is due".to_owned(),
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// Whether the resource is a video, which quiet hours hold back
+pub fn is_video<T: Resource>() -> bool {
+    matches!(
+        resource_kind::<T>(),
+        "InternalVideo" | "ExternalVideo" | "WebLectureVideo" | "ZoomRecording"
+    )
+}
+
+impl QuietHours {
+    /// Parses a range of times like `09:00-18:00`, which may go past midnight
+    pub fn parse(range: &str) -> Result<QuietHours> {
+        let (start, end) = range
+            .split_once('-')
+            .ok_or("Quiet hours should look like 09:00-18:00")?;
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time.trim(), "%H:%M")
+                .map_err(|_| "Quiet hours should look like 09:00-18:00")
+        };
+        Ok(QuietHours {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.is_quiet_at(Local::now().time())
+    }
+
+    fn is_quiet_at(&self, now: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= now && now < self.end
+        } else {
+            now >= self.start || now < self.end
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms(hour, minute, 0)
+    }
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.ymd(year, month, day).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn parses_cron_fields() {
+        assert_eq!(
+            parse_field("*/15", 0, 59)
+                .unwrap()
+                .iter()
+                .filter(|matches| **matches)
+                .count(),
+            4
+        );
+        let hours = parse_field("1-3,20", 0, 23).unwrap();
+        assert!(hours[1] && hours[2] && hours[3] && hours[20]);
+        assert!(!hours[0] && !hours[4] && !hours[19]);
+        // a single value with a step goes on to the end
+        let minutes = parse_field("50/5", 0, 59).unwrap();
+        assert!(minutes[50] && minutes[55] && !minutes[45]);
+    }
+
+    #[test]
+    fn rejects_invalid_cron_fields() {
+        assert!(parse_field("60", 0, 59).is_err());
+        assert!(parse_field("0", 1, 31).is_err());
+        assert!(parse_field("*/0", 0, 59).is_err());
+        assert!(parse_field("a-b", 0, 59).is_err());
+        assert!(parse_field("", 0, 59).is_err());
+        assert!(Cron::parse("0 3 * *").is_err());
+        assert!(Cron::parse("0 3 * * * *").is_err());
+        assert!(Cron::parse("0 24 * * *").is_err());
+    }
+
+    #[test]
+    fn finds_the_next_matching_time() {
+        let cron = Cron::parse(DEFAULT_CRON).unwrap();
+        assert_eq!(
+            cron.next_after(local(2023, 6, 14, 12, 30)),
+            Some(local(2023, 6, 15, 3, 0))
+        );
+        assert_eq!(
+            cron.next_after(local(2023, 6, 14, 2, 59)),
+            Some(local(2023, 6, 14, 3, 0))
+        );
+        // Sunday is both 0 and 7
+        let sundays = Cron::parse("0 12 * * 7").unwrap();
+        assert_eq!(
+            sundays.next_after(local(2023, 6, 14, 0, 0)),
+            Some(local(2023, 6, 18, 12, 0))
+        );
+        // days match either field when both are restricted
+        let either = Cron::parse("0 12 15 * 0").unwrap();
+        assert_eq!(
+            either.next_after(local(2023, 6, 14, 0, 0)),
+            Some(local(2023, 6, 15, 12, 0))
+        );
+        assert!(Cron::parse("0 0 31 2 *")
+            .unwrap()
+            .next_after(local(2023, 6, 14, 0, 0))
+            .is_none());
+    }
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        let quiet = QuietHours::parse("09:00-18:00").unwrap();
+        assert!(!quiet.is_quiet_at(time(8, 59)));
+        assert!(quiet.is_quiet_at(time(9, 0)));
+        assert!(quiet.is_quiet_at(time(17, 59)));
+        assert!(!quiet.is_quiet_at(time(18, 0)));
+    }
+
+    #[test]
+    fn quiet_hours_past_midnight() {
+        let quiet = QuietHours::parse("22:00 - 06:30").unwrap();
+        assert!(quiet.is_quiet_at(time(22, 0)));
+        assert!(quiet.is_quiet_at(time(0, 0)));
+        assert!(quiet.is_quiet_at(time(6, 29)));
+        assert!(!quiet.is_quiet_at(time(6, 30)));
+        assert!(!quiet.is_quiet_at(time(12, 0)));
+    }
+
+    #[test]
+    fn rejects_invalid_quiet_hours() {
+        assert!(QuietHours::parse("09:00").is_err());
+        assert!(QuietHours::parse("9am-5pm").is_err());
+        assert!(QuietHours::parse("25:00-06:00").is_err());
+    }
+}
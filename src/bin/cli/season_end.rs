@@ -0,0 +1,191 @@
+// End of semester cleanup: archiving the modules of a finished term, optionally compressing
+// their videos first, and forgetting the state that was kept for them
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use fluminurs::util::sanitise_filename;
+use fluminurs::Result;
+use tokio::process::Command;
+
+use crate::archive::{is_video, write_zip};
+use crate::subscriptions::Subscriptions;
+
+// The same prefix as the temporary files of downloads, which are left behind by interrupted syncs
+const TEMP_FILE_PREFIX: &str = "~!";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    // the module folders are moved into a folder of their own
+    Snapshot,
+}
+
+pub struct SeasonEndOptions {
+    pub root: PathBuf,
+    pub term: String,
+    pub archive_format: ArchiveFormat,
+    pub ffmpeg_path: String,
+}
+
+fn is_temp_file(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy().starts_with(TEMP_FILE_PREFIX))
+        .unwrap_or(false)
+}
+
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            list_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn remove_temp_files(files: &mut Vec<PathBuf>) -> usize {
+    let (temp_files, rest): (Vec<_>, Vec<_>) = files.drain(..).partition(|file| is_temp_file(file));
+    *files = rest;
+    temp_files
+        .iter()
+        .filter(|file| fs::remove_file(file).is_ok())
+        .count()
+}
+
+// Re-encodes the video into a space-saving preset, keeping it only if it turns out smaller
+async fn compress_video(ffmpeg_path: &str, video: &Path) -> Result<bool> {
+    let file_name = video.file_name().ok_or("Invalid video path")?;
+    let temp = video.with_file_name(format!(
+        "{}{}",
+        TEMP_FILE_PREFIX,
+        file_name.to_string_lossy()
+    ));
+    let success = Command::new(ffmpeg_path)
+        .arg("-y")
+        .arg("-i")
+        .arg(video)
+        .args(["-c:v", "libx264", "-preset", "slow", "-crf", "28"])
+        .args(["-c:a", "aac", "-b:a", "96k"])
+        .arg(&temp)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .map_err(|_| "Unable to start ffmpeg")?
+        .success();
+    let smaller = success
+        && match (fs::metadata(video), fs::metadata(&temp)) {
+            (Ok(original), Ok(compressed)) => compressed.len() < original.len(),
+            _ => false,
+        };
+    if smaller {
+        fs::rename(&temp, video).map_err(|_| "Unable to replace video")?;
+    } else {
+        fs::remove_file(&temp).ok();
+    }
+    Ok(smaller)
+}
+
+/// Archives the folders of `finished_modules` (by module code) that are in the root,
+/// asking `confirm` before each step.
+pub async fn season_end<F: Fn(&str) -> bool>(
+    options: &SeasonEndOptions,
+    finished_modules: &[String],
+    subscriptions: &Subscriptions,
+    confirm: F,
+) -> Result<()> {
+    let module_folders = finished_modules
+        .iter()
+        .map(|code| sanitise_filename(code))
+        .collect::<Vec<_>>();
+    let module_dirs = module_folders
+        .iter()
+        .map(|folder| options.root.join(folder))
+        .filter(|dir| dir.is_dir())
+        .collect::<Vec<_>>();
+    if module_dirs.is_empty() {
+        outln!(
+            "No modules of term {} that are no longer accessible were found in {}",
+            options.term,
+            options.root.display()
+        );
+        return Ok(());
+    }
+    outln!(
+        "These modules of term {} are no longer accessible:",
+        options.term
+    );
+    for dir in &module_dirs {
+        outln!("- {}", dir.display());
+    }
+    if !confirm("Archive them?") {
+        return Ok(());
+    }
+
+    let mut files = vec![];
+    for dir in &module_dirs {
+        list_files(dir, &mut files).map_err(|_| "Unable to list module folder")?;
+    }
+    let removed = remove_temp_files(&mut files);
+    if removed > 0 {
+        outln!("Removed {} leftover temporary files", removed);
+    }
+
+    let videos = files
+        .iter()
+        .filter(|file| is_video(file))
+        .collect::<Vec<_>>();
+    if !videos.is_empty()
+        && confirm(&format!(
+            "Compress {} videos with ffmpeg to save space? This takes a while and loses some quality.",
+            videos.len()
+        ))
+    {
+        for video in videos {
+            match compress_video(&options.ffmpeg_path, video).await {
+                Ok(true) => outln!("Compressed {}", video.display()),
+                Ok(false) => outln!("Kept {}, as it would not get smaller", video.display()),
+                Err(e) => errln!("Failed compressing {}: {}", video.display(), e),
+            }
+        }
+    }
+
+    let archive_name = format!("fluminurs-{}", sanitise_filename(&options.term));
+    match options.archive_format {
+        ArchiveFormat::Zip => {
+            let archive = options.root.join(format!("{}.zip", archive_name));
+            let (root, zip_path) = (options.root.clone(), archive.clone());
+            // writing the archive is blocking, and may take a while
+            tokio::task::spawn_blocking(move || write_zip(&root, &zip_path, &files, &[]))
+                .await
+                .map_err(|_| "Unable to write archive")??;
+            outln!("Archived to {}", archive.display());
+            if confirm("Delete the archived module folders?") {
+                for dir in &module_dirs {
+                    fs::remove_dir_all(dir).map_err(|_| "Unable to delete module folder")?;
+                }
+            }
+        }
+        ArchiveFormat::Snapshot => {
+            let snapshot = options.root.join(archive_name);
+            fs::create_dir_all(&snapshot).map_err(|_| "Unable to create snapshot folder")?;
+            for dir in &module_dirs {
+                let name = dir.file_name().ok_or("Invalid module folder")?;
+                fs::rename(dir, snapshot.join(name)).map_err(|_| "Unable to move module folder")?;
+            }
+            outln!("Moved the module folders to {}", snapshot.display());
+        }
+    }
+
+    if subscriptions.forget_modules(&module_folders) {
+        subscriptions.save()?;
+        outln!("Removed the subscriptions of the archived modules");
+    }
+    Ok(())
+}
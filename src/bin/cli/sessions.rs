@@ -0,0 +1,297 @@
+// Named download sessions: the arguments of a run and the downloads it has yet to finish are
+// kept in a file as it goes, so that an interrupted run can be resumed exactly where it stopped.
+// Resuming downloads what was left in the queue, whatever the skip/overwrite settings say.
+//
+// In watch mode, the session carries on across restarts of the daemon instead: the first sync
+// after a restart finishes what was left in the queue, and downloads that keep failing for
+// reasons that will not pass by themselves are held back for longer and longer, rather than
+// being tried again on every sync.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use fluminurs::resource::Resource;
+use fluminurs::{Error, Result};
+
+use crate::attention;
+use crate::clean::TEMP_FILE_PREFIX;
+
+// how long a download that failed is held back at first, doubling with every failure after
+const FIRST_HOLD_BACK: Duration = Duration::from_secs(60 * 60);
+const MAX_HOLD_BACK: Duration = Duration::from_secs(24 * 60 * 60);
+// finished and failed downloads are written out at most this often, as there may be thousands
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct SessionState {
+    // the command line of the run, without the program name
+    args: Vec<String>,
+    // the kinds of resources and destinations that downloads were queued for
+    batches: BTreeSet<String>,
+    // the downloads that were queued and have not finished
+    pending: BTreeSet<String>,
+    // in watch mode, the downloads that failed, which are held back until they are due again
+    failed: BTreeMap<String, FailedDownload>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FailedDownload {
+    error: String,
+    attempts: u32,
+    // in seconds since the Unix epoch
+    retry_after: u64,
+}
+
+pub struct Session {
+    name: String,
+    file: String,
+    resuming: bool,
+    // whether the session lasts across the syncs of a daemon, rather than a single run
+    watching: bool,
+    // the batches whose leftovers were already downloaded by this process
+    resumed: Mutex<BTreeSet<String>>,
+    state: Mutex<SessionState>,
+    // when the state was last written, if it changed since then
+    unsaved_since: Mutex<Option<Instant>>,
+}
+
+fn session_file(name: &str) -> String {
+    format!("{}.session.json", name)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn read_state(file: &str) -> Result<SessionState> {
+    let content = fs::read_to_string(file).map_err(|_| "No such session to resume")?;
+    serde_json::from_str(&content).map_err(|_| "Unable to parse session file")
+}
+
+// E.g. `File:downloads`, as a session may download several kinds of resources to one place
+fn batch_key<T: Resource>(destination: &Path) -> String {
+    format!(
+        "{}:{}",
+        std::any::type_name::<T>(),
+        destination.to_string_lossy()
+    )
+}
+
+fn item_key(destination: &Path, resource: &impl Resource) -> String {
+    destination
+        .join(resource.path())
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl Session {
+    /// Starts a session afresh, forgetting whatever was left of an earlier one by this name
+    pub fn start(name: &str, args: Vec<String>) -> Result<Session> {
+        let session = Session {
+            name: name.to_owned(),
+            file: session_file(name),
+            resuming: false,
+            watching: false,
+            resumed: Mutex::default(),
+            unsaved_since: Mutex::default(),
+            state: Mutex::new(SessionState {
+                args,
+                ..SessionState::default()
+            }),
+        };
+        session.save()?;
+        Ok(session)
+    }
+
+    /// The command line that the session was started with, to run it again when resuming
+    pub fn recorded_args(name: &str) -> Result<Vec<String>> {
+        Ok(read_state(&session_file(name))?.args)
+    }
+
+    pub fn resume(name: &str) -> Result<Session> {
+        let file = session_file(name);
+        Ok(Session {
+            name: name.to_owned(),
+            state: Mutex::new(read_state(&file)?),
+            file,
+            resuming: true,
+            watching: false,
+            resumed: Mutex::default(),
+            unsaved_since: Mutex::default(),
+        })
+    }
+
+    /// Carries on with the session of a daemon by this name if there is one, or starts it.
+    /// The arguments are recorded when given, and left as they are when resuming the session.
+    pub fn watch(name: &str, args: Option<Vec<String>>) -> Result<Session> {
+        let file = session_file(name);
+        let (state, resuming) = match read_state(&file) {
+            Ok(state) => match args {
+                Some(args) => (SessionState { args, ..state }, true),
+                None => (state, true),
+            },
+            Err(_) => (
+                SessionState {
+                    args: args.unwrap_or_default(),
+                    ..SessionState::default()
+                },
+                false,
+            ),
+        };
+        let session = Session {
+            name: name.to_owned(),
+            file,
+            resuming,
+            watching: true,
+            resumed: Mutex::default(),
+            unsaved_since: Mutex::default(),
+            state: Mutex::new(state),
+        };
+        session.save()?;
+        Ok(session)
+    }
+
+    fn save(&self) -> Result<()> {
+        let content = serde_json::to_string(&*self.state.lock().unwrap())
+            .map_err(|_| "Unable to serialise session")?;
+        // an interrupted write must not leave a session that cannot be resumed
+        let temp_file = format!("{}{}", TEMP_FILE_PREFIX, self.file);
+        fs::write(&temp_file, content).map_err(|_| "Unable to write session file")?;
+        fs::rename(&temp_file, &self.file).map_err(|_| "Unable to write session file")?;
+        *self.unsaved_since.lock().unwrap() = None;
+        Ok(())
+    }
+
+    // Saves the state once its changes have waited for a while, leaving the rest to `flush`.
+    // A download that finished since the last save is merely downloaded again when resuming.
+    fn save_soon(&self) -> Result<()> {
+        let changed_at = *self
+            .unsaved_since
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now);
+        if changed_at.elapsed() >= SAVE_INTERVAL {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Saves whatever changed since the state was last written, e.g. at the end of a sync
+    pub fn flush(&self) -> Result<()> {
+        if self.unsaved_since.lock().unwrap().is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Picks the resources of a batch that are to be downloaded, and queues them.
+    /// When resuming, these are the ones that were left in the queue, unless the run was
+    /// interrupted before it got to the batch. The flag tells whether they are leftovers.
+    pub fn queue<'a, T: Resource>(
+        &self,
+        destination: &Path,
+        resources: &'a [T],
+    ) -> Result<(Vec<&'a T>, bool)> {
+        let batch = batch_key::<T>(destination);
+        let mut state = self.state.lock().unwrap();
+        let first_time = self.resumed.lock().unwrap().insert(batch.clone());
+        if self.resuming && first_time && state.batches.contains(&batch) {
+            let leftovers = resources
+                .iter()
+                .filter(|resource| state.pending.contains(&item_key(destination, *resource)))
+                .collect::<Vec<_>>();
+            // a daemon whose queue was done goes on to sync as usual
+            if !self.watching || !leftovers.is_empty() {
+                return Ok((leftovers, true));
+            }
+        }
+        let now = unix_now();
+        let (queued, held_back): (Vec<_>, Vec<_>) = resources.iter().partition(|resource| {
+            state
+                .failed
+                .get(&item_key(destination, *resource))
+                .is_none_or(|failed| failed.retry_after <= now)
+        });
+        if !held_back.is_empty() {
+            outln!(
+                "Holding back {} downloads that failed recently",
+                held_back.len()
+            );
+        }
+        state.batches.insert(batch);
+        state.pending.extend(
+            queued
+                .iter()
+                .map(|resource| item_key(destination, *resource)),
+        );
+        drop(state);
+        self.save()?;
+        Ok((queued, false))
+    }
+
+    /// Takes a finished download off the queue
+    pub fn finish<T: Resource>(&self, destination: &Path, resource: &T) -> Result<()> {
+        let key = item_key(destination, resource);
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(&key);
+        state.failed.remove(&key);
+        drop(state);
+        self.save_soon()
+    }
+
+    /// Takes a failed download off the queue of a daemon, holding it back for a while unless
+    /// the failure is likely to pass. Other sessions keep it in the queue, for `resume`.
+    pub fn fail<T: Resource>(&self, destination: &Path, resource: &T, error: Error) -> Result<()> {
+        if !self.watching {
+            return Ok(());
+        }
+        let key = item_key(destination, resource);
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(&key);
+        if attention::is_transient(error) {
+            state.failed.remove(&key);
+        } else {
+            let attempts = state.failed.get(&key).map_or(0, |failed| failed.attempts) + 1;
+            let hold_back = FIRST_HOLD_BACK
+                .saturating_mul(1 << (attempts - 1).min(16))
+                .min(MAX_HOLD_BACK);
+            state.failed.insert(
+                key,
+                FailedDownload {
+                    error: error.to_owned(),
+                    attempts,
+                    retry_after: unix_now() + hold_back.as_secs(),
+                },
+            );
+        }
+        drop(state);
+        self.save_soon()
+    }
+
+    /// Removes the session once nothing is left, or says how to carry on otherwise
+    pub fn end(&self) -> Result<()> {
+        let remaining = self.state.lock().unwrap().pending.len();
+        if remaining == 0 {
+            fs::remove_file(&self.file).map_err(|_| "Unable to remove session file")?;
+            outln!("Session {} is complete", self.name);
+        } else {
+            self.flush()?;
+            outln!(
+                "Session {} has {} downloads left, which `resume {}` tries again",
+                self.name,
+                remaining,
+                self.name
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+// A summary of what a module has, to help decide what to archive first.
+// Sizes come from the local copies where there are any, and from the server otherwise.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use futures_util::{stream, StreamExt};
+
+use fluminurs::module::Module;
+use fluminurs::resource::Resource;
+use fluminurs::util::format_date;
+use fluminurs::weblecture::WebLectureVideo;
+use fluminurs::Api;
+
+use crate::{format_duration, format_size};
+
+const NO_EXTENSION: &str = "(none)";
+
+pub struct ModuleStats<'a> {
+    module: &'a Module,
+    root: &'a Path,
+    // the number of files of each extension
+    file_types: BTreeMap<String, usize>,
+    total_size: u64,
+    // resources whose size could be found neither locally nor on the server
+    unknown_sizes: usize,
+    // the number of resources of each kind, in the order they were added
+    counts: Vec<(&'static str, usize)>,
+    video_duration: Duration,
+    last_activity: Option<SystemTime>,
+}
+
+impl<'a> ModuleStats<'a> {
+    /// `root` is where the resources of the module were downloaded to, if they were
+    pub fn new(module: &'a Module, root: &'a Path) -> ModuleStats<'a> {
+        ModuleStats {
+            module,
+            root,
+            file_types: BTreeMap::new(),
+            total_size: 0,
+            unknown_sizes: 0,
+            counts: vec![],
+            video_duration: Duration::default(),
+            last_activity: None,
+        }
+    }
+
+    fn add_counts<T: Resource>(&mut self, kind: &'static str, resources: &[T]) {
+        self.counts.push((kind, resources.len()));
+        self.last_activity = resources
+            .iter()
+            .map(Resource::last_updated)
+            .chain(self.last_activity)
+            .max();
+    }
+
+    async fn add_sizes<T: Resource>(&mut self, api: &Api, resources: &[T]) {
+        let root = self.root;
+        let sizes = stream::iter(resources.iter())
+            .map(|resource| async move {
+                match fs::metadata(root.join(resource.path())) {
+                    Ok(metadata) if metadata.is_file() => Some(metadata.len()),
+                    _ => resource
+                        .remote_info(api)
+                        .await
+                        .ok()
+                        .flatten()
+                        .and_then(|info| info.size),
+                }
+            })
+            .buffer_unordered(16)
+            .collect::<Vec<_>>()
+            .await;
+        for size in sizes {
+            match size {
+                Some(size) => self.total_size += size,
+                None => self.unknown_sizes += 1,
+            }
+        }
+    }
+
+    pub async fn add_files<T: Resource>(&mut self, api: &Api, files: &[T]) {
+        for file in files {
+            let extension = file
+                .path()
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_else(|| NO_EXTENSION.to_owned());
+            *self.file_types.entry(extension).or_default() += 1;
+        }
+        self.add_counts("files", files);
+        self.add_sizes(api, files).await;
+    }
+
+    pub async fn add_videos<T: Resource>(&mut self, api: &Api, kind: &'static str, videos: &[T]) {
+        self.add_counts(kind, videos);
+        self.add_sizes(api, videos).await;
+    }
+
+    /// Web lectures also count towards the video hours, which requires launching Panopto for each
+    pub async fn add_weblectures(&mut self, api: &Api, weblectures: &[WebLectureVideo]) {
+        self.add_counts("web lectures", weblectures);
+        let details = stream::iter(weblectures.iter())
+            .map(|weblecture| weblecture.get_details(api))
+            .buffer_unordered(4)
+            .collect::<Vec<_>>()
+            .await;
+        for details in details {
+            match details {
+                Ok(details) => {
+                    self.video_duration += details.duration;
+                    match details.estimated_size {
+                        Some(size) => self.total_size += size,
+                        None => self.unknown_sizes += 1,
+                    }
+                }
+                Err(_) => self.unknown_sizes += 1,
+            }
+        }
+    }
+
+    /// Resources that only count, like announcements, which take no space worth mentioning
+    pub fn add_others<T: Resource>(&mut self, kind: &'static str, resources: &[T]) {
+        self.add_counts(kind, resources);
+    }
+
+    pub fn print(&self) {
+        outln!(
+            "{} {} ({})",
+            self.module.code,
+            self.module.name,
+            self.module.term
+        );
+        for (kind, count) in &self.counts {
+            outln!("{}: {}", kind, count);
+            if *kind == "files" {
+                for (extension, count) in &self.file_types {
+                    outln!("  {}: {}", extension, count);
+                }
+            }
+        }
+        if self.unknown_sizes == 0 {
+            outln!("Total size: {}", format_size(self.total_size));
+        } else {
+            outln!(
+                "Total size: at least {} ({} of unknown size)",
+                format_size(self.total_size),
+                self.unknown_sizes
+            );
+        }
+        outln!(
+            "Web lecture hours: {}",
+            format_duration(self.video_duration)
+        );
+        outln!(
+            "Last activity: {}",
+            self.last_activity
+                .map(format_date)
+                .unwrap_or_else(|| "never".to_owned())
+        );
+    }
+}
@@ -0,0 +1,169 @@
+// Subscriptions to folders and channels, so that syncs only process the parts of modules
+// that the user cares about. Anything that appears later at the top level of a module is still
+// synced, and flagged for review, until the user subscribes to it or unsubscribes from it.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use fluminurs::Result;
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct SubscriptionState {
+    subscribed: BTreeSet<String>,
+    // top level entries that are not new, because they existed when subscribing or were turned down
+    reviewed: BTreeSet<String>,
+    // whether the top level entries that existed when subscribing have been recorded
+    initialised: bool,
+}
+
+pub struct Subscriptions {
+    file: String,
+    state: Mutex<SubscriptionState>,
+    // top level entries seen during the current sync that were not reviewed
+    flagged: Mutex<BTreeSet<String>>,
+}
+
+// Subscriptions are compared component by component, so that `CS2103T/Lecture` does not cover
+// `CS2103T/Lectures`, and separators do not matter
+fn normalise(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+fn covers(entries: &BTreeSet<String>, path: &Path) -> bool {
+    entries
+        .iter()
+        .any(|entry| path.starts_with(normalise(Path::new(entry))))
+}
+
+// Stored with forward slashes, so that the file can be moved between platforms
+fn to_entry(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// The folder or channel directly inside the module folder, e.g. `CS2103T/Lectures`.
+// A resource directly inside the module folder is its own top level entry.
+fn top_level(path: &Path) -> Option<String> {
+    let mut components = path.components();
+    let module = components.next()?;
+    let entry = components.next()?;
+    Some(to_entry(&[module, entry].iter().collect::<PathBuf>()))
+}
+
+fn parse_entry(path: &str) -> Result<String> {
+    let normalised = normalise(Path::new(path));
+    if normalised.as_os_str().is_empty() {
+        Err("Invalid folder to subscribe to")
+    } else {
+        Ok(to_entry(&normalised))
+    }
+}
+
+impl Subscriptions {
+    pub fn load(subscriptions_file: &str) -> Result<Subscriptions> {
+        let state = match fs::read_to_string(subscriptions_file) {
+            Ok(content) => {
+                serde_json::from_str(&content).map_err(|_| "Unable to parse subscriptions file")?
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => SubscriptionState::default(),
+            Err(_) => return Err("Unable to read subscriptions file"),
+        };
+        Ok(Subscriptions {
+            file: subscriptions_file.to_owned(),
+            state: Mutex::new(state),
+            flagged: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let content = serde_json::to_string_pretty(&*self.state.lock().unwrap())
+            .map_err(|_| "Unable to serialise subscriptions")?;
+        fs::write(&self.file, content).map_err(|_| "Unable to write subscriptions file")
+    }
+
+    /// Without any subscriptions, everything is synced
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().unwrap().subscribed.is_empty()
+    }
+
+    pub fn subscribed(&self) -> Vec<String> {
+        self.state
+            .lock()
+            .unwrap()
+            .subscribed
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn subscribe(&self, path: &str) -> Result<()> {
+        let entry = parse_entry(path)?;
+        let mut state = self.state.lock().unwrap();
+        state.reviewed.remove(&entry);
+        state.subscribed.insert(entry);
+        Ok(())
+    }
+
+    /// Also marks the entry as reviewed, so that it is no longer flagged as new
+    pub fn unsubscribe(&self, path: &str) -> Result<()> {
+        let entry = parse_entry(path)?;
+        let mut state = self.state.lock().unwrap();
+        state.subscribed.remove(&entry);
+        state.reviewed.insert(entry);
+        Ok(())
+    }
+
+    /// Drops every entry in the folders of these modules, returning whether there were any
+    pub fn forget_modules(&self, module_folders: &[String]) -> bool {
+        let in_modules = |entry: &String| {
+            let module = entry.split('/').next().unwrap_or(entry);
+            module_folders.iter().any(|folder| folder == module)
+        };
+        let mut state = self.state.lock().unwrap();
+        let count = state.subscribed.len() + state.reviewed.len();
+        state.subscribed.retain(|entry| !in_modules(entry));
+        state.reviewed.retain(|entry| !in_modules(entry));
+        count != state.subscribed.len() + state.reviewed.len()
+    }
+
+    /// Whether a resource at this path should be synced
+    pub fn matches(&self, path: &Path) -> bool {
+        let path = normalise(path);
+        let state = self.state.lock().unwrap();
+        if covers(&state.subscribed, &path) {
+            return true;
+        }
+        match top_level(&path) {
+            Some(entry) if !covers(&state.reviewed, Path::new(&entry)) => {
+                self.flagged.lock().unwrap().insert(entry);
+                // on the first sync, everything that exists already was passed over when subscribing
+                state.initialised
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the new top level entries that were synced, to be reviewed by the user.
+    /// On the first sync after subscribing, records the existing entries instead.
+    pub fn finish_sync(&self) -> Result<Vec<String>> {
+        let flagged = std::mem::take(&mut *self.flagged.lock().unwrap());
+        let mut state = self.state.lock().unwrap();
+        if state.initialised {
+            return Ok(flagged.into_iter().collect());
+        }
+        state.reviewed.extend(flagged);
+        state.initialised = true;
+        drop(state);
+        self.save()?;
+        Ok(vec![])
+    }
+}
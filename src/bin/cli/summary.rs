@@ -0,0 +1,128 @@
+// How each kind of resource fared in a run, printed at the end of it, so that a glance (or a
+// script, through the exit code) tells whether everything came down, and how fast.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use fluminurs::resource::{OverwriteResult, Resource};
+use fluminurs::Result;
+
+use crate::changes::resource_kind;
+use crate::{format_duration, format_size};
+
+/// Returned from the run when some downloads failed, so that it exits with a non-zero code
+pub const SOME_DOWNLOADS_FAILED: &str = "Some downloads failed";
+
+#[derive(Default)]
+struct Counts {
+    downloaded: usize,
+    updated: usize,
+    skipped: usize,
+    failed: usize,
+}
+
+// What was downloaded, over the time from the start of the first download to the end of the last,
+// as downloads run side by side
+#[derive(Default)]
+struct Transfers {
+    bytes: u64,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+}
+
+#[derive(Default)]
+pub struct RunSummary {
+    counts: Mutex<BTreeMap<&'static str, Counts>>,
+    transfers: Mutex<Transfers>,
+    // unlike the counts, this is kept across the syncs of a daemon
+    any_failed: AtomicBool,
+}
+
+impl RunSummary {
+    /// Records the final result of a download, after any retries, which took `duration`
+    pub fn record<T: Resource>(
+        &self,
+        result: &Result<OverwriteResult>,
+        path: &Path,
+        duration: Duration,
+    ) {
+        if let Ok(OverwriteResult::NewFile | OverwriteResult::Overwritten)
+        | Ok(OverwriteResult::Renamed { .. }) = result
+        {
+            let now = Instant::now();
+            let mut transfers = self.transfers.lock().unwrap();
+            transfers.bytes += fs::metadata(path).map_or(0, |metadata| metadata.len());
+            let started = now.checked_sub(duration).unwrap_or(now);
+            transfers.started = Some(transfers.started.map_or(started, |s| s.min(started)));
+            transfers.finished = Some(now);
+        }
+
+        let mut counts = self.counts.lock().unwrap();
+        let counts = counts.entry(resource_kind::<T>()).or_default();
+        match result {
+            Ok(OverwriteResult::NewFile) => counts.downloaded += 1,
+            Ok(OverwriteResult::Overwritten) | Ok(OverwriteResult::Renamed { .. }) => {
+                counts.updated += 1
+            }
+            Ok(OverwriteResult::Skipped) => counts.skipped += 1,
+            Ok(OverwriteResult::AlreadyHave) => {}
+            Err(_) => {
+                counts.failed += 1;
+                self.any_failed.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn any_failed(&self) -> bool {
+        self.any_failed.load(Ordering::Relaxed)
+    }
+
+    /// Prints the counts of each kind of resource that had anything happen to it,
+    /// and starts over for the next run
+    pub fn report(&self) {
+        let transfers = std::mem::take(&mut *self.transfers.lock().unwrap());
+        let counts = std::mem::take(&mut *self.counts.lock().unwrap());
+        let counts = counts
+            .into_iter()
+            .filter(|(_, counts)| {
+                counts.downloaded + counts.updated + counts.skipped + counts.failed > 0
+            })
+            .collect::<Vec<_>>();
+        if counts.is_empty() {
+            return;
+        }
+        outln!();
+        outln!(
+            "{:<24}{:>12}{:>10}{:>10}{:>10}",
+            "Resource",
+            "Downloaded",
+            "Updated",
+            "Skipped",
+            "Failed"
+        );
+        for (kind, counts) in &counts {
+            outln!(
+                "{:<24}{:>12}{:>10}{:>10}{:>10}",
+                kind,
+                counts.downloaded,
+                counts.updated,
+                counts.skipped,
+                counts.failed
+            );
+        }
+        if let (Some(started), Some(finished)) = (transfers.started, transfers.finished) {
+            let elapsed = finished - started;
+            let speed = transfers.bytes as f64 / elapsed.as_secs_f64().max(0.001);
+            outln!(
+                "Downloaded {} in {} ({}/s on average)",
+                format_size(transfers.bytes),
+                format_duration(elapsed),
+                format_size(speed as u64)
+            );
+        }
+    }
+}
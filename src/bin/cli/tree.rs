@@ -0,0 +1,97 @@
+// Listings drawn as a tree of the modules and their folders, with the number of items and their
+// total size under each folder, to help with picking the folders to filter for.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::format_size;
+
+#[derive(Default)]
+struct Folder {
+    folders: BTreeMap<String, Folder>,
+    files: Vec<(String, Option<u64>)>,
+}
+
+impl Folder {
+    fn insert(&mut self, path: &Path, size: Option<u64>) {
+        let mut components = path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let name = match components.pop() {
+            Some(name) => name,
+            None => return,
+        };
+        let folder = components.into_iter().fold(self, |folder, component| {
+            folder.folders.entry(component).or_default()
+        });
+        folder.files.push((name, size));
+    }
+
+    // The number of files under the folder, and the total size of those whose size is known
+    fn totals(&self) -> (usize, Option<u64>) {
+        self.folders
+            .values()
+            .map(Folder::totals)
+            .chain(self.files.iter().map(|(_, size)| (1, *size)))
+            .fold((0, None), |(count, size), (more, more_size)| {
+                (
+                    count + more,
+                    match (size, more_size) {
+                        (Some(size), Some(more_size)) => Some(size + more_size),
+                        (size, more_size) => size.or(more_size),
+                    },
+                )
+            })
+    }
+
+    fn describe(&self) -> String {
+        match self.totals() {
+            (1, Some(size)) => format!("1 item, {}", format_size(size)),
+            (1, None) => "1 item".to_owned(),
+            (count, Some(size)) => format!("{} items, {}", count, format_size(size)),
+            (count, None) => format!("{} items", count),
+        }
+    }
+
+    fn print(&self, prefix: &str) {
+        let count = self.folders.len() + self.files.len();
+        let folders = self
+            .folders
+            .iter()
+            .map(|(name, folder)| (name, Some(folder), None));
+        let files = self.files.iter().map(|(name, size)| (name, None, *size));
+        for (i, (name, folder, size)) in folders.chain(files).enumerate() {
+            let last = i + 1 == count;
+            let branch = if last { "└── " } else { "├── " };
+            match folder {
+                Some(folder) => {
+                    outln!("{}{}{}/ ({})", prefix, branch, name, folder.describe());
+                    folder.print(&format!("{}{}", prefix, if last { "    " } else { "│   " }));
+                }
+                None => match size {
+                    Some(size) => outln!("{}{}{} ({})", prefix, branch, name, format_size(size)),
+                    None => outln!("{}{}{}", prefix, branch, name),
+                },
+            }
+        }
+    }
+}
+
+/// Prints the paths as a tree, with each module at the top
+pub fn print_tree<'a, I: IntoIterator<Item = (&'a Path, Option<u64>)>>(paths: I) {
+    let mut root = Folder::default();
+    for (path, size) in paths {
+        root.insert(path, size);
+    }
+    for (name, folder) in &root.folders {
+        outln!("{}/ ({})", name, folder.describe());
+        folder.print("");
+    }
+    for (name, size) in &root.files {
+        match size {
+            Some(size) => outln!("{} ({})", name, format_size(*size)),
+            None => outln!("{}", name),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+// Exports the announcements, forum threads and lesson plans of each module as a vault of Markdown
+// notes, for note-taking apps like Obsidian and Logseq. Each note has YAML front matter with its
+// module, date and tags, and links to a note for its module, which lists all of them in turn.
+//
+// Notes are only written again once their resource is updated on LumiNUS, at which point any
+// edits made to them are lost; the notes of the modules are written again on every export.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use futures_util::{stream, StreamExt};
+
+use fluminurs::module::Module;
+use fluminurs::resource::Resource;
+use fluminurs::util::{format_date, sanitise_filename};
+use fluminurs::{Api, Result};
+
+use crate::changes::module_code;
+use crate::clean::TEMP_FILE_PREFIX;
+
+const PARALLELISM: usize = 8;
+
+pub struct Vault {
+    root: PathBuf,
+    // the links to the notes of each module, by the kind of note
+    notes: BTreeMap<String, BTreeMap<&'static str, Vec<String>>>,
+}
+
+// Strings are written as JSON, which YAML reads as quoted strings
+fn yaml_string(text: &str) -> String {
+    serde_json::to_string(text).expect("Strings are always serialisable")
+}
+
+fn tag(text: &str) -> String {
+    text.to_lowercase()
+        .replace(|c: char| !c.is_alphanumeric(), "-")
+}
+
+// How notes link to each other, which is their path in the vault without the extension
+fn link(path: &Path) -> String {
+    path.with_extension("")
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn module_link(code: &str) -> String {
+    format!("{}/{}", code, code)
+}
+
+fn write_note(path: &Path, content: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|_| "Unable to create directory")?;
+    }
+    let mut temp_name = std::ffi::OsString::from(TEMP_FILE_PREFIX);
+    temp_name.push(path.file_name().unwrap_or_default());
+    let temp_path = path.with_file_name(temp_name);
+    fs::write(&temp_path, content).map_err(|_| "Unable to write note")?;
+    fs::rename(&temp_path, path).map_err(|_| "Unable to write note")
+}
+
+fn is_up_to_date<T: Resource>(path: &Path, resource: &T) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified >= resource.last_updated())
+}
+
+impl Vault {
+    pub fn new<P: AsRef<Path>>(root: P) -> Vault {
+        Vault {
+            root: root.as_ref().to_owned(),
+            notes: BTreeMap::new(),
+        }
+    }
+
+    /// Writes a note for each resource, which are listed under `kind` (e.g. `Announcements`) in
+    /// the note of their module, and tagged with it
+    pub async fn export<T: Resource>(&mut self, api: &Api, resources: &[T], kind: &'static str) {
+        let root = &self.root;
+        let written = stream::iter(resources)
+            .map(|resource| async move {
+                let path = root.join(resource.path());
+                let code = module_code(resource.path()).unwrap_or_default();
+                if !is_up_to_date(&path, resource) {
+                    let mut body = vec![];
+                    resource.download_to_writer(api, &mut body).await?;
+                    let note = format!(
+                        "---\nmodule: {}\ndate: {}\ntags: [luminus, {}, {}]\n---\n\n{}\n\nModule: [[{}|{}]]\n",
+                        yaml_string(&code),
+                        format_date(resource.last_updated()),
+                        tag(&code),
+                        tag(kind),
+                        String::from_utf8_lossy(&body).trim_end(),
+                        module_link(&code),
+                        code
+                    );
+                    write_note(&path, &note)?;
+                    filetime::set_file_mtime(
+                        &path,
+                        filetime::FileTime::from_system_time(resource.last_updated()),
+                    )
+                    .ok();
+                    outln!("Exported {}", path.to_string_lossy());
+                }
+                Ok((code, link(resource.path())))
+            })
+            .buffer_unordered(PARALLELISM)
+            .collect::<Vec<Result<_>>>()
+            .await;
+        for note in written {
+            match note {
+                Ok((code, link)) => self
+                    .notes
+                    .entry(code)
+                    .or_default()
+                    .entry(kind)
+                    .or_default()
+                    .push(link),
+                Err(e) => errln!("Failed to export note: {}", e),
+            }
+        }
+    }
+
+    /// Writes the note of each module, which links to all of its notes
+    pub fn write_module_notes(&self, modules: &[Module]) -> Result<()> {
+        for module in modules {
+            let code = sanitise_filename(&module.code);
+            let mut note = format!(
+                "---\nmodule: {}\nname: {}\ntags: [luminus, {}]\n---\n\n# {} {}\n",
+                yaml_string(&code),
+                yaml_string(&module.name),
+                tag(&code),
+                module.code,
+                module.name
+            );
+            for (kind, links) in self.notes.get(&code).into_iter().flatten() {
+                note.push_str(&format!("\n## {}\n\n", kind));
+                let mut links = links.clone();
+                links.sort();
+                for link in links {
+                    let title = link.rsplit('/').next().unwrap_or(&link).to_owned();
+                    note.push_str(&format!("- [[{}|{}]]\n", link, title));
+                }
+            }
+            write_note(&self.root.join(module_link(&code) + ".md"), &note)?;
+        }
+        Ok(())
+    }
+}
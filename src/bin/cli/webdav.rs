@@ -0,0 +1,394 @@
+// A read-only WebDAV server of the files of each module, to browse LumiNUS as a network drive
+// without syncing everything. Folders are listed from the server when they are opened (and kept
+// for a few minutes), and files are streamed from LumiNUS whenever they are read. Anyone who can
+// reach the address can read the files of the account, so it only listens on this machine unless
+// told otherwise.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Utc};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use fluminurs::file::File;
+use fluminurs::module::Module;
+use fluminurs::resource::{sort_and_make_all_paths_unique, SimpleDownloadableResource};
+use fluminurs::{Api, Result};
+
+const MAX_REQUEST_SIZE: usize = 8192;
+// PROPFIND requests have a body, which is read and ignored, as every property is always listed
+const MAX_BODY_SIZE: usize = 65536;
+const LISTING_TTL: Duration = Duration::from_secs(5 * 60);
+// the characters that are escaped in the paths of links
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}')
+    .add(b'/')
+    .add(b'&');
+
+struct Listing {
+    listed: Instant,
+    files: Arc<Vec<File>>,
+}
+
+struct WebDav {
+    api: Api,
+    modules: BTreeMap<String, Module>,
+    // the files of each module, by module code
+    listings: Mutex<HashMap<String, Listing>>,
+}
+
+enum Entry<'a> {
+    Folder(PathBuf),
+    File(&'a File),
+}
+
+struct Request {
+    method: String,
+    path: Vec<String>,
+    headers: HashMap<String, String>,
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn href(path: &Path, is_folder: bool) -> String {
+    let mut href = String::new();
+    for component in path.components() {
+        href.push('/');
+        href.extend(utf8_percent_encode(
+            &component.as_os_str().to_string_lossy(),
+            PATH_SEGMENT,
+        ));
+    }
+    if is_folder {
+        href.push('/');
+    }
+    href
+}
+
+fn http_date(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+fn propfind_response(entry: &Entry) -> String {
+    let (path, properties) = match entry {
+        Entry::Folder(path) => (
+            href(path, true),
+            "<D:resourcetype><D:collection/></D:resourcetype>".to_owned(),
+        ),
+        Entry::File(file) => (
+            href(file.path(), false),
+            format!(
+                "<D:resourcetype/><D:getlastmodified>{}</D:getlastmodified>",
+                http_date(file.last_updated())
+            ),
+        ),
+    };
+    let name = match entry {
+        Entry::Folder(path) => path.file_name(),
+        Entry::File(file) => file.path().file_name(),
+    }
+    .map(|name| name.to_string_lossy().into_owned())
+    .unwrap_or_default();
+    format!(
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop><D:displayname>{}</D:displayname>{}\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+        escape_xml(&path),
+        escape_xml(&name),
+        properties
+    )
+}
+
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<Request>> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    let header_end = loop {
+        if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+        if request.len() >= MAX_REQUEST_SIZE {
+            return Ok(None);
+        }
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buffer[..read]);
+    };
+    let head = String::from_utf8_lossy(&request[..header_end]).into_owned();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or("").split_whitespace();
+    let method = request_line.next().unwrap_or("").to_owned();
+    let target = request_line.next().unwrap_or("");
+    let target = target.split('?').next().unwrap_or("");
+    let path = target
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        .collect::<Vec<_>>();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_owned()))
+        .collect::<HashMap<_, _>>();
+
+    let body_length = headers
+        .get("content-length")
+        .and_then(|length| length.parse::<usize>().ok())
+        .unwrap_or(0);
+    if body_length > MAX_BODY_SIZE {
+        return Ok(None);
+    }
+    let mut remaining = body_length.saturating_sub(request.len() - header_end);
+    while remaining > 0 {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        remaining = remaining.saturating_sub(read);
+    }
+    Ok(Some(Request {
+        method,
+        path,
+        headers,
+    }))
+}
+
+async fn respond_status(stream: &mut TcpStream, status_line: &str) -> std::io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status_line
+    );
+    stream.write_all(response.as_bytes()).await
+}
+
+/// Serves the files of the modules on `address` until the process is stopped
+pub async fn serve(api: Api, modules: Vec<Module>, address: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .await
+        .map_err(|_| "Unable to listen on the given address")?;
+    let webdav = Arc::new(WebDav {
+        api,
+        modules: modules
+            .into_iter()
+            .filter(|module| module.has_access())
+            .map(|module| (module.code.clone(), module))
+            .collect(),
+        listings: Mutex::new(HashMap::new()),
+    });
+    outln!(
+        "Serving the files of {} modules on http://{}",
+        webdav.modules.len(),
+        address
+    );
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(_) => continue,
+        };
+        let webdav = webdav.clone();
+        tokio::spawn(async move {
+            // a broken connection only affects its own request
+            webdav.handle_connection(stream).await.ok();
+        });
+    }
+}
+
+impl WebDav {
+    async fn handle_connection(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let request = match read_request(&mut stream).await? {
+            Some(request) => request,
+            None => {
+                respond_status(&mut stream, "400 Bad Request").await?;
+                return stream.shutdown().await;
+            }
+        };
+        match request.method.as_str() {
+            "OPTIONS" => {
+                stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\nDAV: 1\r\nAllow: OPTIONS, GET, HEAD, PROPFIND\r\n\
+                          Content-Length: 0\r\nConnection: close\r\n\r\n",
+                    )
+                    .await?
+            }
+            "PROPFIND" => self.propfind(&mut stream, &request).await?,
+            "GET" | "HEAD" => self.get(&mut stream, &request).await?,
+            // the drive is read-only
+            _ => respond_status(&mut stream, "405 Method Not Allowed").await?,
+        }
+        stream.shutdown().await
+    }
+
+    // The files of the module, listed again once the listing is a few minutes old
+    async fn files(&self, code: &str) -> Result<Arc<Vec<File>>> {
+        if let Some(listing) = self.listings.lock().unwrap().get(code) {
+            if listing.listed.elapsed() < LISTING_TTL {
+                return Ok(listing.files.clone());
+            }
+        }
+        let module = self.modules.get(code).ok_or("Module does not exist")?;
+        let mut files = module
+            .workbin_root(|code| PathBuf::from(code))
+            .load(&self.api, module.is_teaching(), false, None, None)
+            .await?;
+        sort_and_make_all_paths_unique(&mut files);
+        let files = Arc::new(files);
+        self.listings.lock().unwrap().insert(
+            code.to_owned(),
+            Listing {
+                listed: Instant::now(),
+                files: files.clone(),
+            },
+        );
+        Ok(files)
+    }
+
+    async fn propfind(&self, stream: &mut TcpStream, request: &Request) -> std::io::Result<()> {
+        let path = request.path.iter().collect::<PathBuf>();
+        // listing everything below a folder at once would load too much, so depth 1 stands in
+        let with_children = request.headers.get("depth").map(String::as_str) != Some("0");
+        let mut responses = String::new();
+
+        match request.path.first() {
+            None => {
+                responses.push_str(&propfind_response(&Entry::Folder(PathBuf::new())));
+                if with_children {
+                    for code in self.modules.keys() {
+                        responses.push_str(&propfind_response(&Entry::Folder(PathBuf::from(code))));
+                    }
+                }
+            }
+            Some(code) if self.modules.contains_key(code) => {
+                let files = match self.files(code).await {
+                    Ok(files) => files,
+                    Err(e) => {
+                        errln!("Failed to list the files of {}: {}", code, e);
+                        return respond_status(stream, "502 Bad Gateway").await;
+                    }
+                };
+                if let Some(file) = files.iter().find(|file| file.path() == path) {
+                    responses.push_str(&propfind_response(&Entry::File(file)));
+                } else {
+                    // folders are only known by the files in them, so empty ones are left out
+                    let below = files
+                        .iter()
+                        .filter(|file| file.path().starts_with(&path))
+                        .collect::<Vec<_>>();
+                    if below.is_empty() && request.path.len() > 1 {
+                        return respond_status(stream, "404 Not Found").await;
+                    }
+                    responses.push_str(&propfind_response(&Entry::Folder(path.clone())));
+                    if with_children {
+                        let mut folders = below
+                            .iter()
+                            .filter_map(|file| {
+                                let relative = file.path().strip_prefix(&path).ok()?;
+                                let mut components = relative.components();
+                                let first = components.next()?;
+                                components.next().map(|_| path.join(first))
+                            })
+                            .collect::<Vec<_>>();
+                        folders.dedup();
+                        for folder in folders {
+                            responses.push_str(&propfind_response(&Entry::Folder(folder)));
+                        }
+                        for file in below
+                            .iter()
+                            .filter(|file| file.path().parent() == Some(path.as_path()))
+                        {
+                            responses.push_str(&propfind_response(&Entry::File(file)));
+                        }
+                    }
+                }
+            }
+            Some(_) => return respond_status(stream, "404 Not Found").await,
+        }
+
+        let body = format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n{}</D:multistatus>\n",
+            responses
+        );
+        let response = format!(
+            "HTTP/1.1 207 Multi-Status\r\nContent-Type: application/xml; charset=utf-8\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await
+    }
+
+    async fn get(&self, stream: &mut TcpStream, request: &Request) -> std::io::Result<()> {
+        let path = request.path.iter().collect::<PathBuf>();
+        let code = match request.path.first() {
+            Some(code) if self.modules.contains_key(code) => code,
+            _ => return respond_status(stream, "404 Not Found").await,
+        };
+        let files = match self.files(code).await {
+            Ok(files) => files,
+            Err(_) => return respond_status(stream, "502 Bad Gateway").await,
+        };
+        let file = match files.iter().find(|file| file.path() == path) {
+            Some(file) => file,
+            // folders have nothing to show without PROPFIND
+            None => return respond_status(stream, "404 Not Found").await,
+        };
+        let url = match file.get_download_url(&self.api).await {
+            Ok(url) => url,
+            Err(_) => return respond_status(stream, "502 Bad Gateway").await,
+        };
+
+        let mut upstream = self.api.get_client().get(url);
+        // so that players can seek in videos without reading everything before
+        if let Some(range) = request.headers.get("range") {
+            upstream = upstream.header(reqwest::header::RANGE, range.as_str());
+        }
+        let mut res = match upstream.send().await {
+            Ok(res) if res.status().is_success() => res,
+            _ => return respond_status(stream, "502 Bad Gateway").await,
+        };
+        let mut headers = format!(
+            "HTTP/1.1 {} {}\r\nLast-Modified: {}\r\nConnection: close\r\n",
+            res.status().as_u16(),
+            res.status().canonical_reason().unwrap_or(""),
+            http_date(file.last_updated())
+        );
+        for name in [
+            reqwest::header::CONTENT_LENGTH,
+            reqwest::header::CONTENT_RANGE,
+            reqwest::header::CONTENT_TYPE,
+            reqwest::header::ACCEPT_RANGES,
+        ] {
+            if let Some(value) = res.headers().get(&name).and_then(|v| v.to_str().ok()) {
+                headers.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        headers.push_str("\r\n");
+        stream.write_all(headers.as_bytes()).await?;
+        if request.method == "GET" {
+            while let Ok(Some(chunk)) = res.chunk().await {
+                stream.write_all(&chunk).await?;
+            }
+        }
+        Ok(())
+    }
+}
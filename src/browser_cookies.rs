@@ -0,0 +1,378 @@
+// Importing cookies from the cookie stores of web browsers.
+// This is a rescue path for when the automated login flows are broken by upstream changes:
+// logging in with a browser and reusing its session still works.
+
+use std::path::{Path, PathBuf};
+
+use reqwest::Url;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::cookie_jar::PersistentCookieJar;
+use crate::Result;
+
+// Only cookies for these domains (and their subdomains) are imported
+const COOKIE_DOMAINS: [&str; 3] = ["nus.edu.sg", "zoom.us", "panopto.com"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Browser {
+    Firefox,
+    Chrome,
+}
+
+impl Browser {
+    pub fn from_name(name: &str) -> Option<Browser> {
+        match name.to_ascii_lowercase().as_str() {
+            "firefox" => Some(Browser::Firefox),
+            "chrome" => Some(Browser::Chrome),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BrowserCookie {
+    pub domain: String,
+    pub name: String,
+    pub value: String,
+    pub path: String,
+    pub secure: bool,
+}
+
+impl BrowserCookie {
+    pub(crate) fn url(&self) -> Option<Url> {
+        Url::parse(&format!(
+            "{}://{}{}",
+            if self.secure { "https" } else { "http" },
+            self.domain.trim_start_matches('.'),
+            self.path
+        ))
+        .ok()
+    }
+
+    pub(crate) fn to_set_cookie(&self) -> String {
+        let mut set_cookie = format!("{}={}; Path={}", self.name, self.value, self.path);
+        // host-only cookies are stored without the leading dot
+        if self.domain.starts_with('.') {
+            set_cookie.push_str(&format!("; Domain={}", self.domain));
+        }
+        if self.secure {
+            set_cookie.push_str("; Secure");
+        }
+        set_cookie
+    }
+}
+
+fn is_wanted_domain(domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.');
+    COOKIE_DOMAINS
+        .iter()
+        .any(|d| domain == *d || domain.ends_with(&format!(".{}", d)))
+}
+
+/// Reads the NUS, Zoom and Panopto cookies from the default profile of the given browser.
+/// Cookies that cannot be decrypted are skipped.
+pub fn read_browser_cookies(browser: Browser) -> Result<Vec<BrowserCookie>> {
+    let cookies = match browser {
+        Browser::Firefox => read_firefox_cookies(&find_firefox_cookie_db()?)?,
+        Browser::Chrome => read_chrome_cookies(&find_chrome_cookie_db()?)?,
+    };
+    Ok(cookies
+        .into_iter()
+        .filter(|cookie| is_wanted_domain(&cookie.domain))
+        .collect())
+}
+
+/// Reads the NUS, Zoom and Panopto cookies from a cookie file in the Netscape format, as exported
+/// by browser extensions or written by curl and yt-dlp. Cookies that have expired are skipped.
+pub fn read_cookie_file<P: AsRef<Path>>(path: P) -> Result<Vec<BrowserCookie>> {
+    let contents = std::fs::read_to_string(path).map_err(|_| "Unable to read cookie file")?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    parse_cookie_file(&contents, now)
+}
+
+// Parses the lines of a cookie file, leaving out the cookies that expired before `now`
+fn parse_cookie_file(contents: &str, now: u64) -> Result<Vec<BrowserCookie>> {
+    let mut cookies = vec![];
+    for line in contents.lines() {
+        // curl marks HttpOnly cookies with a prefix, on what would otherwise be a comment
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<_>>();
+        if fields.len() != 7 {
+            return Err("Cookie file is not in the Netscape format");
+        }
+        let (domain, include_subdomains, path, secure, expiry, name, value) = (
+            fields[0], fields[1], fields[2], fields[3], fields[4], fields[5], fields[6],
+        );
+        // an expiry of 0 is a session cookie, which lasts until the browser is closed
+        let expiry = expiry.parse::<u64>().unwrap_or(0);
+        if (expiry != 0 && expiry < now) || !is_wanted_domain(domain) {
+            continue;
+        }
+        let domain = if include_subdomains.eq_ignore_ascii_case("TRUE") && !domain.starts_with('.')
+        {
+            format!(".{}", domain)
+        } else {
+            domain.to_owned()
+        };
+        cookies.push(BrowserCookie {
+            domain,
+            name: name.to_owned(),
+            value: value.to_owned(),
+            path: path.to_owned(),
+            secure: secure.eq_ignore_ascii_case("TRUE"),
+        });
+    }
+    Ok(cookies)
+}
+
+/// Adds the cookies to a cookie jar that can be used by `Api`.
+pub fn import_into(cookies: &[BrowserCookie], jar: &PersistentCookieJar) {
+    for cookie in cookies {
+        if let Some(url) = cookie.url() {
+            jar.add_cookie_str(&cookie.to_set_cookie(), &url);
+        }
+    }
+}
+
+// The browser keeps its database locked while running, so we read from a copy
+fn open_copy(db_path: &Path) -> Result<(Connection, PathBuf)> {
+    let copy_path = std::env::temp_dir().join(format!(
+        "fluminurs-cookies-{}.sqlite",
+        rand::random::<u32>()
+    ));
+    std::fs::copy(db_path, &copy_path).map_err(|_| "Unable to copy browser cookie database")?;
+    // recent cookies may still be in the write-ahead log
+    let mut wal_path = db_path.as_os_str().to_owned();
+    wal_path.push("-wal");
+    let mut copy_wal_path = copy_path.as_os_str().to_owned();
+    copy_wal_path.push("-wal");
+    if Path::new(&wal_path).exists() {
+        std::fs::copy(&wal_path, &copy_wal_path)
+            .map_err(|_| "Unable to copy browser cookie database")?;
+    }
+    let connection = Connection::open_with_flags(&copy_path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .map_err(|_| "Unable to open browser cookie database")?;
+    Ok((connection, copy_path))
+}
+
+fn remove_copy(copy_path: &Path) {
+    let mut copy_wal_path = copy_path.as_os_str().to_owned();
+    copy_wal_path.push("-wal");
+    std::fs::remove_file(copy_path).ok();
+    std::fs::remove_file(copy_wal_path).ok();
+}
+
+// Picks the most recently used profile that has a cookie database
+fn find_latest_cookie_db(profile_dirs: &[PathBuf], db_names: &[&str]) -> Option<PathBuf> {
+    profile_dirs
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .flat_map(|entry| db_names.iter().map(move |name| entry.path().join(name)))
+        .filter_map(|path| {
+            let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((modified, path))
+        })
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+}
+
+fn find_firefox_cookie_db() -> Result<PathBuf> {
+    let mut profile_dirs = vec![];
+    if let Some(home) = dirs::home_dir() {
+        profile_dirs.push(home.join(".mozilla").join("firefox"));
+    }
+    if let Some(data) = dirs::data_dir() {
+        // macOS and Windows
+        profile_dirs.push(data.join("Firefox").join("Profiles"));
+        profile_dirs.push(data.join("Mozilla").join("Firefox").join("Profiles"));
+    }
+    find_latest_cookie_db(&profile_dirs, &["cookies.sqlite"])
+        .ok_or("Unable to find the Firefox cookie database")
+}
+
+fn read_firefox_cookies(db_path: &Path) -> Result<Vec<BrowserCookie>> {
+    let (connection, copy_path) = open_copy(db_path)?;
+    let cookies = (|| {
+        let mut statement = connection
+            .prepare("SELECT host, name, value, path, isSecure FROM moz_cookies")
+            .map_err(|_| "Unexpected Firefox cookie database format")?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok(BrowserCookie {
+                    domain: row.get(0)?,
+                    name: row.get(1)?,
+                    value: row.get(2)?,
+                    path: row.get(3)?,
+                    secure: row.get::<_, i64>(4)? != 0,
+                })
+            })
+            .map_err(|_| "Unable to read Firefox cookies")?;
+        rows.collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| "Unable to read Firefox cookies")
+    })();
+    drop(connection);
+    remove_copy(&copy_path);
+    cookies
+}
+
+fn find_chrome_cookie_db() -> Result<PathBuf> {
+    let mut profile_dirs = vec![];
+    if let Some(config) = dirs::config_dir() {
+        // Linux and macOS
+        profile_dirs.push(config.join("google-chrome"));
+        profile_dirs.push(config.join("chromium"));
+        profile_dirs.push(config.join("Google").join("Chrome"));
+    }
+    if let Some(local) = dirs::data_local_dir() {
+        // Windows
+        profile_dirs.push(local.join("Google").join("Chrome").join("User Data"));
+    }
+    find_latest_cookie_db(&profile_dirs, &["Cookies", "Network/Cookies"])
+        .ok_or("Unable to find the Chrome cookie database")
+}
+
+fn read_chrome_cookies(db_path: &Path) -> Result<Vec<BrowserCookie>> {
+    let (connection, copy_path) = open_copy(db_path)?;
+    let cookies = (|| {
+        // since database version 24, the decrypted value is prefixed with a hash of the domain
+        let version = connection
+            .query_row("SELECT value FROM meta WHERE key = 'version'", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .ok()
+            .and_then(|version| version.parse::<u32>().ok())
+            .unwrap_or(0);
+        let mut statement = connection
+            .prepare("SELECT host_key, name, value, encrypted_value, path, is_secure FROM cookies")
+            .map_err(|_| "Unexpected Chrome cookie database format")?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Vec<u8>>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, i64>(5)? != 0,
+                ))
+            })
+            .map_err(|_| "Unable to read Chrome cookies")?;
+
+        let mut cookies = vec![];
+        let mut undecryptable = 0;
+        for row in rows {
+            let (domain, name, value, encrypted_value, path, secure) =
+                row.map_err(|_| "Unable to read Chrome cookies")?;
+            if !is_wanted_domain(&domain) {
+                continue;
+            }
+            let value = if encrypted_value.is_empty() {
+                value
+            } else {
+                match decrypt_chrome_value(&encrypted_value, version) {
+                    Some(value) => value,
+                    None => {
+                        undecryptable += 1;
+                        continue;
+                    }
+                }
+            };
+            cookies.push(BrowserCookie {
+                domain,
+                name,
+                value,
+                path,
+                secure,
+            });
+        }
+        if undecryptable > 0 {
+            tracing::warn!(
+                "Skipped {} Chrome cookies that could not be decrypted",
+                undecryptable
+            );
+        }
+        Ok(cookies)
+    })();
+    drop(connection);
+    remove_copy(&copy_path);
+    cookies
+}
+
+// Only the "v10" scheme used on Linux without a keyring is supported.
+// Cookies protected by the system keyring, the macOS Keychain or DPAPI on Windows cannot be read.
+fn decrypt_chrome_value(encrypted_value: &[u8], version: u32) -> Option<String> {
+    use aes::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+
+    if !cfg!(target_os = "linux") || !encrypted_value.starts_with(b"v10") {
+        return None;
+    }
+    let mut key = [0u8; 16];
+    pbkdf2::pbkdf2::<hmac::Hmac<sha1::Sha1>>(b"peanuts", b"saltysalt", 1, &mut key);
+    let iv = [b' '; 16];
+    let mut buffer = encrypted_value[3..].to_vec();
+    let decrypted = cbc::Decryptor::<aes::Aes128>::new(&key.into(), &iv.into())
+        .decrypt_padded_mut::<Pkcs7>(&mut buffer)
+        .ok()?;
+    let decrypted = if version >= 24 && decrypted.len() >= 32 {
+        &decrypted[32..]
+    } else {
+        decrypted
+    };
+    String::from_utf8(decrypted.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn parses_netscape_cookie_files() {
+        let contents = "# Netscape HTTP Cookie File\n\
+            \n\
+            .nus.edu.sg\tTRUE\t/\tTRUE\t1800000000\tsession\tabc\n\
+            #HttpOnly_luminus.nus.edu.sg\tFALSE\t/api\tFALSE\t0\ttoken\tdef\n\
+            nus.zoom.us\tTRUE\t/\tTRUE\t1800000000\t_zm_ssid\tghi\n";
+        let cookies = parse_cookie_file(contents, NOW).unwrap();
+        assert_eq!(cookies.len(), 3);
+        assert_eq!(cookies[0].domain, ".nus.edu.sg");
+        assert_eq!(
+            cookies[0].to_set_cookie(),
+            "session=abc; Path=/; Domain=.nus.edu.sg; Secure"
+        );
+        // HttpOnly cookies are not comments, and session cookies do not expire
+        assert_eq!(cookies[1].domain, "luminus.nus.edu.sg");
+        assert_eq!(cookies[1].to_set_cookie(), "token=def; Path=/api");
+        assert_eq!(
+            cookies[1].url().unwrap().as_str(),
+            "http://luminus.nus.edu.sg/api"
+        );
+        // subdomains are included with a leading dot
+        assert_eq!(cookies[2].domain, ".nus.zoom.us");
+    }
+
+    #[test]
+    fn leaves_out_expired_and_unrelated_cookies() {
+        let contents = ".nus.edu.sg\tTRUE\t/\tTRUE\t1600000000\told\tabc\n\
+            .example.com\tTRUE\t/\tTRUE\t1800000000\tother\tdef\n\
+            .notnus.edu.sg\tTRUE\t/\tTRUE\t1800000000\tlookalike\tghi\n";
+        assert!(parse_cookie_file(contents, NOW).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(parse_cookie_file(".nus.edu.sg\tTRUE\t/\tTRUE\t0\tname\n", NOW).is_err());
+        assert!(parse_cookie_file("name=value; Path=/\n", NOW).is_err());
+        assert!(
+            parse_cookie_file(".nus.edu.sg\tTRUE\t/\tTRUE\t0\tname\tvalue\textra\n", NOW).is_err()
+        );
+    }
+}
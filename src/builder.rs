@@ -0,0 +1,289 @@
+//! Constructing an `Api` with control over how it talks to the servers, for apps (e.g. GUIs or
+//! bots) that bring their own TLS, proxy or cookie configuration, or that point it at a test server.
+
+use std::sync::{Arc, RwLock};
+
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Method, Response, Url};
+use tokio_util::sync::CancellationToken;
+
+use crate::rate_limit::RateLimiter;
+use crate::scheduler::Scheduler;
+use crate::streamer::{VideoContainer, VideoQuality};
+use crate::transport::HttpTransport;
+use crate::{
+    auth_http_post, build_auth_form, build_auth_url, client_builder, exchange_auth_code,
+    infinite_retry_http, until_cancelled, Api, Result, ADFS_OAUTH2_URL, ADFS_REDIRECT_URI,
+    API_BASE_URL, DEFAULT_MAX_RESPONSE_SIZE, INVALID_CREDENTIALS,
+};
+
+// Lets a cookie store of any type be kept until the client is built, and by the `Api` after that
+pub(crate) struct SharedCookieStore(Arc<dyn CookieStore>);
+
+impl std::fmt::Debug for SharedCookieStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedCookieStore")
+    }
+}
+
+impl CookieStore for SharedCookieStore {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        self.0.set_cookies(cookie_headers, url)
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        self.0.cookies(url)
+    }
+}
+
+/// Created with `Api::builder`. Everything that is not set is as with `Api::with_login`.
+pub struct ApiBuilder {
+    client: Option<Client>,
+    cookies: Option<Arc<dyn CookieStore>>,
+    redirect_policy: Option<Policy>,
+    headers: HeaderMap,
+    api_base_url: Option<Url>,
+    adfs_url: Option<Url>,
+    transport: Option<Arc<dyn HttpTransport>>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl ApiBuilder {
+    pub(crate) fn new() -> ApiBuilder {
+        ApiBuilder {
+            client: None,
+            cookies: None,
+            redirect_policy: None,
+            headers: HeaderMap::new(),
+            api_base_url: None,
+            adfs_url: None,
+            transport: None,
+            cancellation: None,
+        }
+    }
+
+    /// Uses this client as it is, instead of building one. The cookie store, redirect policy and
+    /// headers of the builder do not apply to it, and neither do the extra root certificates.
+    /// Logging in needs the client to have a cookie store, as ADFS keeps its session in cookies.
+    pub fn client(self, client: Client) -> ApiBuilder {
+        ApiBuilder {
+            client: Some(client),
+            ..self
+        }
+    }
+
+    /// The cookie store of the client, e.g. a `PersistentCookieJar` with sessions from earlier
+    pub fn cookie_store<C: CookieStore + 'static>(self, cookies: Arc<C>) -> ApiBuilder {
+        ApiBuilder {
+            cookies: Some(cookies),
+            ..self
+        }
+    }
+
+    /// Replaces the default policy, which follows up to 5 redirects. Logging in relies on
+    /// following the redirects of ADFS, so the policy has to allow at least those.
+    pub fn redirect_policy(self, redirect_policy: Policy) -> ApiBuilder {
+        ApiBuilder {
+            redirect_policy: Some(redirect_policy),
+            ..self
+        }
+    }
+
+    /// Headers to send with every request, in addition to the ones that each request sets
+    pub fn default_headers(self, headers: HeaderMap) -> ApiBuilder {
+        ApiBuilder { headers, ..self }
+    }
+
+    /// Where the LumiNUS API is, e.g. a mock server. It has to end with a slash.
+    pub fn api_base_url(self, api_base_url: Url) -> ApiBuilder {
+        ApiBuilder {
+            api_base_url: Some(api_base_url),
+            ..self
+        }
+    }
+
+    /// Where the ADFS OAuth2 authorisation endpoint is
+    pub fn adfs_url(self, adfs_url: Url) -> ApiBuilder {
+        ApiBuilder {
+            adfs_url: Some(adfs_url),
+            ..self
+        }
+    }
+
+    /// Sends the requests of the `Api` instead of the client, e.g. to answer them with canned
+    /// responses in tests. The client is still used to build the requests, and for downloads.
+    pub fn transport(self, transport: Arc<dyn HttpTransport>) -> ApiBuilder {
+        ApiBuilder {
+            transport: Some(transport),
+            ..self
+        }
+    }
+
+    /// Cancelling the token gives up on logging in, as well as on what the `Api` does afterwards,
+    /// as with `Api::with_cancellation_token`
+    pub fn cancellation_token(self, cancellation: CancellationToken) -> ApiBuilder {
+        ApiBuilder {
+            cancellation: Some(cancellation),
+            ..self
+        }
+    }
+
+    // Everything that is needed to talk to the servers, before anyone has logged in
+    fn into_parts(self) -> Result<Parts> {
+        let (client, cookies) = match self.client {
+            Some(client) => (client, None),
+            None => {
+                let cookies = Arc::new(SharedCookieStore(
+                    self.cookies.unwrap_or_else(|| Arc::new(Jar::default())),
+                ));
+                let mut builder = client_builder()?
+                    .cookie_provider(cookies.clone())
+                    .default_headers(self.headers);
+                if let Some(redirect_policy) = self.redirect_policy {
+                    builder = builder.redirect(redirect_policy);
+                }
+                let client = builder
+                    .build()
+                    .map_err(|_| "Unable to create HTTP client")?;
+                (client, Some(cookies))
+            }
+        };
+        let transport = self.transport.unwrap_or_else(|| Arc::new(client.clone()));
+        let api_base_url = match self.api_base_url {
+            Some(url) => url,
+            None => Url::parse(API_BASE_URL).map_err(|_| "Unable to parse API URL")?,
+        };
+        let adfs_url = match self.adfs_url {
+            Some(url) => url,
+            None => Url::parse(ADFS_OAUTH2_URL).map_err(|_| "Unable to parse ADFS URL")?,
+        };
+        Ok(Parts {
+            client,
+            transport,
+            api_base_url,
+            adfs_url,
+            cancellation: self.cancellation.unwrap_or_default(),
+            cookies,
+        })
+    }
+
+    /// An `Api` that is not logged in to LumiNUS, as with `Api::anonymous`
+    pub fn build(self) -> Result<Api> {
+        Ok(self.into_parts()?.finish(String::new()))
+    }
+
+    /// An `Api` that uses a token obtained elsewhere, as with `Api::with_token_and_cookies`
+    pub fn build_with_token(self, token: &str) -> Result<Api> {
+        Ok(self.into_parts()?.finish(token.to_owned()))
+    }
+
+    /// Logs in with the given credentials
+    pub async fn login(self, username: &str, password: &str) -> Result<Api> {
+        let parts = self.into_parts()?;
+        let params = build_auth_form(username, password);
+        let jwt = until_cancelled(&parts.cancellation, async {
+            let auth_resp = auth_http_post(
+                &parts.client,
+                &*parts.transport,
+                build_auth_url(&parts.adfs_url),
+                Some(&params),
+                false,
+            )
+            .await?;
+            if !auth_resp.url().as_str().starts_with(ADFS_REDIRECT_URI) {
+                return Err(INVALID_CREDENTIALS);
+            }
+            parts.exchange_auth_code(&auth_resp).await
+        })
+        .await;
+        // the login page may have changed in ways that only a browser gets past
+        #[cfg(feature = "headless-login")]
+        let jwt = match (jwt, &parts.cookies) {
+            (Err(e), Some(cookies)) if crate::headless_login::should_fall_back(e) => {
+                tracing::warn!(error = e, "Unable to log in, trying in a headless browser");
+                until_cancelled(&parts.cancellation, async {
+                    crate::headless_login::login(&parts.adfs_url, username, password, &**cookies)
+                        .await?;
+                    parts.reuse_session().await
+                })
+                .await
+            }
+            (jwt, _) => jwt,
+        };
+        Ok(parts.finish(jwt?))
+    }
+
+    /// Logs in by reusing an existing ADFS session from the cookie store
+    pub async fn login_with_cookies(self) -> Result<Api> {
+        let parts = self.into_parts()?;
+        let jwt = until_cancelled(&parts.cancellation, parts.reuse_session()).await?;
+        Ok(parts.finish(jwt))
+    }
+}
+
+struct Parts {
+    client: Client,
+    transport: Arc<dyn HttpTransport>,
+    api_base_url: Url,
+    adfs_url: Url,
+    cancellation: CancellationToken,
+    cookies: Option<Arc<SharedCookieStore>>,
+}
+
+impl Parts {
+    // A token from the ADFS session in the cookie store
+    async fn reuse_session(&self) -> Result<String> {
+        let auth_resp = infinite_retry_http(
+            &self.client,
+            &*self.transport,
+            None,
+            build_auth_url(&self.adfs_url),
+            Method::GET,
+            None,
+            |req| req,
+        )
+        .await?;
+        if !auth_resp.url().as_str().starts_with(ADFS_REDIRECT_URI) {
+            return Err("No valid ADFS session found in the cookies");
+        }
+        self.exchange_auth_code(&auth_resp).await
+    }
+
+    async fn exchange_auth_code(&self, auth_resp: &Response) -> Result<String> {
+        exchange_auth_code(
+            &self.client,
+            &*self.transport,
+            &self.api_base_url,
+            auth_resp,
+        )
+        .await
+    }
+
+    fn finish(self, jwt: String) -> Api {
+        Api {
+            jwt: Arc::new(RwLock::new(jwt)),
+            client: self.client,
+            transport: self.transport,
+            api_base_url: self.api_base_url,
+            adfs_url: self.adfs_url,
+            ffmpeg_path: String::new(),
+            ffmpeg_available: Arc::default(),
+            panopto_podcast: false,
+            announcement_html: false,
+            video_container: VideoContainer::Mp4,
+            video_quality: VideoQuality::Best,
+            max_response_size: Some(DEFAULT_MAX_RESPONSE_SIZE),
+            low_memory: false,
+            scheduler: Arc::new(Scheduler::default()),
+            rate_limiter: Arc::new(RateLimiter::default()),
+            download_proxy: None,
+            cancellation: self.cancellation,
+            listing_cache: None,
+            panopto_sessions: Arc::default(),
+            trash_dir: None,
+            cookies: self.cookies,
+        }
+    }
+}
@@ -0,0 +1,170 @@
+// Writing iCalendar (RFC 5545) files, so that meetings and lectures can be shown in calendar apps
+
+use std::time::SystemTime;
+
+use crate::util::html_to_text;
+
+const PRODID: &str = concat!("-//fluminurs//", env!("CARGO_PKG_VERSION"), "//EN");
+
+#[derive(Debug, Clone)]
+pub struct CalendarEvent {
+    pub uid: String,
+    pub summary: String,
+    pub start: SystemTime,
+    pub end: Option<SystemTime>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+}
+
+fn format_ics_time(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time)
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string()
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+// Lines longer than 75 octets have to be folded, without splitting UTF-8 characters
+fn push_line(ics: &mut String, line: &str) {
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > 75 {
+            ics.push_str("\r\n ");
+            length = 1;
+        }
+        ics.push(c);
+        length += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+/// Serialises the events into a calendar that can be imported or subscribed to.
+pub fn to_ics(events: &[CalendarEvent]) -> String {
+    let stamp = format_ics_time(SystemTime::now());
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(&mut ics, &format!("PRODID:{}", PRODID));
+    push_line(&mut ics, "CALSCALE:GREGORIAN");
+    for event in events {
+        push_line(&mut ics, "BEGIN:VEVENT");
+        push_line(&mut ics, &format!("UID:{}", escape_text(&event.uid)));
+        push_line(&mut ics, &format!("DTSTAMP:{}", stamp));
+        push_line(
+            &mut ics,
+            &format!("DTSTART:{}", format_ics_time(event.start)),
+        );
+        if let Some(end) = event.end {
+            push_line(&mut ics, &format!("DTEND:{}", format_ics_time(end)));
+        }
+        push_line(
+            &mut ics,
+            &format!("SUMMARY:{}", escape_text(&event.summary)),
+        );
+        if let Some(description) = &event.description {
+            push_line(
+                &mut ics,
+                &format!("DESCRIPTION:{}", escape_text(&html_to_text(description))),
+            );
+        }
+        if let Some(url) = &event.url {
+            push_line(&mut ics, &format!("URL:{}", url));
+        }
+        push_line(&mut ics, "END:VEVENT");
+    }
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    fn event() -> CalendarEvent {
+        CalendarEvent {
+            uid: "zoom-123@fluminurs".to_owned(),
+            summary: "CS2040S Lecture; Week 1, part 2".to_owned(),
+            // 2023-01-09 02:00 UTC
+            start: UNIX_EPOCH + Duration::from_secs(1_673_229_600),
+            end: Some(UNIX_EPOCH + Duration::from_secs(1_673_236_800)),
+            description: Some("<p>Bring a laptop</p>\n<p>Slides &amp; notes</p>".to_owned()),
+            url: Some("https://nus-sg.zoom.us/j/123".to_owned()),
+        }
+    }
+
+    // The lines of the calendar, leaving out the time that it was written
+    fn lines(ics: &str) -> Vec<&str> {
+        ics.split("\r\n")
+            .filter(|line| !line.starts_with("DTSTAMP:"))
+            .collect()
+    }
+
+    #[test]
+    fn writes_events() {
+        let ics = to_ics(&[event()]);
+        let prodid = format!("PRODID:{}", PRODID);
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        assert_eq!(
+            lines(&ics),
+            [
+                "BEGIN:VCALENDAR",
+                "VERSION:2.0",
+                prodid.as_str(),
+                "CALSCALE:GREGORIAN",
+                "BEGIN:VEVENT",
+                "UID:zoom-123@fluminurs",
+                "DTSTART:20230109T020000Z",
+                "DTEND:20230109T040000Z",
+                r"SUMMARY:CS2040S Lecture\; Week 1\, part 2",
+                r"DESCRIPTION:Bring a laptop\nSlides & notes",
+                "URL:https://nus-sg.zoom.us/j/123",
+                "END:VEVENT",
+                "END:VCALENDAR",
+                "",
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_out_what_an_event_does_not_have() {
+        let event = CalendarEvent {
+            end: None,
+            description: None,
+            url: None,
+            ..event()
+        };
+        let ics = to_ics(&[event]);
+        assert!(ics.contains("DTSTART:20230109T020000Z\r\nSUMMARY:"));
+        assert!(!ics.contains("DTEND:"));
+        assert!(!ics.contains("DESCRIPTION:"));
+        assert!(!ics.contains("URL:"));
+        assert!(!to_ics(&[]).contains("BEGIN:VEVENT"));
+    }
+
+    #[test]
+    fn folds_long_lines_between_characters() {
+        let event = CalendarEvent {
+            summary: "é".repeat(60),
+            ..event()
+        };
+        let ics = to_ics(&[event]);
+        let summary = ics
+            .split("\r\n")
+            .skip_while(|line| !line.starts_with("SUMMARY:"))
+            .take(2)
+            .collect::<Vec<_>>();
+        assert!(summary.iter().all(|line| line.len() <= 75));
+        assert_eq!(
+            format!("{}{}", summary[0], &summary[1][1..]),
+            format!("SUMMARY:{}", "é".repeat(60))
+        );
+    }
+}
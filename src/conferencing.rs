@@ -1,6 +1,6 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use async_trait::async_trait;
 use futures_util::future;
@@ -8,15 +8,27 @@ use reqwest::header::REFERER;
 use reqwest::{Method, Url};
 use scraper::{Html, Selector};
 use serde::Deserialize;
+use tokio::io::AsyncWrite;
 
 use crate::resource;
-use crate::resource::{OverwriteMode, OverwriteResult, Resource};
+use crate::resource::{
+    OverwriteMode, OverwriteResult, RemoteInfo, Resource, RetryableError, RetryableResult,
+};
+use crate::streamer::{make_video_extension, remux_video, VideoContainer, VideoMetadata};
 use crate::util::{parse_time, sanitise_filename};
-use crate::{Api, ApiData, Result};
+use crate::{Api, ApiData, Error, Result};
+
+/// Zoom did not let us in to the recording with its password, or there was no password to give
+pub const RECORDING_PASSWORD_REJECTED: Error = "Recording password was rejected by Zoom";
 
 const ZOOM_VALIDATE_MEETING_PASSWORD_URL: &str = "https://nus-sg.zoom.us/rec/validate_meet_passwd";
 const ZOOM_PASSWORD_URL_PREFIX: &str = "/rec/share";
 const ZOOM_DOWNLOAD_REFERER_URL: &str = "https://nus-sg.zoom.us/";
+// How long all API calls are held back after a "TooManyRequests", doubling each time in a row
+const TOO_MANY_REQUESTS_BACKOFF_START: Duration = Duration::from_secs(1);
+const MAX_TOO_MANY_REQUESTS_BACKOFF: Duration = Duration::from_secs(30);
+// A 404 is often only a hiccup, which goes away by itself soon after
+const NOT_FOUND_RETRY_DELAY: Duration = Duration::from_millis(500);
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -24,6 +36,9 @@ struct Conference {
     id: String,
     name: String,
     start_date: String,
+    end_date: Option<String>,
+    #[serde(rename = "joinURL")]
+    join_url: Option<String>,
     #[serde(rename = "isPublishRecordURL")]
     is_publish_record_url: bool, // not sure if we should use this or recordType == 1
 }
@@ -48,6 +63,9 @@ struct CloudRecordInstance {
 #[serde(rename_all = "camelCase")]
 struct ZoomValidationResponse {
     status: bool,
+    #[serde(default)]
+    error_code: i64,
+    error_message: Option<String>,
 }
 
 pub struct ConferencingHandle {
@@ -65,6 +83,27 @@ pub struct ZoomRecording {
     share_url: String,
     password: String,
     start_date: SystemTime,
+    include_assets: bool,
+    include_views: bool,
+}
+
+// The files that Zoom offers on the share page of a recording
+struct ZoomAssets {
+    video: Url,
+    // the other views of the recording, like the shared screen, by their type
+    views: Vec<(String, Url)>,
+    chat: Option<Url>,
+    transcript: Option<Url>,
+}
+
+/// A scheduled Zoom meeting of a module, whether or not it has been recorded.
+#[derive(Debug, Clone)]
+pub struct Meeting {
+    pub id: String,
+    pub name: String,
+    pub start_date: SystemTime,
+    pub end_date: Option<SystemTime>,
+    pub join_url: Option<String>,
 }
 
 impl ConferencingHandle {
@@ -72,18 +111,74 @@ impl ConferencingHandle {
         ConferencingHandle { id, path }
     }
 
+    async fn fetch_conferences(&self, api: &Api) -> Result<ApiData<Vec<Conference>>> {
+        api.api_as_json_paged::<Conference>(&format!(
+            "zoom/Meeting/{}/Meetings?sortby=startDate%20asc&populate=null",
+            self.id
+        ))
+        .await
+    }
+
+    // loads all meetings, without polling for their recordings
+    pub async fn load_meetings(&self, api: &Api) -> Result<Vec<Meeting>> {
+        match self.fetch_conferences(api).await?.data {
+            Some(conferences) => conferences
+                .into_iter()
+                .map(|c| {
+                    Ok(Meeting {
+                        id: c.id,
+                        name: c.name,
+                        start_date: parse_time(&c.start_date)?,
+                        // a missing or odd end only makes the meeting open-ended
+                        end_date: c.end_date.as_deref().and_then(|date| parse_time(date).ok()),
+                        join_url: c.join_url,
+                    })
+                })
+                .collect(),
+            None => Err("Invalid API response from server: type mismatch"),
+        }
+    }
+
     // loads all conferences
     pub async fn load(self, api: &Api) -> Result<Vec<ZoomRecording>> {
-        let conferencing_resp = api
-            .api_as_json::<ApiData<Vec<Conference>>>(
-                &format!(
-                    "zoom/Meeting/{}/Meetings?offset=0&sortby=startDate%20asc&populate=null",
-                    self.id
-                ),
-                Method::GET,
-                None,
-            )
-            .await?;
+        self.load_except(api, |_, _| false).await
+    }
+
+    /// Like `load`, but without the recordings that are already in `destination` (which the
+    /// paths of the recordings are relative to). Since recordings are not changed once they are
+    /// up, there is no need to ask Zoom about those meetings again.
+    pub async fn load_missing(self, api: &Api, destination: &Path) -> Result<Vec<ZoomRecording>> {
+        let stems = file_stems(&destination.join(&self.path)).await;
+        self.load_except(api, |c, conferences| {
+            let name = sanitise_filename(&c.name);
+            let unique = conferences
+                .iter()
+                .filter(|other| other.name == c.name)
+                .count()
+                == 1;
+            stems
+                .iter()
+                .any(|stem| match stem.strip_prefix(name.as_str()) {
+                    // the ID is appended to the names of meetings that share their name,
+                    // so without it, the file may be of any of those meetings
+                    Some(rest) => {
+                        rest.contains(&c.id)
+                            || (unique && (rest.is_empty() || is_number_suffix(rest)))
+                    }
+                    None => false,
+                })
+        })
+        .await
+    }
+
+    // Loads the recordings of the conferences, except those that `skip` says to leave out,
+    // given each conference and all of them
+    async fn load_except<F: Fn(&Conference, &[Conference]) -> bool>(
+        self,
+        api: &Api,
+        skip: F,
+    ) -> Result<Vec<ZoomRecording>> {
+        let conferencing_resp = self.fetch_conferences(api).await?;
 
         // Unfortunately, we can't tell if a recording is available from just the conference_resp,
         // so we have to poll each conference individually.
@@ -91,19 +186,28 @@ impl ConferencingHandle {
         // then the recording link will be unclickable on Luminus,
         // so there's probably really no recording then
         // We also poll future meetings, since the meeting time is just a guideline anyway.
-        // todo: We could be nicer to the server by looking at our local files first,
-        // and only polling those that we don't have, since recordings can't be updated.
         match conferencing_resp.data {
-            Some(conferences) => future::join_all(
-                conferences
+            Some(conferences) => {
+                let conferences = conferences
                     .into_iter()
                     .filter(|c| c.is_publish_record_url)
-                    .map(|c| load_cloud_record(api, c, &self.path)),
-            )
-            .await
-            .into_iter()
-            .collect::<Result<Vec<_>>>()
-            .map(|v| v.into_iter().flatten().collect::<Vec<_>>()),
+                    .collect::<Vec<_>>();
+                let skipped = conferences
+                    .iter()
+                    .map(|c| skip(c, &conferences))
+                    .collect::<Vec<_>>();
+                future::join_all(
+                    conferences
+                        .into_iter()
+                        .zip(skipped)
+                        .filter(|(_, skipped)| !skipped)
+                        .map(|(c, _)| load_cloud_record(api, c, &self.path)),
+                )
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>>>()
+                .map(|v| v.into_iter().flatten().collect::<Vec<_>>())
+            }
             None => Err("Invalid API response from server: type mismatch"),
         }
     }
@@ -116,11 +220,14 @@ async fn load_cloud_record(
 ) -> Result<Vec<ZoomRecording>> {
     // Note: Sometimes, we get back {"code":400,"status":"fail","message":"TooManyRequests"}
     // which is probably similar to the comment in infinite_retry_http, but only now it is not a HTTP error code.
-    // When this happens, we should retry until succeeded.
+    // When this happens, we should back off (along with all the other API calls) and retry until succeeded.
     // Sometimes, we also get code: 404, even though the meeting actually exists,
     // but sometimes 404 means that there's really no recording for the meeting ... let's just try 5 times before failing?
+    // the polls of all the modules together are what the server would throttle otherwise
+    let _permit = api.rate_limiter.poll().await;
     let request_path = format!("zoom/Meeting/{}/cloudrecord", conference.id);
     let mut num_404_tries = 0;
+    let mut backoff = TOO_MANY_REQUESTS_BACKOFF_START;
     let cloud_record = loop {
         let cloud_record = api
             .api_as_json::<CloudRecord>(&request_path, Method::GET, None)
@@ -131,10 +238,18 @@ async fn load_cloud_record(
         }
         if cloud_record.code == Some(404) {
             num_404_tries += 1;
+            tokio::time::sleep(NOT_FOUND_RETRY_DELAY).await;
+        } else {
+            tracing::debug!(
+                ?backoff,
+                "Too many requests for Zoom recordings, backing off"
+            );
+            api.rate_limiter.pause(backoff);
+            backoff = (backoff * 2).min(MAX_TOO_MANY_REQUESTS_BACKOFF);
         }
     };
 
-    let start_date = parse_time(&conference.start_date);
+    let start_date = parse_time(&conference.start_date)?;
     let mut conference_id = conference.id;
     let conference_name: &str = &conference.name;
 
@@ -145,12 +260,15 @@ async fn load_cloud_record(
                 .into_iter()
                 .map(|cri| ZoomRecording {
                     id: std::mem::take(&mut conference_id), // ok to use std::mem::take because this lambda is only called once
-                    path: path.join(make_mp4_extension(Path::new(&sanitise_filename(
-                        conference_name,
-                    )))),
+                    path: path.join(make_video_extension(
+                        api,
+                        Path::new(&sanitise_filename(conference_name)),
+                    )),
                     share_url: cri.share_url,
                     password: cri.password,
                     start_date,
+                    include_assets: false,
+                    include_views: false,
                 })
                 .collect::<Vec<_>>(),
             _ => record_instances
@@ -158,13 +276,15 @@ async fn load_cloud_record(
                 .enumerate()
                 .map(|(i, cri)| ZoomRecording {
                     id: conference_id.clone(),
-                    path: path.join(make_mp4_extension(Path::new(&append_number(
-                        &sanitise_filename(conference_name),
-                        i + 1,
-                    )))),
+                    path: path.join(make_video_extension(
+                        api,
+                        Path::new(&append_number(&sanitise_filename(conference_name), i + 1)),
+                    )),
                     share_url: cri.share_url,
                     password: cri.password,
                     start_date,
+                    include_assets: false,
+                    include_views: false,
                 })
                 .collect::<Vec<_>>(),
         }),
@@ -172,8 +292,24 @@ async fn load_cloud_record(
     }
 }
 
-fn make_mp4_extension(path: &Path) -> PathBuf {
-    path.with_extension("mp4")
+// The names of the files in the directory, without their extensions
+async fn file_stems(dir: &Path) -> Vec<String> {
+    let mut stems = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if let Some(stem) = Path::new(&entry.file_name()).file_stem() {
+                stems.push(stem.to_string_lossy().into_owned());
+            }
+        }
+    }
+    stems
+}
+
+// Whether this is what `append_number` appends, e.g. ` (2)`
+fn is_number_suffix(text: &str) -> bool {
+    text.strip_prefix(" (")
+        .and_then(|text| text.strip_suffix(')'))
+        .is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
 }
 
 fn append_number(text: &str, number: usize) -> String {
@@ -204,26 +340,190 @@ impl Resource for ZoomRecording {
         temp_destination: &Path,
         overwrite: OverwriteMode,
     ) -> Result<OverwriteResult> {
-        resource::do_retryable_download(
+        let metadata = VideoMetadata::from_path(&self.path, Some(self.start_date));
+        let metadata = &metadata;
+        let result = resource::do_retryable_download(
             api,
+            self,
             destination,
             temp_destination,
             overwrite,
-            self.last_updated(),
             move |api| self.get_download_url(api),
-            move |api, url, temp_destination| {
-                resource::download_chunks(api, url, temp_destination, |req| {
-                    Api::add_desktop_user_agent(req)
-                        .header(reqwest::header::RANGE, "bytes=0-")
-                        .header(reqwest::header::REFERER, ZOOM_DOWNLOAD_REFERER_URL)
-                })
-            },
+            move |api, url, temp_destination| download_video(api, url, temp_destination, metadata),
         )
+        .await?;
+        if self.include_views {
+            self.download_views(api, destination, metadata).await?;
+        }
+        if self.include_assets {
+            self.download_assets(api, destination).await?;
+        }
+        Ok(result)
+    }
+
+    async fn download_to_writer(
+        &self,
+        api: &Api,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        let url = self.get_download_url(api).await?;
+        resource::write_chunks(api, url, writer, |req| {
+            Api::add_desktop_user_agent(req)
+                .header(reqwest::header::RANGE, "bytes=0-")
+                .header(reqwest::header::REFERER, ZOOM_DOWNLOAD_REFERER_URL)
+        })
         .await
     }
+
+    async fn remote_info(&self, api: &Api) -> Result<Option<RemoteInfo>> {
+        let url = self.get_download_url(api).await?;
+        let remote_info = resource::fetch_remote_info(api, url, |req| {
+            Api::add_desktop_user_agent(req)
+                .header(reqwest::header::REFERER, ZOOM_DOWNLOAD_REFERER_URL)
+        })
+        .await?;
+        // the size of a remuxed recording is not known in advance
+        Ok(Some(if api.video_container == VideoContainer::Mp4 {
+            remote_info
+        } else {
+            RemoteInfo {
+                size: None,
+                ..remote_info
+            }
+        }))
+    }
 }
 impl ZoomRecording {
+    /// Creates a recording from a Zoom share link, e.g. one that was shared outside of LumiNUS.
+    /// Such recordings have no known start date, so they are never considered updated.
+    pub fn from_share_url(share_url: String, password: String, path: PathBuf) -> ZoomRecording {
+        ZoomRecording {
+            id: share_url.clone(),
+            path,
+            share_url,
+            password,
+            start_date: SystemTime::UNIX_EPOCH,
+            include_assets: false,
+            include_views: false,
+        }
+    }
+
+    /// Also download the chat log (`.chat.txt`) and transcript (`.vtt`) of the recording,
+    /// if Zoom has them, next to the video.
+    pub fn with_assets(self, include_assets: bool) -> ZoomRecording {
+        ZoomRecording {
+            include_assets,
+            ..self
+        }
+    }
+
+    /// Also download the other views of the recording that Zoom has, like the gallery or the
+    /// shared screen, next to the video with the type of the view before the extension
+    /// (e.g. `Lecture.share.mp4`). The share page is only fetched again for this while one of
+    /// the views is missing.
+    pub fn with_views(self, include_views: bool) -> ZoomRecording {
+        ZoomRecording {
+            include_views,
+            ..self
+        }
+    }
+
+    fn view_path(destination: &Path, view: &str) -> PathBuf {
+        let extension = destination
+            .extension()
+            .map(|extension| extension.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        destination.with_extension(format!("{}.{}", view, extension))
+    }
+
+    // Which views the recording has is only known from its share page, so it is kept in a
+    // hidden file next to the video (e.g. `.Lecture.mp4.views`) once they were all downloaded
+    fn views_record_path(destination: &Path) -> PathBuf {
+        let mut name = std::ffi::OsString::from(".");
+        name.push(destination.file_name().unwrap_or_default());
+        name.push(".views");
+        destination.with_file_name(name)
+    }
+
+    // Like the assets, views that we already have are not downloaded again
+    async fn download_views(
+        &self,
+        api: &Api,
+        destination: &Path,
+        metadata: &VideoMetadata,
+    ) -> Result<()> {
+        let record_path = Self::views_record_path(destination);
+        if let Ok(record) = tokio::fs::read_to_string(&record_path).await {
+            if record
+                .lines()
+                .all(|view| Self::view_path(destination, view).exists())
+            {
+                return Ok(());
+            }
+        }
+        let assets = self.get_assets(api).await?;
+        for (view, url) in &assets.views {
+            let path = Self::view_path(destination, view);
+            if path.exists() {
+                continue;
+            }
+            let mut temp_name = std::ffi::OsString::from("~!");
+            temp_name.push(path.file_name().ok_or("Invalid view path")?);
+            let temp_path = path.with_file_name(temp_name);
+            download_video(api, url.clone(), &temp_path, metadata)
+                .await
+                .map_err(|e| match e {
+                    RetryableError::Retry(e) | RetryableError::Fail(e) => e,
+                })?;
+            tokio::fs::rename(&temp_path, &path)
+                .await
+                .map_err(|_| "Unable to move temporary file")?;
+        }
+        let record = assets
+            .views
+            .iter()
+            .map(|(view, _)| view.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        tokio::fs::write(&record_path, record)
+            .await
+            .map_err(|_| "Unable to record the views of the recording")
+    }
+
+    fn chat_path(destination: &Path) -> PathBuf {
+        destination.with_extension("chat.txt")
+    }
+
+    fn transcript_path(destination: &Path) -> PathBuf {
+        destination.with_extension("vtt")
+    }
+
+    // Recordings never change, so assets that we already have are not downloaded again
+    async fn download_assets(&self, api: &Api, destination: &Path) -> Result<()> {
+        let chat_path = Self::chat_path(destination);
+        let transcript_path = Self::transcript_path(destination);
+        if chat_path.exists() && transcript_path.exists() {
+            return Ok(());
+        }
+        let assets = self.get_assets(api).await?;
+        for (url, path) in [
+            (assets.chat, chat_path),
+            (assets.transcript, transcript_path),
+        ] {
+            if let Some(url) = url {
+                if !path.exists() {
+                    download_asset(api, url, &path).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     async fn get_download_url(&self, api: &Api) -> Result<Url> {
+        Ok(self.get_assets(api).await?.video)
+    }
+
+    async fn get_assets(&self, api: &Api) -> Result<ZoomAssets> {
         let share_url = Url::parse(&self.share_url).map_err(|_| "Unable to parse share URL")?;
         let share_resp = api
             .custom_request(
@@ -268,7 +568,7 @@ impl ZoomRecording {
             let validate_resp = api
                 .custom_request(
                     Url::parse(ZOOM_VALIDATE_MEETING_PASSWORD_URL)
-                        .expect("Unable to parse Zoom validation URL"),
+                        .map_err(|_| "Unable to parse Zoom validation URL")?,
                     Method::POST,
                     Some(&form),
                     move |req| {
@@ -277,13 +577,25 @@ impl ZoomRecording {
                     },
                 )
                 .await?;
-            let validate_resp_data = validate_resp
-                .json::<ZoomValidationResponse>()
+            let validate_resp_text = validate_resp
+                .text()
                 .await
-                .map_err(|_| "Unable to parse response JSON from Zoom validation")?;
+                .map_err(|_| "Unable to get response text from Zoom validation")?;
+            let validate_resp_data = serde_json::from_str::<ZoomValidationResponse>(
+                &validate_resp_text,
+            )
+            .map_err(|_| {
+                tracing::debug!(response = %validate_resp_text, "Unable to parse Zoom validation");
+                "Unable to parse response JSON from Zoom validation"
+            })?;
 
             if !validate_resp_data.status {
-                return Err("Recording password was rejected by Zoom");
+                tracing::warn!(
+                    error_code = validate_resp_data.error_code,
+                    error_message = ?validate_resp_data.error_message,
+                    "Zoom validation failed"
+                );
+                return Err(RECORDING_PASSWORD_REJECTED);
             }
 
             let resp = api
@@ -292,7 +604,7 @@ impl ZoomRecording {
 
             if resp.url().path().starts_with(ZOOM_PASSWORD_URL_PREFIX) {
                 // Zoom still wants a password, so we probably failed to get in
-                return Err("Zoom still wants a password even though we already supplied it");
+                return Err(RECORDING_PASSWORD_REJECTED);
             }
 
             resp
@@ -301,24 +613,141 @@ impl ZoomRecording {
             share_resp
         };
 
+        let video_resp_url = video_resp.url().clone();
         let resp_html = video_resp
             .text()
             .await
             .map_err(|_| "Unable to get response text")?;
 
-        // We use regex here because we're trying to get some data from an embedded javascript script
-        let video_url_regex =
-            regex::Regex::new("viewMp4Url:[\\s]*\'([^\']*)\'").expect("Unable to parse regex");
-        let url = Url::parse(
-            video_url_regex
-                .captures(&resp_html)
-                .ok_or("Parse error")?
-                .get(1)
-                .ok_or("Parse error")?
-                .as_str(),
-        )
-        .map_err(|_| "Unable to parse conference download URL")?;
+        parse_share_page(&resp_html, &video_resp_url).inspect_err(|_| {
+            tracing::debug!(page = %resp_html, "Unable to parse Zoom share page");
+        })
+    }
+}
+
+// The URLs of the share page by their key (e.g. `viewMp4Url`), from the JSON that the page is
+// bootstrapped with, which is less likely to change with the layout of the page
+fn bootstrap_urls(html: &str) -> Option<BTreeMap<String, String>> {
+    let (_, rest) = html.split_once("window.__data__")?;
+    let json = &rest[rest.find('{')?..];
+    // the blob is followed by the rest of the script, which the stream leaves alone
+    let data = serde_json::Deserializer::from_str(json)
+        .into_iter::<serde_json::Value>()
+        .next()?
+        .ok()?;
+    let mut urls = BTreeMap::new();
+    collect_urls(&data, &mut urls);
+    Some(urls)
+}
 
-        Ok(url)
+fn collect_urls(value: &serde_json::Value, urls: &mut BTreeMap<String, String>) {
+    match value {
+        serde_json::Value::Object(object) => {
+            for (key, value) in object {
+                match value {
+                    serde_json::Value::String(url) if key.ends_with("Url") && !url.is_empty() => {
+                        urls.entry(key.clone()).or_insert_with(|| url.clone());
+                    }
+                    value => collect_urls(value, urls),
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                collect_urls(value, urls);
+            }
+        }
+        _ => {}
+    }
+}
+
+// The same from the page's scripts, for pages without the JSON
+fn script_urls(html: &str) -> BTreeMap<String, String> {
+    let url_regex = regex::Regex::new("([A-Za-z]+Url):[\\s]*[\'\"]([^\'\"]+)[\'\"]")
+        .expect("Unable to parse regex");
+    let mut urls = BTreeMap::new();
+    for captures in url_regex.captures_iter(html) {
+        urls.entry(captures[1].to_owned())
+            .or_insert_with(|| captures[2].to_owned());
+    }
+    urls
+}
+
+fn parse_share_page(html: &str, page_url: &Url) -> Result<ZoomAssets> {
+    let urls = bootstrap_urls(html)
+        .filter(|urls| urls.contains_key("viewMp4Url"))
+        .unwrap_or_else(|| script_urls(html));
+    // URLs may be relative to the share page
+    let find_url = |key: &str| urls.get(key).and_then(|url| page_url.join(url).ok());
+
+    let video = find_url("viewMp4Url").ok_or("Unable to find the video on the Zoom share page")?;
+
+    // other views have their own URLs, like `shareMp4Url` for the shared screen
+    let mut views: Vec<(String, Url)> = vec![];
+    for key in urls.keys() {
+        let view = match key.strip_suffix("Mp4Url") {
+            Some(view) if view != "view" => view.to_lowercase(),
+            _ => continue,
+        };
+        if let Some(view_url) = find_url(key) {
+            if view_url != video && !views.iter().any(|(_, url)| *url == view_url) {
+                views.push((view, view_url));
+            }
+        }
+    }
+
+    Ok(ZoomAssets {
+        video,
+        views,
+        chat: find_url("chatUrl"),
+        transcript: find_url("transcriptUrl"),
+    })
+}
+
+async fn download_video(
+    api: &Api,
+    url: Url,
+    temp_destination: &Path,
+    metadata: &VideoMetadata,
+) -> RetryableResult<()> {
+    let edit_request = |req| {
+        Api::add_desktop_user_agent(req)
+            .header(reqwest::header::RANGE, "bytes=0-")
+            .header(reqwest::header::REFERER, ZOOM_DOWNLOAD_REFERER_URL)
+    };
+    if api.video_container == VideoContainer::Mp4 {
+        return resource::download_chunks(api, url, temp_destination, edit_request).await;
+    }
+    // Zoom only serves mp4, so other containers need a remux after downloading
+    let mp4_destination = temp_destination.with_extension("mp4");
+    resource::download_chunks(api, url, &mp4_destination, edit_request).await?;
+    let result = remux_video(api, &mp4_destination, temp_destination, metadata).await;
+    tokio::fs::remove_file(&mp4_destination).await.ok();
+    result
+}
+
+async fn download_asset(api: &Api, url: Url, path: &Path) -> Result<()> {
+    let mut temp_name = std::ffi::OsString::from("~!");
+    temp_name.push(path.file_name().ok_or("Invalid asset path")?);
+    let temp_path = path.with_file_name(temp_name);
+    let result = async {
+        let mut file = tokio::fs::File::create(&temp_path)
+            .await
+            .map_err(|_| "Unable to create temporary file")?;
+        // error pages are refused here, as an asset that exists is never fetched again
+        resource::write_chunks(api, url, &mut file, |req| {
+            Api::add_desktop_user_agent(req)
+                .header(reqwest::header::REFERER, ZOOM_DOWNLOAD_REFERER_URL)
+        })
+        .await?;
+        drop(file);
+        tokio::fs::rename(&temp_path, path)
+            .await
+            .map_err(|_| "Unable to move temporary file")
+    }
+    .await;
+    if result.is_err() {
+        tokio::fs::remove_file(&temp_path).await.ok();
     }
+    result
 }
@@ -0,0 +1,215 @@
+// A cookie jar that survives across runs, so that the Zoom and Panopto sessions can be reused
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use reqwest::cookie::CookieStore as ReqwestCookieStore;
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto;
+use crate::Result;
+
+const JAR_VERSION: u32 = 1;
+
+// Cookies are grouped by the domain they belong to, so that the file is easy to inspect
+// and the cookies of a single service can be dropped without touching the others
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct JarFile {
+    version: u32,
+    domains: BTreeMap<String, Vec<serde_json::Value>>,
+}
+
+pub struct PersistentCookieJar {
+    store: RwLock<cookie_store::CookieStore>,
+    // None for a jar that is only kept for the run
+    path: Option<PathBuf>,
+    passphrase: Option<String>,
+}
+
+impl PersistentCookieJar {
+    /// Loads the jar from `path`, or starts an empty one if the file doesn't exist yet.
+    /// If a passphrase is given, the jar is encrypted when saved.
+    /// Encrypted jars can only be loaded with the passphrase they were saved with.
+    pub fn load<P: AsRef<Path>>(path: P, passphrase: Option<&str>) -> Result<PersistentCookieJar> {
+        let path = path.as_ref().to_owned();
+        let store = match std::fs::read(&path) {
+            Ok(data) => parse_jar_file(&decode(&data, passphrase)?)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                cookie_store::CookieStore::default()
+            }
+            Err(_) => return Err("Unable to read cookie jar"),
+        };
+        Ok(PersistentCookieJar {
+            store: RwLock::new(store),
+            path: Some(path),
+            passphrase: passphrase.map(|p| p.to_owned()),
+        })
+    }
+
+    /// A jar that is never saved, so the sessions in it only last for the run.
+    pub fn in_memory() -> PersistentCookieJar {
+        PersistentCookieJar {
+            store: RwLock::new(cookie_store::CookieStore::default()),
+            path: None,
+            passphrase: None,
+        }
+    }
+
+    /// Whether the jar file at `path` is encrypted, and hence needs a passphrase to be loaded.
+    pub fn is_encrypted<P: AsRef<Path>>(path: P) -> bool {
+        std::fs::read(path)
+            .map(|data| crypto::is_encrypted(&data))
+            .unwrap_or(false)
+    }
+
+    /// Writes all unexpired cookies, including session cookies, back to the file.
+    /// The file is only readable by the user, as the cookies are as good as the credentials.
+    pub fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut jar_file = JarFile {
+            version: JAR_VERSION,
+            domains: BTreeMap::new(),
+        };
+        {
+            let store = self.read();
+            for cookie in store.iter_unexpired() {
+                let domain = String::from(&cookie.domain);
+                let cookie =
+                    serde_json::to_value(cookie).map_err(|_| "Unable to serialise cookie")?;
+                jar_file.domains.entry(domain).or_default().push(cookie);
+            }
+        }
+        let data =
+            serde_json::to_vec_pretty(&jar_file).map_err(|_| "Unable to serialise cookies")?;
+        let data = encode(data, self.passphrase.as_deref())?;
+        write_private(path, &data).map_err(|_| "Unable to write cookie jar")
+    }
+
+    /// The domains that the jar has cookies for.
+    pub fn domains(&self) -> Vec<String> {
+        let store = self.read();
+        let mut domains = store
+            .iter_unexpired()
+            .map(|cookie| String::from(&cookie.domain))
+            .collect::<Vec<_>>();
+        domains.sort();
+        domains.dedup();
+        domains
+    }
+
+    /// Forgets all cookies of the domain, e.g. when its session is known to be broken.
+    pub fn clear_domain(&self, domain: &str) {
+        let mut store = self.write();
+        let cookies = store
+            .iter_any()
+            .filter(|cookie| String::from(&cookie.domain) == domain)
+            .map(|cookie| (String::from(&cookie.path), cookie.name().to_owned()))
+            .collect::<Vec<_>>();
+        for (path, name) in cookies {
+            store.remove(domain, &path, &name);
+        }
+    }
+
+    /// Adds a cookie in `Set-Cookie` format, as if it was sent in a response from `url`.
+    pub fn add_cookie_str(&self, cookie: &str, url: &Url) {
+        let cookies = cookie::Cookie::parse(cookie)
+            .ok()
+            .map(|c| c.into_owned())
+            .into_iter();
+        self.write().store_response_cookies(cookies, url);
+    }
+
+    // A panic while the lock was held can at worst have left some cookies of a response
+    // unstored, so the store is still used rather than failing every request after it
+    fn read(&self) -> RwLockReadGuard<'_, cookie_store::CookieStore> {
+        self.store.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, cookie_store::CookieStore> {
+        self.store.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl ReqwestCookieStore for PersistentCookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let cookies = cookie_headers.filter_map(|header| {
+            let header = std::str::from_utf8(header.as_bytes()).ok()?;
+            cookie::Cookie::parse(header).ok().map(|c| c.into_owned())
+        });
+        self.write().store_response_cookies(cookies, url);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let header = self
+            .read()
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if header.is_empty() {
+            None
+        } else {
+            HeaderValue::from_str(&header).ok()
+        }
+    }
+}
+
+fn parse_jar_file(data: &[u8]) -> Result<cookie_store::CookieStore> {
+    let jar_file =
+        serde_json::from_slice::<JarFile>(data).map_err(|_| "Corrupt cookie jar file")?;
+    if jar_file.version != JAR_VERSION {
+        return Err("Unsupported cookie jar version");
+    }
+    // the cookie store reads one JSON cookie per line, and drops the expired ones
+    let mut lines = String::new();
+    for cookie in jar_file.domains.values().flatten() {
+        lines.push_str(&cookie.to_string());
+        lines.push('\n');
+    }
+    cookie_store::CookieStore::load_json(Cursor::new(lines)).map_err(|_| "Corrupt cookie jar file")
+}
+
+// Writes to a temporary file next to `path` that only the user can read, then moves it over
+// `path`, so that a crash halfway leaves the old jar as it was
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let result = options
+        .open(&temp_path)
+        .and_then(|mut file| file.write_all(data).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        fs::remove_file(&temp_path).ok();
+    }
+    result
+}
+
+fn decode(data: &[u8], passphrase: Option<&str>) -> Result<Vec<u8>> {
+    match passphrase {
+        Some(passphrase) if crypto::is_encrypted(data) => crypto::decrypt(passphrase, data),
+        None if crypto::is_encrypted(data) => {
+            Err("The cookie jar is encrypted, but no passphrase was given")
+        }
+        _ => Ok(data.to_vec()),
+    }
+}
+
+fn encode(data: Vec<u8>, passphrase: Option<&str>) -> Result<Vec<u8>> {
+    match passphrase {
+        Some(passphrase) => crypto::encrypt(passphrase, &data),
+        None => Ok(data),
+    }
+}
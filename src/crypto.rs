@@ -0,0 +1,70 @@
+// Passphrase-based encryption for the files we keep on disk.
+// The key is derived with Argon2 and the data is sealed with AES-256-GCM.
+// Without the `encryption` feature, encrypted files can still be recognised but not read or written.
+
+#[cfg(feature = "encryption")]
+use aes_gcm::aead::{Aead, KeyInit};
+#[cfg(feature = "encryption")]
+use aes_gcm::{Aes256Gcm, Nonce};
+#[cfg(feature = "encryption")]
+use argon2::Argon2;
+
+use crate::Result;
+
+const MAGIC: &[u8] = b"FLUMINURS-ENC1";
+#[cfg(feature = "encryption")]
+const SALT_LEN: usize = 16;
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 12;
+
+#[cfg(feature = "encryption")]
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Aes256Gcm> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| "Unable to derive encryption key")?;
+    Aes256Gcm::new_from_slice(&key).map_err(|_| "Unable to derive encryption key")
+}
+
+/// Whether the data was produced by `encrypt`.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+#[cfg(feature = "encryption")]
+pub fn encrypt(passphrase: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let salt: [u8; SALT_LEN] = rand::random();
+    let nonce: [u8; NONCE_LEN] = rand::random();
+    let ciphertext = derive_key(passphrase, &salt)?
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| "Unable to encrypt data")?;
+
+    let mut data = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+    Ok(data)
+}
+
+#[cfg(feature = "encryption")]
+pub fn decrypt(passphrase: &str, data: &[u8]) -> Result<Vec<u8>> {
+    if !is_encrypted(data) || data.len() < MAGIC.len() + SALT_LEN + NONCE_LEN {
+        return Err("Data is not encrypted by fluminurs");
+    }
+    let (salt, rest) = data[MAGIC.len()..].split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    derive_key(passphrase, salt)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Unable to decrypt data (wrong passphrase?)")
+}
+
+#[cfg(not(feature = "encryption"))]
+pub fn encrypt(_passphrase: &str, _plaintext: &[u8]) -> Result<Vec<u8>> {
+    Err("Encryption support is not enabled")
+}
+
+#[cfg(not(feature = "encryption"))]
+pub fn decrypt(_passphrase: &str, _data: &[u8]) -> Result<Vec<u8>> {
+    Err("Encryption support is not enabled")
+}
@@ -4,12 +4,13 @@ use std::time::SystemTime;
 use async_trait::async_trait;
 use futures_util::future;
 use futures_util::future::{BoxFuture, FutureExt};
-use reqwest::{Method, Url};
+use reqwest::multipart::{Form, Part};
+use reqwest::{Method, StatusCode, Url};
 use serde::Deserialize;
 
 use crate::resource::SimpleDownloadableResource;
-use crate::util::{append_extension, parse_time, sanitise_filename};
-use crate::{Api, ApiData, Result};
+use crate::util::{append_extension, parse_time, prefix_with_index, sanitise_filename};
+use crate::{Api, ApiData, Result, REQUIRES_TEACHING_ACCESS};
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -21,7 +22,26 @@ struct ApiFileDirectory {
     creator_name: Option<String>,
     #[serde(rename = "creatorUserID")]
     creator_user_id: Option<String>,
+    created_date: Option<String>,
     last_updated_date: String,
+    file_size: Option<u64>,
+}
+
+/// The order by which files in a folder are numbered, when their names are prefixed with an index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexPrefix {
+    ServerOrder,
+    UploadDate,
+}
+
+/// How the files in the folders below a module's files are named, when they are all put in one
+/// folder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flatten {
+    /// `Tutorials/Week 1/Sheet.pdf` becomes `Tutorials - Week 1 - Sheet.pdf`
+    PrefixFolders,
+    /// `Tutorials/Week 1/Sheet.pdf` becomes `Sheet.pdf`
+    NamesOnly,
 }
 
 pub struct DirectoryHandle {
@@ -35,7 +55,12 @@ pub struct DirectoryHandle {
 pub struct File {
     id: String,
     path: PathBuf,
+    // the name on the server, which the local name may have been changed from
+    name: String,
     last_updated: SystemTime,
+    size: Option<u64>,
+    // the folder that the file is in, unless it was looked up on its own
+    folder_id: Option<String>,
 }
 
 impl DirectoryHandle {
@@ -47,23 +72,82 @@ impl DirectoryHandle {
         }
     }
 
-    // loads all files recursively and returns a flattened list
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Looks up a folder below this one, one level at a time, e.g. `Tutorials/Week 1`
+    pub async fn subfolder(self, api: &Api, relative_path: &Path) -> Result<DirectoryHandle> {
+        let mut folder = self;
+        for component in relative_path.components() {
+            let name = component.as_os_str().to_string_lossy();
+            let subdirs = api
+                .api_as_json::<ApiData<Vec<ApiFileDirectory>>>(
+                    &format!("files/?ParentID={}", folder.id),
+                    Method::GET,
+                    None,
+                )
+                .await?
+                .data
+                .ok_or("Invalid API response from server: type mismatch")?;
+            // local folders are named after the sanitised names
+            let subdir = subdirs
+                .into_iter()
+                .find(|s| sanitise_filename(&s.name) == name)
+                .ok_or("Folder does not exist on the server")?;
+            folder = DirectoryHandle {
+                id: subdir.id,
+                path: folder.path.join(name.as_ref()),
+                allow_upload: subdir.allow_upload.unwrap_or(false),
+            };
+        }
+        Ok(folder)
+    }
+
+    /// Uploads a local file into this folder, which needs teaching access to the module
+    pub async fn upload(&self, api: &Api, local_path: &Path) -> Result<()> {
+        let file_name = local_path
+            .file_name()
+            .ok_or("Invalid file to upload")?
+            .to_string_lossy()
+            .into_owned();
+        self.upload_as(api, local_path, &file_name).await
+    }
+
+    /// Uploads a local file into this folder under another name, e.g. that of the file on the
+    /// server that it was downloaded from
+    pub async fn upload_as(&self, api: &Api, local_path: &Path, file_name: &str) -> Result<()> {
+        let file_name = file_name.to_owned();
+        let content = tokio::fs::read(local_path)
+            .await
+            .map_err(|_| "Unable to read file to upload")?;
+        let form = Form::new().part("file", Part::bytes(content).file_name(file_name));
+        let res = api
+            .api_multipart(&format!("files/{}/upload", self.id), form)
+            .await?;
+        match res.status() {
+            StatusCode::FORBIDDEN => Err(REQUIRES_TEACHING_ACCESS),
+            status if status.is_success() => Ok(()),
+            _ => Err("Upload rejected by the server"),
+        }
+    }
+
+    // loads all files recursively and returns a flattened list, with the files of the subfolders
+    // moved into this folder if `flatten` is given
     pub fn load(
         self,
         api: &Api,
         include_uploadable: bool,
         regularize_uploadable: bool,
-    ) -> BoxFuture<Result<Vec<File>>> {
+        index_prefix: Option<IndexPrefix>,
+        flatten: Option<Flatten>,
+    ) -> BoxFuture<'_, Result<Vec<File>>> {
         debug_assert!(include_uploadable || !self.allow_upload);
 
         async move {
             let get_subdirs = || async {
                 let subdirs_resp = api
-                    .api_as_json::<ApiData<Vec<ApiFileDirectory>>>(
-                        &format!("files/?ParentID={}", self.id),
-                        Method::GET,
-                        None,
-                    )
+                    .api_listing::<ApiFileDirectory>(&format!("files/?ParentID={}", self.id))
                     .await?;
                 match subdirs_resp.data {
                     Some(subdirs) => future::join_all(
@@ -76,7 +160,15 @@ impl DirectoryHandle {
                                 allow_upload: s.allow_upload.unwrap_or(false),
                                 /* last_updated: parse_time(&s.last_updated_date), */
                             })
-                            .map(|dh| dh.load(api, include_uploadable, regularize_uploadable)),
+                            .map(|dh| {
+                                dh.load(
+                                    api,
+                                    include_uploadable,
+                                    regularize_uploadable,
+                                    index_prefix,
+                                    flatten,
+                                )
+                            }),
                     )
                     .await
                     .into_iter()
@@ -88,60 +180,78 @@ impl DirectoryHandle {
 
             let get_files = || async {
                 let files_resp = api
-                    .api_as_json::<ApiData<Vec<ApiFileDirectory>>>(
-                        &format!(
-                            "files/{}/file{}",
-                            self.id,
-                            if self.allow_upload {
-                                "?populate=Creator"
-                            } else {
-                                ""
-                            }
-                        ),
-                        Method::GET,
-                        None,
-                    )
+                    .api_listing::<ApiFileDirectory>(&format!(
+                        "files/{}/file{}",
+                        self.id,
+                        if self.allow_upload {
+                            "?populate=Creator"
+                        } else {
+                            ""
+                        }
+                    ))
                     .await?;
-                match files_resp.data {
-                    Some(files) => Ok(files
-                        .into_iter()
-                        .map(|s| File {
-                            id: s.id,
-                            path: self.path.join({
-                                let name_for_download =
-                                    s.file_name.as_deref().unwrap_or(s.name.as_str());
-                                if self.allow_upload {
-                                    if regularize_uploadable {
-                                        sanitise_filename(
-                                            append_extension(
-                                                s.creator_user_id.as_deref().unwrap_or("Unknown"),
-                                                name_for_download,
-                                            )
-                                            .as_str(),
-                                        )
-                                    } else {
-                                        sanitise_filename(
-                                            format!(
-                                                "{} - {}",
-                                                s.creator_name.as_deref().unwrap_or("Unknown"),
-                                                name_for_download
-                                            )
-                                            .as_str(),
+                let files = match files_resp.data {
+                    Some(files) => files,
+                    None => return Err("Invalid API response from server: type mismatch"),
+                };
+                let indices = match index_prefix {
+                    Some(index_prefix) => file_indices(&files, index_prefix)?,
+                    None => vec![],
+                };
+                let count = files.len();
+                files
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, s)| {
+                        let name = {
+                            let name_for_download =
+                                s.file_name.as_deref().unwrap_or(s.name.as_str());
+                            if self.allow_upload {
+                                if regularize_uploadable {
+                                    sanitise_filename(
+                                        append_extension(
+                                            s.creator_user_id.as_deref().unwrap_or("Unknown"),
+                                            name_for_download,
                                         )
-                                    }
+                                        .as_str(),
+                                    )
                                 } else {
-                                    sanitise_filename(name_for_download)
+                                    sanitise_filename(
+                                        format!(
+                                            "{} - {}",
+                                            s.creator_name.as_deref().unwrap_or("Unknown"),
+                                            name_for_download
+                                        )
+                                        .as_str(),
+                                    )
                                 }
+                            } else {
+                                sanitise_filename(name_for_download)
+                            }
+                        };
+                        Ok(File {
+                            path: self.path.join(match indices.get(i) {
+                                Some(index) => prefix_with_index(*index, count, &name),
+                                None => name,
                             }),
-                            last_updated: parse_time(&s.last_updated_date),
+                            name: s.file_name.unwrap_or(s.name),
+                            id: s.id,
+                            last_updated: parse_time(&s.last_updated_date)?,
+                            size: s.file_size,
+                            folder_id: Some(self.id.clone()),
                         })
-                        .collect::<Vec<_>>()),
-                    None => Err("Invalid API response from server: type mismatch"),
-                }
+                    })
+                    .collect::<Result<Vec<_>>>()
             };
 
             let (res_subdirs, res_files) = future::join(get_subdirs(), get_files()).await;
             let mut files = res_subdirs?;
+            if let Some(flatten) = flatten {
+                // the subfolders have already flattened theirs, so each file is one level down
+                for file in &mut files {
+                    file.path = flatten_path(&self.path, &file.path, flatten);
+                }
+            }
             files.append(&mut res_files?);
 
             Ok(files)
@@ -150,6 +260,94 @@ impl DirectoryHandle {
     }
 }
 
+fn flatten_path(folder: &Path, path: &Path, flatten: Flatten) -> PathBuf {
+    let relative_path = path.strip_prefix(folder).unwrap_or(path);
+    let name = match flatten {
+        Flatten::PrefixFolders => relative_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" - "),
+        Flatten::NamesOnly => relative_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    folder.join(name)
+}
+
+// The position of each file in the order given by `index_prefix`
+fn file_indices(files: &[ApiFileDirectory], index_prefix: IndexPrefix) -> Result<Vec<usize>> {
+    let mut order = (0..files.len()).collect::<Vec<_>>();
+    if index_prefix == IndexPrefix::UploadDate {
+        // older files may lack a creation date, so their last updated date stands in for it
+        let upload_dates = files
+            .iter()
+            .map(|file| {
+                parse_time(
+                    file.created_date
+                        .as_deref()
+                        .unwrap_or(&file.last_updated_date),
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        order.sort_by_key(|&i| upload_dates[i]);
+    }
+    let mut indices = vec![0; files.len()];
+    for (index, i) in order.into_iter().enumerate() {
+        indices[i] = index;
+    }
+    Ok(indices)
+}
+
+impl File {
+    /// The id of the folder that the file is in, which stays the same when the folder is renamed
+    pub fn folder_id(&self) -> Option<&str> {
+        self.folder_id.as_deref()
+    }
+
+    /// The name of the file on the server, before it was sanitised, numbered or otherwise renamed
+    /// to make its local path
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// A file that is attached to something else, e.g. an announcement, rather than in a folder
+    pub(crate) fn attachment(
+        id: String,
+        path: PathBuf,
+        name: String,
+        last_updated: SystemTime,
+        size: Option<u64>,
+    ) -> File {
+        File {
+            id,
+            path,
+            name,
+            last_updated,
+            size,
+            folder_id: None,
+        }
+    }
+
+    /// Looks up a single file by its id, e.g. from a LumiNUS link.
+    /// The path of the returned file is just its file name.
+    pub async fn from_id(api: &Api, id: &str) -> Result<File> {
+        let file = api
+            .api_as_json::<ApiFileDirectory>(&format!("files/file/{}", id), Method::GET, None)
+            .await?;
+        let name = file.file_name.unwrap_or(file.name);
+        Ok(File {
+            path: PathBuf::from(sanitise_filename(&name)),
+            name,
+            id: file.id,
+            last_updated: parse_time(&file.last_updated_date)?,
+            size: file.file_size,
+            folder_id: None,
+        })
+    }
+}
+
 #[async_trait]
 impl SimpleDownloadableResource for File {
     fn id(&self) -> &str {
@@ -167,6 +365,10 @@ impl SimpleDownloadableResource for File {
         self.last_updated
     }
 
+    fn size(&self) -> Option<u64> {
+        self.size
+    }
+
     async fn get_download_url(&self, api: &Api) -> Result<Url> {
         let data = api
             .api_as_json::<ApiData<String>>(
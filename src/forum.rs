@@ -0,0 +1,197 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use futures_util::future;
+use reqwest::Method;
+use serde::Deserialize;
+use tokio::io::AsyncWrite;
+
+use crate::resource;
+use crate::resource::{OverwriteMode, OverwriteResult, Resource, RetryableError};
+use crate::util::{html_to_text, parse_time, sanitise_filename};
+use crate::{Api, ApiData, Result};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiForum {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiThread {
+    id: String,
+    title: String,
+    last_updated_date: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiPost {
+    message: String,
+    creator_name: Option<String>,
+    created_date: String,
+    attachments: Option<Vec<ApiAttachment>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiAttachment {
+    name: String,
+}
+
+pub struct ForumHandle {
+    id: String,
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct ForumThread {
+    id: String,
+    title: String,
+    path: PathBuf,
+    last_updated: SystemTime,
+}
+
+impl ForumHandle {
+    pub fn new(id: String, path: PathBuf) -> ForumHandle {
+        ForumHandle { id, path }
+    }
+
+    // loads all threads of all forums of the module
+    pub async fn load(self, api: &Api) -> Result<Vec<ForumThread>> {
+        let forums_resp = api
+            .api_as_json::<ApiData<Vec<ApiForum>>>(
+                &format!("forum/?ParentID={}", self.id),
+                Method::GET,
+                None,
+            )
+            .await?;
+
+        match forums_resp.data {
+            Some(forums) => future::join_all(
+                forums
+                    .into_iter()
+                    .map(|f| Self::load_forum(api, f, &self.path)),
+            )
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .map(|v| v.into_iter().flatten().collect::<Vec<_>>()),
+            None => Ok(vec![]), // this module has no forums
+        }
+    }
+
+    async fn load_forum(api: &Api, forum: ApiForum, path: &Path) -> Result<Vec<ForumThread>> {
+        let threads_resp = api
+            .api_as_json_paged::<ApiThread>(&format!("forum/{}/Topic", forum.id))
+            .await?;
+
+        let forum_path = path.join(Path::new(&sanitise_filename(&forum.name)));
+
+        match threads_resp.data {
+            Some(threads) => threads
+                .into_iter()
+                .map(|t| {
+                    Ok(ForumThread {
+                        id: t.id,
+                        path: forum_path
+                            .join(make_md_extension(Path::new(&sanitise_filename(&t.title)))),
+                        title: t.title,
+                        last_updated: parse_time(&t.last_updated_date)?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>(),
+            None => Err("Invalid API response from server: type mismatch"),
+        }
+    }
+}
+
+fn make_md_extension(path: &Path) -> PathBuf {
+    path.with_extension("md")
+}
+
+impl ForumThread {
+    async fn get_markdown(&self, api: &Api) -> Result<String> {
+        let posts_resp = api
+            .api_as_json_paged::<ApiPost>(&format!(
+                "forum/Topic/{}/Post?populate=Creator,Attachments&sortby=createdDate%20ASC",
+                self.id
+            ))
+            .await?;
+
+        let posts = posts_resp
+            .data
+            .ok_or("Invalid API response from server: type mismatch")?;
+
+        let mut markdown = format!("# {}\n", self.title);
+        for post in posts {
+            let created = chrono::DateTime::<chrono::Local>::from(parse_time(&post.created_date)?);
+            markdown.push_str(&format!(
+                "\n---\n\n**{}** on {}\n\n{}\n",
+                post.creator_name.as_deref().unwrap_or("Unknown"),
+                created.format("%Y-%m-%d %H:%M"),
+                html_to_text(&post.message)
+            ));
+            let attachments = post.attachments.unwrap_or_default();
+            if !attachments.is_empty() {
+                markdown.push_str("\nAttachments:\n");
+                for attachment in attachments {
+                    markdown.push_str(&format!("- {}\n", attachment.name));
+                }
+            }
+        }
+        Ok(markdown)
+    }
+}
+
+#[async_trait]
+impl Resource for ForumThread {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+    fn path_mut(&mut self) -> &mut PathBuf {
+        &mut self.path
+    }
+
+    fn last_updated(&self) -> SystemTime {
+        self.last_updated
+    }
+
+    async fn download(
+        &self,
+        api: &Api,
+        destination: &Path,
+        temp_destination: &Path,
+        overwrite: OverwriteMode,
+    ) -> Result<OverwriteResult> {
+        resource::do_retryable_download(
+            api,
+            self,
+            destination,
+            temp_destination,
+            overwrite,
+            move |api| self.get_markdown(api),
+            move |_, markdown, temp_destination| async move {
+                tokio::fs::write(temp_destination, markdown)
+                    .await
+                    .map_err(|_| RetryableError::Fail("Failed writing to disk"))
+            },
+        )
+        .await
+    }
+
+    async fn download_to_writer(
+        &self,
+        api: &Api,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        resource::write_text(&self.get_markdown(api).await?, writer).await
+    }
+}
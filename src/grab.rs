@@ -0,0 +1,91 @@
+// Resolving share links into resources, so that resources can be downloaded with just a URL
+
+use std::path::Path;
+
+use reqwest::Url;
+
+use crate::conferencing::ZoomRecording;
+use crate::file::File;
+use crate::multimedia::ExternalVideo;
+use crate::panopto;
+use crate::streamer::make_video_extension;
+use crate::util::sanitise_filename;
+use crate::{Api, Result};
+
+const LUMINUS_HOST: &str = "luminus.nus.edu.sg";
+
+pub enum LinkResource {
+    File(File),
+    Panopto(ExternalVideo),
+    Zoom(ZoomRecording),
+}
+
+/// Works out what kind of resource the link points to, and resolves it to a resource
+/// whose path is just its file name.
+/// Supported are LumiNUS file links, Panopto viewer links and Zoom recording share links.
+/// Panopto and Zoom links work with `Api::anonymous()` if they are publicly accessible,
+/// while LumiNUS links need an `Api` that is logged in.
+pub async fn resolve_link(api: &Api, link: &str, password: Option<&str>) -> Result<LinkResource> {
+    let url = Url::parse(link).map_err(|_| "Unable to parse link")?;
+    let host = url.host_str().unwrap_or("");
+
+    if host.eq_ignore_ascii_case(LUMINUS_HOST) {
+        resolve_luminus_link(api, &url).await
+    } else if host.ends_with("panopto.com") {
+        resolve_panopto_link(api, &url).await
+    } else if host.ends_with("zoom.us") && url.path().starts_with("/rec/") {
+        resolve_zoom_link(api, &url, password)
+    } else {
+        Err("Unsupported link: only LumiNUS file, Panopto viewer and Zoom recording links are supported")
+    }
+}
+
+// File links look like `/modules/<module id>/files/<folder id>/<file id>`,
+// possibly with more folders in between, so the file is the last id after `files`
+async fn resolve_luminus_link(api: &Api, url: &Url) -> Result<LinkResource> {
+    let file_id = url
+        .path_segments()
+        .and_then(|segments| {
+            segments
+                .skip_while(|segment| *segment != "files" && *segment != "file")
+                .skip(1)
+                .filter(|segment| !segment.is_empty())
+                .last()
+        })
+        .ok_or("LumiNUS link does not point to a file")?;
+    if api.is_anonymous() {
+        return Err("LumiNUS links can only be downloaded when logged in");
+    }
+    Ok(LinkResource::File(File::from_id(api, file_id).await?))
+}
+
+async fn resolve_panopto_link(api: &Api, url: &Url) -> Result<LinkResource> {
+    // both Viewer.aspx and Embed.aspx links carry the session id in the query
+    let delivery_id = url
+        .query_pairs()
+        .find(|(k, _)| k.eq_ignore_ascii_case("id"))
+        .map(|(_, v)| v.into_owned())
+        .ok_or("Panopto link does not contain a session id")?;
+    let name = panopto::get_session_name(api, &delivery_id)
+        .await?
+        .unwrap_or_else(|| delivery_id.clone());
+    let path = make_video_extension(api, Path::new(&sanitise_filename(&name)));
+    Ok(LinkResource::Panopto(ExternalVideo::from_delivery_id(
+        delivery_id,
+        path,
+    )))
+}
+
+fn resolve_zoom_link(api: &Api, url: &Url, password: Option<&str>) -> Result<LinkResource> {
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|segment| !segment.is_empty())
+        .ok_or("Zoom link does not contain a recording id")?;
+    let path = make_video_extension(api, Path::new(&sanitise_filename(name)));
+    Ok(LinkResource::Zoom(ZoomRecording::from_share_url(
+        url.as_str().to_owned(),
+        password.unwrap_or("").to_owned(),
+        path,
+    )))
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use reqwest::Method;
+use serde::Deserialize;
+use tokio::io::AsyncWrite;
+
+use crate::resource;
+use crate::resource::{OverwriteMode, OverwriteResult, Resource, RetryableError};
+use crate::util::{csv_record, parse_time};
+use crate::{teaching_access_error, Api, ApiData, Result};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiGradebookItem {
+    id: String,
+    name: String,
+    max_mark: Option<f64>,
+    last_updated_date: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiMark {
+    #[serde(rename = "gradebookItemID")]
+    item_id: String,
+    mark: Option<f64>,
+    grade: Option<String>,
+    #[serde(rename = "userID")]
+    user_id: Option<String>,
+    user_name: Option<String>,
+}
+
+pub struct GradebookHandle {
+    id: String,
+    path: PathBuf,
+    is_teaching: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Gradebook {
+    id: String,
+    path: PathBuf,
+    items: Vec<GradebookItem>,
+    full_class: bool,
+    last_updated: SystemTime,
+}
+
+#[derive(Debug, Clone)]
+struct GradebookItem {
+    id: String,
+    name: String,
+    max_mark: Option<f64>,
+}
+
+impl GradebookHandle {
+    pub fn new(id: String, path: PathBuf, is_teaching: bool) -> GradebookHandle {
+        GradebookHandle {
+            id,
+            path,
+            is_teaching,
+        }
+    }
+
+    // Loads the gradebook items of the module.
+    // If `full_class` is set and the user teaches the module, the marks of the whole class are exported,
+    // otherwise only the marks of the current user are.
+    pub async fn load(self, api: &Api, full_class: bool) -> Result<Vec<Gradebook>> {
+        let items_resp = api
+            .api_as_json::<ApiData<Vec<ApiGradebookItem>>>(
+                &format!("gradebook/?ParentID={}", self.id),
+                Method::GET,
+                None,
+            )
+            .await?;
+
+        let items = match items_resp.data {
+            Some(items) if !items.is_empty() => items,
+            _ => return Ok(vec![]), // this module has no gradebook
+        };
+        let last_updated = items
+            .iter()
+            .map(|item| parse_time(&item.last_updated_date))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .max()
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+
+        Ok(vec![Gradebook {
+            id: self.id,
+            path: self.path.join("Gradebook.csv"),
+            items: items
+                .into_iter()
+                .map(|item| GradebookItem {
+                    id: item.id,
+                    name: item.name,
+                    max_mark: item.max_mark,
+                })
+                .collect(),
+            full_class: full_class && self.is_teaching,
+            last_updated,
+        }])
+    }
+}
+
+fn format_mark(mark: Option<f64>) -> String {
+    mark.map(|m| m.to_string()).unwrap_or_default()
+}
+
+impl Gradebook {
+    async fn get_csv(&self, api: &Api) -> Result<String> {
+        let marks = api
+            .api_as_json::<ApiData<Vec<ApiMark>>>(
+                &format!(
+                    "gradebook/mark{}?ParentID={}",
+                    if self.full_class { "" } else { "/me" },
+                    self.id
+                ),
+                Method::GET,
+                None,
+            )
+            .await
+            .map_err(|e| {
+                if self.full_class {
+                    teaching_access_error(e)
+                } else {
+                    e
+                }
+            })?
+            .data
+            .ok_or("Invalid API response from server: type mismatch")?;
+
+        if self.full_class {
+            // one row per student, one column per gradebook item
+            let mut students: BTreeMap<(String, String), BTreeMap<String, Option<f64>>> =
+                BTreeMap::new();
+            for mark in marks {
+                students
+                    .entry((
+                        mark.user_id.unwrap_or_default(),
+                        mark.user_name.unwrap_or_default(),
+                    ))
+                    .or_default()
+                    .insert(mark.item_id, mark.mark);
+            }
+            let mut header = vec!["Student ID".to_owned(), "Name".to_owned()];
+            header.extend(self.items.iter().map(|item| item.name.clone()));
+            let mut csv = csv_record(&header);
+            for ((user_id, user_name), student_marks) in students {
+                let mut row = vec![user_id, user_name];
+                row.extend(
+                    self.items
+                        .iter()
+                        .map(|item| format_mark(student_marks.get(&item.id).copied().flatten())),
+                );
+                csv.push_str(&csv_record(&row));
+            }
+            Ok(csv)
+        } else {
+            let mut csv = csv_record(&["Item", "Mark", "Max Mark", "Grade"]);
+            for item in &self.items {
+                let mark = marks.iter().find(|m| m.item_id == item.id);
+                csv.push_str(&csv_record(&[
+                    item.name.clone(),
+                    format_mark(mark.and_then(|m| m.mark)),
+                    format_mark(item.max_mark),
+                    mark.and_then(|m| m.grade.clone()).unwrap_or_default(),
+                ]));
+            }
+            Ok(csv)
+        }
+    }
+}
+
+#[async_trait]
+impl Resource for Gradebook {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+    fn path_mut(&mut self) -> &mut PathBuf {
+        &mut self.path
+    }
+
+    fn last_updated(&self) -> SystemTime {
+        self.last_updated
+    }
+
+    async fn download(
+        &self,
+        api: &Api,
+        destination: &Path,
+        temp_destination: &Path,
+        overwrite: OverwriteMode,
+    ) -> Result<OverwriteResult> {
+        resource::do_retryable_download(
+            api,
+            self,
+            destination,
+            temp_destination,
+            overwrite,
+            move |api| self.get_csv(api),
+            move |_, csv, temp_destination| async move {
+                tokio::fs::write(temp_destination, csv)
+                    .await
+                    .map_err(|_| RetryableError::Fail("Failed writing to disk"))
+            },
+        )
+        .await
+    }
+
+    async fn download_to_writer(
+        &self,
+        api: &Api,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        resource::write_text(&self.get_csv(api).await?, writer).await
+    }
+}
@@ -0,0 +1,188 @@
+// Logging in with a headless Chrome, for when the login forms change in ways that the scraping of
+// the usual login cannot follow (e.g. new scripts or a captcha). The browser goes through the same
+// ADFS and Zoom sign-in flows as a user would, and its cookies are then copied into the cookie store
+// of the client, which carries on from there with the sessions.
+
+use std::time::{Duration, Instant};
+
+use chromiumoxide::browser::{Browser, BrowserConfig};
+use chromiumoxide::cdp::browser_protocol::network::{
+    CookieParam, GetCookiesParams, SetCookiesParams,
+};
+use chromiumoxide::Page;
+use futures_util::StreamExt;
+use reqwest::cookie::CookieStore;
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+
+use crate::browser_cookies::BrowserCookie;
+use crate::{
+    build_auth_url, Error, Result, ADFS_REDIRECT_URI, CANCELLED, INVALID_CREDENTIALS,
+    ZOOM_REDIRECT_URL, ZOOM_SIGNIN_URL,
+};
+
+// How long the sign-in may take, e.g. for the pages of the identity provider to load
+const SIGN_IN_TIMEOUT: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// The sites whose sessions are handed to the browser, so that it need not log in again, and taken
+// back from it afterwards
+const SESSION_URLS: [&str; 2] = ["https://vafs.nus.edu.sg/", "https://nus-sg.zoom.us/"];
+
+/// Whether the error of the usual login is one that the browser might get past, i.e. the pages
+/// could not be made sense of. Credentials that were rejected are not tried again, as that only
+/// brings the account closer to being locked out.
+pub(crate) fn should_fall_back(e: Error) -> bool {
+    e != INVALID_CREDENTIALS && e != CANCELLED
+}
+
+/// Logs in to ADFS with the credentials in a headless browser, leaving the session in `cookies`
+pub(crate) async fn login(
+    adfs_url: &Url,
+    username: &str,
+    password: &str,
+    cookies: &dyn CookieStore,
+) -> Result<()> {
+    with_browser(cookies, |page| async move {
+        page.goto(build_auth_url(adfs_url).as_str())
+            .await
+            .map_err(|_| "Unable to open the ADFS login page in the browser")?;
+        // an ADFS session that is still valid goes straight to the redirect
+        if !wait_for_url(&page, ADFS_REDIRECT_URI, POLL_INTERVAL).await {
+            fill_in(&page, "#userNameInput", username).await?;
+            fill_in(&page, "#passwordInput", password).await?;
+            page.find_element("#submitButton")
+                .await
+                .map_err(|_| "Unable to find the submit button of the ADFS login page")?
+                .click()
+                .await
+                .map_err(|_| "Unable to submit the ADFS login page")?;
+            if !wait_for_url(&page, ADFS_REDIRECT_URI, SIGN_IN_TIMEOUT).await {
+                return Err("Invalid credentials, or the browser did not get past the login page");
+            }
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Signs in to Zoom in a headless browser with the ADFS session in `cookies`, leaving the Zoom
+/// session there
+pub(crate) async fn login_zoom(cookies: &dyn CookieStore) -> Result<()> {
+    with_browser(cookies, |page| async move {
+        page.goto(ZOOM_SIGNIN_URL)
+            .await
+            .map_err(|_| "Unable to open the Zoom sign-in page in the browser")?;
+        if wait_for_url(&page, ZOOM_REDIRECT_URL, SIGN_IN_TIMEOUT).await {
+            Ok(())
+        } else {
+            Err("The browser did not get signed in to Zoom, is the ADFS session still valid?")
+        }
+    })
+    .await
+}
+
+async fn with_browser<F, Fut>(cookies: &dyn CookieStore, sign_in: F) -> Result<()>
+where
+    F: FnOnce(Page) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let config = BrowserConfig::builder()
+        .build()
+        .map_err(|_| "Unable to find Chrome to log in with")?;
+    let (mut browser, mut handler) = Browser::launch(config)
+        .await
+        .map_err(|_| "Unable to start Chrome to log in with")?;
+    let events = tokio::spawn(async move { while handler.next().await.is_some() {} });
+
+    let result = async {
+        let page = browser
+            .new_page("about:blank")
+            .await
+            .map_err(|_| "Unable to open a page in the browser")?;
+        page.execute(SetCookiesParams::new(seeded_cookies(cookies)))
+            .await
+            .map_err(|_| "Unable to give the cookies to the browser")?;
+        sign_in(page.clone()).await?;
+        let browser_cookies = page
+            .execute(GetCookiesParams {
+                urls: Some(SESSION_URLS.iter().map(|url| url.to_string()).collect()),
+            })
+            .await
+            .map_err(|_| "Unable to get the cookies from the browser")?
+            .result
+            .cookies;
+        for cookie in browser_cookies {
+            let cookie = BrowserCookie {
+                domain: cookie.domain,
+                name: cookie.name,
+                value: cookie.value,
+                path: cookie.path,
+                secure: cookie.secure,
+            };
+            if let (Some(url), Ok(header)) =
+                (cookie.url(), HeaderValue::from_str(&cookie.to_set_cookie()))
+            {
+                cookies.set_cookies(&mut std::iter::once(&header), &url);
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    browser.close().await.ok();
+    browser.wait().await.ok();
+    events.abort();
+    result
+}
+
+// The cookies of the store that the sign-in flows use, as the browser takes them
+fn seeded_cookies(cookies: &dyn CookieStore) -> Vec<CookieParam> {
+    SESSION_URLS
+        .iter()
+        .filter_map(|url| {
+            let header = cookies.cookies(&Url::parse(url).ok()?)?;
+            let header = header.to_str().ok()?.to_owned();
+            Some((*url, header))
+        })
+        .flat_map(|(url, header)| {
+            header
+                .split("; ")
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(name, value)| {
+                    let mut cookie = CookieParam::new(name, value);
+                    cookie.url = Some(url.to_owned());
+                    cookie
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+async fn fill_in(page: &Page, selector: &str, text: &str) -> Result<()> {
+    page.find_element(selector)
+        .await
+        .map_err(|_| "Unable to find the login form in the browser")?
+        .click()
+        .await
+        .map_err(|_| "Unable to fill in the login form in the browser")?
+        .type_str(text)
+        .await
+        .map_err(|_| "Unable to fill in the login form in the browser")?;
+    Ok(())
+}
+
+// Whether the page reaches a URL starting with `prefix` within the time
+async fn wait_for_url(page: &Page, prefix: &str, timeout: Duration) -> bool {
+    let started = Instant::now();
+    loop {
+        if let Ok(Some(url)) = page.url().await {
+            if url.starts_with(prefix) {
+                return true;
+            }
+        }
+        if started.elapsed() >= timeout {
+            return false;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
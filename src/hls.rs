@@ -0,0 +1,363 @@
+// A minimal HLS downloader, for when ffmpeg is not available.
+// It handles unencrypted streams, by concatenating the segments,
+// which gives a playable MPEG-TS (or fragmented MP4) file without remuxing.
+
+use std::path::Path;
+
+use reqwest::{Method, Url};
+use tokio::io::AsyncWriteExt;
+
+use crate::resource::{RetryableError, RetryableResult};
+use crate::streamer::VideoQuality;
+use crate::{Api, Error};
+
+const ENCRYPTED_STREAM: Error = "Encrypted streams can only be downloaded with ffmpeg";
+const BYTE_RANGE_STREAM: Error = "Streams with byte ranges can only be downloaded with ffmpeg";
+
+// Whether the stream failed to download only because it is one that ffmpeg is needed for
+pub(crate) fn needs_ffmpeg(error: Error) -> bool {
+    error == ENCRYPTED_STREAM || error == BYTE_RANGE_STREAM
+}
+
+struct MediaPlaylist {
+    init_segment: Option<Url>,
+    segments: Vec<Url>,
+}
+
+async fn fetch_playlist(api: &Api, url: &Url) -> RetryableResult<String> {
+    api.get_text(url.clone(), Method::GET, None, Api::add_desktop_user_agent)
+        .await
+        .map_err(RetryableError::Retry)
+}
+
+// Looks up the value of an attribute in a tag like `#EXT-X-MAP:URI="init.mp4",BYTERANGE="..."`
+fn attribute<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let (_, attributes) = line.split_once(':')?;
+    let key = format!("{}=", name);
+    // the name has to start an attribute, since e.g. AVERAGE-BANDWIDTH ends with BANDWIDTH
+    let start = attributes
+        .match_indices(&key)
+        .map(|(i, _)| i)
+        .find(|i| *i == 0 || attributes[..*i].ends_with(','))?;
+    let value = &attributes[start + key.len()..];
+    match value.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next(),
+        None => value.split(',').next(),
+    }
+}
+
+struct Variant {
+    bandwidth: u64,
+    height: Option<u32>,
+    url: Url,
+}
+
+fn parse_variants(playlist: &str, base_url: &Url) -> Vec<Variant> {
+    let mut lines = playlist.lines().map(str::trim);
+    let mut variants = vec![];
+    while let Some(line) = lines.next() {
+        if !line.starts_with("#EXT-X-STREAM-INF:") {
+            continue;
+        }
+        let bandwidth = attribute(line, "BANDWIDTH")
+            .and_then(|bandwidth| bandwidth.parse::<u64>().ok())
+            .unwrap_or(0);
+        // e.g. RESOLUTION=1280x720
+        let height = attribute(line, "RESOLUTION")
+            .and_then(|resolution| resolution.split_once('x'))
+            .and_then(|(_, height)| height.parse::<u32>().ok());
+        let uri = match lines.find(|line| !line.is_empty() && !line.starts_with('#')) {
+            Some(uri) => uri,
+            None => break,
+        };
+        if let Ok(url) = base_url.join(uri) {
+            variants.push(Variant {
+                bandwidth,
+                height,
+                url,
+            });
+        }
+    }
+    variants
+}
+
+// Audio can also come as a separate rendition, e.g. `#EXT-X-MEDIA:TYPE=AUDIO,URI="audio.m3u8"`
+fn audio_rendition(playlist: &str, base_url: &Url) -> Option<Url> {
+    playlist
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            line.starts_with("#EXT-X-MEDIA:") && attribute(line, "TYPE") == Some("AUDIO")
+        })
+        .find_map(|line| base_url.join(attribute(line, "URI")?).ok())
+}
+
+// Picks the variant of a master playlist that matches the quality
+fn select_variant(playlist: &str, base_url: &Url, quality: VideoQuality) -> Option<Url> {
+    let variants = parse_variants(playlist, base_url);
+    let by_bandwidth = |variants: Vec<Variant>| variants.into_iter().max_by_key(|v| v.bandwidth);
+    match quality {
+        VideoQuality::Best => by_bandwidth(variants).map(|v| v.url),
+        VideoQuality::Worst => variants
+            .into_iter()
+            .min_by_key(|v| v.bandwidth)
+            .map(|v| v.url),
+        VideoQuality::Height(max_height) => {
+            let (fitting, too_big): (Vec<_>, Vec<_>) = variants
+                .into_iter()
+                .partition(|v| v.height.map(|h| h <= max_height).unwrap_or(true));
+            // if every variant is too big, the smallest of them is the closest
+            by_bandwidth(fitting)
+                .or_else(|| too_big.into_iter().min_by_key(|v| v.bandwidth))
+                .map(|v| v.url)
+        }
+        VideoQuality::Audio => audio_rendition(playlist, base_url).or_else(|| {
+            // without a separate rendition, audio only variants are those without a resolution
+            let audio_only = variants
+                .into_iter()
+                .filter(|v| v.height.is_none())
+                .collect::<Vec<_>>();
+            by_bandwidth(audio_only).map(|v| v.url)
+        }),
+    }
+}
+
+/// Finds the URL of the variant of the stream that matches the quality.
+/// Streams that are not master playlists are returned as they are.
+pub async fn resolve_variant(
+    api: &Api,
+    stream_url_path: &str,
+    quality: VideoQuality,
+) -> RetryableResult<String> {
+    let url = Url::parse(stream_url_path)
+        .map_err(|_| RetryableError::Fail("Unable to parse stream URL"))?;
+    let playlist = fetch_playlist(api, &url).await?;
+    if !playlist.contains("#EXT-X-STREAM-INF:") {
+        return Ok(stream_url_path.to_owned());
+    }
+    select_variant(&playlist, &url, quality)
+        .map(|url| url.into())
+        .ok_or(RetryableError::Fail(
+            "No variant of the stream matches the video quality",
+        ))
+}
+
+fn parse_media_playlist(playlist: &str, base_url: &Url) -> RetryableResult<MediaPlaylist> {
+    let mut init_segment = None;
+    let mut segments = vec![];
+    for line in playlist.lines().map(str::trim) {
+        if line.starts_with("#EXT-X-KEY:") && attribute(line, "METHOD") != Some("NONE") {
+            return Err(RetryableError::Fail(ENCRYPTED_STREAM));
+        } else if line.starts_with("#EXT-X-BYTERANGE:")
+            || (line.starts_with("#EXT-X-MAP:") && attribute(line, "BYTERANGE").is_some())
+        {
+            return Err(RetryableError::Fail(BYTE_RANGE_STREAM));
+        } else if line.starts_with("#EXT-X-MAP:") {
+            init_segment = attribute(line, "URI").and_then(|uri| base_url.join(uri).ok());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            segments.push(
+                base_url
+                    .join(line)
+                    .map_err(|_| RetryableError::Fail("Unable to parse HLS segment URL"))?,
+            );
+        }
+    }
+    if segments.is_empty() {
+        return Err(RetryableError::Fail("No segments in HLS playlist"));
+    }
+    Ok(MediaPlaylist {
+        init_segment,
+        segments,
+    })
+}
+
+/// Downloads the variant of an HLS stream that matches the quality, by concatenating its segments.
+/// Encrypted streams are not supported.
+pub async fn download_stream(
+    api: &Api,
+    stream_url_path: &str,
+    temp_destination: &Path,
+    quality: VideoQuality,
+) -> RetryableResult<()> {
+    let mut url = Url::parse(stream_url_path)
+        .map_err(|_| RetryableError::Fail("Unable to parse stream URL"))?;
+    let mut playlist = fetch_playlist(api, &url).await?;
+    if playlist.contains("#EXT-X-STREAM-INF:") {
+        url = select_variant(&playlist, &url, quality).ok_or(RetryableError::Fail(
+            "No variant of the stream matches the video quality",
+        ))?;
+        playlist = fetch_playlist(api, &url).await?;
+    }
+    let media_playlist = parse_media_playlist(&playlist, &url)?;
+
+    let mut file = tokio::fs::File::create(temp_destination)
+        .await
+        .map_err(|_| RetryableError::Fail("Unable to open temporary file"))?;
+    for segment in media_playlist
+        .init_segment
+        .iter()
+        .chain(media_playlist.segments.iter())
+    {
+        let mut res = Api::add_desktop_user_agent(api.get_client().get(segment.clone()))
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|_| RetryableError::Retry("Failed downloading HLS segment"))?;
+        while let Some(chunk) = res
+            .chunk()
+            .await
+            .map_err(|_| RetryableError::Retry("Failed during streaming"))?
+        {
+            file.write_all(&chunk)
+                .await
+                .map_err(|_| RetryableError::Fail("Failed writing to disk"))?;
+        }
+    }
+    file.flush()
+        .await
+        .map_err(|_| RetryableError::Fail("Failed writing to disk"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASTER_PLAYLIST: &str = "#EXTM3U
+#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",URI=\"subs.m3u8\"
+#EXT-X-STREAM-INF:AVERAGE-BANDWIDTH=100,BANDWIDTH=3000000,RESOLUTION=1920x1080
+1080p/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=1500000,RESOLUTION=1280x720,CODECS=\"avc1.4d401f,mp4a.40.2\"
+720p/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=800000,RESOLUTION=640x360
+
+360p/index.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=64000
+audio/index.m3u8
+";
+
+    fn base_url() -> Url {
+        Url::parse("https://stream.example.com/session/master.m3u8").unwrap()
+    }
+
+    fn select(playlist: &str, quality: VideoQuality) -> Option<String> {
+        select_variant(playlist, &base_url(), quality).map(String::from)
+    }
+
+    #[test]
+    fn reads_attributes() {
+        let line = "#EXT-X-STREAM-INF:AVERAGE-BANDWIDTH=100,BANDWIDTH=3000,CODECS=\"a,b\"";
+        assert_eq!(attribute(line, "BANDWIDTH"), Some("3000"));
+        assert_eq!(attribute(line, "AVERAGE-BANDWIDTH"), Some("100"));
+        assert_eq!(attribute(line, "CODECS"), Some("a,b"));
+        assert_eq!(attribute(line, "RESOLUTION"), None);
+        assert_eq!(attribute("#EXTM3U", "URI"), None);
+    }
+
+    #[test]
+    fn selects_variants_by_quality() {
+        assert_eq!(
+            select(MASTER_PLAYLIST, VideoQuality::Best).as_deref(),
+            Some("https://stream.example.com/session/1080p/index.m3u8")
+        );
+        assert_eq!(
+            select(MASTER_PLAYLIST, VideoQuality::Worst).as_deref(),
+            Some("https://stream.example.com/session/audio/index.m3u8")
+        );
+        assert_eq!(
+            select(MASTER_PLAYLIST, VideoQuality::Height(720)).as_deref(),
+            Some("https://stream.example.com/session/720p/index.m3u8")
+        );
+        assert_eq!(
+            select(MASTER_PLAYLIST, VideoQuality::Height(480)).as_deref(),
+            Some("https://stream.example.com/session/360p/index.m3u8")
+        );
+        assert_eq!(
+            select(MASTER_PLAYLIST, VideoQuality::Audio).as_deref(),
+            Some("https://stream.example.com/session/audio/index.m3u8")
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_smallest_variant_that_is_too_big() {
+        let playlist = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=3000000,RESOLUTION=1920x1080
+1080p.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=1500000,RESOLUTION=1280x720
+720p.m3u8
+";
+        assert_eq!(
+            select(playlist, VideoQuality::Height(360)).as_deref(),
+            Some("https://stream.example.com/session/720p.m3u8")
+        );
+        assert_eq!(select(playlist, VideoQuality::Audio), None);
+    }
+
+    #[test]
+    fn prefers_a_separate_audio_rendition() {
+        let playlist = "#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",URI=\"https://cdn.example.com/audio.m3u8\"
+#EXT-X-STREAM-INF:BANDWIDTH=1500000,RESOLUTION=1280x720,AUDIO=\"aac\"
+720p.m3u8
+";
+        assert_eq!(
+            select(playlist, VideoQuality::Audio).as_deref(),
+            Some("https://cdn.example.com/audio.m3u8")
+        );
+    }
+
+    #[test]
+    fn parses_media_playlists() {
+        let playlist = "#EXTM3U
+#EXT-X-TARGETDURATION:10
+#EXT-X-KEY:METHOD=NONE
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXTINF:10.0,
+segment0.m4s
+#EXTINF:10.0,
+/other/segment1.m4s
+#EXT-X-ENDLIST
+";
+        let parsed = parse_media_playlist(playlist, &base_url()).ok().unwrap();
+        assert_eq!(
+            parsed.init_segment.map(String::from).as_deref(),
+            Some("https://stream.example.com/session/init.mp4")
+        );
+        assert_eq!(
+            parsed
+                .segments
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>(),
+            [
+                "https://stream.example.com/session/segment0.m4s",
+                "https://stream.example.com/other/segment1.m4s",
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_streams_that_need_ffmpeg_to_it() {
+        let encrypted = "#EXTM3U
+#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\"
+#EXTINF:10.0,
+segment0.ts
+";
+        assert!(matches!(
+            parse_media_playlist(encrypted, &base_url()),
+            Err(RetryableError::Fail(error)) if error == ENCRYPTED_STREAM && needs_ffmpeg(error)
+        ));
+        let byte_ranges = "#EXTM3U
+#EXTINF:10.0,
+#EXT-X-BYTERANGE:1000@0
+video.ts
+";
+        assert!(matches!(
+            parse_media_playlist(byte_ranges, &base_url()),
+            Err(RetryableError::Fail(error)) if error == BYTE_RANGE_STREAM && needs_ffmpeg(error)
+        ));
+        assert!(matches!(
+            parse_media_playlist("#EXTM3U\n#EXT-X-ENDLIST\n", &base_url()),
+            Err(RetryableError::Fail(error)) if !needs_ffmpeg(error)
+        ));
+    }
+}
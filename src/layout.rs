@@ -0,0 +1,198 @@
+//! Layouts for where resources are saved, given as templates like `{term}/{module}/{type}/{path}`
+//! or `{module} - {name}`, instead of the `<code>/Multimedia` style layout.
+//!
+//! The placeholders are:
+//! - `{term}`: the term of the module, e.g. `2110`
+//! - `{module}`: the module code, e.g. `CS2040S`
+//! - `{course}`: the name of the module, e.g. `Data Structures and Algorithms`
+//! - `{type}`: the type of the resource, e.g. `Multimedia` or `Files`
+//! - `{dir}`: the folders that the resource is in, within its type
+//! - `{name}`: the file name of the resource
+//! - `{path}`: the same as `{dir}/{name}`
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::module::Module;
+use crate::resource::Resource;
+use crate::util::sanitise_filename;
+use crate::Result;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Field {
+    Term,
+    Module,
+    Course,
+    Type,
+    Dir,
+    Name,
+    Path,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    segments: Vec<Segment>,
+}
+
+// The components of a path joined with slashes, which are split up again after rendering
+fn join_components(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl PathTemplate {
+    /// Parses a layout, which has to place each resource by its name or path
+    pub fn parse(template: &str) -> Result<PathTemplate> {
+        let mut segments = vec![];
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_owned()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or("Unclosed placeholder in layout")?
+                + start;
+            segments.push(Segment::Field(match &rest[start + 1..end] {
+                "term" => Field::Term,
+                "module" => Field::Module,
+                "course" => Field::Course,
+                "type" => Field::Type,
+                "dir" => Field::Dir,
+                "name" => Field::Name,
+                "path" => Field::Path,
+                _ => return Err("Unknown placeholder in layout"),
+            }));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_owned()));
+        }
+        // without the name, every resource of a type would be saved to the same file
+        if !segments
+            .iter()
+            .any(|s| matches!(s, Segment::Field(Field::Name | Field::Path)))
+        {
+            return Err("Layout has to contain {name} or {path}");
+        }
+        Ok(PathTemplate { segments })
+    }
+
+    /// Where a resource of the module goes, given its `path` within the folder of its type
+    pub fn render(&self, module: &Module, kind: &str, path: &Path) -> PathBuf {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => rendered.push_str(text),
+                Segment::Field(Field::Term) => rendered.push_str(&sanitise_filename(&module.term)),
+                Segment::Field(Field::Module) => {
+                    rendered.push_str(&sanitise_filename(&module.code))
+                }
+                Segment::Field(Field::Course) => {
+                    rendered.push_str(&sanitise_filename(&module.name))
+                }
+                Segment::Field(Field::Type) => rendered.push_str(kind),
+                Segment::Field(Field::Dir) => {
+                    rendered.push_str(&join_components(path.parent().unwrap_or(Path::new(""))))
+                }
+                Segment::Field(Field::Name) => rendered.push_str(
+                    &path
+                        .file_name()
+                        .map(|name| name.to_string_lossy())
+                        .unwrap_or_default(),
+                ),
+                Segment::Field(Field::Path) => rendered.push_str(&join_components(path)),
+            }
+        }
+        // empty folders (e.g. `{dir}` of a resource at the top) are left out, and so is anything
+        // that would leave the destination
+        rendered
+            .split('/')
+            .map(Path::new)
+            .filter(|component| matches!(component.components().next(), Some(Component::Normal(_))))
+            .collect()
+    }
+
+    /// Moves the resources of the module to where the layout puts them, given their paths within
+    /// the folder of their type
+    pub fn lay_out<T: Resource>(&self, module: &Module, kind: &str, resources: &mut [T]) {
+        for resource in resources {
+            let path = self.render(module, kind, resource.path());
+            *resource.path_mut() = path;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module() -> Module {
+        serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "name": "CS2040S",
+            "courseName": "Data Structures/Algorithms",
+            "term": "2110",
+        }))
+        .unwrap()
+    }
+
+    fn render(template: &str, path: &str) -> PathBuf {
+        PathTemplate::parse(template)
+            .unwrap()
+            .render(&module(), "Files", Path::new(path))
+    }
+
+    #[test]
+    fn renders_every_placeholder() {
+        assert_eq!(
+            render("{term}/{module}/{type}/{path}", "Lectures/L1.pdf"),
+            Path::new("2110/CS2040S/Files/Lectures/L1.pdf")
+        );
+        assert_eq!(
+            render("{module} - {name}", "Lectures/L1.pdf"),
+            Path::new("CS2040S - L1.pdf")
+        );
+        assert_eq!(
+            render("{dir}/{type}/{name}", "Lectures/Week 1/L1.pdf"),
+            Path::new("Lectures/Week 1/Files/L1.pdf")
+        );
+        // the slash in the name of the module does not make a folder
+        assert_eq!(
+            render("{course}/{name}", "L1.pdf"),
+            Path::new("Data Structures-Algorithms/L1.pdf")
+        );
+    }
+
+    #[test]
+    fn leaves_out_empty_folders() {
+        assert_eq!(
+            render("{module}/{dir}/{name}", "L1.pdf"),
+            Path::new("CS2040S/L1.pdf")
+        );
+    }
+
+    #[test]
+    fn stays_within_the_destination() {
+        assert_eq!(render("/../{path}", "L1.pdf"), Path::new("L1.pdf"));
+        assert_eq!(
+            render("./{module}/../{name}", "L1.pdf"),
+            Path::new("CS2040S/L1.pdf")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_layouts() {
+        assert!(PathTemplate::parse("{module}/{name").is_err());
+        assert!(PathTemplate::parse("{module}/{title}").is_err());
+        assert!(PathTemplate::parse("{term}/{module}").is_err());
+        assert!(PathTemplate::parse("").is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use futures_util::future;
+use reqwest::Method;
+use serde::Deserialize;
+use tokio::io::AsyncWrite;
+
+use crate::resource;
+use crate::resource::{OverwriteMode, OverwriteResult, Resource, RetryableError};
+use crate::util::{format_date, html_to_text, parse_time};
+use crate::{Api, ApiData, Result};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiLesson {
+    id: String,
+    name: String,
+    start_date: Option<String>,
+    last_updated_date: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiActivity {
+    name: String,
+    description: Option<String>,
+    url: Option<String>,
+}
+
+pub struct LessonPlanHandle {
+    id: String,
+    code: String,
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct LessonPlan {
+    id: String,
+    code: String,
+    path: PathBuf,
+    lessons: Vec<Lesson>,
+    last_updated: SystemTime,
+}
+
+#[derive(Debug, Clone)]
+struct Lesson {
+    id: String,
+    name: String,
+    start_date: Option<SystemTime>,
+}
+
+impl LessonPlanHandle {
+    pub fn new(id: String, code: String, path: PathBuf) -> LessonPlanHandle {
+        LessonPlanHandle { id, code, path }
+    }
+
+    pub async fn load(self, api: &Api) -> Result<Vec<LessonPlan>> {
+        let lessons_resp = api
+            .api_as_json::<ApiData<Vec<ApiLesson>>>(
+                &format!("lessonplan/Lesson/?ModuleID={}", self.id),
+                Method::GET,
+                None,
+            )
+            .await?;
+
+        let lessons = match lessons_resp.data {
+            Some(lessons) if !lessons.is_empty() => lessons,
+            _ => return Ok(vec![]), // this module has no lesson plan
+        };
+        let last_updated = lessons
+            .iter()
+            .map(|lesson| parse_time(&lesson.last_updated_date))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .max()
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+
+        Ok(vec![LessonPlan {
+            id: self.id,
+            code: self.code,
+            path: self.path.join("Lesson Plan.md"),
+            lessons: lessons
+                .into_iter()
+                .map(|lesson| Lesson {
+                    id: lesson.id,
+                    name: lesson.name,
+                    // the schedule is only informational, so odd dates are left out
+                    start_date: lesson
+                        .start_date
+                        .as_deref()
+                        .and_then(|date| parse_time(date).ok()),
+                })
+                .collect(),
+            last_updated,
+        }])
+    }
+}
+
+impl LessonPlan {
+    async fn get_markdown(&self, api: &Api) -> Result<String> {
+        let activities = future::join_all(self.lessons.iter().map(|lesson| async move {
+            api.api_as_json::<ApiData<Vec<ApiActivity>>>(
+                &format!("lessonplan/Activity/?LessonID={}", lesson.id),
+                Method::GET,
+                None,
+            )
+            .await
+        }))
+        .await;
+
+        let mut markdown = format!("# Lesson Plan for {}\n", self.code);
+        for (lesson, activities) in self.lessons.iter().zip(activities) {
+            match lesson.start_date {
+                Some(start_date) => markdown.push_str(&format!(
+                    "\n## {} ({})\n\n",
+                    lesson.name,
+                    format_date(start_date)
+                )),
+                None => markdown.push_str(&format!("\n## {}\n\n", lesson.name)),
+            }
+            for activity in activities?.data.unwrap_or_default() {
+                match &activity.url {
+                    Some(url) => markdown.push_str(&format!("- [{}]({})\n", activity.name, url)),
+                    None => markdown.push_str(&format!("- {}\n", activity.name)),
+                }
+                if let Some(description) = &activity.description {
+                    let description = html_to_text(description);
+                    let description = description.trim();
+                    if !description.is_empty() {
+                        markdown.push_str(&format!("  {}\n", description.replace('\n', "\n  ")));
+                    }
+                }
+            }
+        }
+        Ok(markdown)
+    }
+}
+
+#[async_trait]
+impl Resource for LessonPlan {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+    fn path_mut(&mut self) -> &mut PathBuf {
+        &mut self.path
+    }
+
+    fn last_updated(&self) -> SystemTime {
+        self.last_updated
+    }
+
+    async fn download(
+        &self,
+        api: &Api,
+        destination: &Path,
+        temp_destination: &Path,
+        overwrite: OverwriteMode,
+    ) -> Result<OverwriteResult> {
+        resource::do_retryable_download(
+            api,
+            self,
+            destination,
+            temp_destination,
+            overwrite,
+            move |api| self.get_markdown(api),
+            move |_, markdown, temp_destination| async move {
+                tokio::fs::write(temp_destination, markdown)
+                    .await
+                    .map_err(|_| RetryableError::Fail("Failed writing to disk"))
+            },
+        )
+        .await
+    }
+
+    async fn download_to_writer(
+        &self,
+        api: &Api,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        resource::write_text(&self.get_markdown(api).await?, writer).await
+    }
+}
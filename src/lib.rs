@@ -1,29 +1,101 @@
+//! A client for LumiNUS, and for the services that it links to (Panopto and Zoom).
+//!
+//! Start with an [`Api`], which logs in and is shared (by cloning) between everything that talks to
+//! the server. Each kind of resource has a module, e.g. [`file`] or [`weblecture`], whose items are
+//! loaded from a [`module::Module`] and implement [`resource::Resource`] to be downloaded.
+//!
+//! Failures are returned as [`Error`]s, which are short descriptions of what went wrong. The ones
+//! that callers may want to react to are named constants, e.g. [`SESSION_EXPIRED`]. Nothing in the
+//! library panics on what the server returns.
+
 use std::collections::HashMap;
+use std::future::Future;
+use std::io::{self, BufReader, Read};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
 
-use reqwest::header::{CONTENT_TYPE, REFERER, USER_AGENT};
+use reqwest::cookie::CookieStore;
+use reqwest::header::{HeaderMap, CONTENT_TYPE, REFERER, RETRY_AFTER, USER_AGENT};
 use reqwest::redirect::Policy;
 use reqwest::Certificate;
 use reqwest::Method;
-use reqwest::{Client, RequestBuilder, Response, Url};
+use reqwest::StatusCode;
+use reqwest::{Client, ClientBuilder, RequestBuilder, Response, Url};
 use scraper::{Html, Selector};
 use serde::de::DeserializeOwned;
 use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
 
+use self::builder::{ApiBuilder, SharedCookieStore};
+use self::listing_cache::ListingCache;
 use self::module::Module;
+use self::rate_limit::RateLimiter;
+use self::scheduler::Scheduler;
+use self::streamer::{VideoContainer, VideoQuality};
+use self::transport::HttpTransport;
 
+pub mod announcement;
+#[cfg(feature = "browser-cookies")]
+pub mod browser_cookies;
+pub mod builder;
+pub mod calendar;
 pub mod conferencing;
+pub mod cookie_jar;
+pub mod crypto;
 pub mod file;
+pub mod forum;
+pub mod grab;
+pub mod gradebook;
+#[cfg(feature = "headless-login")]
+mod headless_login;
+pub mod hls;
+pub mod layout;
+pub mod lesson_plan;
+pub mod listing_cache;
 pub mod module;
 pub mod multimedia;
 pub mod panopto;
+pub mod quiz;
+pub mod rate_limit;
 pub mod resource;
+pub mod roster;
+pub mod scheduler;
 pub mod streamer;
+pub mod transport;
 pub mod util;
 pub mod weblecture;
+pub mod weblink;
 
 pub type Error = &'static str;
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// The server refused the request (403 Forbidden)
+pub const ACCESS_DENIED: Error = "Access denied by the server";
+/// The endpoint is only available to those teaching the module
+pub const REQUIRES_TEACHING_ACCESS: Error = "Requires teaching access to the module";
+/// The response is larger than the limit set with `Api::with_max_response_size`
+pub const RESPONSE_TOO_LARGE: Error = "Response from the server is too large";
+/// The session can no longer be renewed, so the user has to log in again
+pub const SESSION_EXPIRED: Error = "Session expired, and the ADFS session could not be reused";
+/// The username or password was rejected when logging in
+pub const INVALID_CREDENTIALS: Error = "Invalid credentials";
+/// The operation was given up on, because the token given to `Api::with_cancellation_token` was cancelled
+pub const CANCELLED: Error = "Cancelled";
+/// The limit on the size of responses, in bytes, unless set otherwise
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 64 << 20;
+/// Where a download proxy serves files, given the `key` to cache them by and the `url` to fetch
+pub const DOWNLOAD_PROXY_PATH: &str = "/fetch";
+
+// For endpoints meant for teaching staff, refusals mean that the user only takes the module
+fn teaching_access_error(e: Error) -> Error {
+    if e == ACCESS_DENIED {
+        REQUIRES_TEACHING_ACCESS
+    } else {
+        e
+    }
+}
+
 const ADFS_OAUTH2_URL: &str = "https://vafs.nus.edu.sg/adfs/oauth2/authorize";
 const ADFS_CLIENT_ID: &str = "E10493A3B1024F14BDC7D0D8B9F649E9-234390";
 const ADFS_RESOURCE_TYPE: &str = "sg_edu_nus_oauth";
@@ -33,6 +105,20 @@ const OCP_APIM_SUBSCRIPTION_KEY: &str = "6963c200ca9440de8fa1eede730d8f7e";
 const OCP_APIM_SUBSCRIPTION_KEY_HEADER: &str = "Ocp-Apim-Subscription-Key";
 const ADFS_REFERER_URL: &str = "https://vafs.nus.edu.sg/";
 const ZOOM_REFERER_URL: &str = "https://nus-sg.zoom.us/";
+// How much of an unexpected response is logged
+const MAX_LOGGED_BODY_LENGTH: usize = 512;
+// How long to wait before sending a request again after it failed, doubling with every failure
+const RETRY_BACKOFF_START: Duration = Duration::from_millis(500);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+// Servers asking for longer than this are most likely confused
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+// The number of items asked for in each page of a listing
+const PAGE_SIZE: usize = 100;
+// No listing is anywhere near this long, so a server that keeps sending pages is stuck
+const MAX_PAGES: usize = 1000;
+// The number of chunks of a response that may wait to be parsed
+const JSON_STREAM_BUFFER_CHUNKS: usize = 16;
+const LOW_MEMORY_JSON_STREAM_BUFFER_CHUNKS: usize = 2;
 const ZOOM_SIGNIN_URL: &str = "https://nus-sg.zoom.us/signin";
 const ZOOM_REDIRECT_URL: &str = "https://nus-sg.zoom.us/profile";
 
@@ -58,20 +144,25 @@ struct ApiData<T> {
     data: Option<T>,
 }
 
+// A page of a listing, with the number of items in the whole listing where the server says
+#[derive(Deserialize)]
+struct ApiPage<T> {
+    data: Option<Vec<T>>,
+    total: Option<usize>,
+}
+
 #[derive(Deserialize)]
 struct TokenResponse {
     access_token: String,
 }
 
-fn full_api_url(path: &str) -> Url {
-    Url::parse(API_BASE_URL)
-        .and_then(|u| u.join(path))
-        .expect("Unable to join URL's")
+fn full_api_url(api_base_url: &Url, path: &str) -> Result<Url> {
+    api_base_url.join(path).map_err(|_| "Unable to join URL's")
 }
 
-fn build_auth_url() -> Url {
+fn build_auth_url(adfs_url: &Url) -> Url {
     let nonce = generate_random_bytes(16);
-    let mut url = Url::parse(ADFS_OAUTH2_URL).expect("Unable to parse ADFS URL");
+    let mut url = adfs_url.clone();
     url.query_pairs_mut()
         .append_pair("response_type", "code")
         .append_pair("client_id", ADFS_CLIENT_ID)
@@ -91,7 +182,7 @@ fn build_auth_form<'a>(username: &'a str, password: &'a str) -> HashMap<&'static
     map
 }
 
-fn build_token_form<'a>(code: &'a str) -> HashMap<&'static str, &'a str> {
+fn build_token_form(code: &str) -> HashMap<&'static str, &str> {
     let mut map = HashMap::new();
     map.insert("grant_type", "authorization_code");
     map.insert("client_id", ADFS_CLIENT_ID);
@@ -101,25 +192,76 @@ fn build_token_form<'a>(code: &'a str) -> HashMap<&'static str, &'a str> {
     map
 }
 
+#[cfg(feature = "bundled-intermediate-cert")]
 fn hack_get_intermediate_cert() -> Result<Certificate> {
     Certificate::from_pem(include_bytes!("DigiCert_TLS_RSA_SHA256_2020_CA1.pem"))
         .map_err(|_| "Unable to load TLS intermediate certificate")
 }
 
-fn build_client() -> Result<Client> {
-    Client::builder()
-        .http1_title_case_headers()
-        .cookie_store(true)
-        .add_root_certificate(hack_get_intermediate_cert()?)
-        .redirect(Policy::custom(|attempt| {
-            if attempt.previous().len() > 5 {
-                attempt.error("too many redirects")
-            } else {
-                attempt.follow()
-            }
-        }))
-        .build()
-        .map_err(|_| "Unable to create HTTP client")
+// Certificates given at runtime, for when NUS changes its certificate chain
+static EXTRA_ROOT_CERTIFICATES: RwLock<Vec<Certificate>> = RwLock::new(Vec::new());
+
+const PEM_CERTIFICATE_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_CERTIFICATE_END: &str = "-----END CERTIFICATE-----";
+
+/// Trusts the certificates in a PEM file, which may hold several of them, as additional roots
+/// for the clients of every `Api` created afterwards. Returns the number of certificates added.
+pub fn add_root_certificates(pem: &[u8]) -> Result<usize> {
+    let pem = std::str::from_utf8(pem).map_err(|_| "Invalid PEM file")?;
+    let mut certificates = vec![];
+    let mut rest = pem;
+    while let Some(begin) = rest.find(PEM_CERTIFICATE_BEGIN) {
+        let end = rest[begin..]
+            .find(PEM_CERTIFICATE_END)
+            .map(|end| begin + end + PEM_CERTIFICATE_END.len())
+            .ok_or("Invalid PEM file: unterminated certificate")?;
+        certificates.push(
+            Certificate::from_pem(&rest.as_bytes()[begin..end])
+                .map_err(|_| "Invalid certificate in PEM file")?,
+        );
+        rest = &rest[end..];
+    }
+    if certificates.is_empty() {
+        return Err("No certificates found in PEM file");
+    }
+    let count = certificates.len();
+    EXTRA_ROOT_CERTIFICATES
+        .write()
+        .unwrap()
+        .extend(certificates);
+    Ok(count)
+}
+
+// A client builder with the certificates and redirect policy that the servers need
+fn client_builder() -> Result<ClientBuilder> {
+    let mut builder = Client::builder().http1_title_case_headers();
+    #[cfg(feature = "bundled-intermediate-cert")]
+    {
+        builder = builder.add_root_certificate(hack_get_intermediate_cert()?);
+    }
+    for certificate in EXTRA_ROOT_CERTIFICATES.read().unwrap().iter() {
+        builder = builder.add_root_certificate(certificate.clone());
+    }
+    Ok(builder.redirect(Policy::custom(|attempt| {
+        if attempt.previous().len() > 5 {
+            attempt.error("too many redirects")
+        } else {
+            attempt.follow()
+        }
+    })))
+}
+
+// Runs the future until it finishes, or until the token is cancelled, whichever is first.
+// Dropping the future is how the retry loops in it are broken out of.
+async fn until_cancelled<T>(
+    cancellation: &CancellationToken,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    tokio::select! {
+        biased;
+        _ = cancellation.cancelled() => Err(CANCELLED),
+        result = future => result,
+    }
 }
 
 fn generate_random_bytes(size: usize) -> String {
@@ -128,8 +270,25 @@ fn generate_random_bytes(size: usize) -> String {
         .collect()
 }
 
+// How long the server asked us to wait in `Retry-After`, given either in seconds or as a date
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => chrono::DateTime::parse_from_rfc2822(value)
+            .ok()
+            .map(|date| {
+                SystemTime::from(date)
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default()
+            }),
+    }
+}
+
 async fn infinite_retry_http<F>(
     client: &Client,
+    transport: &dyn HttpTransport,
+    rate_limiter: Option<&RateLimiter>,
     url: Url,
     method: Method,
     form: Option<&HashMap<&str, &str>>,
@@ -146,7 +305,11 @@ where
 
     // LumiNUS randomly returns 400 to a perfectly good request for no apparent reason
     // We'll just ignore it and repeat the request
+    let mut backoff = RETRY_BACKOFF_START;
     let res = loop {
+        if let Some(rate_limiter) = rate_limiter {
+            rate_limiter.wait().await;
+        }
         let request_builder = client.request(method.clone(), url.clone());
         let request_builder = if let Some(form) = &form {
             request_builder
@@ -159,39 +322,120 @@ where
             .build()
             .map_err(|_| "Failed to build request")?;
 
-        match client.execute(request).await {
+        match transport.execute(request).await {
+            Ok(res) if res.status() == StatusCode::TOO_MANY_REQUESTS => {
+                let delay = retry_after(res.headers())
+                    .unwrap_or(backoff)
+                    .min(MAX_RETRY_AFTER);
+                tracing::warn!(%method, %url, ?delay, "Too many requests, waiting before retrying");
+                // everything else that is rate limited waits along, as it would only be refused too
+                match rate_limiter {
+                    Some(rate_limiter) => rate_limiter.pause(delay),
+                    None => tokio::time::sleep(delay).await,
+                }
+            }
             Ok(res) => {
+                tracing::debug!(%method, %url, status = %res.status(), "HTTP response");
                 break res;
             }
             Err(e) => {
-                println!("Error in infinite retry HTTP for {} {}: {}", method, url, e);
+                tracing::warn!(%method, %url, error = e, ?backoff, "HTTP request failed, retrying");
+                tokio::time::sleep(backoff).await;
             }
         }
+        backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
     };
     Ok(res)
 }
 
 async fn auth_http_post(
     client: &Client,
+    transport: &dyn HttpTransport,
     url: Url,
     form: Option<&HashMap<&str, &str>>,
     with_apim: bool,
 ) -> Result<Response> {
-    infinite_retry_http(client, url, Method::POST, form, move |req| {
-        if with_apim {
-            req.header(OCP_APIM_SUBSCRIPTION_KEY_HEADER, OCP_APIM_SUBSCRIPTION_KEY)
-        } else {
-            req
-        }
-    })
+    infinite_retry_http(
+        client,
+        transport,
+        None,
+        url,
+        Method::POST,
+        form,
+        move |req| {
+            if with_apim {
+                req.header(OCP_APIM_SUBSCRIPTION_KEY_HEADER, OCP_APIM_SUBSCRIPTION_KEY)
+            } else {
+                req
+            }
+        },
+    )
     .await
 }
 
+// Exchanges the code that ADFS redirected back with for a LumiNUS token
+async fn exchange_auth_code(
+    client: &Client,
+    transport: &dyn HttpTransport,
+    api_base_url: &Url,
+    auth_resp: &Response,
+) -> Result<String> {
+    let code = auth_resp
+        .url()
+        .query_pairs()
+        .find(|(key, _)| key == "code")
+        .map(|(_key, code)| code.into_owned())
+        .ok_or("Unknown authentication failure (no code returned)")?;
+    let token_resp = auth_http_post(
+        client,
+        transport,
+        full_api_url(api_base_url, "login/adfstoken")?,
+        Some(&build_token_form(&code)),
+        true,
+    )
+    .await?;
+    if !token_resp.status().is_success() {
+        return Err("Unknown authentication failure (no token returned)");
+    }
+    let token = token_resp
+        .json::<TokenResponse>()
+        .await
+        .map_err(|_| "Failed to deserialise token exchange response")?;
+    Ok(token.access_token)
+}
+
 #[derive(Debug, Clone)]
 pub struct Api {
-    jwt: String,
+    // shared between clones, so that a new token from logging in again is used by all of them
+    jwt: Arc<RwLock<String>>,
     client: Client,
+    // sends what `client` builds
+    transport: Arc<dyn HttpTransport>,
+    api_base_url: Url,
+    adfs_url: Url,
     ffmpeg_path: String,
+    // whether `ffmpeg_path` could be started, shared between clones so that it is checked once
+    ffmpeg_available: Arc<tokio::sync::OnceCell<bool>>,
+    panopto_podcast: bool,
+    announcement_html: bool,
+    video_container: VideoContainer,
+    video_quality: VideoQuality,
+    max_response_size: Option<usize>,
+    low_memory: bool,
+    // shared between clones, so that the limits hold across everything downloading at once
+    scheduler: Arc<Scheduler>,
+    // shared between clones, so that the API is not called more often by running more of them
+    rate_limiter: Arc<RateLimiter>,
+    download_proxy: Option<Url>,
+    // shared between clones, so that cancelling stops everything that was started from the `Api`
+    cancellation: CancellationToken,
+    listing_cache: Option<Arc<ListingCache>>,
+    // shared between clones, so that Panopto is signed in to once for all of them
+    panopto_sessions: Arc<panopto::PanoptoSessions>,
+    // where files that downloads replace are moved to, instead of being deleted
+    trash_dir: Option<PathBuf>,
+    // the cookie store of the client, unless the client was given to the builder ready-made
+    cookies: Option<Arc<SharedCookieStore>>,
 }
 
 impl Api {
@@ -199,20 +443,214 @@ impl Api {
         &self.client
     }
 
-    async fn api_as_json<T: DeserializeOwned + 'static>(
+    pub fn get_transport(&self) -> Arc<dyn HttpTransport> {
+        self.transport.clone()
+    }
+
+    /// The cookie store of the client, unless it was given to `ApiBuilder::client` ready-made
+    pub fn get_cookie_store(&self) -> Option<Arc<dyn CookieStore>> {
+        self.cookies
+            .clone()
+            .map(|cookies| cookies as Arc<dyn CookieStore>)
+    }
+
+    pub(crate) async fn cancellable<T>(
+        &self,
+        future: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        until_cancelled(&self.cancellation, future).await
+    }
+
+    async fn api_as_json<T: DeserializeOwned + Send + 'static>(
         &self,
         path: &str,
         method: Method,
         form: Option<&HashMap<&str, &str>>,
     ) -> Result<T> {
-        let res = self.api(path, method, form).await?;
-        res.json::<T>()
-            .await
+        let mut reauthenticated = false;
+        loop {
+            let res = self.api(path, method.clone(), form).await?;
+            if res.status() == StatusCode::FORBIDDEN {
+                return Err(ACCESS_DENIED);
+            }
+            let (parsed, excerpt) = self.parse_json_body::<T>(res).await?;
+            let err = match parsed {
+                Ok(data) => return Ok(data),
+                Err(err) => err,
+            };
+            let excerpt = String::from_utf8_lossy(&excerpt);
+            tracing::debug!(
+                "Unable to deserialize JSON from {} ({}), response starts with:\n{}",
+                path,
+                err,
+                redact_body(&excerpt)
+            );
+            // an expired token gets us bounced to the login page instead of an error
+            if reauthenticated || self.is_anonymous() || !looks_like_login_page(&excerpt) {
+                return Err("Unable to deserialize JSON");
+            }
+            tracing::info!("Got a login page from {}, logging in again", path);
+            self.reauthenticate().await?;
+            reauthenticated = true;
+        }
+    }
+
+    // Fetches every page of a listing that the server caps, and puts them together.
+    // Servers that ignore the paging return everything at once, which ends it just the same.
+    async fn api_as_json_paged<T: DeserializeOwned + Send + 'static>(
+        &self,
+        path: &str,
+    ) -> Result<ApiData<Vec<T>>> {
+        let separator = if path.contains('?') { '&' } else { '?' };
+        let mut items = Vec::<serde_json::Value>::new();
+        let mut last_count = 0;
+        for _ in 0..MAX_PAGES {
+            let page = self
+                .api_as_json::<ApiPage<serde_json::Value>>(
+                    &format!(
+                        "{}{}offset={}&limit={}",
+                        path,
+                        separator,
+                        items.len(),
+                        PAGE_SIZE
+                    ),
+                    Method::GET,
+                    None,
+                )
+                .await?;
+            let data = match page.data {
+                Some(data) => data,
+                None if items.is_empty() => return Ok(ApiData { data: None }),
+                None => break,
+            };
+            // a server that ignores the offset sends the same page again
+            if last_count > 0 && items[items.len() - last_count..] == data[..] {
+                break;
+            }
+            let count = data.len();
+            items.extend(data);
+            let done = match page.total {
+                Some(total) => items.len() >= total,
+                None => count < PAGE_SIZE,
+            };
+            // a page larger than asked for means that the server ignored the limit
+            if done || count == 0 || count > PAGE_SIZE {
+                break;
+            }
+            last_count = count;
+        }
+        items
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect::<serde_json::Result<Vec<T>>>()
+            .map(|items| ApiData { data: Some(items) })
             .map_err(|_| "Unable to deserialize JSON")
-        /*let res = self.api(path, method, form).await?;
-        let text = res.text().await.map_err(|_| "Unable to get text")?;
-        println!("{}", text.as_str());
-        serde_json::from_str(&text).map_err(|_| "Unable to deserialize JSON")*/
+    }
+
+    // Like `api_as_json_paged`, but for listings that change rarely (e.g. of folders), which are
+    // taken from the listing cache while they are fresh
+    async fn api_listing<T: DeserializeOwned + Send + 'static>(
+        &self,
+        path: &str,
+    ) -> Result<ApiData<Vec<T>>> {
+        let cache = match &self.listing_cache {
+            Some(cache) => cache,
+            None => return self.api_as_json_paged(path).await,
+        };
+        let items = match cache.get(path) {
+            Some(items) => items,
+            None => match self
+                .api_as_json_paged::<serde_json::Value>(path)
+                .await?
+                .data
+            {
+                Some(items) => {
+                    cache.insert(path, items.clone());
+                    items
+                }
+                None => return Ok(ApiData { data: None }),
+            },
+        };
+        items
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect::<serde_json::Result<Vec<T>>>()
+            .map(|items| ApiData { data: Some(items) })
+            .map_err(|_| "Unable to deserialize cached listing")
+    }
+
+    // Parses the body as it arrives, so that large listings are never buffered as a whole.
+    // Also returns the start of the body, for diagnosing bodies that fail to parse.
+    async fn parse_json_body<T: DeserializeOwned + Send + 'static>(
+        &self,
+        mut res: Response,
+    ) -> Result<(serde_json::Result<T>, Vec<u8>)> {
+        let buffer_chunks = if self.low_memory {
+            LOW_MEMORY_JSON_STREAM_BUFFER_CHUNKS
+        } else {
+            JSON_STREAM_BUFFER_CHUNKS
+        };
+        let (sender, receiver) = tokio::sync::mpsc::channel(buffer_chunks);
+        let parser = tokio::task::spawn_blocking(move || {
+            serde_json::from_reader::<_, T>(BufReader::new(ChunkReader::new(receiver)))
+        });
+        let mut excerpt = Vec::new();
+        let mut size = 0;
+        while let Some(chunk) = res
+            .chunk()
+            .await
+            .map_err(|_| "Unable to get response body")?
+        {
+            size += chunk.len();
+            // returning drops the sender, which ends the body for the parser
+            self.check_response_size(size)?;
+            if excerpt.len() < MAX_LOGGED_BODY_LENGTH {
+                let length = chunk.len().min(MAX_LOGGED_BODY_LENGTH - excerpt.len());
+                excerpt.extend_from_slice(&chunk[..length]);
+            }
+            // the parser stops early at invalid JSON, and has no use for the rest of the body
+            if sender.send(chunk).await.is_err() {
+                break;
+            }
+        }
+        drop(sender);
+        let parsed = parser.await.map_err(|_| "Unable to parse response body")?;
+        Ok((parsed, excerpt))
+    }
+
+    fn check_response_size(&self, size: usize) -> Result<()> {
+        match self.max_response_size {
+            Some(max_response_size) if size > max_response_size => Err(RESPONSE_TOO_LARGE),
+            _ => Ok(()),
+        }
+    }
+
+    /// Gets a new token by reusing the ADFS session in the cookie store.
+    async fn reauthenticate(&self) -> Result<()> {
+        let auth_resp = self
+            .cancellable(infinite_retry_http(
+                &self.client,
+                &*self.transport,
+                None,
+                build_auth_url(&self.adfs_url),
+                Method::GET,
+                None,
+                |req| req,
+            ))
+            .await?;
+        if !auth_resp.url().as_str().starts_with(ADFS_REDIRECT_URI) {
+            return Err(SESSION_EXPIRED);
+        }
+        let jwt = self
+            .cancellable(exchange_auth_code(
+                &self.client,
+                &*self.transport,
+                &self.api_base_url,
+                &auth_resp,
+            ))
+            .await?;
+        *self.jwt.write().unwrap() = jwt;
+        Ok(())
     }
 
     pub async fn api(
@@ -221,15 +659,43 @@ impl Api {
         method: Method,
         form: Option<&HashMap<&str, &str>>,
     ) -> Result<Response> {
-        let url = full_api_url(path);
+        let url = full_api_url(&self.api_base_url, path)?;
+        let jwt = self.jwt.read().unwrap().clone();
 
-        infinite_retry_http(&self.client, url, method, form, move |req| {
-            req.header(OCP_APIM_SUBSCRIPTION_KEY_HEADER, OCP_APIM_SUBSCRIPTION_KEY)
-                .bearer_auth(self.jwt.as_str())
-        })
+        self.cancellable(infinite_retry_http(
+            &self.client,
+            &*self.transport,
+            Some(&self.rate_limiter),
+            url,
+            method,
+            form,
+            move |req| {
+                req.header(OCP_APIM_SUBSCRIPTION_KEY_HEADER, OCP_APIM_SUBSCRIPTION_KEY)
+                    .bearer_auth(jwt.as_str())
+            },
+        ))
         .await
     }
 
+    /// Sends a multipart form, e.g. a file upload. Unlike `api`, this is not retried,
+    /// since the form cannot be sent again.
+    pub async fn api_multipart(
+        &self,
+        path: &str,
+        form: reqwest::multipart::Form,
+    ) -> Result<Response> {
+        let jwt = self.jwt.read().unwrap().clone();
+        let request = self
+            .client
+            .post(full_api_url(&self.api_base_url, path)?)
+            .header(OCP_APIM_SUBSCRIPTION_KEY_HEADER, OCP_APIM_SUBSCRIPTION_KEY)
+            .bearer_auth(jwt.as_str())
+            .multipart(form)
+            .build()
+            .map_err(|_| "Failed to build request")?;
+        self.cancellable(self.transport.execute(request)).await
+    }
+
     // Add a desktop user agent to the request (for those endpoints that are picky about it)
     pub fn add_desktop_user_agent(req: RequestBuilder) -> RequestBuilder {
         req.header(
@@ -248,7 +714,16 @@ impl Api {
     where
         F: (Fn(RequestBuilder) -> RequestBuilder),
     {
-        infinite_retry_http(&self.client, url, method, form, edit_request).await
+        self.cancellable(infinite_retry_http(
+            &self.client,
+            &*self.transport,
+            None,
+            url,
+            method,
+            form,
+            edit_request,
+        ))
+        .await
     }
 
     pub async fn get_text<F>(
@@ -262,9 +737,49 @@ impl Api {
         F: (Fn(RequestBuilder) -> RequestBuilder),
     {
         // Panapto displays a 500 internal server error page without a desktop user-agent
-        let res = infinite_retry_http(&self.client, url, method, form, edit_request).await?;
+        let mut res = self.custom_request(url, method, form, edit_request).await?;
+
+        self.cancellable(async {
+            let mut body = Vec::new();
+            while let Some(chunk) = res.chunk().await.map_err(|_| "Unable to get text")? {
+                self.check_response_size(body.len() + chunk.len())?;
+                body.extend_from_slice(&chunk);
+            }
+            Ok(String::from_utf8_lossy(&body).into_owned())
+        })
+        .await
+    }
+
+    /// Fetches something that LumiNUS content links to, e.g. an image in an announcement, which
+    /// needs the token of the API when it is on the same host
+    pub(crate) async fn get_linked(&self, url: Url) -> Result<Vec<u8>> {
+        let jwt = (url.host_str() == self.api_base_url.host_str())
+            .then(|| self.jwt.read().unwrap().clone());
+        let mut res = self
+            .custom_request(url, Method::GET, None, move |req| match &jwt {
+                Some(jwt) => req
+                    .header(OCP_APIM_SUBSCRIPTION_KEY_HEADER, OCP_APIM_SUBSCRIPTION_KEY)
+                    .bearer_auth(jwt.as_str()),
+                None => req,
+            })
+            .await?;
+        if !res.status().is_success() {
+            return Err("Unable to fetch linked content");
+        }
 
-        res.text().await.map_err(|_| "Unable to get text")
+        self.cancellable(async {
+            let mut body = Vec::new();
+            while let Some(chunk) = res
+                .chunk()
+                .await
+                .map_err(|_| "Unable to fetch linked content")?
+            {
+                self.check_response_size(body.len() + chunk.len())?;
+                body.extend_from_slice(&chunk);
+            }
+            Ok(body)
+        })
+        .await
     }
 
     async fn current_term(&self) -> Result<String> {
@@ -307,7 +822,7 @@ impl Api {
                 m1.code.cmp(&m2.code).then_with(|| m2.term.cmp(&m1.term))
             });
             selected_modules.dedup_by(|other, latest| if other.code == latest.code {
-                println!("Warning: module {} appeared in more than one semester, only latest semester will be retrieved", other.code);
+                tracing::warn!("Module {} appeared in more than one semester, only latest semester will be retrieved", other.code);
                 true
             } else {
                 false
@@ -325,93 +840,255 @@ impl Api {
             .user_name_original)
     }
 
-    pub async fn with_login<'a>(username: &str, password: &str) -> Result<Api> {
-        let params = build_auth_form(username, password);
-        let client = build_client()?;
+    /// For more control over how the `Api` talks to the servers, e.g. with a client of your own
+    pub fn builder() -> ApiBuilder {
+        ApiBuilder::new()
+    }
 
-        let auth_resp = auth_http_post(&client, build_auth_url(), Some(&params), false).await?;
-        if !auth_resp.url().as_str().starts_with(ADFS_REDIRECT_URI) {
-            return Err("Invalid credentials");
-        }
-        let code = auth_resp
-            .url()
-            .query_pairs()
-            .find(|(key, _)| key == "code")
-            .map(|(_key, code)| code.into_owned())
-            .ok_or("Unknown authentication failure (no code returned)")?;
-        let token_resp = auth_http_post(
-            &client,
-            full_api_url("login/adfstoken"),
-            Some(&build_token_form(&code)),
-            true,
-        )
-        .await?;
-        if !token_resp.status().is_success() {
-            return Err("Unknown authentication failure (no token returned)");
-        }
-        let token = token_resp
-            .json::<TokenResponse>()
+    pub async fn with_login(username: &str, password: &str) -> Result<Api> {
+        Api::builder().login(username, password).await
+    }
+
+    /// Logs in with the given credentials, using a cookie jar (e.g. a `PersistentCookieJar`)
+    /// that may already contain session cookies for the other services.
+    pub async fn with_login_and_cookies<C: CookieStore + 'static>(
+        username: &str,
+        password: &str,
+        cookies: Arc<C>,
+    ) -> Result<Api> {
+        Api::builder()
+            .cookie_store(cookies)
+            .login(username, password)
             .await
-            .map_err(|_| "Failed to deserialise token exchange response")?;
-        Ok(Api {
-            jwt: token.access_token,
-            client,
-            ffmpeg_path: String::new(),
-        })
+    }
+
+    /// Logs in by reusing an existing ADFS session from the cookie jar, without any credentials.
+    pub async fn with_cookies<C: CookieStore + 'static>(cookies: Arc<C>) -> Result<Api> {
+        Api::builder()
+            .cookie_store(cookies)
+            .login_with_cookies()
+            .await
+    }
+
+    /// Uses a token obtained elsewhere (e.g. by an earlier login), without logging in at all.
+    /// Once it expires, the ADFS session in the cookie jar is reused to get a new one, if there is one.
+    pub fn with_token_and_cookies<C: CookieStore + 'static>(
+        token: &str,
+        cookies: Arc<C>,
+    ) -> Result<Api> {
+        Api::builder().cookie_store(cookies).build_with_token(token)
+    }
+
+    /// Creates an `Api` that is not logged in to LumiNUS.
+    /// Only public resources (e.g. shared Panopto sessions or Zoom recordings) can be accessed with it.
+    pub fn anonymous() -> Result<Api> {
+        Api::builder().build()
+    }
+
+    pub fn is_anonymous(&self) -> bool {
+        self.jwt.read().unwrap().is_empty()
     }
 
     // Assumes ADFS is already logged in
     pub async fn login_zoom(&mut self) -> Result<()> {
-        let (idp_url, saml_request) = zoom_signin_get_saml_request(&self.client).await?;
-        let (sso_url, saml_response) =
-            idp_signon_post_fetch_saml_response(&self.client, &idp_url, &saml_request).await?;
-        sso_post_saml_response(&self.client, &sso_url, &saml_response).await
+        let transport = &*self.transport;
+        let result = self
+            .cancellable(async {
+                let (idp_url, saml_request) =
+                    zoom_signin_get_saml_request(&self.client, transport).await?;
+                let (sso_url, saml_response) = idp_signon_post_fetch_saml_response(
+                    &self.client,
+                    transport,
+                    &idp_url,
+                    &saml_request,
+                )
+                .await?;
+                sso_post_saml_response(&self.client, transport, &sso_url, &saml_response).await
+            })
+            .await;
+        #[cfg(feature = "headless-login")]
+        if let (Err(e), Some(cookies)) = (result, &self.cookies) {
+            if headless_login::should_fall_back(e) {
+                tracing::warn!(
+                    error = e,
+                    "Unable to sign in to Zoom, trying in a headless browser"
+                );
+                return self
+                    .cancellable(headless_login::login_zoom(&**cookies))
+                    .await;
+            }
+        }
+        result
     }
 
     pub fn with_ffmpeg<S: Into<String>>(self: Api, ffmpeg_path: S) -> Api {
         Api {
-            jwt: self.jwt,
-            client: self.client,
             ffmpeg_path: ffmpeg_path.into(),
+            ffmpeg_available: Arc::default(),
+            ..self
+        }
+    }
+
+    pub fn with_video_container(self: Api, video_container: VideoContainer) -> Api {
+        Api {
+            video_container,
+            ..self
+        }
+    }
+
+    pub fn with_video_quality(self: Api, video_quality: VideoQuality) -> Api {
+        Api {
+            video_quality,
+            ..self
+        }
+    }
+
+    /// Limits the size of API responses and pages, so that memory stays bounded on small devices.
+    /// `None` lifts the limit.
+    pub fn with_max_response_size(self: Api, max_response_size: Option<usize>) -> Api {
+        Api {
+            max_response_size,
+            ..self
+        }
+    }
+
+    /// Trades speed for memory: responses are parsed with less of them buffered, and streams
+    /// are downloaded without ffmpeg where possible, one segment at a time.
+    pub fn with_low_memory(self: Api, low_memory: bool) -> Api {
+        Api { low_memory, ..self }
+    }
+
+    /// Limits how many downloads may run at once, in total and from each of LumiNUS, Panopto and Zoom
+    pub fn with_connection_limits(
+        self: Api,
+        max_connections: usize,
+        max_connections_per_host: usize,
+    ) -> Api {
+        Api {
+            scheduler: Arc::new(Scheduler::new(max_connections, max_connections_per_host)),
+            ..self
+        }
+    }
+
+    /// Fetches the files behind resolved download URLs through a caching proxy, such as the one
+    /// of `fluminurs-cli cache-proxy`, so that devices sharing it download each file only once
+    pub fn with_download_proxy(self: Api, download_proxy: Option<Url>) -> Api {
+        Api {
+            download_proxy,
+            ..self
+        }
+    }
+
+    /// Gives up on whatever this `Api` and its clones are doing once the token is cancelled,
+    /// with `CANCELLED` as the error, and on anything they are asked to do afterwards.
+    /// Downloads that are given up on leave no temporary files behind.
+    pub fn with_cancellation_token(self: Api, cancellation: CancellationToken) -> Api {
+        Api {
+            cancellation,
+            ..self
+        }
+    }
+
+    /// Limits the calls to the LumiNUS API to this many per second, or not at all if none.
+    /// Downloads are limited separately, with `with_connection_limits`.
+    pub fn with_rate_limit(self: Api, requests_per_second: Option<u32>) -> Api {
+        Api {
+            rate_limiter: Arc::new(RateLimiter::new(requests_per_second)),
+            ..self
+        }
+    }
+
+    /// Takes listings of folders and recordings from the cache while they are fresh, and puts
+    /// the ones that are fetched into it. Saving the cache is up to the caller.
+    pub fn with_listing_cache(self: Api, listing_cache: Option<Arc<ListingCache>>) -> Api {
+        Api {
+            listing_cache,
+            ..self
+        }
+    }
+
+    /// Moves the files that are replaced by downloads (with `OverwriteMode::Overwrite`) or pruned
+    /// (the old versions beyond what `OverwriteMode::Versioned` keeps) into a folder for the day
+    /// within `trash_dir`, instead of deleting them
+    pub fn with_trash_dir(self: Api, trash_dir: Option<PathBuf>) -> Api {
+        Api { trash_dir, ..self }
+    }
+
+    /// Sends the requests of this `Api` through `transport` from now on, e.g. one that wraps
+    /// `get_transport` to keep count of failed requests
+    pub fn with_transport(self: Api, transport: Arc<dyn HttpTransport>) -> Api {
+        Api { transport, ..self }
+    }
+
+    /// Points a resolved download URL at the download proxy, if there is one. The cache key
+    /// identifies the version of the file, as download URLs change with every resolution.
+    pub(crate) fn proxied_download_url(&self, url: Url, cache_key: &str) -> Url {
+        match &self.download_proxy {
+            Some(proxy) => {
+                let mut proxied = proxy.clone();
+                proxied.set_path(DOWNLOAD_PROXY_PATH);
+                proxied
+                    .query_pairs_mut()
+                    .clear()
+                    .append_pair("key", cache_key)
+                    .append_pair("url", url.as_str());
+                proxied
+            }
+            None => url,
+        }
+    }
+
+    /// Makes Panopto sessions download as their pre-rendered podcast MP4 where there is one,
+    /// instead of muxing all the streams of the session.
+    pub fn with_panopto_podcast(self: Api, panopto_podcast: bool) -> Api {
+        Api {
+            panopto_podcast,
+            ..self
+        }
+    }
+
+    /// Saves a copy of each announcement as sanitised HTML next to its Markdown, with the images
+    /// it embeds downloaded alongside, instead of only the text.
+    pub fn with_announcement_html(self: Api, announcement_html: bool) -> Api {
+        Api {
+            announcement_html,
+            ..self
         }
     }
 }
 
-async fn zoom_signin_get_saml_request(client: &Client) -> Result<(String, String)> {
+async fn zoom_signin_get_saml_request(
+    client: &Client,
+    transport: &dyn HttpTransport,
+) -> Result<(String, String)> {
     let resp = infinite_retry_http(
         client,
-        Url::parse(ZOOM_SIGNIN_URL).expect("Unable to parse Zoom URL"),
+        transport,
+        None,
+        Url::parse(ZOOM_SIGNIN_URL).map_err(|_| "Unable to parse Zoom URL")?,
         Method::GET,
         None,
         move |req| req.header(REFERER, ZOOM_REFERER_URL),
     )
     .await?;
-    let document = Html::parse_document(
-        &resp
-            .text()
-            .await
-            .map_err(|_| "Unable to get response text")?,
-    );
-    let form_selector = Selector::parse(r#"form[method="post"]"#).unwrap();
-    let idp_url = document
-        .select(&form_selector)
-        .next()
-        .and_then(|element| element.value().attr("action"))
-        .ok_or("Unable to find form action URL")?;
-    let saml_request_selector = Selector::parse(r#"input[name="SAMLRequest"]"#).unwrap();
-    let saml_request = document
-        .select(&saml_request_selector)
-        .next()
-        .and_then(|element| element.value().attr("value"))
-        .ok_or("Unable to find SAMLRequest value")?;
-    Ok((
-        htmlescape::decode_html(idp_url).map_err(|_| "Unable to decode URL")?,
-        saml_request.to_owned(),
-    ))
+    let url = resp.url().clone();
+    let html = resp
+        .text()
+        .await
+        .map_err(|_| "Unable to get response text")?;
+    scrape_saml_form(
+        &url,
+        &html,
+        "SAMLRequest",
+        "Zoom sign-in",
+        "Expected a login form on the Zoom sign-in page, found none (see debug log)",
+        "Expected a SAMLRequest input on the Zoom sign-in page, found none (see debug log)",
+    )
 }
 
 async fn idp_signon_post_fetch_saml_response(
     client: &Client,
+    transport: &dyn HttpTransport,
     idp_url: &str,
     saml_request: &str,
 ) -> Result<(String, String)> {
@@ -419,50 +1096,328 @@ async fn idp_signon_post_fetch_saml_response(
     form_data.insert("SAMLRequest", saml_request);
     let resp = infinite_retry_http(
         client,
-        Url::parse(idp_url).expect("Unable to parse LDP URL"),
+        transport,
+        None,
+        Url::parse(idp_url).map_err(|_| "Unable to parse IdP URL from the Zoom sign-in page")?,
         Method::POST,
         Some(&form_data),
         move |req| req.header(REFERER, ZOOM_REFERER_URL),
     )
     .await?;
-    let document = Html::parse_document(
-        &resp
-            .text()
-            .await
-            .map_err(|_| "Unable to get response text")?,
-    );
+    let url = resp.url().clone();
+    let html = resp
+        .text()
+        .await
+        .map_err(|_| "Unable to get response text")?;
+    scrape_saml_form(
+        &url,
+        &html,
+        "SAMLResponse",
+        "IdP",
+        "Expected a form on the IdP page, found none; the ADFS session may have expired (see debug log)",
+        "Expected a SAMLResponse input on the IdP page, found none (see debug log)",
+    )
+}
+
+/// Finds the action of the POST form and the value of the SAML input on one hop of an SSO flow.
+/// These pages are scraped, so they break whenever upstream changes them; when the page doesn't
+/// have the expected structure, we log its title and a redacted copy to make fixing it easier.
+fn scrape_saml_form(
+    url: &Url,
+    html: &str,
+    input_name: &str,
+    page_name: &str,
+    missing_form_error: Error,
+    missing_input_error: Error,
+) -> Result<(String, String)> {
+    let document = Html::parse_document(html);
     let form_selector = Selector::parse(r#"form[method="post"]"#).unwrap();
-    let sso_url = document
+    let input_selector = Selector::parse(&format!(r#"input[name="{}"]"#, input_name))
+        .map_err(|_| "Unable to parse SAML input selector")?;
+
+    let action = document
         .select(&form_selector)
         .next()
-        .and_then(|element| element.value().attr("action"))
-        .ok_or("Unable to find form action URL")?;
-    let saml_response_selector = Selector::parse(r#"input[name="SAMLResponse"]"#).unwrap();
-    let saml_response = document
-        .select(&saml_response_selector)
+        .and_then(|element| element.value().attr("action"));
+    let action = match action {
+        Some(action) => action,
+        None => {
+            log_unexpected_page(page_name, "a POST form", url, &document, html);
+            return Err(missing_form_error);
+        }
+    };
+    let value = document
+        .select(&input_selector)
         .next()
-        .and_then(|element| element.value().attr("value"))
-        .ok_or("Unable to find SAMLResponse value")?;
+        .and_then(|element| element.value().attr("value"));
+    let value = match value {
+        Some(value) => value,
+        None => {
+            log_unexpected_page(page_name, input_name, url, &document, html);
+            return Err(missing_input_error);
+        }
+    };
     Ok((
-        htmlescape::decode_html(sso_url).map_err(|_| "Unable to decode URL")?,
-        saml_response.to_owned(),
+        htmlescape::decode_html(action).map_err(|_| "Unable to decode URL")?,
+        value.to_owned(),
     ))
 }
 
-async fn sso_post_saml_response(client: &Client, sso_url: &str, saml_response: &str) -> Result<()> {
+fn log_unexpected_page(page_name: &str, expected: &str, url: &Url, document: &Html, html: &str) {
+    let title_selector = Selector::parse("title").unwrap();
+    let title = document
+        .select(&title_selector)
+        .next()
+        .map(|element| element.text().collect::<String>())
+        .unwrap_or_default();
+    tracing::warn!(
+        "{} page: expected {}, found none (page title: {:?}, url: {})",
+        page_name,
+        expected,
+        title.trim(),
+        url
+    );
+    tracing::debug!("{} page contents:\n{}", page_name, redact_html(html));
+}
+
+// Reads the chunks of a response as they are received, for parsing on a blocking thread
+struct ChunkReader<B> {
+    receiver: tokio::sync::mpsc::Receiver<B>,
+    chunk: Option<B>,
+    offset: usize,
+}
+
+impl<B> ChunkReader<B> {
+    fn new(receiver: tokio::sync::mpsc::Receiver<B>) -> ChunkReader<B> {
+        ChunkReader {
+            receiver,
+            chunk: None,
+            offset: 0,
+        }
+    }
+}
+
+impl<B: AsRef<[u8]>> Read for ChunkReader<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(chunk) = &self.chunk {
+                let remaining = &chunk.as_ref()[self.offset..];
+                if !remaining.is_empty() {
+                    let length = remaining.len().min(buf.len());
+                    buf[..length].copy_from_slice(&remaining[..length]);
+                    self.offset += length;
+                    return Ok(length);
+                }
+            }
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = Some(chunk);
+                    self.offset = 0;
+                }
+                // the sender is gone at the end of the body
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+// Whether a response that should have been JSON is actually the ADFS (or some other) login page
+fn looks_like_login_page(body: &str) -> bool {
+    let body = body.trim_start().to_ascii_lowercase();
+    body.starts_with('<')
+        && body.contains("<form")
+        && (body.contains("password") || body.contains("login") || body.contains("sign in"))
+}
+
+// Like `redact_html`, but also blanks out JSON values that could carry tokens or credentials
+fn redact_body(body: &str) -> String {
+    let json_regex =
+        regex::Regex::new(r#"(?i)("[^"]*(token|jwt|password|secret|key)[^"]*"\s*:\s*)"[^"]*""#)
+            .expect("Unable to parse regex");
+    json_regex
+        .replace_all(&redact_html(body), "$1\"<redacted>\"")
+        .into_owned()
+}
+
+// Blanks out all attribute values that could carry tokens or credentials
+fn redact_html(html: &str) -> String {
+    let value_regex = regex::Regex::new(r#"(?i)\b(value|content)\s*=\s*("[^"]*"|'[^']*')"#)
+        .expect("Unable to parse regex");
+    value_regex
+        .replace_all(html, "$1=\"<redacted>\"")
+        .into_owned()
+}
+
+async fn sso_post_saml_response(
+    client: &Client,
+    transport: &dyn HttpTransport,
+    sso_url: &str,
+    saml_response: &str,
+) -> Result<()> {
     let mut form_data = HashMap::new();
     form_data.insert("SAMLResponse", saml_response);
     let resp = infinite_retry_http(
         client,
-        Url::parse(sso_url).expect("Unable to parse SSO URL"),
+        transport,
+        None,
+        Url::parse(sso_url).map_err(|_| "Unable to parse SSO URL from the IdP page")?,
         Method::POST,
         Some(&form_data),
         move |req| req.header(REFERER, ADFS_REFERER_URL),
     )
     .await?;
     if !resp.url().as_str().starts_with(ZOOM_REDIRECT_URL) {
+        tracing::warn!(
+            "Zoom SSO: expected to be redirected to {}, but ended up at {}",
+            ZOOM_REDIRECT_URL,
+            resp.url()
+        );
         Err("Zoom SSO failed")
     } else {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::canned::CannedTransport;
+
+    const CERTIFICATE: &[u8] = include_bytes!("DigiCert_TLS_RSA_SHA256_2020_CA1.pem");
+
+    fn api_with(transport: &Arc<CannedTransport>) -> Api {
+        Api::builder()
+            .transport(transport.clone())
+            .build_with_token("token")
+            .unwrap()
+    }
+
+    #[test]
+    fn loads_every_certificate_in_a_pem_file() {
+        assert_eq!(add_root_certificates(CERTIFICATE), Ok(1));
+        let bundle = [CERTIFICATE, b"\n", CERTIFICATE].concat();
+        assert_eq!(add_root_certificates(&bundle), Ok(2));
+    }
+
+    #[test]
+    fn rejects_invalid_pem_files() {
+        assert!(add_root_certificates(b"").is_err());
+        assert!(add_root_certificates(b"not a certificate").is_err());
+        assert!(add_root_certificates(&[0xff, 0xfe]).is_err());
+        let unterminated = &CERTIFICATE[..CERTIFICATE.len() - PEM_CERTIFICATE_END.len() - 2];
+        assert!(add_root_certificates(unterminated).is_err());
+        let invalid = format!(
+            "{}\nbm90IGEgY2VydGlmaWNhdGU=\n{}\n",
+            PEM_CERTIFICATE_BEGIN, PEM_CERTIFICATE_END
+        );
+        assert!(add_root_certificates(invalid.as_bytes()).is_err());
+    }
+
+    #[tokio::test]
+    async fn lists_the_modules_of_a_term() {
+        let transport = Arc::new(CannedTransport::default().respond(
+            "/v2/api/module",
+            200,
+            r#"{"data": [
+                {"id": "1", "name": "CS2040S", "courseName": "Data Structures", "term": "2110"},
+                {"id": "2", "name": "CS1010", "courseName": "Programming", "term": "2110"},
+                {"id": "3", "name": "MA1521", "courseName": "Calculus", "term": "2020"}
+            ]}"#,
+        ));
+        let modules = api_with(&transport)
+            .modules(Some("2110".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(
+            modules.iter().map(|m| m.code.as_str()).collect::<Vec<_>>(),
+            ["CS1010", "CS2040S"]
+        );
+        let requests = transport.requests.lock().unwrap();
+        let headers = requests[0].headers();
+        assert_eq!(headers["Authorization"], "Bearer token");
+        assert_eq!(
+            headers[OCP_APIM_SUBSCRIPTION_KEY_HEADER],
+            OCP_APIM_SUBSCRIPTION_KEY
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_refused_requests() {
+        let transport =
+            Arc::new(CannedTransport::default().respond("/v2/api/user/Profile", 403, ""));
+        assert_eq!(api_with(&transport).name().await, Err(ACCESS_DENIED));
+    }
+
+    #[tokio::test]
+    async fn logs_in_through_adfs() {
+        let transport = Arc::new(
+            CannedTransport::default()
+                .redirect(
+                    "/adfs/oauth2/authorize",
+                    "https://luminus.nus.edu.sg/auth/callback?code=abc",
+                    "",
+                )
+                .respond(
+                    "/v2/api/login/adfstoken",
+                    200,
+                    r#"{"access_token": "fresh"}"#,
+                )
+                .respond(
+                    "/v2/api/user/Profile",
+                    200,
+                    r#"{"userNameOriginal": "Jane Doe"}"#,
+                ),
+        );
+        let api = Api::builder()
+            .transport(transport.clone())
+            .login("e0123456", "password")
+            .await
+            .unwrap();
+        assert!(!api.is_anonymous());
+        assert_eq!(api.name().await.as_deref(), Ok("Jane Doe"));
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[2].headers()["Authorization"], "Bearer fresh");
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_credentials() {
+        // ADFS shows the form again instead of redirecting back to LumiNUS
+        let transport = Arc::new(CannedTransport::default().respond(
+            "/adfs/oauth2/authorize",
+            200,
+            "<form></form>",
+        ));
+        let result = Api::builder()
+            .transport(transport)
+            .login("e0123456", "wrong")
+            .await;
+        assert_eq!(result.err(), Some(INVALID_CREDENTIALS));
+    }
+
+    #[tokio::test]
+    async fn takes_fresh_listings_from_the_cache() {
+        let transport = Arc::new(CannedTransport::default().respond(
+            "/v2/api/files/?ParentID=1&offset=0&limit=100",
+            200,
+            r#"{"data": [{"id": "a"}, {"id": "b"}], "total": 2}"#,
+        ));
+        let cache = Arc::new(ListingCache::load(
+            std::env::temp_dir().join("fluminurs-test-listings-never-written.json"),
+            Duration::from_secs(60),
+        ));
+        let api = api_with(&transport).with_listing_cache(Some(cache.clone()));
+        for _ in 0..2 {
+            let listing = api
+                .api_listing::<serde_json::Value>("files/?ParentID=1")
+                .await
+                .unwrap();
+            assert_eq!(listing.data.map(|items| items.len()), Some(2));
+        }
+        assert_eq!(
+            transport.requested(),
+            ["/v2/api/files/?ParentID=1&offset=0&limit=100"]
+        );
+        assert_eq!(cache.take_hits(), 1);
+    }
+}
@@ -0,0 +1,98 @@
+// Listings of folders and recordings, kept across runs for a while, so that a run soon after
+// another one can start downloading without walking every folder of every module again
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    // seconds since the epoch
+    fetched: u64,
+    items: Vec<serde_json::Value>,
+}
+
+#[derive(Debug)]
+pub struct ListingCache {
+    path: PathBuf,
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+    // how many listings were taken from the cache rather than fetched
+    hits: AtomicUsize,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl ListingCache {
+    /// Loads the cache from `path`, or starts an empty one if the file doesn't exist yet or can't
+    /// be read. Listings are used for `ttl` after they were fetched, and fetched again after that.
+    pub fn load<P: AsRef<Path>>(path: P, ttl: Duration) -> ListingCache {
+        let path = path.as_ref().to_owned();
+        let entries = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        ListingCache {
+            path,
+            ttl,
+            entries: Mutex::new(entries),
+            hits: AtomicUsize::new(0),
+        }
+    }
+
+    /// Forgets every listing, so that all of them are fetched again, e.g. for `--refresh`
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// How many listings were taken from the cache since this was last asked, so that users can
+    /// be told that what they see may be a few minutes old
+    pub fn take_hits(&self) -> usize {
+        self.hits.swap(0, Ordering::Relaxed)
+    }
+
+    /// Writes the listings that are still fresh back to the file.
+    pub fn save(&self) -> Result<()> {
+        let oldest = now().saturating_sub(self.ttl.as_secs());
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.fetched >= oldest);
+        let data = serde_json::to_vec(&*entries).map_err(|_| "Unable to serialise listings")?;
+        std::fs::write(&self.path, data).map_err(|_| "Unable to write listing cache")
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<Vec<serde_json::Value>> {
+        let oldest = now().saturating_sub(self.ttl.as_secs());
+        let items = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(key)
+            .filter(|entry| entry.fetched >= oldest)
+            .map(|entry| entry.items.clone());
+        if items.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        items
+    }
+
+    pub(crate) fn insert(&self, key: &str, items: Vec<serde_json::Value>) {
+        self.entries.lock().unwrap().insert(
+            key.to_owned(),
+            Entry {
+                fetched: now(),
+                items,
+            },
+        );
+    }
+}
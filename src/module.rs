@@ -1,15 +1,22 @@
 use std::path::PathBuf;
 
-use reqwest::Method;
 use serde::Deserialize;
 
+use crate::announcement::{fetch_announcements, AnnouncementHandle};
 use crate::conferencing::ConferencingHandle;
 use crate::file::DirectoryHandle;
+use crate::forum::ForumHandle;
+use crate::gradebook::GradebookHandle;
+use crate::lesson_plan::LessonPlanHandle;
 use crate::multimedia::MultimediaHandle;
+use crate::quiz::QuizHandle;
+use crate::roster::RosterHandle;
 use crate::util::sanitise_filename;
 use crate::weblecture::WebLectureHandle;
-use crate::{Api, ApiData, Result};
+use crate::weblink::WeblinkHandle;
+use crate::{Api, Result};
 
+#[allow(dead_code)]
 #[derive(Debug, Deserialize, Clone)]
 struct Access {
     #[serde(rename = "access_Full")]
@@ -29,9 +36,24 @@ struct Access {
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Announcement {
+    pub id: String,
     pub title: String,
     pub description: String,
+    pub display_from: Option<String>,
+    pub last_updated_date: Option<String>,
+    pub attachments: Option<Vec<AnnouncementAttachment>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AnnouncementAttachment {
+    pub id: String,
+    pub name: String,
+    pub file_name: Option<String>,
+    pub last_updated_date: Option<String>,
+    pub file_size: Option<u64>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -64,24 +86,20 @@ impl Module {
         !self.is_teaching()
     }
 
+    // The roster contains personal data of the students, so it is only available to teaching staff
+    fn can_view_roster(&self) -> bool {
+        self.access
+            .as_ref()
+            .map(|access| access.full || access.settings_read)
+            .unwrap_or(false)
+    }
+
     pub fn has_access(&self) -> bool {
         self.access.is_some()
     }
 
     pub async fn get_announcements(&self, api: &Api, archived: bool) -> Result<Vec<Announcement>> {
-        let path = format!(
-            "announcement/{}/{}?sortby=displayFrom%20ASC",
-            if archived { "Archived" } else { "NonArchived" },
-            self.id
-        );
-        let api_data = api
-            .api_as_json::<ApiData<Vec<Announcement>>>(&path, Method::GET, None)
-            .await?;
-        if let Some(announcements) = api_data.data {
-            Ok(announcements)
-        } else {
-            Err("Invalid API response from server: type mismatch")
-        }
+        fetch_announcements(api, &self.id, archived).await
     }
 
     pub fn workbin_root<F: FnOnce(&str) -> PathBuf>(&self, make_path: F) -> DirectoryHandle {
@@ -96,10 +114,57 @@ impl Module {
         WebLectureHandle::new(self.id.clone(), make_path(&sanitise_filename(&self.code)))
     }
 
+    pub fn announcements_root<F: FnOnce(&str) -> PathBuf>(
+        &self,
+        make_path: F,
+    ) -> AnnouncementHandle {
+        AnnouncementHandle::new(self.id.clone(), make_path(&sanitise_filename(&self.code)))
+    }
+
     pub fn conferencing_root<F: FnOnce(&str) -> PathBuf>(
         &self,
         make_path: F,
     ) -> ConferencingHandle {
         ConferencingHandle::new(self.id.clone(), make_path(&sanitise_filename(&self.code)))
     }
+
+    pub fn forum_root<F: FnOnce(&str) -> PathBuf>(&self, make_path: F) -> ForumHandle {
+        ForumHandle::new(self.id.clone(), make_path(&sanitise_filename(&self.code)))
+    }
+
+    pub fn quiz_root<F: FnOnce(&str) -> PathBuf>(&self, make_path: F) -> QuizHandle {
+        QuizHandle::new(self.id.clone(), make_path(&sanitise_filename(&self.code)))
+    }
+
+    pub fn gradebook_root<F: FnOnce(&str) -> PathBuf>(&self, make_path: F) -> GradebookHandle {
+        GradebookHandle::new(
+            self.id.clone(),
+            make_path(&sanitise_filename(&self.code)),
+            self.is_teaching(),
+        )
+    }
+
+    pub fn weblink_root<F: FnOnce(&str) -> PathBuf>(&self, make_path: F) -> WeblinkHandle {
+        WeblinkHandle::new(self.id.clone(), make_path(&sanitise_filename(&self.code)))
+    }
+
+    pub fn lesson_plan_root<F: FnOnce(&str) -> PathBuf>(&self, make_path: F) -> LessonPlanHandle {
+        LessonPlanHandle::new(
+            self.id.clone(),
+            self.code.clone(),
+            make_path(&sanitise_filename(&self.code)),
+        )
+    }
+
+    /// Returns `None` if the user is not allowed to see the class roster of this module.
+    pub fn roster_root<F: FnOnce(&str) -> PathBuf>(&self, make_path: F) -> Option<RosterHandle> {
+        if self.can_view_roster() {
+            Some(RosterHandle::new(
+                self.id.clone(),
+                make_path(&sanitise_filename(&self.code)),
+            ))
+        } else {
+            None
+        }
+    }
 }
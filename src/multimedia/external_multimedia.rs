@@ -4,12 +4,15 @@ use std::time::SystemTime;
 use async_trait::async_trait;
 use reqwest::{Method, Url};
 use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWrite;
 
 use crate::multimedia::Channel;
 use crate::panopto;
 use crate::resource;
 use crate::resource::{OverwriteMode, OverwriteResult, Resource};
-use crate::streamer::stream_and_mux_videos;
+use crate::streamer::{
+    make_video_extension, stream_and_mux_videos, stream_primary_video_to_writer, VideoMetadata,
+};
 use crate::util::sanitise_filename;
 use crate::{Api, Result};
 
@@ -52,6 +55,16 @@ pub struct ExternalVideo {
     path: PathBuf,
 }
 
+impl ExternalVideo {
+    /// Creates a video for the Panopto session with the given delivery id
+    pub fn from_delivery_id(delivery_id: String, path: PathBuf) -> ExternalVideo {
+        ExternalVideo {
+            id: delivery_id,
+            path,
+        }
+    }
+}
+
 pub(super) async fn load_external_channel(
     api: &Api,
     channel: Channel,
@@ -59,16 +72,15 @@ pub(super) async fn load_external_channel(
 ) -> Result<Vec<ExternalVideo>> {
     let channel_path = path.join(Path::new(&sanitise_filename(&channel.name)));
 
-    let response = panopto::launch(
+    let url = panopto::resolve(
         api,
         &format!("lti/Launch/mediaweb?context_id={}", channel.id),
     )
     .await?;
 
-    // response.url() looks like this: https://mediaweb.ap.panopto.com/Panopto/Pages/Sessions/List.aspx?embedded=1#folderID="xxxxxx"
+    // url looks like this: https://mediaweb.ap.panopto.com/Panopto/Pages/Sessions/List.aspx?embedded=1#folderID="xxxxxx"
     // where 'xxxxxx' (without quotes) is the thing we want to extract
-    let query_parameters: ExternalMultimediaRequestQueryParameters = response
-        .url()
+    let query_parameters: ExternalMultimediaRequestQueryParameters = url
         .fragment()
         .ok_or("Query parameters missing from external multimedia response")
         .and_then(|s| {
@@ -96,7 +108,7 @@ pub(super) async fn load_external_channel(
 
     let panopto_url =
         Url::parse("https://mediaweb.ap.panopto.com/Panopto/Services/Data.svc/GetSessions")
-            .expect("Invalid URL");
+            .map_err(|_| "Invalid URL")?;
 
     let json = ExternalMultimediaRequest { query_parameters };
 
@@ -115,9 +127,10 @@ pub(super) async fn load_external_channel(
         .into_iter()
         .map(|m| ExternalVideo {
             id: m.delivery_id,
-            path: channel_path.join(super::make_mp4_extension(Path::new(&sanitise_filename(
-                &m.session_name,
-            )))),
+            path: channel_path.join(make_video_extension(
+                api,
+                Path::new(&sanitise_filename(&m.session_name)),
+            )),
         })
         .collect::<Vec<_>>())
 }
@@ -148,17 +161,29 @@ impl Resource for ExternalVideo {
         overwrite: OverwriteMode,
     ) -> Result<OverwriteResult> {
         let delivery_id: &str = self.id();
+        // external multimedia do not have dates either
+        let metadata = VideoMetadata::from_path(&self.path, None);
+        let metadata = &metadata;
         resource::do_retryable_download(
             api,
+            self,
             destination,
             temp_destination,
             overwrite,
-            self.last_updated(),
             move |api| panopto::get_stream_specs(api, delivery_id),
             move |api, stream_specs, temp_destination| async move {
-                stream_and_mux_videos(api, &stream_specs, temp_destination).await
+                stream_and_mux_videos(api, &stream_specs, temp_destination, metadata).await
             },
         )
         .await
     }
+
+    async fn download_to_writer(
+        &self,
+        api: &Api,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        let stream_specs = panopto::get_stream_specs(api, self.id()).await?;
+        stream_primary_video_to_writer(api, &stream_specs, writer).await
+    }
 }
@@ -5,10 +5,11 @@ use async_trait::async_trait;
 use futures_util::future;
 use reqwest::Method;
 use serde::Deserialize;
+use tokio::io::AsyncWrite;
 
 use crate::resource;
 use crate::resource::{OverwriteMode, OverwriteResult, Resource};
-use crate::streamer::stream_video;
+use crate::streamer::{make_video_extension, stream_video, stream_video_to_writer, VideoMetadata};
 use crate::util::{parse_time, sanitise_filename};
 use crate::{Api, ApiData, Result};
 
@@ -96,39 +97,34 @@ impl MultimediaHandle {
 
     async fn load_channel(api: &Api, channel: Channel, path: &Path) -> Result<Vec<InternalVideo>> {
         let channel_resp = api
-            .api_as_json::<ApiData<Vec<InternalMedia>>>(
-                &format!("multimedia/{}/medias", channel.id),
-                Method::GET,
-                None,
-            )
+            .api_listing::<InternalMedia>(&format!("multimedia/{}/medias", channel.id))
             .await?;
 
         let channel_path = path.join(Path::new(&sanitise_filename(&channel.name)));
 
         match channel_resp.data {
-            Some(medias) => Ok(medias
+            Some(medias) => medias
                 .into_iter()
                 .filter_map(|m| match m.stream_url_path {
-                    Some(stream_url_path) => Some(InternalVideo {
-                        id: m.id,
-                        stream_url_path,
-                        path: channel_path
-                            .join(make_mp4_extension(Path::new(&sanitise_filename(&m.name)))),
-                        last_updated: parse_time(&m.last_updated_date),
-                    }),
+                    Some(stream_url_path) => Some(parse_time(&m.last_updated_date).map(
+                        |last_updated| InternalVideo {
+                            id: m.id,
+                            stream_url_path,
+                            path: channel_path.join(make_video_extension(
+                                api,
+                                Path::new(&sanitise_filename(&m.name)),
+                            )),
+                            last_updated,
+                        },
+                    )),
                     None => None,
                 })
-                .collect::<Vec<_>>()),
+                .collect::<Result<Vec<_>>>(),
             None => Err("Invalid API response from server: type mismatch"),
         }
     }
 }
 
-// TODO: check file extension?
-fn make_mp4_extension(path: &Path) -> PathBuf {
-    path.with_extension("mp4")
-}
-
 #[async_trait]
 impl Resource for InternalVideo {
     fn id(&self) -> &str {
@@ -153,17 +149,27 @@ impl Resource for InternalVideo {
         temp_destination: &Path,
         overwrite: OverwriteMode,
     ) -> Result<OverwriteResult> {
+        let metadata = VideoMetadata::from_path(&self.path, Some(self.last_updated));
+        let metadata = &metadata;
         resource::do_retryable_download(
             api,
+            self,
             destination,
             temp_destination,
             overwrite,
-            self.last_updated(),
             move |_| future::ready(Ok(self.stream_url_path.as_str())),
             move |api, stream_url_path, temp_destination| {
-                stream_video(api, stream_url_path, temp_destination)
+                stream_video(api, stream_url_path, temp_destination, metadata)
             },
         )
         .await
     }
+
+    async fn download_to_writer(
+        &self,
+        api: &Api,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        stream_video_to_writer(api, &self.stream_url_path, writer).await
+    }
 }
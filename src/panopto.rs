@@ -1,13 +1,21 @@
 // Utilities for Panopto (web lectures and external multimedia)
 
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use futures_util::future;
 use reqwest::{Method, Response, Url};
+use scraper::{Html, Selector};
 use serde::Deserialize;
 
 use crate::streamer::StreamSpec;
 use crate::{Api, Result};
 
+const PANOPTO_LOGIN_PATH: &str = "/Panopto/Pages/Auth/Login.aspx";
+// Upper bound on the number of auto-submitted forms we follow during Panopto sign-in
+const MAX_SIGN_IN_HOPS: usize = 5;
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct PanoptoRequestConstructionDetails {
@@ -23,23 +31,211 @@ struct PanoptoQueryParameter {
     value: String,
 }
 
-pub async fn launch(api: &Api, api_path: &str) -> Result<Response> {
+/// The Panopto sessions of an `Api` and its clones, so that each host is signed in to once, and
+/// each LTI launch is only done once to find out where it leads
+#[derive(Debug, Default)]
+pub(crate) struct PanoptoSessions {
+    // the page that each launch landed on, by the API path that it was launched from
+    launches: Mutex<HashMap<String, Url>>,
+    // whether each host has been signed in to, which the first launch to it holds on to while it
+    // signs in, so that the others wait for its session instead of each starting their own
+    hosts: Mutex<HashMap<String, Arc<tokio::sync::Mutex<bool>>>>,
+}
+
+impl PanoptoSessions {
+    fn host(&self, host: &str) -> Arc<tokio::sync::Mutex<bool>> {
+        self.hosts
+            .lock()
+            .unwrap()
+            .entry(host.to_owned())
+            .or_default()
+            .clone()
+    }
+}
+
+async fn launch_details(
+    api: &Api,
+    api_path: &str,
+) -> Result<(Url, PanoptoRequestConstructionDetails)> {
     let query_params = api
         .api_as_json::<Option<PanoptoRequestConstructionDetails>>(api_path, Method::GET, None)
         .await?
         .ok_or("Invalid API response from server: type mismatch")?;
-
     let url =
         Url::parse(&query_params.launch_url).map_err(|_| "Unable to parse Panopto launch URL")?;
+    Ok((url, query_params))
+}
 
+/// Launches Panopto through LTI from the API path, signing in to Panopto if it asks for it
+pub async fn launch(api: &Api, api_path: &str) -> Result<Response> {
+    let (url, query_params) = launch_details(api, api_path).await?;
+    launch_with(api, url, &query_params).await
+}
+
+/// Where launching Panopto from the API path leads, e.g. to the viewer of a session with its
+/// delivery id. Only the first launch from each path is done, and only the first launch to each
+/// host signs in, with the others reusing its session.
+pub async fn resolve(api: &Api, api_path: &str) -> Result<Url> {
+    let sessions = &api.panopto_sessions;
+    if let Some(url) = sessions.launches.lock().unwrap().get(api_path) {
+        return Ok(url.clone());
+    }
+    let (url, query_params) = launch_details(api, api_path).await?;
+    let host = sessions.host(url.host_str().unwrap_or_default());
+    let mut signed_in = host.lock().await;
+    let response = if *signed_in {
+        drop(signed_in);
+        launch_with(api, url, &query_params).await?
+    } else {
+        let response = launch_with(api, url, &query_params).await?;
+        *signed_in = true;
+        response
+    };
+    let url = response.url().clone();
+    sessions
+        .launches
+        .lock()
+        .unwrap()
+        .insert(api_path.to_owned(), url.clone());
+    Ok(url)
+}
+
+async fn launch_with(
+    api: &Api,
+    url: Url,
+    query_params: &PanoptoRequestConstructionDetails,
+) -> Result<Response> {
     let form: HashMap<&str, &str> = query_params
         .data_items
         .iter()
         .map(|item| (item.key.as_str(), item.value.as_str()))
         .collect();
 
-    api.custom_request(url, Method::POST, Some(&form), Api::add_desktop_user_agent)
+    let response = api
+        .custom_request(url, Method::POST, Some(&form), Api::add_desktop_user_agent)
+        .await?;
+
+    if is_login_page(response.url()) {
+        // Some folders (e.g. restricted ones) need a Panopto sign-in on top of the LTI launch
+        sign_in(api, response).await
+    } else {
+        Ok(response)
+    }
+}
+
+fn is_login_page(url: &Url) -> bool {
+    url.path().eq_ignore_ascii_case(PANOPTO_LOGIN_PATH)
+}
+
+/// Attempts the Panopto SSO flow, reusing the existing ADFS session in the cookie store.
+/// The login page is bounced to the external identity provider, which (since we are already
+/// logged in to ADFS) replies with self-submitting SAML forms that we post back ourselves.
+async fn sign_in(api: &Api, login_response: Response) -> Result<Response> {
+    let login_url = login_response.url().clone();
+    let html = login_response
+        .text()
         .await
+        .map_err(|_| "Unable to get Panopto login page")?;
+    let provider = find_identity_provider(&html, &login_url)
+        .ok_or("Panopto sign-in required, but no identity provider was found on the login page")?;
+
+    let mut bounce_url = login_url;
+    bounce_url
+        .query_pairs_mut()
+        .append_pair("instance", &provider)
+        .append_pair("AllowBounce", "true");
+    let mut response = api
+        .custom_request(bounce_url, Method::GET, None, Api::add_desktop_user_agent)
+        .await?;
+
+    for _ in 0..MAX_SIGN_IN_HOPS {
+        if is_login_page(response.url()) {
+            break;
+        }
+        let url = response.url().clone();
+        let html = response
+            .text()
+            .await
+            .map_err(|_| "Unable to get Panopto sign-in response")?;
+        let (action, fields) = match find_saml_form(&html) {
+            Some(saml_form) => saml_form,
+            None => {
+                // No more forms to submit, so we have landed on the page we originally wanted
+                return api
+                    .custom_request(url, Method::GET, None, Api::add_desktop_user_agent)
+                    .await;
+            }
+        };
+        let action = url
+            .join(&action)
+            .map_err(|_| "Unable to parse Panopto sign-in form URL")?;
+        let form: HashMap<&str, &str> = fields
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        response = api
+            .custom_request(
+                action,
+                Method::POST,
+                Some(&form),
+                Api::add_desktop_user_agent,
+            )
+            .await?;
+    }
+
+    if is_login_page(response.url()) {
+        Err("Access denied: Panopto still requires sign-in after SSO")
+    } else {
+        Ok(response)
+    }
+}
+
+fn find_identity_provider(html: &str, base_url: &Url) -> Option<String> {
+    let document = Html::parse_document(html);
+    let option_selector = Selector::parse(r#"select[id$="providerDropdown"] option"#).unwrap();
+    let link_selector = Selector::parse(r#"a[href*="instance="]"#).unwrap();
+    document
+        .select(&option_selector)
+        .filter_map(|el| el.value().attr("value"))
+        .find(|value| !value.is_empty())
+        .map(|value| value.to_owned())
+        .or_else(|| {
+            document
+                .select(&link_selector)
+                .filter_map(|el| el.value().attr("href"))
+                .filter_map(|href| base_url.join(href).ok())
+                .find_map(|url| {
+                    url.query_pairs()
+                        .find(|(k, _)| k == "instance")
+                        .map(|(_, v)| v.into_owned())
+                })
+        })
+}
+
+/// Finds a form carrying a SAMLRequest or SAMLResponse, returning its action and all its inputs.
+fn find_saml_form(html: &str) -> Option<(String, Vec<(String, String)>)> {
+    let document = Html::parse_document(html);
+    let form_selector = Selector::parse(r#"form[method="post"]"#).unwrap();
+    let input_selector = Selector::parse("input[name]").unwrap();
+    document.select(&form_selector).find_map(|form| {
+        let fields: Vec<(String, String)> = form
+            .select(&input_selector)
+            .filter_map(|input| {
+                Some((
+                    input.value().attr("name")?.to_owned(),
+                    input.value().attr("value").unwrap_or("").to_owned(),
+                ))
+            })
+            .collect();
+        if !fields
+            .iter()
+            .any(|(name, _)| name == "SAMLRequest" || name == "SAMLResponse")
+        {
+            return None;
+        }
+        let action = htmlescape::decode_html(form.value().attr("action")?).ok()?;
+        Some((action, fields))
+    })
 }
 
 #[derive(Debug, Deserialize)]
@@ -48,10 +244,22 @@ struct DeliveryInfo {
     delivery: Delivery,
 }
 
+// E.g. {"ErrorCode":4,"ErrorMessage":"You are not authorized to view this session"}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DeliveryInfoError {
+    error_code: i32,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct Delivery {
     streams: Vec<Stream>,
+    // a single pre-rendered stream, which only exists if podcast downloads are enabled for the session
+    #[serde(default)]
+    podcast_streams: Vec<Stream>,
+    duration: Option<f64>,
+    session_name: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -61,22 +269,44 @@ struct Stream {
     stream_url: String,
 }
 
-pub async fn get_stream_specs(api: &Api, delivery_id: &str) -> Result<Vec<StreamSpec>> {
+/// Duration and approximate download size of a Panopto session
+#[derive(Debug, Clone, Copy)]
+pub struct SessionDetails {
+    pub duration: Duration,
+    pub estimated_size: Option<u64>,
+}
+
+async fn get_delivery(api: &Api, delivery_id: &str) -> Result<Delivery> {
     let post_data = make_delivery_info_post_data(delivery_id);
     let delivery_info = api
         .custom_request(
             Url::parse("https://mediaweb.ap.panopto.com/Panopto/Pages/Viewer/DeliveryInfo.aspx")
-                .expect("Unable to parse Panopto DeliverInfo URL"),
+                .map_err(|_| "Unable to parse Panopto DeliverInfo URL")?,
             Method::POST,
             Some(&post_data),
             Api::add_desktop_user_agent,
         )
         .await?
-        .json::<DeliveryInfo>()
+        .text()
         .await
-        .map_err(|_| "Unable to deserialize JSON")?;
+        .map_err(|_| "Unable to get text")?;
+    match serde_json::from_str::<DeliveryInfo>(&delivery_info) {
+        Ok(delivery_info) => Ok(delivery_info.delivery),
+        Err(_) => match serde_json::from_str::<DeliveryInfoError>(&delivery_info) {
+            Ok(DeliveryInfoError { error_code }) if error_code != 0 => {
+                Err("Access denied: Panopto refused to provide this session")
+            }
+            _ => Err("Unable to deserialize JSON"),
+        },
+    }
+}
 
-    let streams = delivery_info.delivery.streams;
+pub async fn get_stream_specs(api: &Api, delivery_id: &str) -> Result<Vec<StreamSpec>> {
+    let delivery = get_delivery(api, delivery_id).await?;
+    let streams = match delivery.podcast_streams.into_iter().next() {
+        Some(podcast) if api.panopto_podcast => vec![podcast],
+        _ => delivery.streams,
+    };
 
     if streams.is_empty() {
         Err("No streams available on DeliveryInfo")
@@ -91,6 +321,51 @@ pub async fn get_stream_specs(api: &Api, delivery_id: &str) -> Result<Vec<Stream
     }
 }
 
+pub async fn get_session_name(api: &Api, delivery_id: &str) -> Result<Option<String>> {
+    Ok(get_delivery(api, delivery_id).await?.session_name)
+}
+
+/// Gets the duration of a session, and estimates its size from the highest bandwidth
+/// advertised by each stream's master playlist (since that's the variant ffmpeg picks).
+/// This costs one extra request per stream.
+pub async fn get_session_details(api: &Api, delivery_id: &str) -> Result<SessionDetails> {
+    let delivery = get_delivery(api, delivery_id).await?;
+    let duration_seconds = delivery.duration.unwrap_or(0.0).max(0.0);
+
+    let bandwidths = future::join_all(
+        delivery
+            .streams
+            .iter()
+            .map(|s| get_max_bandwidth(api, &s.stream_url)),
+    )
+    .await;
+    let estimated_size = bandwidths
+        .into_iter()
+        .try_fold(0u64, |total, bandwidth| {
+            Some(total + (bandwidth? as f64 * duration_seconds / 8.0) as u64)
+        })
+        .filter(|_| !delivery.streams.is_empty());
+
+    Ok(SessionDetails {
+        duration: Duration::from_secs_f64(duration_seconds),
+        estimated_size,
+    })
+}
+
+// Returns the highest BANDWIDTH (in bits per second) among the variants of an m3u8 playlist
+async fn get_max_bandwidth(api: &Api, stream_url: &str) -> Option<u64> {
+    let url = Url::parse(stream_url).ok()?;
+    let playlist = api
+        .get_text(url, Method::GET, None, Api::add_desktop_user_agent)
+        .await
+        .ok()?;
+    let bandwidth_regex = regex::Regex::new(r"[:,]BANDWIDTH=(\d+)").expect("Unable to parse regex");
+    bandwidth_regex
+        .captures_iter(&playlist)
+        .filter_map(|c| c.get(1)?.as_str().parse::<u64>().ok())
+        .max()
+}
+
 /// Constructs the form params required for the post request to DeliveryInfo.aspx
 fn make_delivery_info_post_data(delivery_id: &str) -> HashMap<&str, &str> {
     // These params are used by Panopto's web frontend,
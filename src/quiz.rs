@@ -0,0 +1,258 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use futures_util::future;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWrite;
+
+use crate::resource;
+use crate::resource::{OverwriteMode, OverwriteResult, Resource, RetryableError};
+use crate::util::{html_to_text, parse_time, sanitise_filename};
+use crate::{Api, ApiData, Result};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiQuiz {
+    id: String,
+    title: String,
+    last_updated_date: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiQuestion {
+    id: String,
+    question: String,
+    options: Option<Vec<ApiOption>>,
+    mark: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiOption {
+    id: String,
+    text: String,
+    is_correct: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiAttempt {
+    submitted_date: Option<String>,
+    score: Option<f64>,
+    answers: Option<Vec<ApiAnswer>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiAnswer {
+    #[serde(rename = "questionID")]
+    question_id: String,
+    #[serde(rename = "optionIDs")]
+    option_ids: Option<Vec<String>>,
+    text: Option<String>,
+    score: Option<f64>,
+}
+
+// The exported format, which is meant to stay readable without LumiNUS around
+#[derive(Debug, Serialize)]
+struct QuizExport {
+    title: String,
+    questions: Vec<QuestionExport>,
+    attempts: Vec<AttemptExport>,
+}
+
+#[derive(Debug, Serialize)]
+struct QuestionExport {
+    id: String,
+    question: String,
+    options: Vec<OptionExport>,
+    mark: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct OptionExport {
+    id: String,
+    text: String,
+    is_correct: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+struct AttemptExport {
+    submitted_date: Option<String>,
+    score: Option<f64>,
+    answers: Vec<AnswerExport>,
+}
+
+#[derive(Debug, Serialize)]
+struct AnswerExport {
+    question_id: String,
+    selected_options: Vec<String>,
+    text: Option<String>,
+    score: Option<f64>,
+}
+
+pub struct QuizHandle {
+    id: String,
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct Quiz {
+    id: String,
+    title: String,
+    path: PathBuf,
+    last_updated: SystemTime,
+}
+
+impl QuizHandle {
+    pub fn new(id: String, path: PathBuf) -> QuizHandle {
+        QuizHandle { id, path }
+    }
+
+    pub async fn load(self, api: &Api) -> Result<Vec<Quiz>> {
+        let quizzes_resp = api
+            .api_as_json::<ApiData<Vec<ApiQuiz>>>(
+                &format!("quiz/?ParentID={}", self.id),
+                Method::GET,
+                None,
+            )
+            .await?;
+
+        match quizzes_resp.data {
+            Some(quizzes) => quizzes
+                .into_iter()
+                .map(|q| {
+                    Ok(Quiz {
+                        id: q.id,
+                        path: self
+                            .path
+                            .join(make_json_extension(Path::new(&sanitise_filename(&q.title)))),
+                        title: q.title,
+                        last_updated: parse_time(&q.last_updated_date)?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>(),
+            None => Ok(vec![]), // this module has no quizzes
+        }
+    }
+}
+
+fn make_json_extension(path: &Path) -> PathBuf {
+    path.with_extension("json")
+}
+
+impl Quiz {
+    async fn get_export(&self, api: &Api) -> Result<String> {
+        let (questions_resp, attempts_resp) = future::join(
+            api.api_as_json::<ApiData<Vec<ApiQuestion>>>(
+                &format!("quiz/{}/questions", self.id),
+                Method::GET,
+                None,
+            ),
+            api.api_as_json::<ApiData<Vec<ApiAttempt>>>(
+                &format!("quiz/{}/attempts/me?populate=answers", self.id),
+                Method::GET,
+                None,
+            ),
+        )
+        .await;
+        let questions = questions_resp?
+            .data
+            .ok_or("Invalid API response from server: type mismatch")?;
+        // there are no attempts if the quiz was never taken
+        let attempts = attempts_resp?.data.unwrap_or_default();
+
+        let export = QuizExport {
+            title: self.title.clone(),
+            questions: questions
+                .into_iter()
+                .map(|q| QuestionExport {
+                    id: q.id,
+                    question: html_to_text(&q.question),
+                    options: q
+                        .options
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|o| OptionExport {
+                            id: o.id,
+                            text: html_to_text(&o.text),
+                            is_correct: o.is_correct,
+                        })
+                        .collect(),
+                    mark: q.mark,
+                })
+                .collect(),
+            attempts: attempts
+                .into_iter()
+                .map(|a| AttemptExport {
+                    submitted_date: a.submitted_date,
+                    score: a.score,
+                    answers: a
+                        .answers
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|ans| AnswerExport {
+                            question_id: ans.question_id,
+                            selected_options: ans.option_ids.unwrap_or_default(),
+                            text: ans.text.as_deref().map(html_to_text),
+                            score: ans.score,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+        serde_json::to_string_pretty(&export).map_err(|_| "Unable to serialise quiz")
+    }
+}
+
+#[async_trait]
+impl Resource for Quiz {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+    fn path_mut(&mut self) -> &mut PathBuf {
+        &mut self.path
+    }
+
+    fn last_updated(&self) -> SystemTime {
+        self.last_updated
+    }
+
+    async fn download(
+        &self,
+        api: &Api,
+        destination: &Path,
+        temp_destination: &Path,
+        overwrite: OverwriteMode,
+    ) -> Result<OverwriteResult> {
+        resource::do_retryable_download(
+            api,
+            self,
+            destination,
+            temp_destination,
+            overwrite,
+            move |api| self.get_export(api),
+            move |_, export, temp_destination| async move {
+                tokio::fs::write(temp_destination, export)
+                    .await
+                    .map_err(|_| RetryableError::Fail("Failed writing to disk"))
+            },
+        )
+        .await
+    }
+
+    async fn download_to_writer(
+        &self,
+        api: &Api,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        resource::write_text(&self.get_export(api).await?, writer).await
+    }
+}
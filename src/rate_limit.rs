@@ -0,0 +1,65 @@
+// Spaces out the calls to the LumiNUS API, which answers too many at once with "TooManyRequests",
+// and holds all of them back for a while when it asks us to slow down
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::Instant;
+
+/// The number of API calls that may be made per second, unless set otherwise
+pub const DEFAULT_REQUESTS_PER_SECOND: u32 = 20;
+// Polling Zoom recordings is what the server throttles most readily
+const MAX_CONCURRENT_POLLS: usize = 4;
+
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    // none for no limit
+    interval: Option<Duration>,
+    // when the next call may be made
+    next: Mutex<Instant>,
+    polls: Semaphore,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(requests_per_second: Option<u32>) -> RateLimiter {
+        RateLimiter {
+            interval: requests_per_second
+                .filter(|&rate| rate > 0)
+                .map(|rate| Duration::from_secs(1) / rate),
+            next: Mutex::new(Instant::now()),
+            polls: Semaphore::new(MAX_CONCURRENT_POLLS),
+        }
+    }
+
+    /// Waits for the turn of a call, which is taken even if the call is given up on
+    pub(crate) async fn wait(&self) {
+        let turn = {
+            let mut next = self.next.lock().unwrap();
+            let turn = (*next).max(Instant::now());
+            *next = turn + self.interval.unwrap_or_default();
+            turn
+        };
+        tokio::time::sleep_until(turn).await;
+    }
+
+    /// Waits until fewer than a few polls (which may take several calls each) are running
+    pub(crate) async fn poll(&self) -> SemaphorePermit<'_> {
+        self.polls
+            .acquire()
+            .await
+            .expect("Semaphore is never closed")
+    }
+
+    /// Holds back every call for at least `delay`, e.g. as long as the server asked for
+    pub(crate) fn pause(&self, delay: Duration) {
+        let mut next = self.next.lock().unwrap();
+        *next = (*next).max(Instant::now() + delay);
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> RateLimiter {
+        RateLimiter::new(Some(DEFAULT_REQUESTS_PER_SECOND))
+    }
+}
@@ -1,21 +1,55 @@
 use std::ffi::{OsStr, OsString};
 use std::marker::Sync;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::time::SystemTime;
 
 use async_trait::async_trait;
 use futures_util::future::Future;
-use reqwest::{RequestBuilder, Url};
-use tokio::io::AsyncWriteExt;
+use md5::{Digest, Md5};
+use reqwest::header::{HeaderMap, IF_MODIFIED_SINCE};
+use reqwest::{RequestBuilder, StatusCode, Url};
+use serde::Deserialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::Instrument;
 
-use crate::{Api, Error, Result};
+use crate::{Api, Error, Result, CANCELLED};
+
+/// Returned by `download_chunks` when the server refuses a download URL, which happens once
+/// time-limited URLs expire. `do_retryable_download` resolves the URL again when it sees this.
+pub const DOWNLOAD_URL_EXPIRED: Error = "Download URL expired";
+/// The server refuses the download even with a freshly resolved URL, so the user lacks access
+pub const DOWNLOAD_REFUSED: Error = "Server keeps refusing the download URL";
+/// Returned by `download_chunks` when the server says that the file has not changed since the
+/// local copy was written. `do_retryable_download` then keeps the local copy.
+pub const NOT_MODIFIED: Error = "File has not been modified since it was downloaded";
+
+tokio::task_local! {
+    // When the local copy that a download is to replace was last modified, so that
+    // `download_chunks` can ask for the file only if it changed since
+    static LOCAL_COPY_MODIFIED: SystemTime;
+}
+
+// A URL that is refused even when fresh is not going to work
+const MAX_URL_REFRESHES: usize = 3;
+// A file that keeps arriving cut short or corrupted is not going to arrive whole
+const MAX_DAMAGED_DOWNLOADS: usize = 3;
+const DOWNLOAD_TRUNCATED: Error = "Download was truncated";
+const DOWNLOAD_CORRUPTED: Error = "Download does not match its checksum";
+const VERSIONS_DIR_NAME: &str = ".versions";
+// appended to the stem of old versions, which sorts them from the oldest
+const VERSION_TIME_FORMAT: &str = "%Y-%m-%d_%H%M%S";
+const VERSION_TIME_LENGTH: usize = 17;
 
 #[async_trait]
-pub trait Resource {
+pub trait Resource: Sync {
     fn id(&self) -> &str;
     fn path(&self) -> &Path;
     fn path_mut(&mut self) -> &mut PathBuf;
     fn last_updated(&self) -> SystemTime;
+    /// The size of the resource in bytes, if the listing tells it
+    fn size(&self) -> Option<u64> {
+        None
+    }
     async fn download(
         &self,
         api: &Api,
@@ -23,19 +57,78 @@ pub trait Resource {
         temp_destination: &Path,
         overwrite: OverwriteMode,
     ) -> Result<OverwriteResult>;
+    /// Writes the resource directly into `writer` (e.g. stdout) instead of a file.
+    /// Resources that can be streamed do so without temporary files, so unlike `download` this
+    /// cannot be retried. The rest are downloaded into the temporary directory first.
+    async fn download_to_writer(
+        &self,
+        api: &Api,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        let name = format!("fluminurs-{:016x}", rand::random::<u64>());
+        let destination = std::env::temp_dir().join(&name);
+        let temp_destination = std::env::temp_dir().join(format!("{}.part", name));
+        let result = async {
+            self.download(
+                api,
+                &destination,
+                &temp_destination,
+                OverwriteMode::Overwrite,
+            )
+            .await?;
+            let mut file = tokio::fs::File::open(&destination)
+                .await
+                .map_err(|_| "Unable to open downloaded file")?;
+            tokio::io::copy(&mut file, writer)
+                .await
+                .map_err(|_| "Failed writing to output")?;
+            writer.flush().await.map_err(|_| "Failed writing to output")
+        }
+        .await;
+        tokio::fs::remove_file(&destination).await.ok();
+        tokio::fs::remove_file(&temp_destination).await.ok();
+        result
+    }
+    /// Resolves the resource on the server without downloading it, to check local copies against.
+    /// Resources that are generated locally or streamed have nothing to check, and return `None`.
+    async fn remote_info(&self, _api: &Api) -> Result<Option<RemoteInfo>> {
+        Ok(None)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteInfo {
+    pub size: Option<u64>,
+    pub last_modified: Option<SystemTime>,
 }
 
 #[async_trait]
-pub trait SimpleDownloadableResource {
+pub trait SimpleDownloadableResource: Sync {
     fn id(&self) -> &str;
     fn path(&self) -> &Path;
     fn path_mut(&mut self) -> &mut PathBuf;
     fn last_updated(&self) -> SystemTime;
+    fn size(&self) -> Option<u64> {
+        None
+    }
     async fn get_download_url(&self, api: &Api) -> Result<Url>;
+
+    /// The download URL, going through the download proxy of the `Api` if it has one
+    async fn get_proxied_download_url(&self, api: &Api) -> Result<Url> {
+        let url = self.get_download_url(api).await?;
+        let last_updated = self
+            .last_updated()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        // a file that is updated gets a new key, so that the proxy fetches it again
+        let cache_key = format!("{}-{}", self.id(), last_updated);
+        Ok(api.proxied_download_url(url, &cache_key))
+    }
 }
 
 #[async_trait]
-impl<T: SimpleDownloadableResource + Sync> Resource for T {
+impl<T: SimpleDownloadableResource> Resource for T {
     fn id(&self) -> &str {
         self.id()
     }
@@ -51,6 +144,10 @@ impl<T: SimpleDownloadableResource + Sync> Resource for T {
         self.last_updated()
     }
 
+    fn size(&self) -> Option<u64> {
+        self.size()
+    }
+
     async fn download(
         &self,
         api: &Api,
@@ -60,17 +157,31 @@ impl<T: SimpleDownloadableResource + Sync> Resource for T {
     ) -> Result<OverwriteResult> {
         do_retryable_download(
             api,
+            self,
             destination,
             temp_destination,
             overwrite,
-            self.last_updated(),
-            move |api| self.get_download_url(api),
+            move |api| self.get_proxied_download_url(api),
             move |api, url, temp_destination| {
                 download_chunks(api, url, temp_destination, move |req| req)
             },
         )
         .await
     }
+
+    async fn download_to_writer(
+        &self,
+        api: &Api,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        let url = self.get_proxied_download_url(api).await?;
+        write_chunks(api, url, writer, move |req| req).await
+    }
+
+    async fn remote_info(&self, api: &Api) -> Result<Option<RemoteInfo>> {
+        let url = self.get_proxied_download_url(api).await?;
+        fetch_remote_info(api, url, move |req| req).await.map(Some)
+    }
 }
 
 // Makes the paths of all the given files unique, based on the last updated time and the id.
@@ -102,11 +213,15 @@ pub fn sort_and_make_all_paths_unique<T: Resource>(resources: &mut [T]) {
     });
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OverwriteMode {
     Skip,
     Overwrite,
     Rename,
+    /// Moves the old file into a `.versions` folder next to it, named after when it was last
+    /// modified, keeping at most this many old versions of each file
+    Versioned(usize),
 }
 
 #[derive(Clone)]
@@ -118,7 +233,7 @@ pub enum OverwriteResult {
     Renamed { renamed_path: PathBuf },
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 pub enum RetryableError {
     Retry(Error),
     Fail(Error),
@@ -126,22 +241,31 @@ pub enum RetryableError {
 
 pub type RetryableResult<T> = std::result::Result<T, RetryableError>;
 
+/// Downloads the resource to `destination`, which is where its path under the download folder
+/// is on disk. Files that it replaces keep that path in the trash.
 pub async fn do_retryable_download<
     'a,
-    F1: FnOnce(&'a Api) -> Fut1 + 'a,
+    R: Resource + ?Sized,
+    F1: Fn(&'a Api) -> Fut1 + 'a,
     Fut1: Future<Output = Result<C>>,
     F2: Fn(&'a Api, C, &'a Path) -> Fut2 + 'a,
     Fut2: Future<Output = RetryableResult<()>>,
     C: Clone,
 >(
     api: &'a Api,
+    resource: &R,
     destination: &Path,
     temp_destination: &'a Path,
     overwrite: OverwriteMode,
-    last_updated: SystemTime,
     before_download_file: F1,
     download_file: F2,
 ) -> Result<OverwriteResult> {
+    let path = resource.path();
+    let last_updated = resource.last_updated();
+    let local_copy_modified = tokio::fs::metadata(destination)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .ok();
     let (should_download, result) = prepare_path(destination, overwrite, last_updated).await?;
     if should_download {
         let before_download_data = before_download_file(api).await?;
@@ -150,14 +274,47 @@ pub async fn do_retryable_download<
                 .await
                 .map_err(|_| "Unable to create directory")?;
         };
-        infinite_retry_download(
-            api,
-            before_download_data,
-            destination,
-            temp_destination,
-            download_file,
-        )
-        .await?;
+        let download = api.cancellable(
+            infinite_retry_download(
+                api,
+                before_download_data,
+                path,
+                destination,
+                temp_destination,
+                before_download_file,
+                download_file,
+            )
+            .instrument(tracing::info_span!("download", path = %destination.display())),
+        );
+        let downloaded = match local_copy_modified {
+            Some(modified) => LOCAL_COPY_MODIFIED.scope(modified, download).await,
+            None => download.await,
+        };
+        match downloaded {
+            Err(CANCELLED) => {
+                // the download was dropped halfway, so nothing else cleans up after it
+                tokio::fs::remove_file(temp_destination).await.ok();
+                return Err(CANCELLED);
+            }
+            Err(NOT_MODIFIED) => {
+                // only the listing changed, so the local copy stays where it was
+                if let OverwriteResult::Renamed { renamed_path } = &result {
+                    tokio::fs::rename(renamed_path, destination)
+                        .await
+                        .map_err(|_| "Failed restoring unchanged file")?;
+                }
+                filetime::set_file_mtime(
+                    destination,
+                    filetime::FileTime::from_system_time(last_updated),
+                )
+                .map_err(|_| "Unable to set last modified time")?;
+                return Ok(OverwriteResult::AlreadyHave);
+            }
+            downloaded => downloaded?,
+        }
+        if let OverwriteMode::Versioned(keep) = overwrite {
+            prune_versions(api, path, destination, keep).await;
+        }
 
         // set the last modified time manually to the time we got from the server,
         // so that in case our local machine has unsynced time, or the file got updated while we are downloading it,
@@ -180,13 +337,37 @@ pub async fn download_chunks<F>(
 where
     F: (Fn(RequestBuilder) -> RequestBuilder),
 {
+    let _permit = api.scheduler.acquire(&download_url).await;
     let mut file = tokio::fs::File::create(temp_destination)
         .await
         .map_err(|_| RetryableError::Fail("Unable to open temporary file"))?;
-    let mut res = edit_request(api.get_client().get(download_url))
+    let mut request = edit_request(api.get_client().get(download_url));
+    if let Ok(modified) = LOCAL_COPY_MODIFIED.try_with(|modified| *modified) {
+        request = request.header(IF_MODIFIED_SINCE, http_date(modified));
+    }
+    let mut res = request
         .send()
         .await
         .map_err(|_| RetryableError::Retry("Failed during download"))?;
+    let status = res.status();
+    tracing::debug!(url = %res.url(), %status, "Download response");
+    if status == StatusCode::NOT_MODIFIED {
+        return Err(RetryableError::Fail(NOT_MODIFIED));
+    } else if status == StatusCode::FORBIDDEN {
+        return Err(RetryableError::Retry(DOWNLOAD_URL_EXPIRED));
+    } else if status.is_server_error() {
+        return Err(RetryableError::Retry("Server error during download"));
+    } else if !status.is_success() {
+        return Err(RetryableError::Fail("Server refused to provide the file"));
+    }
+    let expected_length = res.content_length();
+    // a checksum is of the whole file, so it does not apply to partial content
+    let expected_md5 = match status {
+        StatusCode::OK => content_md5(res.headers()),
+        _ => None,
+    };
+    let mut length = 0;
+    let mut hasher = Md5::new();
     while let Some(chunk) = res
         .chunk()
         .await
@@ -196,27 +377,151 @@ where
         file.write_all(chunk)
             .await
             .map_err(|_| RetryableError::Fail("Failed writing to disk"))?;
+        length += chunk.len() as u64;
+        if expected_md5.is_some() {
+            hasher.update(chunk);
+        }
+    }
+    file.flush()
+        .await
+        .map_err(|_| RetryableError::Fail("Failed writing to disk"))?;
+    // the connection may be dropped without an error, leaving the file cut short
+    if expected_length
+        .map(|expected| expected != length)
+        .unwrap_or(false)
+    {
+        return Err(RetryableError::Retry(DOWNLOAD_TRUNCATED));
+    }
+    if let Some(expected_md5) = expected_md5 {
+        if hasher.finalize().as_slice() != expected_md5.as_slice() {
+            return Err(RetryableError::Retry(DOWNLOAD_CORRUPTED));
+        }
     }
     Ok(())
 }
 
+// The date format of HTTP headers, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+fn http_date(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+// The MD5 checksum in `Content-MD5`, or in the header that Azure Blob Storage uses instead
+fn content_md5(headers: &HeaderMap) -> Option<Vec<u8>> {
+    ["content-md5", "x-ms-blob-content-md5"]
+        .iter()
+        .filter_map(|name| headers.get(*name)?.to_str().ok())
+        .find_map(|value| base64::decode(value.trim()).ok())
+        .filter(|md5| md5.len() == 16)
+}
+
+pub async fn write_chunks<F>(
+    api: &Api,
+    download_url: reqwest::Url,
+    writer: &mut (dyn AsyncWrite + Send + Unpin),
+    edit_request: F,
+) -> Result<()>
+where
+    F: (Fn(RequestBuilder) -> RequestBuilder),
+{
+    api.cancellable(async {
+        let _permit = api.scheduler.acquire(&download_url).await;
+        let mut res = edit_request(api.get_client().get(download_url))
+            .send()
+            .await
+            .map_err(|_| "Failed during download")?;
+        // error pages are not the file, and must not be written out as if they were
+        let status = res.status();
+        tracing::debug!(url = %res.url(), %status, "Download response");
+        if status == StatusCode::FORBIDDEN {
+            return Err(DOWNLOAD_URL_EXPIRED);
+        } else if status.is_server_error() {
+            return Err("Server error during download");
+        } else if !status.is_success() {
+            return Err("Server refused to provide the file");
+        }
+        while let Some(chunk) = res
+            .chunk()
+            .await
+            .map_err(|_| "Failed during streaming")?
+            .as_deref()
+        {
+            writer
+                .write_all(chunk)
+                .await
+                .map_err(|_| "Failed writing to output")?;
+        }
+        writer.flush().await.map_err(|_| "Failed writing to output")
+    })
+    .await
+}
+
+/// Asks the server about the file behind `download_url`, fetching at most one byte of it.
+pub async fn fetch_remote_info<F>(
+    api: &Api,
+    download_url: reqwest::Url,
+    edit_request: F,
+) -> Result<RemoteInfo>
+where
+    F: (Fn(RequestBuilder) -> RequestBuilder),
+{
+    let res = edit_request(api.get_client().get(download_url))
+        .header(reqwest::header::RANGE, "bytes=0-0")
+        .send()
+        .await
+        .map_err(|_| "Failed to reach server")?;
+    if !res.status().is_success() {
+        return Err("Server refused to provide the file");
+    }
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    // servers that ignore the range send the whole file, with its length
+    let size = match res.status() {
+        reqwest::StatusCode::PARTIAL_CONTENT => header(reqwest::header::CONTENT_RANGE)
+            .and_then(|range| range.rsplit('/').next())
+            .and_then(|total| total.parse::<u64>().ok()),
+        _ => res.content_length(),
+    };
+    let last_modified = header(reqwest::header::LAST_MODIFIED)
+        .and_then(|date| chrono::DateTime::parse_from_rfc2822(date).ok())
+        .map(SystemTime::from);
+    Ok(RemoteInfo {
+        size,
+        last_modified,
+    })
+}
+
+/// Writes an in-memory resource (e.g. generated Markdown) into `writer`.
+pub async fn write_text(text: &str, writer: &mut (dyn AsyncWrite + Send + Unpin)) -> Result<()> {
+    writer
+        .write_all(text.as_bytes())
+        .await
+        .map_err(|_| "Failed writing to output")?;
+    writer.flush().await.map_err(|_| "Failed writing to output")
+}
+
 async fn prepare_path(
     path: &Path,
     overwrite: OverwriteMode,
     last_updated: SystemTime,
 ) -> Result<(bool, OverwriteResult)> {
-    let metadata = tokio::fs::metadata(path).await;
-    if let Err(e) = metadata {
-        return match e.kind() {
-            std::io::ErrorKind::NotFound => Ok((true, OverwriteResult::NewFile)), // do download, because file does not already exist
-            std::io::ErrorKind::PermissionDenied => {
-                Err("Permission denied when retrieving file metadata")
-            }
-            _ => Err("Unable to retrieve file metadata"),
-        };
-    }
+    let metadata = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            return match e.kind() {
+                std::io::ErrorKind::NotFound => Ok((true, OverwriteResult::NewFile)), // do download, because file does not already exist
+                std::io::ErrorKind::PermissionDenied => {
+                    Err("Permission denied when retrieving file metadata")
+                }
+                _ => Err("Unable to retrieve file metadata"),
+            };
+        }
+    };
     let old_time = metadata
-        .unwrap()
         .modified()
         .map_err(|_| "File system does not support last modified time")?;
     if last_updated <= old_time {
@@ -228,7 +533,7 @@ async fn prepare_path(
             OverwriteMode::Rename => {
                 let (path_stem, path_extension) =
                     split_file_name_into_step_and_extension_properly(path.file_name());
-                let mut new_stem = path_stem.expect("File does not have name");
+                let mut new_stem = path_stem.ok_or("File does not have name")?;
                 let date = chrono::DateTime::<chrono::Local>::from(old_time).date();
                 use chrono::Datelike;
                 new_stem.push(format!(
@@ -258,25 +563,162 @@ async fn prepare_path(
                     .map_err(|_| "Failed renaming existing file")?;
                 Ok((true, OverwriteResult::Renamed { renamed_path })) // do download, because we renamed the old file
             }
+            OverwriteMode::Versioned(_) => {
+                let (path_stem, path_extension) =
+                    split_file_name_into_step_and_extension_properly(path.file_name());
+                let mut new_stem = path_stem.ok_or("File does not have name")?;
+                new_stem.push(
+                    chrono::DateTime::<chrono::Local>::from(old_time)
+                        .format(&format!("_{}", VERSION_TIME_FORMAT))
+                        .to_string(),
+                );
+                let versions_dir = path.with_file_name(VERSIONS_DIR_NAME);
+                tokio::fs::create_dir_all(&versions_dir)
+                    .await
+                    .map_err(|_| "Unable to create versions directory")?;
+                let mut i = 0;
+                let mut suffixed_stem = new_stem.clone();
+                let renamed_path = loop {
+                    let mut name = suffixed_stem.clone();
+                    if let Some(ext) = &path_extension {
+                        name.push(".");
+                        name.push(ext);
+                    }
+                    let renamed_path = versions_dir.join(name);
+                    if !renamed_path.exists() {
+                        break renamed_path;
+                    }
+                    i += 1;
+                    suffixed_stem = new_stem.clone();
+                    suffixed_stem.push(format!("_{}", i));
+                };
+                tokio::fs::rename(path, renamed_path.clone())
+                    .await
+                    .map_err(|_| "Failed moving existing file into versions")?;
+                Ok((true, OverwriteResult::Renamed { renamed_path })) // do download, because we moved the old file away
+            }
         }
     }
 }
 
+// Removes the oldest versions of the file beyond the `keep` latest ones, which is done only once
+// the new version is downloaded, as the old one is put back if it turns out to be unchanged
+async fn prune_versions(api: &Api, path: &Path, destination: &Path, keep: usize) {
+    let (path_stem, path_extension) =
+        split_file_name_into_step_and_extension_properly(destination.file_name());
+    let stem = match path_stem {
+        Some(stem) => format!("{}_", stem.to_string_lossy()),
+        None => return,
+    };
+    let extension = path_extension.map(|ext| format!(".{}", ext.to_string_lossy()));
+    let mut entries = match tokio::fs::read_dir(destination.with_file_name(VERSIONS_DIR_NAME)).await
+    {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut versions = vec![];
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let rest = match (name.strip_prefix(&stem), &extension) {
+            (Some(rest), Some(extension)) => rest.strip_suffix(extension.as_str()),
+            (Some(rest), None) => Some(rest).filter(|rest| !rest.contains('.')),
+            (None, _) => None,
+        };
+        // only versions of this file, and not of another one whose name starts the same way
+        let is_version = rest.is_some_and(|rest| {
+            rest.get(..VERSION_TIME_LENGTH).is_some_and(|time| {
+                chrono::NaiveDateTime::parse_from_str(time, VERSION_TIME_FORMAT).is_ok()
+            })
+        });
+        if is_version {
+            versions.push(entry.path());
+        }
+    }
+    versions.sort();
+    let excess = versions.len().saturating_sub(keep);
+    for version in versions.into_iter().take(excess) {
+        let removed = match &api.trash_dir {
+            Some(trash_dir) => {
+                let version_path = path
+                    .with_file_name(VERSIONS_DIR_NAME)
+                    .join(version.file_name().unwrap_or_default());
+                move_to_trash(trash_dir, &version_path, &version).await
+            }
+            None => tokio::fs::remove_file(&version)
+                .await
+                .map_err(|_| "Unable to remove old version"),
+        };
+        if let Err(e) = removed {
+            tracing::warn!(path = %version.display(), error = e, "Unable to prune old version");
+        }
+    }
+}
+
+// Moves the file at `destination` (if there is one) into the folder of the day in the trash,
+// where it keeps its `path` under the download folder, e.g. `CS1010/Lectures/L1.pdf` goes to
+// `<trash>/2023-02-14/CS1010/Lectures/L1.pdf`
+async fn move_to_trash(trash_dir: &Path, path: &Path, destination: &Path) -> Result<()> {
+    if tokio::fs::metadata(destination).await.is_err() {
+        return Ok(());
+    }
+    // the path of a resource has no root, but nothing may escape the trash all the same
+    let relative_path = path
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect::<PathBuf>();
+    let trash_path = trash_dir
+        .join(chrono::Local::now().format("%Y-%m-%d").to_string())
+        .join(relative_path);
+    if let Some(parent) = trash_path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|_| "Unable to create trash directory")?;
+    }
+    // a file that was replaced more than once in a day keeps every copy
+    let mut i = 0;
+    let mut unique_path = trash_path.clone();
+    while tokio::fs::metadata(&unique_path).await.is_ok() {
+        i += 1;
+        let mut name = trash_path.file_name().unwrap_or_default().to_owned();
+        name.push(format!(".{}", i));
+        unique_path = trash_path.with_file_name(name);
+    }
+    if tokio::fs::rename(destination, &unique_path).await.is_err() {
+        // the trash may be on another drive, which a file cannot be renamed onto
+        tokio::fs::copy(destination, &unique_path)
+            .await
+            .map_err(|_| "Unable to move replaced file into the trash")?;
+        tokio::fs::remove_file(destination)
+            .await
+            .map_err(|_| "Unable to remove replaced file")?;
+    }
+    Ok(())
+}
+
 async fn infinite_retry_download<
     'a,
-    F: Fn(&'a Api, C, &'a Path) -> Fut + 'a,
-    Fut: Future<Output = RetryableResult<()>>,
+    F1: Fn(&'a Api) -> Fut1 + 'a,
+    Fut1: Future<Output = Result<C>>,
+    F2: Fn(&'a Api, C, &'a Path) -> Fut2 + 'a,
+    Fut2: Future<Output = RetryableResult<()>>,
     C: Clone,
 >(
     api: &'a Api,
-    before_download_data: C,
+    mut before_download_data: C,
+    path: &Path,
     destination: &Path,
     temp_destination: &'a Path,
-    download_file: F,
+    before_download_file: F1,
+    download_file: F2,
 ) -> Result<()> {
+    let mut url_refreshes = 0;
+    let mut damaged_downloads = 0;
     loop {
         match download_file(api, before_download_data.clone(), temp_destination).await {
             Ok(_) => {
+                if let Some(trash_dir) = &api.trash_dir {
+                    move_to_trash(trash_dir, path, destination).await?;
+                }
                 tokio::fs::rename(temp_destination, destination)
                     .await
                     .map_err(|_| "Unable to move temporary file")?;
@@ -284,7 +726,28 @@ async fn infinite_retry_download<
             }
             Err(err) => {
                 let success = tokio::fs::remove_file(temp_destination).await.is_ok();
+                tracing::debug!(path = %destination.display(), error = ?err, "Download attempt failed");
                 match err {
+                    RetryableError::Retry(DOWNLOAD_URL_EXPIRED) => {
+                        if !success {
+                            return Err("Unable to delete temporary file");
+                        }
+                        if url_refreshes == MAX_URL_REFRESHES {
+                            return Err(DOWNLOAD_REFUSED);
+                        }
+                        url_refreshes += 1;
+                        // the URL was resolved too long ago, so we get a fresh one
+                        before_download_data = before_download_file(api).await?;
+                    }
+                    RetryableError::Retry(err @ (DOWNLOAD_TRUNCATED | DOWNLOAD_CORRUPTED)) => {
+                        if !success {
+                            return Err("Unable to delete temporary file");
+                        }
+                        if damaged_downloads == MAX_DAMAGED_DOWNLOADS {
+                            return Err(err);
+                        }
+                        damaged_downloads += 1;
+                    }
                     RetryableError::Retry(_) => {
                         if !success {
                             return Err("Unable to delete temporary file");
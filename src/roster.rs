@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use futures_util::future;
+use reqwest::Method;
+use serde::Deserialize;
+use tokio::io::AsyncWrite;
+
+use crate::resource;
+use crate::resource::{OverwriteMode, OverwriteResult, Resource, RetryableError};
+use crate::util::{csv_record, parse_time};
+use crate::{teaching_access_error, Api, ApiData, Result};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiStudent {
+    #[serde(rename = "userID")]
+    user_id: String,
+    name: String,
+    email: Option<String>,
+    last_updated_date: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiGroup {
+    id: String,
+    name: String,
+    last_updated_date: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiGroupMember {
+    #[serde(rename = "userID")]
+    user_id: String,
+}
+
+pub struct RosterHandle {
+    id: String,
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct Roster {
+    id: String,
+    path: PathBuf,
+    students: Vec<ApiStudent>,
+    groups: Vec<ApiGroup>,
+    last_updated: SystemTime,
+}
+
+impl RosterHandle {
+    pub fn new(id: String, path: PathBuf) -> RosterHandle {
+        RosterHandle { id, path }
+    }
+
+    // Loads the students and groups of the module. The members of the groups are only fetched
+    // when downloading, as that takes a request per group.
+    pub async fn load(self, api: &Api) -> Result<Vec<Roster>> {
+        let (students_resp, groups_resp) = future::join(
+            api.api_as_json::<ApiData<Vec<ApiStudent>>>(
+                &format!("classroster/?ParentID={}", self.id),
+                Method::GET,
+                None,
+            ),
+            api.api_as_json::<ApiData<Vec<ApiGroup>>>(
+                &format!("group/?ParentID={}", self.id),
+                Method::GET,
+                None,
+            ),
+        )
+        .await;
+        let students = students_resp
+            .map_err(teaching_access_error)?
+            .data
+            .ok_or("Invalid API response from server: type mismatch")?;
+        // modules without groups are common
+        let groups = groups_resp
+            .map_err(teaching_access_error)?
+            .data
+            .unwrap_or_default();
+
+        // the roster changes when a student enrols or drops, or a group is changed
+        let last_updated = students
+            .iter()
+            .filter_map(|student| student.last_updated_date.as_deref())
+            .chain(
+                groups
+                    .iter()
+                    .filter_map(|group| group.last_updated_date.as_deref()),
+            )
+            .map(parse_time)
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .max()
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+
+        Ok(vec![Roster {
+            id: self.id,
+            path: self.path.join("Roster.csv"),
+            students,
+            groups,
+            last_updated,
+        }])
+    }
+}
+
+impl Roster {
+    async fn get_csv(&self, api: &Api) -> Result<String> {
+        let groups = &self.groups;
+        let members = future::join_all(groups.iter().map(|group| async move {
+            api.api_as_json::<ApiData<Vec<ApiGroupMember>>>(
+                &format!("group/{}/member", group.id),
+                Method::GET,
+                None,
+            )
+            .await
+        }))
+        .await;
+
+        let mut student_groups: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        for (group, members) in groups.iter().zip(members) {
+            for member in members
+                .map_err(teaching_access_error)?
+                .data
+                .unwrap_or_default()
+            {
+                student_groups
+                    .entry(member.user_id)
+                    .or_default()
+                    .push(&group.name);
+            }
+        }
+
+        let mut csv = csv_record(&["Student ID", "Name", "Email", "Groups"]);
+        for student in &self.students {
+            let groups = student_groups
+                .get(&student.user_id)
+                .map(|groups| groups.join("; "))
+                .unwrap_or_default();
+            csv.push_str(&csv_record(&[
+                student.user_id.clone(),
+                student.name.clone(),
+                student.email.clone().unwrap_or_default(),
+                groups,
+            ]));
+        }
+        Ok(csv)
+    }
+}
+
+#[async_trait]
+impl Resource for Roster {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+    fn path_mut(&mut self) -> &mut PathBuf {
+        &mut self.path
+    }
+
+    fn last_updated(&self) -> SystemTime {
+        self.last_updated
+    }
+
+    async fn download(
+        &self,
+        api: &Api,
+        destination: &Path,
+        temp_destination: &Path,
+        overwrite: OverwriteMode,
+    ) -> Result<OverwriteResult> {
+        resource::do_retryable_download(
+            api,
+            self,
+            destination,
+            temp_destination,
+            overwrite,
+            move |api| self.get_csv(api),
+            move |_, csv, temp_destination| async move {
+                tokio::fs::write(temp_destination, csv)
+                    .await
+                    .map_err(|_| RetryableError::Fail("Failed writing to disk"))
+            },
+        )
+        .await
+    }
+
+    async fn download_to_writer(
+        &self,
+        api: &Api,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        resource::write_text(&self.get_csv(api).await?, writer).await
+    }
+}
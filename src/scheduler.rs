@@ -0,0 +1,80 @@
+// Limits on simultaneous downloads, shared by everything that downloads through clones of an `Api`,
+// so that downloading several kinds of resources at once stays within what each service tolerates
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use reqwest::Url;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// The number of downloads that may run at once, unless set otherwise
+pub const DEFAULT_MAX_CONNECTIONS: usize = 32;
+/// The number of downloads from one service that may run at once, unless set otherwise
+pub const DEFAULT_MAX_CONNECTIONS_PER_HOST: usize = 16;
+
+#[derive(Debug)]
+pub(crate) struct Scheduler {
+    global: Arc<Semaphore>,
+    per_host: usize,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+/// Held for as long as the download runs
+pub(crate) struct Permit {
+    _host: OwnedSemaphorePermit,
+    _global: OwnedSemaphorePermit,
+}
+
+// Panopto and Zoom serve from many hosts (e.g. CDNs), which are limited together
+fn host_group(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    if host.contains("panopto") {
+        "panopto".to_owned()
+    } else if host.ends_with("zoom.us") {
+        "zoom".to_owned()
+    } else {
+        host.to_owned()
+    }
+}
+
+impl Scheduler {
+    pub(crate) fn new(max_connections: usize, max_connections_per_host: usize) -> Scheduler {
+        Scheduler {
+            global: Arc::new(Semaphore::new(max_connections.max(1))),
+            per_host: max_connections_per_host.max(1),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits until a download from the host of this URL may start
+    pub(crate) async fn acquire(&self, url: &Url) -> Permit {
+        let host = self
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(host_group(url))
+            .or_insert_with(|| Arc::new(Semaphore::new(self.per_host)))
+            .clone();
+        // always the host first, so that nothing holds a global permit while waiting for its host
+        let host = host
+            .acquire_owned()
+            .await
+            .expect("Semaphore is never closed");
+        let global = self
+            .global
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("Semaphore is never closed");
+        Permit {
+            _host: host,
+            _global: global,
+        }
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        Scheduler::new(DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_CONNECTIONS_PER_HOST)
+    }
+}
@@ -1,25 +1,247 @@
 use crate::resource::{RetryableError, RetryableResult};
-use crate::Api;
+use crate::scheduler::Permit;
+use crate::{Api, Error, Result};
 use std::ffi::{OsStr, OsString};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::time::SystemTime;
+
+use reqwest::Url;
+use tokio::io::AsyncWrite;
 use tokio::process::Command;
 
+use crate::hls;
+
+/// Returned for videos that cannot be downloaded without ffmpeg, e.g. those that need muxing
+/// or are encrypted
+pub const FFMPEG_REQUIRED: Error =
+    "This video needs ffmpeg, which could not be started (install it, or point --ffmpeg to it)";
+
+// Checked once per `Api`, so that a missing ffmpeg is not tried again for every video
+async fn ffmpeg_available(api: &Api) -> bool {
+    *api.ffmpeg_available
+        .get_or_init(|| async {
+            let available = Command::new(&api.ffmpeg_path)
+                .arg("-version")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .await
+                .is_ok();
+            if !available {
+                tracing::warn!(
+                    "Unable to start ffmpeg at {:?}, falling back to downloading streams directly",
+                    api.ffmpeg_path
+                );
+            }
+            available
+        })
+        .await
+}
+
+/// The container that downloaded videos are written in.
+/// Some streams carry codecs that cannot be remuxed into mp4 cleanly, but always fit in mkv.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoContainer {
+    Mp4,
+    Mkv,
+}
+
+impl VideoContainer {
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoContainer::Mp4 => "mp4",
+            VideoContainer::Mkv => "mkv",
+        }
+    }
+
+    fn ffmpeg_format(self) -> &'static str {
+        match self {
+            VideoContainer::Mp4 => "mp4",
+            VideoContainer::Mkv => "matroska",
+        }
+    }
+}
+
+/// Which variant of a stream is downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoQuality {
+    Best,
+    Worst,
+    /// The best variant that is at most this many pixels high
+    Height(u32),
+    Audio,
+}
+
+// ffmpeg picks the best variant by itself, so only the other qualities need to look at the playlist
+async fn select_stream(api: &Api, stream_url_path: &str) -> RetryableResult<String> {
+    match api.video_quality {
+        VideoQuality::Best => Ok(stream_url_path.to_owned()),
+        quality => hls::resolve_variant(api, stream_url_path, quality).await,
+    }
+}
+
+// TODO: check file extension?
+pub(crate) fn make_video_extension(api: &Api, path: &Path) -> PathBuf {
+    path.with_extension(api.video_container.extension())
+}
+
+/// Metadata that is embedded into downloaded videos, so that media libraries can display them sensibly
+#[derive(Debug, Clone, Default)]
+pub struct VideoMetadata {
+    pub title: Option<String>,
+    pub module: Option<String>,
+    pub date: Option<SystemTime>,
+}
+
+impl VideoMetadata {
+    /// Derives the title and module from the path of a resource, which is
+    /// `<module code>/.../<title>.<extension>`.
+    pub fn from_path(path: &Path, date: Option<SystemTime>) -> VideoMetadata {
+        VideoMetadata {
+            title: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned()),
+            module: match path.components().next() {
+                Some(Component::Normal(code)) if path.components().count() > 1 => {
+                    Some(code.to_string_lossy().into_owned())
+                }
+                _ => None,
+            },
+            // resources without a known date are dated at the epoch
+            date: date.filter(|date| *date != SystemTime::UNIX_EPOCH),
+        }
+    }
+
+    fn append_args<'a>(&self, cmd: &'a mut Command) -> &'a mut Command {
+        if let Some(title) = &self.title {
+            cmd.arg("-metadata").arg(format!("title={}", title));
+        }
+        if let Some(module) = &self.module {
+            cmd.arg("-metadata").arg(format!("album={}", module));
+        }
+        if let Some(date) = self.date {
+            cmd.arg("-metadata").arg(format!(
+                "date={}",
+                chrono::DateTime::<chrono::Local>::from(date).format("%Y-%m-%d")
+            ));
+        }
+        cmd
+    }
+}
+
 /// Uses ffmpeg to stream a given m3u8 video file.
-/// If there are multiple streams, ffmpeg automatically chooses the one with highest quality,
-/// which is what we want.
+/// If there are multiple variants, the one matching the configured quality is chosen.
+/// Without ffmpeg, or to save memory, the stream is downloaded as is, without metadata.
 pub async fn stream_video(
     api: &Api,
     stream_url_path: &str,
     temp_destination: &Path,
+    metadata: &VideoMetadata,
+) -> RetryableResult<()> {
+    let _permit = acquire_stream_permit(api, stream_url_path).await?;
+    stream_video_unscheduled(api, stream_url_path, temp_destination, metadata).await
+}
+
+async fn acquire_stream_permit(api: &Api, stream_url_path: &str) -> RetryableResult<Permit> {
+    let url = Url::parse(stream_url_path)
+        .map_err(|_| RetryableError::Fail("Unable to parse stream URL"))?;
+    Ok(api.scheduler.acquire(&url).await)
+}
+
+// `stream_video` without waiting for its turn, for when the caller already has
+async fn stream_video_unscheduled(
+    api: &Api,
+    stream_url_path: &str,
+    temp_destination: &Path,
+    metadata: &VideoMetadata,
 ) -> RetryableResult<()> {
+    if api.low_memory || !ffmpeg_available(api).await {
+        let result =
+            hls::download_stream(api, stream_url_path, temp_destination, api.video_quality).await;
+        // streams that the native downloader cannot handle are left to ffmpeg, if there is one
+        match result {
+            Err(RetryableError::Fail(e))
+                if hls::needs_ffmpeg(e) && !ffmpeg_available(api).await =>
+            {
+                return Err(RetryableError::Fail(FFMPEG_REQUIRED));
+            }
+            Err(RetryableError::Fail(_)) if api.low_memory && ffmpeg_available(api).await => {}
+            result => return result,
+        }
+    }
+    let stream_url_path = &select_stream(api, stream_url_path).await?;
     stream_impl(
         api,
         move |cmd| cmd.arg("-i").arg(stream_url_path),
         temp_destination,
+        metadata,
     )
     .await
 }
 
+/// Uses ffmpeg to stream a given m3u8 video file into `writer` as MPEG-TS,
+/// which (unlike mp4) can be written out progressively, e.g. when piping to a player.
+pub async fn stream_video_to_writer(
+    api: &Api,
+    stream_url_path: &str,
+    writer: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<()> {
+    let flatten = |e| match e {
+        RetryableError::Retry(e) | RetryableError::Fail(e) => e,
+    };
+    api.cancellable(async {
+        let _permit = acquire_stream_permit(api, stream_url_path)
+            .await
+            .map_err(flatten)?;
+        let stream_url_path = select_stream(api, stream_url_path).await.map_err(flatten)?;
+        let mut child = Command::new(&api.ffmpeg_path)
+            .arg("-i")
+            .arg(stream_url_path)
+            .arg("-c")
+            .arg("copy")
+            .arg("-f")
+            .arg("mpegts")
+            .arg("pipe:1")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|_| FFMPEG_REQUIRED)?;
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or("Unable to capture ffmpeg output")?;
+        tokio::io::copy(&mut stdout, writer)
+            .await
+            .map_err(|_| "Failed writing to output")?;
+        let success = child
+            .wait()
+            .await
+            .map_err(|_| "Failed waiting for ffmpeg")?
+            .success();
+        if success {
+            Ok(())
+        } else {
+            Err("ffmpeg returned nonzero exit code")
+        }
+    })
+    .await
+}
+
+/// Like `stream_video_to_writer`, but for sessions made up of multiple streams.
+/// Muxing needs seekable temporary files, so only the primary (first) stream is written.
+pub async fn stream_primary_video_to_writer(
+    api: &Api,
+    streams: &[StreamSpec],
+    writer: &mut (dyn AsyncWrite + Send + Unpin),
+) -> Result<()> {
+    let primary = streams.first().ok_or("No streams available")?;
+    stream_video_to_writer(api, &primary.stream_url_path, writer).await
+}
+
 #[derive(Debug, Clone)]
 pub struct StreamSpec {
     pub stream_url_path: String,
@@ -27,35 +249,42 @@ pub struct StreamSpec {
 }
 
 /// Uses ffmpeg to stream multiple m3u8 video files and mux them together.
-/// If there are multiple streams, ffmpeg automatically chooses the one with highest quality,
-/// which is what we want.
+/// Each of them is streamed in the configured quality.
 pub async fn stream_and_mux_videos(
     api: &Api,
     streams: &[StreamSpec],
     temp_destination: &Path,
+    metadata: &VideoMetadata,
 ) -> RetryableResult<()> {
-    assert!(!streams.is_empty());
-    if streams.len() == 1 {
+    if streams.is_empty() {
+        Err(RetryableError::Fail("No streams to download"))
+    } else if streams.len() == 1 {
         // if there's only one video, we should ignore the offset
-        stream_video(api, &streams[0].stream_url_path, temp_destination).await
+        stream_video(api, &streams[0].stream_url_path, temp_destination, metadata).await
+    } else if !ffmpeg_available(api).await {
+        Err(RetryableError::Fail(FFMPEG_REQUIRED))
     } else {
         // we have multiple videos, we have to stream each of them to separate temporary files, then mux them together
         // the reason why we need temp files is here:
         // https://stackoverflow.com/questions/68890149/download-multiple-files-with-ffmpeg-keep-one-stream-from-each-according-to-def
 
         // generate the temp file names
-        let temp_stream_dests: Vec<PathBuf> = (0..streams.len())
+        let temp_stream_dests = (0..streams.len())
             .map(|i| make_temp_stream_file_name(temp_destination, i))
-            .collect();
-
-        // stream the streams to the temp files
-        let stream_results: Vec<RetryableResult<()>> = futures_util::future::join_all(
-            streams
-                .iter()
-                .zip(temp_stream_dests.iter())
-                .map(|(s, dest)| stream_video(api, &s.stream_url_path, dest)),
-        )
-        .await;
+            .collect::<Result<Vec<PathBuf>>>()
+            .map_err(RetryableError::Fail)?;
+
+        // the streams of a session count as one download, as waiting for a turn for each of them
+        // could leave several sessions holding some of the turns, and waiting for the rest
+        let _permit = acquire_stream_permit(api, &streams[0].stream_url_path).await?;
+
+        // stream the streams to the temp files, leaving the metadata for the muxed video
+        let no_metadata = VideoMetadata::default();
+        let stream_results: Vec<RetryableResult<()>> =
+            futures_util::future::join_all(streams.iter().zip(temp_stream_dests.iter()).map(
+                |(s, dest)| stream_video_unscheduled(api, &s.stream_url_path, dest, &no_metadata),
+            ))
+            .await;
         // throw RetryableError::Fail if any
         stream_results
             .iter()
@@ -66,7 +295,7 @@ pub async fn stream_and_mux_videos(
         stream_results
             .iter()
             .copied()
-            .find(|sr| matches!(sr, Err(_)))
+            .find(|sr| sr.is_err())
             .transpose()?;
 
         // now we know that all downloads succeeded
@@ -88,6 +317,7 @@ pub async fn stream_and_mux_videos(
                 (0..streams.len()).fold(cmd, move |cmd, i| cmd.arg("-map").arg(i.to_string()))
             },
             temp_destination,
+            metadata,
         )
         .await?;
 
@@ -99,8 +329,24 @@ pub async fn stream_and_mux_videos(
     }
 }
 
-fn make_temp_stream_file_name(name: &Path, index: usize) -> PathBuf {
-    let old_filename = name.file_name().expect("Path needs file name");
+/// Uses ffmpeg to remux a downloaded video into the configured container.
+pub async fn remux_video(
+    api: &Api,
+    source: &Path,
+    temp_destination: &Path,
+    metadata: &VideoMetadata,
+) -> RetryableResult<()> {
+    stream_impl(
+        api,
+        move |cmd| cmd.arg("-i").arg(source),
+        temp_destination,
+        metadata,
+    )
+    .await
+}
+
+fn make_temp_stream_file_name(name: &Path, index: usize) -> Result<PathBuf> {
+    let old_filename = name.file_name().ok_or("Path needs file name")?;
     let prepend = OsStr::new("~!");
     let index_string = index.to_string();
     let after_prepend = prepend;
@@ -114,25 +360,31 @@ fn make_temp_stream_file_name(name: &Path, index: usize) -> PathBuf {
     new_filename.push(old_filename);
     let mut res = name.to_path_buf();
     res.set_file_name(new_filename);
-    res
+    Ok(res)
 }
 
 async fn stream_impl(
     api: &Api,
     input_args_appender: impl FnOnce(&mut Command) -> &mut Command,
     temp_destination: &Path,
+    metadata: &VideoMetadata,
 ) -> RetryableResult<()> {
-    let success = input_args_appender(
-        Command::new(&api.ffmpeg_path).arg("-y"), // flag to overwrite output file without prompting
-    )
-    .arg("-c")
-    .arg("copy")
-    .arg(temp_destination.as_os_str())
-    .output()
-    .await
-    .map_err(|_| RetryableError::Fail("Failed to start ffmpeg"))?
-    .status
-    .success();
+    let success = metadata
+        .append_args(input_args_appender(
+            Command::new(&api.ffmpeg_path).arg("-y"), // flag to overwrite output file without prompting
+        ))
+        .arg("-c")
+        .arg("copy")
+        .arg("-f")
+        .arg(api.video_container.ffmpeg_format())
+        .arg(temp_destination.as_os_str())
+        // so that a download that is given up on does not leave ffmpeg running
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|_| RetryableError::Fail(FFMPEG_REQUIRED))?
+        .status
+        .success();
     if success {
         Ok(())
     } else {
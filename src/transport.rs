@@ -0,0 +1,124 @@
+//! Sending the requests that an `Api` makes to LumiNUS, Panopto and Zoom, which can be replaced
+//! (with `ApiBuilder::transport`) to answer them with canned responses, e.g. in tests.
+
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use reqwest::{Client, Request, Response};
+
+use crate::Result;
+
+#[async_trait]
+pub trait HttpTransport: Debug + Send + Sync {
+    /// Sends the request and returns the response, whatever its status.
+    /// An error means that there was no response at all, and most requests are then sent again.
+    /// A canned response can be made from an `http::Response` with `Response::from`.
+    async fn execute(&self, request: Request) -> Result<Response>;
+}
+
+/// The transport unless set otherwise, which sends requests over the network
+#[async_trait]
+impl HttpTransport for Client {
+    async fn execute(&self, request: Request) -> Result<Response> {
+        Client::execute(self, request).await.map_err(|e| {
+            tracing::debug!(error = %e, "HTTP request failed");
+            "Failed to send request"
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod canned {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use reqwest::{ResponseBuilderExt, Url};
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct CannedResponse {
+        status: u16,
+        body: String,
+        // where the response came from after following redirects, if not from the request URL
+        url: Option<Url>,
+    }
+
+    /// Answers requests with the responses given for their path, or for their path and query,
+    /// and keeps the requests for the test to look at. Anything else is answered with a 404.
+    #[derive(Debug, Default)]
+    pub(crate) struct CannedTransport {
+        responses: HashMap<String, CannedResponse>,
+        pub(crate) requests: Mutex<Vec<Request>>,
+    }
+
+    impl CannedTransport {
+        pub(crate) fn respond(mut self, path: &str, status: u16, body: &str) -> CannedTransport {
+            self.responses.insert(
+                path.to_owned(),
+                CannedResponse {
+                    status,
+                    body: body.to_owned(),
+                    url: None,
+                },
+            );
+            self
+        }
+
+        /// Answers as if the request was redirected to `url`, which then responded with the body
+        pub(crate) fn redirect(mut self, path: &str, url: &str, body: &str) -> CannedTransport {
+            self.responses.insert(
+                path.to_owned(),
+                CannedResponse {
+                    status: 200,
+                    body: body.to_owned(),
+                    url: Some(Url::parse(url).unwrap()),
+                },
+            );
+            self
+        }
+
+        /// The paths and queries of the requests that were sent, in order
+        pub(crate) fn requested(&self) -> Vec<String> {
+            self.requests
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|request| path_and_query(request.url()))
+                .collect()
+        }
+    }
+
+    fn path_and_query(url: &Url) -> String {
+        match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_owned(),
+        }
+    }
+
+    #[async_trait]
+    impl HttpTransport for CannedTransport {
+        async fn execute(&self, request: Request) -> Result<Response> {
+            let url = request.url().clone();
+            let canned = self
+                .responses
+                .get(&path_and_query(&url))
+                .or_else(|| self.responses.get(url.path()));
+            self.requests.lock().unwrap().push(request);
+            let (status, body, url) = match canned {
+                Some(canned) => (
+                    canned.status,
+                    canned.body.clone(),
+                    canned.url.clone().unwrap_or(url),
+                ),
+                None => (404, String::new(), url),
+            };
+            Ok(http::Response::builder()
+                .status(status)
+                .url(url)
+                .body(body)
+                .map_err(|_| "Unable to build canned response")?
+                .into())
+        }
+    }
+}
@@ -1,6 +1,55 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::sync::RwLock;
 use std::time::SystemTime;
 
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::Result;
+
+/// How `sanitise_filename` normalises the Unicode in names, so that a name comes out the same
+/// whichever form the server sent it in, and whichever form the file system keeps it in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FilenameNormalisation {
+    /// Names are kept as the server sent them
+    None,
+    /// Names are composed (NFC), e.g. to match names read back from macOS, which decomposes them
+    Nfc,
+    /// Accents are stripped, and the characters that are still not ASCII are written as their
+    /// code points, e.g. "Café 中文" becomes "Cafe u4e2du6587"
+    Ascii,
+}
+
+static FILENAME_NORMALISATION: RwLock<FilenameNormalisation> =
+    RwLock::new(FilenameNormalisation::None);
+
+/// Sets the normalisation for every name that is sanitised from now on
+pub fn set_filename_normalisation(normalisation: FilenameNormalisation) {
+    *FILENAME_NORMALISATION.write().unwrap() = normalisation;
+}
+
+fn normalise(name: &str) -> String {
+    match *FILENAME_NORMALISATION.read().unwrap() {
+        FilenameNormalisation::None => name.to_owned(),
+        FilenameNormalisation::Nfc => name.nfc().collect(),
+        FilenameNormalisation::Ascii => {
+            name.nfkd()
+                .filter(|&c| !is_combining_mark(c))
+                .fold(String::new(), |mut ascii, c| {
+                    if c.is_ascii() {
+                        ascii.push(c);
+                    } else {
+                        let _ = write!(ascii, "u{:04x}", c as u32);
+                    }
+                    ascii
+                })
+        }
+    }
+}
+
 pub fn sanitise_filename(name: &str) -> String {
+    let name = normalise(name);
     if cfg!(windows) {
         sanitize_filename::sanitize_with_options(
             name.trim(),
@@ -23,9 +72,49 @@ pub fn append_extension(stem: &str, ref_filename: &str) -> String {
     }
 }
 
-pub fn parse_time(time: &str) -> SystemTime {
-    SystemTime::from(
-        chrono::DateTime::<chrono::FixedOffset>::parse_from_rfc3339(time)
-            .expect("Failed to parse last updated time"),
-    )
+// Prefixes the name with its 1-based position, padded so that the names sort in that order
+pub fn prefix_with_index(index: usize, count: usize, name: &str) -> String {
+    let width = std::cmp::max(2, count.to_string().len());
+    format!("{:0width$} - {}", index + 1, name, width = width)
+}
+
+/// Parses a time as given by LumiNUS, e.g. `2021-08-09T10:00:00+08:00`
+pub fn parse_time(time: &str) -> Result<SystemTime> {
+    chrono::DateTime::<chrono::FixedOffset>::parse_from_rfc3339(time)
+        .map(SystemTime::from)
+        .map_err(|_| "Unable to parse time from server")
+}
+
+pub fn format_date(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Local>::from(time)
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+// Strips all HTML tags and decodes the entities, leaving only the text
+pub fn html_to_text(html: &str) -> String {
+    let stripped = ammonia::Builder::new()
+        .tags(HashSet::new())
+        .clean(html)
+        .to_string();
+    htmlescape::decode_html(&stripped)
+        .unwrap_or_else(|_| "Unable to decode HTML Entities".to_owned())
+}
+
+// Formats one CSV record, quoting fields as required by RFC 4180
+pub fn csv_record<S: AsRef<str>>(fields: &[S]) -> String {
+    let mut record = fields
+        .iter()
+        .map(|field| {
+            let field = field.as_ref();
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    record.push_str("\r\n");
+    record
 }
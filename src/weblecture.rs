@@ -4,13 +4,18 @@ use std::time::SystemTime;
 use async_trait::async_trait;
 use reqwest::Method;
 use serde::Deserialize;
+use tokio::io::AsyncWrite;
 
 use crate::panopto;
+use crate::panopto::SessionDetails;
 use crate::resource;
 use crate::resource::{OverwriteMode, OverwriteResult, Resource};
-use crate::streamer::{stream_and_mux_videos, StreamSpec};
-use crate::util::{parse_time, sanitise_filename};
-use crate::{Api, ApiData, Result};
+use crate::streamer::{
+    make_video_extension, stream_and_mux_videos, stream_primary_video_to_writer, StreamSpec,
+    VideoMetadata,
+};
+use crate::util::{format_date, parse_time, sanitise_filename};
+use crate::{Api, Result};
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -23,6 +28,8 @@ struct WebLectureResponse {
 struct WebLectureMedia {
     id: String,
     name: String,
+    start_date: Option<String>,
+    end_date: Option<String>,
     last_updated_date: String,
 }
 
@@ -36,6 +43,8 @@ pub struct WebLectureVideo {
     module_id: String,
     id: String,
     path: PathBuf,
+    start_date: Option<SystemTime>,
+    end_date: Option<SystemTime>,
     last_updated: SystemTime,
 }
 
@@ -44,7 +53,9 @@ impl WebLectureHandle {
         WebLectureHandle { id, path }
     }
 
-    pub async fn load(self, api: &Api) -> Result<Vec<WebLectureVideo>> {
+    /// With `date_prefix`, names are prefixed with the date of the session,
+    /// since many sessions share the same name.
+    pub async fn load(self, api: &Api, date_prefix: bool) -> Result<Vec<WebLectureVideo>> {
         let weblecture_resp = api
             .api_as_json::<WebLectureResponse>(
                 &format!("weblecture/?ParentID={}", self.id),
@@ -56,25 +67,42 @@ impl WebLectureHandle {
         match weblecture_resp {
             Ok(weblecture) => {
                 let weblectures_resp = api
-                    .api_as_json::<ApiData<Vec<WebLectureMedia>>>(
-                        &format!("weblecture/{}/sessions", weblecture.id),
-                        Method::GET,
-                        None,
-                    )
+                    .api_listing::<WebLectureMedia>(&format!(
+                        "weblecture/{}/sessions",
+                        weblecture.id
+                    ))
                     .await?;
 
                 match weblectures_resp.data {
-                    Some(weblectures) => Ok(weblectures
+                    Some(weblectures) => weblectures
                         .into_iter()
-                        .map(|w| WebLectureVideo {
-                            module_id: self.id.clone(),
-                            id: w.id,
-                            path: self.path.join(Self::make_mp4_extension(Path::new(
-                                &sanitise_filename(&w.name),
-                            ))),
-                            last_updated: parse_time(&w.last_updated_date),
+                        .map(|w| {
+                            let last_updated = parse_time(&w.last_updated_date)?;
+                            let name = if date_prefix {
+                                format!("{} - {}", format_date(last_updated), w.name)
+                            } else {
+                                w.name
+                            };
+                            Ok(WebLectureVideo {
+                                module_id: self.id.clone(),
+                                id: w.id,
+                                path: self.path.join(make_video_extension(
+                                    api,
+                                    Path::new(&sanitise_filename(&name)),
+                                )),
+                                // the schedule is only informational, so odd dates are left out
+                                start_date: w
+                                    .start_date
+                                    .as_deref()
+                                    .and_then(|date| parse_time(date).ok()),
+                                end_date: w
+                                    .end_date
+                                    .as_deref()
+                                    .and_then(|date| parse_time(date).ok()),
+                                last_updated,
+                            })
                         })
-                        .collect::<Vec<_>>()),
+                        .collect::<Result<Vec<_>>>(),
                     None => Err("Invalid API response from server: type mismatch"),
                 }
             }
@@ -82,11 +110,6 @@ impl WebLectureHandle {
             Err(_) => Ok(vec![]),
         }
     }
-
-    // TODO: check file extension?
-    fn make_mp4_extension(path: &Path) -> PathBuf {
-        path.with_extension("mp4")
-    }
 }
 
 #[async_trait]
@@ -115,27 +138,60 @@ impl Resource for WebLectureVideo {
     ) -> Result<OverwriteResult> {
         let context_id: &str = &self.module_id;
         let resource_link_id: &str = &self.id;
+        let metadata =
+            VideoMetadata::from_path(&self.path, self.start_date.or(Some(self.last_updated)));
+        let metadata = &metadata;
         resource::do_retryable_download(
             api,
+            self,
             destination,
             temp_destination,
             overwrite,
-            self.last_updated(),
             move |api| launch_panopto_and_get_stream_specs(api, context_id, resource_link_id),
             move |api, stream_specs, temp_destination| async move {
-                stream_and_mux_videos(api, &stream_specs, temp_destination).await
+                stream_and_mux_videos(api, &stream_specs, temp_destination, metadata).await
             },
         )
         .await
     }
+
+    async fn download_to_writer(
+        &self,
+        api: &Api,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        let stream_specs =
+            launch_panopto_and_get_stream_specs(api, &self.module_id, &self.id).await?;
+        stream_primary_video_to_writer(api, &stream_specs, writer).await
+    }
 }
 
-async fn launch_panopto_and_get_stream_specs(
+impl WebLectureVideo {
+    /// The scheduled start of the session, if known.
+    pub fn start_date(&self) -> Option<SystemTime> {
+        self.start_date
+    }
+
+    /// The scheduled end of the session, if known.
+    pub fn end_date(&self) -> Option<SystemTime> {
+        self.end_date
+    }
+
+    /// Fetches the duration and estimated size of this web lecture.
+    /// This launches Panopto, so it is much more expensive than listing.
+    pub async fn get_details(&self, api: &Api) -> Result<SessionDetails> {
+        let delivery_id =
+            launch_panopto_and_get_delivery_id(api, &self.module_id, &self.id).await?;
+        panopto::get_session_details(api, &delivery_id).await
+    }
+}
+
+async fn launch_panopto_and_get_delivery_id(
     api: &Api,
     context_id: &str,
     resource_link_id: &str,
-) -> Result<Vec<StreamSpec>> {
-    let response = panopto::launch(
+) -> Result<String> {
+    let url = panopto::resolve(
         api,
         &format!(
             "lti/Launch/panopto?context_id={}&resource_link_id={}",
@@ -144,15 +200,20 @@ async fn launch_panopto_and_get_stream_specs(
     )
     .await?;
 
-    let delivery_id_opt =
-        response
-            .url()
-            .query_pairs()
-            .find_map(|(k, v)| if k == "id" { Some(v) } else { None });
+    let delivery_id_opt = url
+        .query_pairs()
+        .find_map(|(k, v)| if k == "id" { Some(v) } else { None });
 
-    if let Some(delivery_id) = delivery_id_opt {
-        panopto::get_stream_specs(api, &delivery_id).await
-    } else {
-        Err("Unable to get \"id\" query parameter of Panopto viewer")
-    }
+    delivery_id_opt
+        .map(|delivery_id| delivery_id.into_owned())
+        .ok_or("Unable to get \"id\" query parameter of Panopto viewer")
+}
+
+async fn launch_panopto_and_get_stream_specs(
+    api: &Api,
+    context_id: &str,
+    resource_link_id: &str,
+) -> Result<Vec<StreamSpec>> {
+    let delivery_id = launch_panopto_and_get_delivery_id(api, context_id, resource_link_id).await?;
+    panopto::get_stream_specs(api, &delivery_id).await
 }
@@ -0,0 +1,132 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+use futures_util::future;
+use reqwest::Method;
+use serde::Deserialize;
+use tokio::io::AsyncWrite;
+
+use crate::resource;
+use crate::resource::{OverwriteMode, OverwriteResult, Resource, RetryableError};
+use crate::util::{parse_time, sanitise_filename};
+use crate::{Api, ApiData, Result};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiWeblink {
+    id: String,
+    name: String,
+    url: String,
+    last_updated_date: String,
+}
+
+pub struct WeblinkHandle {
+    id: String,
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct Weblink {
+    id: String,
+    path: PathBuf,
+    url: String,
+    last_updated: SystemTime,
+}
+
+impl WeblinkHandle {
+    pub fn new(id: String, path: PathBuf) -> WeblinkHandle {
+        WeblinkHandle { id, path }
+    }
+
+    pub async fn load(self, api: &Api) -> Result<Vec<Weblink>> {
+        let weblinks_resp = api
+            .api_as_json::<ApiData<Vec<ApiWeblink>>>(
+                &format!("weblink/?ParentID={}", self.id),
+                Method::GET,
+                None,
+            )
+            .await?;
+
+        match weblinks_resp.data {
+            Some(weblinks) => weblinks
+                .into_iter()
+                .map(|w| {
+                    Ok(Weblink {
+                        id: w.id,
+                        path: self
+                            .path
+                            .join(make_url_extension(Path::new(&sanitise_filename(&w.name)))),
+                        url: w.url,
+                        last_updated: parse_time(&w.last_updated_date)?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>(),
+            None => Ok(vec![]), // this module has no weblinks
+        }
+    }
+}
+
+fn make_url_extension(path: &Path) -> PathBuf {
+    path.with_extension("url")
+}
+
+impl Weblink {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    // The Internet Shortcut format, which is understood by Windows, macOS and most Linux file managers
+    fn to_shortcut(&self) -> String {
+        format!("[InternetShortcut]\r\nURL={}\r\n", self.url)
+    }
+}
+
+#[async_trait]
+impl Resource for Weblink {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+    fn path_mut(&mut self) -> &mut PathBuf {
+        &mut self.path
+    }
+
+    fn last_updated(&self) -> SystemTime {
+        self.last_updated
+    }
+
+    async fn download(
+        &self,
+        api: &Api,
+        destination: &Path,
+        temp_destination: &Path,
+        overwrite: OverwriteMode,
+    ) -> Result<OverwriteResult> {
+        resource::do_retryable_download(
+            api,
+            self,
+            destination,
+            temp_destination,
+            overwrite,
+            move |_| future::ready(Ok(self.to_shortcut())),
+            move |_, shortcut, temp_destination| async move {
+                tokio::fs::write(temp_destination, shortcut)
+                    .await
+                    .map_err(|_| RetryableError::Fail("Failed writing to disk"))
+            },
+        )
+        .await
+    }
+
+    async fn download_to_writer(
+        &self,
+        _api: &Api,
+        writer: &mut (dyn AsyncWrite + Send + Unpin),
+    ) -> Result<()> {
+        resource::write_text(&self.to_shortcut(), writer).await
+    }
+}